@@ -1,122 +1,183 @@
-use dioxus::core::anyhow;
+use crate::store;
 use flume::Receiver;
 use futures_lite::Stream;
 use p2p::messages::DmMessageTypes;
 use p2p::{
-    AddProgressItem, BlobTicket, ChatClient, DownloadProgress, EndpointId,
-    MessageTypes, Ticket,
+    AddProgressItem, BlobKey, BlobScope, BlobTicket, ChatClient, ChatEvent, CollectionAddProgress,
+    EndpointId, Hlc, MessageTypes, Tag, Ticket, TopicId, TopicState,
 };
 use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::str::FromStr;
-use tokio::sync::{Mutex, OnceCell};
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+/// A specialized `Result` type for the desktop client.
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// The errors returned by the `DesktopClient`. The p2p errors are kept as they are, so the
+/// controller can react to them, e.g. rejoining a topic or telling the user a contact is offline.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The client was not initialized yet.
+    NotInitialized,
+    /// The data directory of the platform could not be found.
+    NoDataDirectory,
+    /// A peer id string could not be parsed.
+    InvalidPeerId(String),
+    /// The p2p layer failed.
+    P2p(p2p::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotInitialized => write!(f, "Client is not initialized"),
+            Self::NoDataDirectory => write!(f, "Could not find data directory"),
+            Self::InvalidPeerId(msg) => write!(f, "Invalid peer ID: {msg}"),
+            Self::P2p(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::P2p(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<p2p::Error> for ClientError {
+    fn from(error: p2p::Error) -> Self {
+        Self::P2p(error)
+    }
+}
+
+/// Writes a topic state that changed. Failures are only logged: the state in memory is still
+/// replicated, and peers share it again after a restart.
+fn save_topic_state(topic: &TopicId, state: &TopicState) {
+    if let Err(e) = store::save_topic_state(topic, state) {
+        eprintln!("Failed to save topic state: {e}");
+    }
+}
+
+fn parse_peer_id(id: &str) -> Result<EndpointId> {
+    id.parse::<EndpointId>()
+        .map_err(|e| ClientError::InvalidPeerId(e.to_string()))
+}
 
 pub struct DesktopClient {
-    client: OnceCell<Mutex<ChatClient>>,
-    message_receivers: HashMap<String, Receiver<MessageTypes>>,
+    client: OnceCell<ChatClient>,
+    topic_tickets: RwLock<HashMap<TopicId, String>>,
+    topic_states: RwLock<HashMap<TopicId, TopicState>>,
 }
 
 impl DesktopClient {
     pub fn new() -> Self {
         Self {
             client: OnceCell::new(),
-            message_receivers: HashMap::new(),
+            topic_tickets: RwLock::new(HashMap::new()),
+            topic_states: RwLock::new(HashMap::new()),
         }
     }
 
-    pub async fn initialize(&self) -> anyhow::Result<()> {
+    pub async fn initialize(&self) -> Result<()> {
         let dir = dirs::data_dir()
-            .ok_or_else(|| anyhow!("Could not find data directory"))?
+            .ok_or(ClientError::NoDataDirectory)?
             .join("nexu");
-        self.client
-            .get_or_try_init(|| async { ChatClient::new(dir).await.map(Mutex::new) })
-            .await?;
+        self.client.get_or_try_init(|| ChatClient::new(dir)).await?;
         Ok(())
     }
 
-    pub async fn peer_id(&self) -> anyhow::Result<EndpointId> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client is not initialized"))?;
+    pub fn peer_id(&self) -> Result<EndpointId> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+
+        Ok(client.peer_id())
+    }
+
+    /// The safety number shared with the peer `id`, compared out-of-band to verify it.
+    pub fn safety_number(&self, id: &str) -> Result<String> {
+        let peer_id = parse_peer_id(id)?;
+        Ok(p2p::safety_number(&self.peer_id()?, &peer_id))
+    }
+
+    pub fn hlc_now(&self) -> Result<Hlc> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
 
-        Ok(client.lock().await.peer_id())
+        Ok(client.hlc_now())
     }
 
-    pub async fn create_topic(&mut self) -> anyhow::Result<String> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client is not initialized"))?;
-        let ticket = client.lock().await.create_topic().await?;
-        let message_receiver = client.lock().await.listen(&ticket.topic)?;
+    pub async fn create_topic(&self) -> Result<String> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        let ticket = client.create_topic().await?;
+        client.listen(&ticket.topic)?;
         let ticket_str = ticket.to_string();
-        self.message_receivers
-            .insert(ticket_str.clone(), message_receiver);
+        self.topic_tickets
+            .write()
+            .expect("topic tickets lock poisoned")
+            .insert(ticket.topic, ticket_str.clone());
         Ok(ticket_str)
     }
 
-    pub async fn join_topic(&mut self, ticket_str: &str) -> anyhow::Result<String> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client is not initialized"))?;
+    pub async fn join_topic(&self, ticket_str: &str) -> Result<String> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
 
         let ticket = Ticket::from_str(ticket_str)?;
-        let topic_id = client.lock().await.join_topic(ticket).await?;
+        let topic_id = client.join_topic(ticket).await?;
 
-        let message_receiver = client.lock().await.listen(&topic_id)?;
+        client.listen(&topic_id)?;
 
-        self.message_receivers
-            .insert(ticket_str.to_string(), message_receiver);
+        self.topic_tickets
+            .write()
+            .expect("topic tickets lock poisoned")
+            .insert(topic_id, ticket_str.to_string());
 
         Ok(ticket_str.to_string())
     }
 
-    pub async fn send(&self, message: MessageTypes) -> anyhow::Result<()> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client is not initialized"))?;
+    pub async fn send(&self, message: MessageTypes) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
 
-        client.lock().await.send(message).await?;
+        client.send(message).await?;
+        Ok(())
+    }
+
+    pub async fn send_to_neighbors(&self, message: MessageTypes) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+
+        client.send_to_neighbors(message).await?;
         Ok(())
     }
 
     #[allow(clippy::cast_sign_loss)]
-    pub async fn get_chat_message(
-        &self,
-        ticket_str: &str,
-        message: &str,
-    ) -> anyhow::Result<p2p::ChatMessage> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client is not initialized"))?;
+    pub fn get_chat_message(&self, ticket_str: &str, message: &str) -> Result<p2p::ChatMessage> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
 
         let ticket = Ticket::from_str(ticket_str)?;
         let timestamp = chrono::Utc::now().timestamp_millis() as u64;
-        let sender = client.lock().await.peer_id();
+        let sender = client.peer_id();
 
-        let message = p2p::ChatMessage::new(sender, message.to_string(), timestamp, ticket.topic);
+        let message = p2p::ChatMessage::new(sender, message.to_string(), timestamp, ticket.topic)
+            .with_hlc(client.hlc_now());
         Ok(message)
     }
 
     #[allow(clippy::cast_sign_loss)]
-    pub async fn get_dm_chat_message(
+    pub fn get_dm_chat_message(
         &self,
         id: &str,
         message: &str,
-    ) -> anyhow::Result<p2p::messages::DmChatMessage> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client is not initialized"))?;
-
-        let sender = client.lock().await.peer_id();
-        let endpoint_id = id.parse::<EndpointId>()?;
+    ) -> Result<p2p::messages::DmChatMessage> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+
+        let sender = client.peer_id();
+        let endpoint_id = parse_peer_id(id)?;
         let timestamp = chrono::Utc::now().timestamp_millis() as u64;
 
         let dm_message =
@@ -125,116 +186,286 @@ impl DesktopClient {
         Ok(dm_message)
     }
 
-    pub async fn leave_topic(&mut self, ticket_str: &str) -> anyhow::Result<()> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client is not initialized"))?;
+    pub fn leave_topic(&self, ticket_str: &str) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
 
         let ticket = Ticket::from_str(ticket_str)?;
-        client.lock().await.leave_topic(&ticket.topic)?;
+        client.leave_topic(&ticket.topic)?;
 
-        self.message_receivers.remove(ticket_str);
+        self.topic_tickets
+            .write()
+            .expect("topic tickets lock poisoned")
+            .remove(&ticket.topic);
 
         Ok(())
     }
 
-    pub const fn get_message_receiver(&mut self) -> &mut HashMap<String, Receiver<MessageTypes>> {
-        &mut self.message_receivers
+    pub fn get_topic_ticket(&self, topic: &TopicId) -> Option<String> {
+        self.topic_tickets
+            .read()
+            .expect("topic tickets lock poisoned")
+            .get(topic)
+            .cloned()
+    }
+
+    pub fn get_topic_state(&self, topic: &TopicId) -> TopicState {
+        self.topic_states
+            .read()
+            .expect("topic states lock poisoned")
+            .get(topic)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn has_topic_state(&self, topic: &TopicId) -> bool {
+        self.topic_states
+            .read()
+            .expect("topic states lock poisoned")
+            .contains_key(topic)
     }
 
-    pub async fn get_global_dm_receiver(
+    pub fn set_topic_states(&self, states: HashMap<TopicId, TopicState>) {
+        *self
+            .topic_states
+            .write()
+            .expect("topic states lock poisoned") = states;
+    }
+
+    pub fn remove_topic_state(&self, topic: &TopicId) {
+        self.topic_states
+            .write()
+            .expect("topic states lock poisoned")
+            .remove(topic);
+        if let Err(e) = store::remove_topic_state(topic) {
+            eprintln!("Failed to remove topic state: {e}");
+        }
+    }
+
+    /// Edits the state of a topic with a new tag, signing the admin settings written by this peer,
+    /// and saves it if the edit changed it.
+    pub fn edit_topic_state(
         &self,
-    ) -> anyhow::Result<Receiver<(EndpointId, DmMessageTypes)>> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client not initialized"))?;
-        let guard = client.lock().await;
-        Ok(guard.incoming_dms())
+        topic: &TopicId,
+        edit: impl FnOnce(&mut TopicState, Tag),
+    ) -> Result<TopicState> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+
+        let tag = Tag::new(client.hlc_now(), client.peer_id());
+        let (state, changed) = {
+            let mut states = self
+                .topic_states
+                .write()
+                .expect("topic states lock poisoned");
+            let state = states.entry(*topic).or_default();
+            let before = state.clone();
+            edit(state, tag);
+            state.sign(*topic, client.endpoint().secret_key());
+            (state.clone(), *state != before)
+        };
+        if changed {
+            save_topic_state(topic, &state);
+        }
+        Ok(state)
     }
 
-    pub async fn connect_to_user(&self, id: &str) -> anyhow::Result<()> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client is not initialized"))?;
+    /// Merges a received topic state, only taking the admin settings signed by the creator of the
+    /// topic, and saves it if the merge changed it.
+    pub fn merge_topic_state(&self, ticket: &Ticket, remote: &TopicState) -> (TopicState, bool) {
+        let (state, changed) = {
+            let mut states = self
+                .topic_states
+                .write()
+                .expect("topic states lock poisoned");
+            let state = states.entry(ticket.topic).or_default();
+            let changed = state.merge(remote, ticket.topic, ticket.creator());
+            (state.clone(), changed)
+        };
+        if changed {
+            save_topic_state(&ticket.topic, &state);
+        }
+        (state, changed)
+    }
 
-        let peer_id = id.parse::<EndpointId>()?;
+    pub fn get_event_receiver(&self) -> Result<Receiver<ChatEvent>> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        Ok(client.events())
+    }
 
-        client.lock().await.connect_peer(peer_id).await?;
+    pub async fn connect_to_user(&self, id: &str) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+
+        let peer_id = parse_peer_id(id)?;
+
+        client.connect_peer(peer_id).await?;
 
         Ok(())
     }
 
-    pub async fn send_dm(&self, id: &str, message: DmMessageTypes) -> anyhow::Result<()> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client is not initialized"))?;
+    pub fn watch_presence(&self, id: &str) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
 
-        let peer_id = id.parse::<EndpointId>()?;
+        let peer_id = parse_peer_id(id)?;
+        client.watch_presence(peer_id);
+        Ok(())
+    }
 
-        client.lock().await.send_dm(peer_id, message).await?;
+    pub fn unwatch_presence(&self, id: &str) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
 
+        let peer_id = parse_peer_id(id)?;
+        client.unwatch_presence(&peer_id);
         Ok(())
     }
 
-    pub async fn save_blob(
+    pub fn block_peer(&self, id: &str) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+
+        let peer_id = parse_peer_id(id)?;
+        client.block_peer(peer_id);
+        client.unwatch_presence(&peer_id);
+        Ok(())
+    }
+
+    pub fn unblock_peer(&self, id: &str) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+
+        let peer_id = parse_peer_id(id)?;
+        client.unblock_peer(&peer_id);
+        Ok(())
+    }
+
+    pub fn start_heartbeat(&self, interval: Duration) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        client.start_heartbeat(interval);
+        Ok(())
+    }
+
+    pub async fn send_dm(&self, id: &str, message: DmMessageTypes) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+
+        let peer_id = parse_peer_id(id)?;
+
+        match client.send_dm(peer_id, message.clone()).await {
+            // The DM channel was never opened or broke, reconnect and retry once.
+            Err(p2p::Error::NotConnected(_) | p2p::Error::Connection(_)) => {
+                client.connect_peer(peer_id).await?;
+                client.send_dm(peer_id, message).await?;
+            }
+            result => result?,
+        }
+
+        Ok(())
+    }
+
+    pub async fn save_blob(&self, blob: Vec<u8>) -> Result<p2p::Hash> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        let tag = client
+            .save_blob(blob.as_slice())
+            .with_tag()
+            .await
+            .map_err(|e| p2p::Error::BlobStore(e.to_string()))?;
+        Ok(tag.hash)
+    }
+
+    pub async fn save_encrypted_blob(
         &self,
         blob: Vec<u8>,
-    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = AddProgressItem> + Send>>> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client is not initialized"))?;
-        let stream = client.lock().await.save_blob(blob.as_slice()).stream().await;
-        Ok(Box::pin(stream))
+    ) -> Result<(BlobKey, Pin<Box<dyn Stream<Item = AddProgressItem> + Send>>)> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        let (key, progress) = client.save_encrypted_blob(blob.as_slice())?;
+        let stream = progress.stream().await;
+        Ok((key, Box::pin(stream)))
     }
 
-    pub async fn save_blob_from_path(
+    /// Encrypts a file into the blob store chunk by chunk, without reading it all in memory.
+    pub async fn save_encrypted_file(
         &self,
-        blob_path: PathBuf,
-    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = AddProgressItem> + Send>>> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client is not initialized"))?;
-        let stream = client.lock().await.save_blob_from_path(&blob_path).stream().await;
-        Ok(Box::pin(stream))
+        path: &Path,
+    ) -> Result<(BlobKey, Pin<Box<dyn Stream<Item = AddProgressItem> + Send>>)> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        let (key, stream) = client.save_encrypted_file(path).await?;
+        Ok((key, Box::pin(stream)))
     }
 
-    pub async fn download_blob(
+    pub fn register_blob_key(&self, hash: p2p::Hash, key: BlobKey) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        client.register_blob_key(hash, key);
+        Ok(())
+    }
+
+    pub fn download_blob(
         &self,
         blob_ticket: &BlobTicket,
-    ) -> anyhow::Result<DownloadProgress> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client is not initialized"))?;
-        
-        Ok(client.lock().await.download_blob(blob_ticket))
+    ) -> Result<impl Future<Output = p2p::Result<()>> + Send + 'static> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+
+        Ok(client.download_blob(blob_ticket))
+    }
+
+    pub fn save_collection(
+        &self,
+        files: Vec<(String, PathBuf)>,
+    ) -> Result<Receiver<CollectionAddProgress>> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        Ok(client.save_collection(files))
+    }
+
+    pub fn download_collection(
+        &self,
+        hash: p2p::Hash,
+        provider: EndpointId,
+    ) -> Result<impl Future<Output = p2p::Result<()>> + Send + 'static> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+
+        Ok(client.download_collection(hash, provider))
+    }
+
+    pub async fn has_collection(&self, hash: p2p::Hash) -> Result<bool> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        Ok(client.has_collection(hash).await?)
+    }
+
+    pub async fn has_blob(&self, hash: p2p::Hash) -> Result<bool> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        Ok(client.has_blob(hash).await?)
+    }
+
+    pub async fn export_collection(
+        &self,
+        hash: p2p::Hash,
+        target_dir: &Path,
+    ) -> Result<Vec<PathBuf>> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        Ok(client.export_collection(hash, target_dir).await?)
+    }
+
+    pub async fn export_blob(&self, hash: p2p::Hash, target: &Path) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        Ok(client.export_blob(hash, target).await?)
+    }
+
+    pub fn grant_blob_access(&self, hash: p2p::Hash, scope: BlobScope) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        client.grant_blob_access(hash, scope);
+        Ok(())
+    }
+
+    pub async fn delete_blobs(&self, hashes: &[p2p::Hash]) -> Result<()> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        Ok(client.delete_blobs(hashes).await?)
     }
 
     pub async fn get_blob_path(
         &self,
         hash: impl Into<p2p::Hash>,
         extension: impl AsRef<OsStr>,
-    ) -> anyhow::Result<PathBuf> {
-        let client = self
-            .client
-            .get()
-            .ok_or_else(|| anyhow!("Client is not initialized"))?;
-        let guard = client.lock().await;
-        guard.get_blob_path(hash, extension).await
-    }
-
-    pub async fn get_store_path(&self) -> PathBuf {
-        let client = self
-            .client
-            .get()
-            .expect("Client is not initialized");
-        let guard = client.lock().await;
-        guard.get_blob_store_path().clone()
+    ) -> Result<PathBuf> {
+        let client = self.client.get().ok_or(ClientError::NotInitialized)?;
+        Ok(client.get_blob_path(hash, extension).await?)
+    }
+
+    pub fn get_store_path(&self) -> PathBuf {
+        let client = self.client.get().expect("Client is not initialized");
+        client.get_blob_store_path().clone()
     }
 }
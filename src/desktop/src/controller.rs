@@ -1,25 +1,26 @@
-use crate::client::DesktopClient;
+use crate::client::{ClientError, DesktopClient};
 use crate::media_server::MediaServer;
+use crate::message_handler;
+use crate::retention;
+use crate::store;
 use crate::utils;
-use crate::utils::topics::save_topics_to_file;
-use base64::Engine;
 use chrono::Utc;
 use dioxus::html::FileData;
 use dioxus::prelude::{ReadableExt, Signal, WritableExt};
 use flume::{Receiver, Sender};
 use futures_lite::StreamExt;
 use p2p::{
-    BlobTicket, DmMessageTypes, DmProfileMetadataMessage, DownloadProgressItem, EndpointAddr,
-    EndpointId, Hash, MessageTypes, Raw, Ticket, TopicMetadataMessage,
+    BlobScope, BlobTicket, CollectionAddProgress, DmDisappearingMessage, DmMessageTypes,
+    DmProfileMetadataMessage, DmTypingMessage, EndpointAddr, EndpointId, Hash, MessageTypes, Raw,
+    Ticket, TopicMetadataMessage, TypingMessage,
 };
-use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use ui::desktop::models::{
-    AppState, BlobMessage, BlobType, ChatMessage, DmBlobMessage, DmChatMessage, Profile,
-    ProfileChat, Topic,
+    AppState, BlobMessage, BlobType, ChatMessage, CollectionMessage, DmBlobMessage, DmChatMessage,
+    DmCollectionMessage, DownloadState, MediaSettings, Profile, ProfileChat, RetentionSettings,
+    Topic,
 };
 
 #[derive(Debug, Clone)]
@@ -51,14 +52,44 @@ pub enum Command {
         name: String,
         blob_type: BlobType,
     },
+    SendCollectionToTopic {
+        ticket_id: String,
+        files: Vec<FileData>,
+        name: Option<String>,
+    },
+    SendCollectionToUser {
+        user_addr: String,
+        files: Vec<FileData>,
+        name: Option<String>,
+    },
+    DownloadCollection {
+        blob_hash: String,
+        user_id: String,
+        target_dir: PathBuf,
+    },
+    DownloadCollectionEntry {
+        blob_hash: String,
+        user_id: String,
+        target: PathBuf,
+    },
     ModifyTopic(Topic),
     ModifyProfile(Profile),
+    ModifyMediaSettings(MediaSettings),
+    ModifyRetentionSettings(RetentionSettings),
     ConnectToUser(String),
+    AcceptContactRequest(String),
+    RejectContactRequest(String),
+    BlockUser(String),
+    UnblockUser(String),
     RemoveContact(String),
+    VerifyContact(String, bool),
+    SetContactDisappearing(String, Option<u64>),
+    SendTypingToTopic(String),
+    SendTypingToUser(String),
 }
 
 pub struct AppController {
-    desktop_client: Arc<Mutex<DesktopClient>>,
+    desktop_client: Arc<DesktopClient>,
     progress_bar: Receiver<u64>,
     pub progress_bar_sender: Sender<u64>,
     command_sender: Sender<Command>,
@@ -70,7 +101,7 @@ impl AppController {
     pub fn new() -> Self {
         let (progress_bar_sender, progress_bar) = flume::unbounded();
         let (command_sender, command_receiver) = flume::unbounded();
-        let desktop_client = Arc::new(Mutex::new(DesktopClient::new()));
+        let desktop_client = Arc::new(DesktopClient::new());
         let media_server = MediaServer::new(3000);
         Self {
             desktop_client,
@@ -84,12 +115,12 @@ impl AppController {
 
     pub async fn start_media_server(&self) {
         self.media_server
-            .start(self.desktop_client.lock().await.get_store_path().await)
+            .start(Arc::clone(&self.desktop_client))
             .await
             .expect("Failed to start media server");
     }
 
-    pub fn get_desktop_client(&self) -> Arc<Mutex<DesktopClient>> {
+    pub fn get_desktop_client(&self) -> Arc<DesktopClient> {
         Arc::clone(&self.desktop_client)
     }
 
@@ -111,7 +142,7 @@ impl AppController {
     pub async fn process_command(
         command: Command,
         app_state: Signal<AppState>,
-        desktop_client: Arc<Mutex<DesktopClient>>,
+        desktop_client: Arc<DesktopClient>,
         progress_sender: Sender<u64>,
     ) {
         match command {
@@ -162,7 +193,7 @@ impl AppController {
                 }
             },
             Command::DownloadBlob { blob_hash, user_id } => {
-                Self::do_download_blob(&blob_hash, &user_id, desktop_client, progress_sender).await;
+                Self::do_download_blob(&blob_hash, &user_id, app_state, desktop_client).await;
             }
             Command::SendMessageToUser { user_addr, message } => {
                 Self::do_send_message_to_user(user_addr, message, app_state, desktop_client).await;
@@ -184,17 +215,91 @@ impl AppController {
                 )
                 .await;
             }
+            Command::SendCollectionToTopic {
+                ticket_id,
+                files,
+                name,
+            } => {
+                Self::do_send_collection_to_topic(
+                    ticket_id,
+                    files,
+                    name,
+                    app_state,
+                    desktop_client,
+                    progress_sender,
+                )
+                .await;
+            }
+            Command::SendCollectionToUser {
+                user_addr,
+                files,
+                name,
+            } => {
+                Self::do_send_collection_to_user(
+                    user_addr,
+                    files,
+                    name,
+                    app_state,
+                    desktop_client,
+                    progress_sender,
+                )
+                .await;
+            }
+            Command::DownloadCollection {
+                blob_hash,
+                user_id,
+                target_dir,
+            } => {
+                Self::do_download_collection(&blob_hash, &user_id, &target_dir, desktop_client)
+                    .await;
+            }
+            Command::DownloadCollectionEntry {
+                blob_hash,
+                user_id,
+                target,
+            } => {
+                Self::do_download_collection_entry(&blob_hash, &user_id, &target, desktop_client)
+                    .await;
+            }
             Command::ModifyTopic(topic) => {
                 Self::do_modify_topic(topic, app_state, desktop_client).await;
             }
             Command::ModifyProfile(profile) => {
                 Self::do_modify_profile(profile, app_state, desktop_client).await;
             }
-            Command::ConnectToUser(user_id) => {
+            Command::ModifyMediaSettings(settings) => {
+                Self::do_modify_media_settings(settings, app_state).await;
+            }
+            Command::ModifyRetentionSettings(settings) => {
+                Self::do_modify_retention_settings(settings, app_state, &desktop_client).await;
+            }
+            Command::ConnectToUser(user_id) | Command::AcceptContactRequest(user_id) => {
                 Self::do_connect_to_user(user_id, app_state, desktop_client).await;
             }
+            Command::RejectContactRequest(user_id) => {
+                Self::do_reject_contact_request(&user_id, app_state);
+            }
+            Command::BlockUser(user_id) => {
+                Self::do_block_user(user_id, app_state, desktop_client).await;
+            }
+            Command::UnblockUser(user_id) => {
+                Self::do_unblock_user(&user_id, app_state, &desktop_client);
+            }
             Command::RemoveContact(profile_id) => {
-                Self::do_remove_contact(profile_id, app_state).await;
+                Self::do_remove_contact(profile_id, app_state, desktop_client).await;
+            }
+            Command::VerifyContact(profile_id, verified) => {
+                Self::do_verify_contact(&profile_id, verified, app_state, &desktop_client);
+            }
+            Command::SetContactDisappearing(profile_id, seconds) => {
+                Self::do_set_contact_disappearing(profile_id, seconds, app_state, desktop_client)
+                    .await;
+            }
+            Command::SendTypingToTopic(ticket_id) => {
+                Self::do_send_typing_to_topic(ticket_id, desktop_client).await;
+            }
+            Command::SendTypingToUser(user_addr) => {
+                Self::do_send_typing_to_user(user_addr, desktop_client).await;
             }
         }
     }
@@ -203,23 +308,36 @@ impl AppController {
     async fn do_create_topic(
         name: String,
         mut app_state: Signal<AppState>,
-        desktop_client: Arc<Mutex<DesktopClient>>,
+        desktop_client: Arc<DesktopClient>,
     ) {
         let result: Result<(), Error> = async {
             let ticket = desktop_client
-                .lock()
-                .await
                 .create_topic()
                 .await
                 .map_err(|e| Error::TopicCreation(e.to_string()))?;
 
+            let topic_id = Ticket::from_str(&ticket)
+                .map_err(|_| Error::InvalidTicket("Invalid ticket string".to_string()))?
+                .topic;
+            let id = desktop_client
+                .peer_id()
+                .map_err(|e| Error::PeerId(e.to_string()))?;
+            desktop_client
+                .edit_topic_state(&topic_id, |state, tag| {
+                    state.name.set(name.clone(), tag);
+                    state.members.insert(id, tag);
+                })
+                .map_err(|e| Error::TopicCreation(e.to_string()))?;
+
             let mut topic = Topic::new(ticket, name, None);
             let profile = app_state.read().get_profile();
             topic.add_member(&profile.id);
+            topic.admin = Some(profile.id);
             app_state.write().add_topic(&topic);
 
-            save_topics_to_file(&app_state.read().get_all_topics())
-                .map_err(|_| Error::FileSave("Failed to save topics to file".to_string()))?;
+            app_state
+                .with_mut(store::save_chats)
+                .map_err(|_| Error::FileSave("Failed to save topics".to_string()))?;
 
             Ok(())
         }
@@ -235,7 +353,7 @@ impl AppController {
     async fn do_join_topic(
         topic_id: String,
         mut app_state: Signal<AppState>,
-        desktop_client: Arc<Mutex<DesktopClient>>,
+        desktop_client: Arc<DesktopClient>,
     ) {
         let result: Result<(), Error> = async {
             if app_state().get_topic(&topic_id).is_some() {
@@ -245,8 +363,6 @@ impl AppController {
             let mut topic = Topic::new_placeholder(topic_id.clone());
 
             let ticket_str = desktop_client
-                .lock()
-                .await
                 .join_topic(&topic_id)
                 .await
                 .map_err(|e| Error::TopicJoin(e.to_string()))?;
@@ -258,25 +374,25 @@ impl AppController {
                 Utc::now().timestamp_millis() as u64,
             ));
 
+            let id = desktop_client
+                .peer_id()
+                .map_err(|e| Error::PeerId(e.to_string()))?;
+            desktop_client
+                .edit_topic_state(&ticket.topic, |state, tag| state.members.insert(id, tag))
+                .map_err(|e| Error::TopicJoin(e.to_string()))?;
+
             let profile = app_state.read().get_profile();
             topic.add_member(&profile.id);
+            topic.admin = ticket.creator().map(|creator| creator.to_string());
             app_state.write().add_topic(&topic);
 
-            save_topics_to_file(&app_state.read().get_all_topics())
-                .map_err(|_| Error::FileSave("Failed to save topics to file".to_string()))?;
+            app_state
+                .with_mut(store::save_chats)
+                .map_err(|_| Error::FileSave("Failed to save topics".to_string()))?;
 
             tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
 
-            let id = desktop_client
-                .lock()
-                .await
-                .peer_id()
-                .await
-                .map_err(|e| Error::PeerId(e.to_string()))?;
-
             desktop_client
-                .lock()
-                .await
                 .send(MessageTypes::JoinTopic(p2p::JoinMessage::new(
                     ticket.topic,
                     id,
@@ -285,6 +401,8 @@ impl AppController {
                 .await
                 .map_err(|e| Error::MessageSend(e.to_string()))?;
 
+            message_handler::announce_profile(&desktop_client, app_state, ticket.topic).await;
+
             Ok(())
         }
         .await;
@@ -299,22 +417,17 @@ impl AppController {
     async fn do_leave_topic(
         topic_id: String,
         mut app_state: Signal<AppState>,
-        desktop_client: Arc<Mutex<DesktopClient>>,
+        desktop_client: Arc<DesktopClient>,
     ) {
         let result: Result<(), Error> = async {
             let id = desktop_client
-                .lock()
-                .await
                 .peer_id()
-                .await
                 .map_err(|e| Error::PeerId(e.to_string()))?;
 
             let ticket = Ticket::from_str(&topic_id)
                 .map_err(|_| Error::InvalidTicket("Failed to parse topic_id".to_string()))?;
 
             desktop_client
-                .lock()
-                .await
                 .send(MessageTypes::LeaveTopic(p2p::LeaveMessage::new(
                     ticket.topic,
                     id,
@@ -324,16 +437,15 @@ impl AppController {
                 .map_err(|e| Error::MessageSend(e.to_string()))?;
 
             desktop_client
-                .lock()
-                .await
                 .leave_topic(&topic_id)
-                .await
                 .map_err(|e| Error::TopicLeave(e.to_string()))?;
+            desktop_client.remove_topic_state(&ticket.topic);
 
             app_state.write().remove_topic(&topic_id);
 
-            save_topics_to_file(&app_state.read().get_all_topics())
-                .map_err(|_| Error::FileSave("Failed to save topics to file".to_string()))?;
+            app_state
+                .with_mut(store::save_chats)
+                .map_err(|_| Error::FileSave("Failed to save topics".to_string()))?;
 
             Ok(())
         }
@@ -350,32 +462,33 @@ impl AppController {
         ticket_id: String,
         message: String,
         mut app_state: Signal<AppState>,
-        desktop_client: Arc<Mutex<DesktopClient>>,
+        desktop_client: Arc<DesktopClient>,
     ) {
         let now = Utc::now().timestamp_millis() as u64;
 
         let result: Result<(), Error> = async {
             let client_ref = desktop_client.clone();
-            let (send_result, peer_id_result) = {
+            let (send_result, peer_id_result, hlc) = {
                 let msg = client_ref
-                    .lock()
-                    .await
                     .get_chat_message(&ticket_id, &message)
-                    .await
                     .map_err(|e| {
                         eprintln!("Failed to create chat message: {e}");
                         Error::MessageCreation(e.to_string())
                     })?;
 
-                let send = client_ref.lock().await.send(MessageTypes::Chat(msg)).await;
-                let peer = client_ref.lock().await.peer_id().await;
-                (send, peer)
+                let hlc = msg.hlc;
+                let send = Self::post_to_topic(
+                    &client_ref,
+                    app_state,
+                    &ticket_id,
+                    MessageTypes::Chat(msg),
+                )
+                .await;
+                let peer = client_ref.peer_id();
+                (send, peer, hlc)
             };
 
-            send_result.map_err(|e| {
-                eprintln!("Failed to send message: {e}");
-                Error::MessageSend(e.to_string())
-            })?;
+            send_result?;
 
             let peer_id = peer_id_result.map_err(|e| {
                 eprintln!("Failed to get peer_id: {e}");
@@ -390,14 +503,15 @@ impl AppController {
                         message.clone(),
                         now,
                         true,
-                    );
+                    )
+                    .with_hlc(hlc.as_u64());
                     topic.add_message(msg);
                 }
             });
 
-            save_topics_to_file(&app_state().get_all_topics()).map_err(|_| {
-                eprintln!("Failed to save topics to file");
-                Error::FileSave("Failed to save topics to file".to_string())
+            app_state.with_mut(store::save_chats).map_err(|_| {
+                eprintln!("Failed to save topics");
+                Error::FileSave("Failed to save topics".to_string())
             })?;
 
             Ok(())
@@ -416,7 +530,7 @@ impl AppController {
         image_data: Vec<u8>,
         image_name: String,
         mut app_state: Signal<AppState>,
-        desktop_client: Arc<Mutex<DesktopClient>>,
+        desktop_client: Arc<DesktopClient>,
         progress_sender: Sender<u64>,
     ) {
         let now = Utc::now().timestamp_millis() as u64;
@@ -426,18 +540,18 @@ impl AppController {
                 .map_err(|_| Error::InvalidTicket("Failed to parse ticket_id".to_string()))?;
 
             let peer_id = desktop_client
-                .lock()
-                .await
                 .peer_id()
-                .await
                 .map_err(|e| Error::PeerId(e.to_string()))?;
 
-            let add_stream = desktop_client
-                .lock()
-                .await
-                .save_blob(image_data.clone())
+            let settings = app_state.read().get_media_settings();
+            let processed = Self::process_image(image_data, image_name, settings).await?;
+            let image_data = processed.bytes;
+            let image_name = processed.name;
+
+            let (key, add_stream) = desktop_client
+                .save_encrypted_blob(image_data.clone())
                 .await
-                .map_err(|e| Error::BlobSave(e.to_string()))?;
+                .map_err(|e| Self::blob_error(app_state, &e))?;
 
             let mut stream = add_stream;
             let mut hash = None;
@@ -467,6 +581,16 @@ impl AppController {
             let hash =
                 hash.ok_or_else(|| Error::BlobSave("Failed to get hash from stream".to_string()))?;
 
+            let preview_data = image_data.clone();
+            let preview =
+                tokio::task::spawn_blocking(move || utils::previews::image_preview(&preview_data))
+                    .await
+                    .ok()
+                    .flatten();
+
+            let hlc = desktop_client
+                .hlc_now()
+                .map_err(|e| Error::MessageCreation(e.to_string()))?;
             let msg = p2p::BlobMessage::new(
                 ticket.topic,
                 peer_id,
@@ -475,17 +599,20 @@ impl AppController {
                 hash,
                 now,
                 p2p::messages::BlobType::Image,
-            );
+                preview.clone(),
+                Some(key),
+            )
+            .with_hlc(hlc);
 
-            desktop_client
-                .lock()
-                .await
-                .send(MessageTypes::Blob(msg))
-                .await
-                .map_err(|e| {
-                    eprintln!("Failed to send message: {e}");
-                    Error::MessageSend(e.to_string())
-                })?;
+            Self::grant_blob_access(&desktop_client, [hash], BlobScope::Topic(ticket.topic))?;
+
+            Self::post_to_topic(
+                &desktop_client,
+                app_state,
+                &ticket_id,
+                MessageTypes::Blob(msg),
+            )
+            .await?;
 
             app_state.with_mut(|state| {
                 if let Some(topic) = state.get_topic_mutable(&ticket_id) {
@@ -498,14 +625,17 @@ impl AppController {
                         now,
                         true,
                         BlobType::Image,
-                    );
+                        preview.map(utils::previews::to_ui_preview),
+                        Some(key.to_string()),
+                    )
+                    .with_hlc(hlc.as_u64());
                     topic.add_blob_message(msg);
                 }
             });
 
-            save_topics_to_file(&app_state().get_all_topics()).map_err(|_| {
-                eprintln!("Failed to save topics to file");
-                Error::FileSave("Failed to save topics to file".to_string())
+            app_state.with_mut(store::save_chats).map_err(|_| {
+                eprintln!("Failed to save topics");
+                Error::FileSave("Failed to save topics".to_string())
             })?;
 
             Ok(())
@@ -525,7 +655,7 @@ impl AppController {
         blob_name: String,
         blob_type: BlobType,
         mut app_state: Signal<AppState>,
-        desktop_client: Arc<Mutex<DesktopClient>>,
+        desktop_client: Arc<DesktopClient>,
         progress_sender: Sender<u64>,
     ) {
         let now = Utc::now().timestamp_millis() as u64;
@@ -535,18 +665,13 @@ impl AppController {
                 .map_err(|_| Error::InvalidTicket("Failed to parse ticket_id".to_string()))?;
 
             let peer_id = desktop_client
-                .lock()
-                .await
                 .peer_id()
-                .await
                 .map_err(|e| Error::PeerId(e.to_string()))?;
 
-            let add_stream = desktop_client
-                .lock()
+            let (key, add_stream) = desktop_client
+                .save_encrypted_file(&blob_data.path())
                 .await
-                .save_blob_from_path(blob_data.path())
-                .await
-                .map_err(|e| Error::BlobSave(e.to_string()))?;
+                .map_err(|e| Self::blob_error(app_state, &e))?;
 
             let mut stream = add_stream;
             let mut hash = None;
@@ -585,6 +710,11 @@ impl AppController {
                 BlobType::Other => p2p::messages::BlobType::Other,
             };
 
+            let preview = Self::build_preview(blob_data.path(), blob_type).await;
+
+            let hlc = desktop_client
+                .hlc_now()
+                .map_err(|e| Error::MessageCreation(e.to_string()))?;
             let msg = p2p::BlobMessage::new(
                 ticket.topic,
                 peer_id,
@@ -593,17 +723,20 @@ impl AppController {
                 hash,
                 now,
                 p2p_blob_type,
-            );
+                preview.clone(),
+                Some(key),
+            )
+            .with_hlc(hlc);
 
-            desktop_client
-                .lock()
-                .await
-                .send(MessageTypes::Blob(msg))
-                .await
-                .map_err(|e| {
-                    eprintln!("Failed to send message: {e}");
-                    Error::MessageSend(e.to_string())
-                })?;
+            Self::grant_blob_access(&desktop_client, [hash], BlobScope::Topic(ticket.topic))?;
+
+            Self::post_to_topic(
+                &desktop_client,
+                app_state,
+                &ticket_id,
+                MessageTypes::Blob(msg),
+            )
+            .await?;
 
             app_state.with_mut(|state| {
                 if let Some(topic) = state.get_topic_mutable(&ticket_id) {
@@ -616,7 +749,10 @@ impl AppController {
                         now,
                         true,
                         blob_type,
-                    );
+                        preview.map(utils::previews::to_ui_preview),
+                        Some(key.to_string()),
+                    )
+                    .with_hlc(hlc.as_u64());
                     topic.add_blob_message(msg);
                 }
             });
@@ -630,11 +766,41 @@ impl AppController {
         }
     }
 
+    /// Runs an image through the media pipeline off the async runtime.
+    async fn process_image(
+        image_data: Vec<u8>,
+        image_name: String,
+        settings: MediaSettings,
+    ) -> Result<utils::media::ProcessedImage, Error> {
+        tokio::task::spawn_blocking(move || {
+            utils::media::process_image(image_data, &image_name, &settings)
+        })
+        .await
+        .map_err(|e| Error::MediaProcessing(e.to_string()))?
+        .map_err(|e| Error::MediaProcessing(e.to_string()))
+    }
+
+    /// Builds the inline preview of an image or video off the async runtime.
+    async fn build_preview(path: PathBuf, blob_type: BlobType) -> Option<p2p::BlobPreview> {
+        tokio::task::spawn_blocking(move || match blob_type {
+            BlobType::Image | BlobType::BigImage => utils::previews::image_preview_from_path(&path),
+            BlobType::Video => utils::previews::video_preview(&path),
+            _ => None,
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    /// Downloads a blob shown in a chat. The transfer events report how it goes, but they are
+    /// dropped when the event queue is full and a download that cannot start sends none, so its
+    /// outcome is recorded too.
+    #[allow(clippy::future_not_send)]
     async fn do_download_blob(
         blob_hash: &str,
         user_id: &str,
-        desktop_client: Arc<Mutex<DesktopClient>>,
-        progress_sender: Sender<u64>,
+        mut app_state: Signal<AppState>,
+        desktop_client: Arc<DesktopClient>,
     ) {
         let result: Result<(), Error> = async {
             let hash = blob_hash
@@ -645,193 +811,501 @@ impl AppController {
             let addr = EndpointAddr::from(endpoint_id);
             let ticket = BlobTicket::new(addr, hash, Raw);
 
-            let progress = desktop_client
-                .lock()
-                .await
+            let download = desktop_client
                 .download_blob(&ticket)
-                .await
                 .map_err(|e| Error::DownloadBlob(format!("Failed to start blob download: {e}")))?;
 
-            let mut stream = progress.stream().await.map_err(|e| {
-                Error::DownloadBlob(format!("Failed to get download progress stream: {e}"))
-            })?;
-
-            while let Some(item) = stream.next().await {
-                match item {
-                    DownloadProgressItem::Progress(progress) => {
-                        progress_sender
-                            .send(progress)
-                            .expect("Message to the channel should not return an error");
-                    }
-                    DownloadProgressItem::Error(e) => {
-                        let _ = progress_sender.send(u64::MAX);
-                        return Err(Error::DownloadBlob(format!(
-                            "Error during blob download: {e}"
-                        )));
-                    }
-                    DownloadProgressItem::TryProvider { id, request } => {
-                        println!("Trying provider {id} for request {request:?}");
-                    }
-                    DownloadProgressItem::ProviderFailed { id, request } => {
-                        eprintln!("Provider {id} failed for request {request:?}");
-                    }
-                    DownloadProgressItem::PartComplete { request } => {
-                        println!("Part complete for request {request:?}");
-                    }
-                    DownloadProgressItem::DownloadError => {
-                        let _ = progress_sender.send(u64::MAX);
-                        return Err(Error::DownloadBlob("Download error occurred".to_string()));
-                    }
-                }
-            }
-
-            let _ = progress_sender.send(u64::MAX);
-
-            Ok(())
+            download
+                .await
+                .map_err(|e| Error::DownloadBlob(e.to_string()))
         }
         .await;
 
-        if let Err(e) = result {
+        let download = if let Err(e) = result {
             eprintln!("Failed to download blob {blob_hash}: {e}");
-        }
+            DownloadState::Failed
+        } else {
+            DownloadState::Done
+        };
+        app_state.with_mut(|state| state.update_download(blob_hash, download));
     }
 
-    pub fn get_blob_from_storage(
-        &self,
-        hash: Hash,
-        extension: impl AsRef<OsStr>,
-    ) -> Option<PathBuf> {
-        let desktop_client = Arc::clone(&self.desktop_client);
+    /// Adds every file to the store as a single collection, reporting the batch progress as a
+    /// percentage of the total size.
+    async fn import_collection(
+        files: &[FileData],
+        desktop_client: &Arc<DesktopClient>,
+        progress_sender: &Sender<u64>,
+    ) -> Result<(Hash, Vec<p2p::CollectionEntry>), Error> {
+        let selection: Vec<PathBuf> = files.iter().map(FileData::path).collect();
+        let paths = utils::collections::expand_directories(&selection)
+            .map_err(|e| Error::BlobSave(e.to_string()))?;
+        if paths.is_empty() {
+            return Err(Error::BlobSave("There are no files to send".to_string()));
+        }
+        let names = utils::collections::relative_entry_names(&paths);
+
+        let progress = desktop_client
+            .save_collection(names.into_iter().zip(paths).collect())
+            .map_err(|e| Error::BlobSave(e.to_string()))?;
+
+        let mut total = 0;
+        while let Ok(item) = progress.recv_async().await {
+            match item {
+                CollectionAddProgress::Size(size) => total = size,
+                CollectionAddProgress::Progress(done) => {
+                    let percent = if total == 0 {
+                        100
+                    } else {
+                        (done.saturating_mul(100) / total).min(100)
+                    };
+                    let _ = progress_sender.send(percent);
+                }
+                CollectionAddProgress::Done { hash, entries } => {
+                    let _ = progress_sender.send(u64::MAX);
+                    return Ok((hash, entries));
+                }
+                CollectionAddProgress::Error(error) => {
+                    let _ = progress_sender.send(u64::MAX);
+                    return Err(Error::BlobSave(error));
+                }
+            }
+        }
 
-        tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                desktop_client
-                    .lock()
-                    .await
-                    .get_blob_path(hash, extension)
-                    .await
-                    .ok()
-            })
-        })
+        let _ = progress_sender.send(u64::MAX);
+        Err(Error::BlobSave(
+            "Collection import finished without a hash".to_string(),
+        ))
     }
 
-    pub fn has_blob_impl(&self, hash: &str, extension: impl AsRef<OsStr>) -> bool {
-        let Ok(hash) = hash.parse::<Hash>() else {
-            return false;
+    /// Tells the user about the failures they can act on: waiting for slow mode, a contact being
+    /// offline, or the blob store failing, e.g. when the disk is full.
+    fn notify_error(mut app_state: Signal<AppState>, error: &ClientError) {
+        let ClientError::P2p(error) = error else {
+            return;
+        };
+        let notice = match error {
+            p2p::Error::SlowMode { wait, .. } => {
+                let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                format!("Slow mode is on in this topic, you can post again in {seconds} seconds.")
+            }
+            p2p::Error::NotConnected(_) | p2p::Error::Connection(_) => {
+                "The contact is offline, the message was not delivered.".to_string()
+            }
+            p2p::Error::BlobStore(_) => {
+                "The file could not be stored, check the free disk space.".to_string()
+            }
+            _ => return,
         };
+        app_state.with_mut(|state| state.push_notice(notice));
+    }
 
-        let desktop_client = Arc::clone(&self.desktop_client);
+    /// Maps a failed post, telling the user why when they can do something about it.
+    fn post_error(app_state: Signal<AppState>, error: &ClientError) -> Error {
+        eprintln!("Failed to send message: {error}");
+        Self::notify_error(app_state, error);
+        Error::MessageSend(error.to_string())
+    }
 
-        tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                desktop_client
-                    .lock()
-                    .await
-                    .get_blob_path(hash, extension)
-                    .await
-                    .is_ok()
-            })
-        })
+    /// Maps a failure to store a blob, telling the user when the blob store is at fault.
+    fn blob_error(app_state: Signal<AppState>, error: &ClientError) -> Error {
+        Self::notify_error(app_state, error);
+        Error::BlobSave(error.to_string())
+    }
+
+    /// Posts a message to a topic. When the subscription is gone, e.g. after the gossip stream
+    /// closed, the topic is joined again and the message sent once more.
+    async fn post_to_topic(
+        desktop_client: &DesktopClient,
+        app_state: Signal<AppState>,
+        ticket_id: &str,
+        message: MessageTypes,
+    ) -> Result<(), Error> {
+        let result = match desktop_client.send(message.clone()).await {
+            Err(ClientError::P2p(p2p::Error::NotSubscribed(_))) => {
+                match desktop_client.join_topic(ticket_id).await {
+                    Ok(_) => desktop_client.send(message).await,
+                    Err(e) => Err(e),
+                }
+            }
+            result => result,
+        };
+        result.map_err(|e| Self::post_error(app_state, &e))
+    }
+
+    /// Lets the peers in `scope` fetch the given blobs, so they can download what was just sent.
+    fn grant_blob_access(
+        desktop_client: &Arc<DesktopClient>,
+        hashes: impl IntoIterator<Item = Hash>,
+        scope: BlobScope,
+    ) -> Result<(), Error> {
+        for hash in hashes {
+            desktop_client
+                .grant_blob_access(hash, scope)
+                .map_err(|e| Error::BlobSave(e.to_string()))?;
+        }
+        Ok(())
     }
 
     #[allow(clippy::future_not_send)]
     #[allow(clippy::cast_sign_loss)]
-    async fn do_send_message_to_user(
-        user_addr: String,
-        message: String,
+    async fn do_send_collection_to_topic(
+        ticket_id: String,
+        files: Vec<FileData>,
+        name: Option<String>,
         mut app_state: Signal<AppState>,
-        desktop_client: Arc<Mutex<DesktopClient>>,
+        desktop_client: Arc<DesktopClient>,
+        progress_sender: Sender<u64>,
     ) {
-        let result: Result<(), Error> = async {
-            let msg = desktop_client
-                .lock()
-                .await
-                .get_dm_chat_message(&user_addr, &message)
-                .await
-                .map_err(|e| Error::MessageCreation(e.to_string()))?;
-
-            desktop_client
-                .lock()
-                .await
-                .connect_to_user(&user_addr)
-                .await
-                .map_err(|e| Error::PeerId(e.to_string()))?;
+        let now = Utc::now().timestamp_millis() as u64;
 
-            desktop_client
-                .lock()
-                .await
-                .send_dm(&user_addr, DmMessageTypes::Chat(msg))
-                .await
-                .map_err(|e| Error::MessageSend(e.to_string()))?;
+        let result: Result<(), Error> = async {
+            let ticket = Ticket::from_str(&ticket_id)
+                .map_err(|_| Error::InvalidTicket("Failed to parse ticket_id".to_string()))?;
 
             let peer_id = desktop_client
-                .lock()
-                .await
                 .peer_id()
-                .await
                 .map_err(|e| Error::PeerId(e.to_string()))?;
 
-            let user_addr_clone = user_addr.clone();
-            let message_clone = message.clone();
+            let name = name.unwrap_or_else(|| {
+                let paths: Vec<PathBuf> = files.iter().map(FileData::path).collect();
+                utils::collections::collection_name(&paths)
+            });
+
+            let (hash, entries) =
+                Self::import_collection(&files, &desktop_client, &progress_sender).await?;
+            let ui_entries = message_handler::to_ui_collection_entries(entries.clone());
+
+            let hashes: Vec<Hash> = entries.iter().map(|entry| entry.hash).collect();
+            Self::grant_blob_access(
+                &desktop_client,
+                std::iter::once(hash).chain(hashes),
+                BlobScope::Topic(ticket.topic),
+            )?;
+
+            let hlc = desktop_client
+                .hlc_now()
+                .map_err(|e| Error::MessageCreation(e.to_string()))?;
+            let msg = p2p::CollectionMessage::new(
+                ticket.topic,
+                peer_id,
+                name.clone(),
+                hash,
+                entries,
+                now,
+            )
+            .with_hlc(hlc);
+
+            Self::post_to_topic(
+                &desktop_client,
+                app_state,
+                &ticket_id,
+                MessageTypes::Collection(msg),
+            )
+            .await?;
 
             app_state.with_mut(|state| {
-                let chat_msg = DmChatMessage::new(
-                    peer_id.to_string(),
-                    user_addr_clone.clone(),
-                    message_clone,
-                    Utc::now().timestamp_millis() as u64,
-                    true,
-                );
-                state.add_dm_message(&user_addr_clone, chat_msg);
+                if let Some(topic) = state.get_topic_mutable(&ticket_id) {
+                    topic.add_collection_message(
+                        CollectionMessage::new(
+                            peer_id.to_string(),
+                            ticket_id.clone(),
+                            hash.to_string(),
+                            name,
+                            ui_entries,
+                            now,
+                            true,
+                        )
+                        .with_hlc(hlc.as_u64()),
+                    );
+                }
             });
 
-            utils::contacts::save_contacts(&app_state.read().get_all_contacts_chat())
-                .map_err(|e| Error::ProfileSave(e.to_string()))?;
+            app_state
+                .with_mut(store::save_chats)
+                .map_err(|_| Error::FileSave("Failed to save topics".to_string()))?;
 
             Ok(())
         }
         .await;
 
         if let Err(e) = result {
-            eprintln!("Failed to send message to user {user_addr}: {e}");
+            eprintln!("Failed to send collection to topic {ticket_id}: {e}");
         }
     }
 
     #[allow(clippy::future_not_send)]
     #[allow(clippy::cast_sign_loss)]
-    async fn do_send_blob_to_user(
+    async fn do_send_collection_to_user(
         user_addr: String,
-        blob_data: FileData,
-        blob_name: String,
-        blob_type: BlobType,
+        files: Vec<FileData>,
+        name: Option<String>,
         mut app_state: Signal<AppState>,
-        desktop_client: Arc<Mutex<DesktopClient>>,
+        desktop_client: Arc<DesktopClient>,
         progress_sender: Sender<u64>,
     ) {
         let now = Utc::now().timestamp_millis() as u64;
 
         let result: Result<(), Error> = async {
             let peer_id = desktop_client
-                .lock()
-                .await
                 .peer_id()
-                .await
                 .map_err(|e| Error::PeerId(e.to_string()))?;
 
-            let add_stream = desktop_client
-                .lock()
-                .await
-                .save_blob_from_path(blob_data.path())
-                .await
-                .map_err(|e| Error::BlobSave(e.to_string()))?;
+            let endpoint_id = EndpointId::from_str(&user_addr)
+                .map_err(|e| Error::InvalidUserId(format!("Invalid user ID: {e}")))?;
 
-            let mut stream = add_stream;
-            let mut hash = None;
-            while let Some(item) = stream.next().await {
-                match item {
-                    p2p::AddProgressItem::CopyProgress(_)
-                    | p2p::AddProgressItem::Size(_)
-                    | p2p::AddProgressItem::CopyDone => {}
+            let name = name.unwrap_or_else(|| {
+                let paths: Vec<PathBuf> = files.iter().map(FileData::path).collect();
+                utils::collections::collection_name(&paths)
+            });
+
+            let (hash, entries) =
+                Self::import_collection(&files, &desktop_client, &progress_sender).await?;
+            let ui_entries = message_handler::to_ui_collection_entries(entries.clone());
+
+            let hashes: Vec<Hash> = entries.iter().map(|entry| entry.hash).collect();
+            Self::grant_blob_access(
+                &desktop_client,
+                std::iter::once(hash).chain(hashes),
+                BlobScope::Peer(endpoint_id),
+            )?;
+
+            let msg = p2p::DmCollectionMessage::new(
+                peer_id,
+                endpoint_id,
+                name.clone(),
+                hash,
+                entries,
+                now,
+            );
+
+            desktop_client
+                .send_dm(&user_addr, DmMessageTypes::Collection(msg))
+                .await
+                .map_err(|e| Self::post_error(app_state, &e))?;
+
+            app_state.with_mut(|state| {
+                state.add_dm_collection_message(
+                    &user_addr,
+                    DmCollectionMessage::new(
+                        peer_id.to_string(),
+                        user_addr.clone(),
+                        hash.to_string(),
+                        name,
+                        ui_entries,
+                        now,
+                        true,
+                    ),
+                );
+            });
+
+            app_state
+                .with_mut(store::save_chats)
+                .map_err(|e| Error::ProfileSave(e.to_string()))?;
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            eprintln!("Failed to send collection to user {user_addr}: {e}");
+        }
+    }
+
+    async fn do_download_collection(
+        blob_hash: &str,
+        user_id: &str,
+        target_dir: &Path,
+        desktop_client: Arc<DesktopClient>,
+    ) {
+        let result: Result<(), Error> = async {
+            let hash = blob_hash
+                .parse::<Hash>()
+                .map_err(|e| Error::DownloadBlob(format!("Invalid collection hash: {e}")))?;
+            let endpoint_id = EndpointId::from_str(user_id)
+                .map_err(|e| Error::InvalidUserId(format!("Invalid user ID: {e}")))?;
+
+            let is_complete = desktop_client.has_collection(hash).await.unwrap_or(false);
+
+            if !is_complete {
+                let download = desktop_client
+                    .download_collection(hash, endpoint_id)
+                    .map_err(|e| {
+                        Error::DownloadBlob(format!("Failed to start collection download: {e}"))
+                    })?;
+
+                download
+                    .await
+                    .map_err(|e| Error::DownloadBlob(e.to_string()))?;
+            }
+
+            desktop_client
+                .export_collection(hash, target_dir)
+                .await
+                .map_err(|e| Error::FileSave(e.to_string()))?;
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            eprintln!("Failed to download collection {blob_hash}: {e}");
+        }
+    }
+
+    async fn do_download_collection_entry(
+        blob_hash: &str,
+        user_id: &str,
+        target: &Path,
+        desktop_client: Arc<DesktopClient>,
+    ) {
+        let result: Result<(), Error> = async {
+            let hash = blob_hash
+                .parse::<Hash>()
+                .map_err(|e| Error::DownloadBlob(format!("Invalid blob hash: {e}")))?;
+            let endpoint_id = EndpointId::from_str(user_id)
+                .map_err(|e| Error::InvalidUserId(format!("Invalid user ID: {e}")))?;
+
+            let is_complete = desktop_client.has_blob(hash).await.unwrap_or(false);
+
+            if !is_complete {
+                let ticket = BlobTicket::new(EndpointAddr::from(endpoint_id), hash, Raw);
+                let download = desktop_client.download_blob(&ticket).map_err(|e| {
+                    Error::DownloadBlob(format!("Failed to start blob download: {e}"))
+                })?;
+
+                download
+                    .await
+                    .map_err(|e| Error::DownloadBlob(e.to_string()))?;
+            }
+
+            desktop_client
+                .export_blob(hash, target)
+                .await
+                .map_err(|e| Error::FileSave(e.to_string()))?;
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            eprintln!("Failed to download collection entry {blob_hash}: {e}");
+        }
+    }
+
+    #[allow(clippy::future_not_send)]
+    #[allow(clippy::cast_sign_loss)]
+    async fn do_send_message_to_user(
+        user_addr: String,
+        message: String,
+        mut app_state: Signal<AppState>,
+        desktop_client: Arc<DesktopClient>,
+    ) {
+        let result: Result<(), Error> = async {
+            let msg = desktop_client
+                .get_dm_chat_message(&user_addr, &message)
+                .map_err(|e| Error::MessageCreation(e.to_string()))?;
+
+            desktop_client
+                .connect_to_user(&user_addr)
+                .await
+                .map_err(|e| Error::PeerId(e.to_string()))?;
+
+            desktop_client
+                .send_dm(&user_addr, DmMessageTypes::Chat(msg))
+                .await
+                .map_err(|e| Self::post_error(app_state, &e))?;
+
+            let peer_id = desktop_client
+                .peer_id()
+                .map_err(|e| Error::PeerId(e.to_string()))?;
+
+            let user_addr_clone = user_addr.clone();
+            let message_clone = message.clone();
+
+            app_state.with_mut(|state| {
+                let chat_msg = DmChatMessage::new(
+                    peer_id.to_string(),
+                    user_addr_clone.clone(),
+                    message_clone,
+                    Utc::now().timestamp_millis() as u64,
+                    true,
+                );
+                state.add_dm_message(&user_addr_clone, chat_msg);
+            });
+
+            app_state
+                .with_mut(store::save_chats)
+                .map_err(|e| Error::ProfileSave(e.to_string()))?;
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            eprintln!("Failed to send message to user {user_addr}: {e}");
+        }
+    }
+
+    #[allow(clippy::future_not_send)]
+    #[allow(clippy::cast_sign_loss)]
+    async fn do_send_blob_to_user(
+        user_addr: String,
+        blob_data: FileData,
+        blob_name: String,
+        blob_type: BlobType,
+        mut app_state: Signal<AppState>,
+        desktop_client: Arc<DesktopClient>,
+        progress_sender: Sender<u64>,
+    ) {
+        let now = Utc::now().timestamp_millis() as u64;
+
+        let result: Result<(), Error> = async {
+            let peer_id = desktop_client
+                .peer_id()
+                .map_err(|e| Error::PeerId(e.to_string()))?;
+
+            let (encrypted, blob_name, blob_size, preview) = if blob_type == BlobType::Image {
+                let image_data = blob_data
+                    .read_bytes()
+                    .await
+                    .map_err(|e| Error::BlobSave(e.to_string()))?
+                    .to_vec();
+                let settings = app_state.read().get_media_settings();
+                let processed = Self::process_image(image_data, blob_name, settings).await?;
+
+                let preview_data = processed.bytes.clone();
+                let preview = tokio::task::spawn_blocking(move || {
+                    utils::previews::image_preview(&preview_data)
+                })
+                .await
+                .ok()
+                .flatten();
+
+                let blob_size = processed.bytes.len() as u64;
+                let encrypted = desktop_client
+                    .save_encrypted_blob(processed.bytes)
+                    .await
+                    .map_err(|e| Self::blob_error(app_state, &e))?;
+
+                (encrypted, processed.name, blob_size, preview)
+            } else {
+                let preview = Self::build_preview(blob_data.path(), blob_type).await;
+                let encrypted = desktop_client
+                    .save_encrypted_file(&blob_data.path())
+                    .await
+                    .map_err(|e| Self::blob_error(app_state, &e))?;
+
+                (encrypted, blob_name, blob_data.size(), preview)
+            };
+
+            let (key, mut stream) = encrypted;
+            let mut hash = None;
+            while let Some(item) = stream.next().await {
+                match item {
+                    p2p::AddProgressItem::CopyProgress(_)
+                    | p2p::AddProgressItem::Size(_)
+                    | p2p::AddProgressItem::CopyDone => {}
                     p2p::AddProgressItem::OutboardProgress(progress) => {
                         progress_sender
                             .send(progress)
@@ -869,18 +1343,20 @@ impl AppController {
                 peer_id,
                 endpoint_id,
                 blob_name.clone(),
-                blob_data.size(),
+                blob_size,
                 hash,
                 now,
                 p2p_blob_type,
+                preview.clone(),
+                Some(key),
             );
 
+            Self::grant_blob_access(&desktop_client, [hash], BlobScope::Peer(endpoint_id))?;
+
             desktop_client
-                .lock()
-                .await
                 .send_dm(&user_addr, DmMessageTypes::Blob(msg))
                 .await
-                .map_err(|e| Error::MessageSend(e.to_string()))?;
+                .map_err(|e| Self::post_error(app_state, &e))?;
 
             let user_addr_clone = user_addr.clone();
 
@@ -890,15 +1366,18 @@ impl AppController {
                     user_addr_clone.clone(),
                     hash.to_string(),
                     blob_name.clone(),
-                    blob_data.size(),
+                    blob_size,
                     now,
                     true,
                     blob_type,
+                    preview.map(utils::previews::to_ui_preview),
+                    Some(key.to_string()),
                 );
                 state.add_dm_blob_message(&user_addr_clone, blob_msg);
             });
 
-            utils::contacts::save_contacts(&app_state.read().get_all_contacts_chat())
+            app_state
+                .with_mut(store::save_chats)
                 .map_err(|e| Error::ProfileSave(e.to_string()))?;
 
             Ok(())
@@ -910,52 +1389,82 @@ impl AppController {
         }
     }
 
+    /// Stores an avatar picked in the UI as a blob, so only its hash is shared and saved.
+    ///
+    /// The UI hands over a freshly picked avatar as a `data:` URL, an unchanged one is already a
+    /// hash.
+    async fn store_avatar(
+        desktop_client: &DesktopClient,
+        avatar: Option<&str>,
+    ) -> Result<Option<Hash>, Error> {
+        let Some(bytes) = avatar.and_then(utils::avatars::decode_data_url) else {
+            return Ok(utils::avatars::avatar_hash(avatar));
+        };
+        if bytes.len() > utils::avatars::MAX_AVATAR_SIZE {
+            return Err(Error::ImageSizeExceeded);
+        }
+
+        let hash = desktop_client
+            .save_blob(bytes)
+            .await
+            .map_err(|e| Error::BlobSave(e.to_string()))?;
+        Ok(Some(hash))
+    }
+
     #[allow(clippy::future_not_send)]
     async fn do_modify_topic(
         topic: Topic,
         mut app_state: Signal<AppState>,
-        desktop_client: Arc<Mutex<DesktopClient>>,
+        desktop_client: Arc<DesktopClient>,
     ) {
         let result: Result<(), Error> = async {
-            if let Some(ref avatar_url) = topic.avatar_url
-                && let Some(base64_data) = avatar_url.strip_prefix("data:")
-                && let Some(comma_pos) = base64_data.find(',')
-            {
-                let base64_str = &base64_data[comma_pos + 1..];
-                if let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(base64_str) {
-                    const MAX_SIZE: usize = 512 * 1024 * 4 / 3; // 512 KB
-                    if decoded.len() > MAX_SIZE {
-                        return Err(Error::ImageSizeExceeded);
-                    }
-                }
+            let ticket = Ticket::from_str(&topic.id).expect("Invalid ticket string");
+            let avatar = Self::store_avatar(&desktop_client, topic.avatar_url.as_deref()).await?;
+            if let Some(hash) = avatar {
+                Self::grant_blob_access(&desktop_client, [hash], BlobScope::Topic(ticket.topic))?;
             }
+            let is_admin = ticket.creator()
+                == Some(
+                    desktop_client
+                        .peer_id()
+                        .map_err(|e| Error::PeerId(e.to_string()))?,
+                );
 
-            app_state.write().modify_topic_name(&topic.id, &topic.name);
-            app_state
-                .write()
-                .modify_topic_avatar(&topic.id, topic.avatar_url.clone());
-            let time = app_state.write().set_last_changed_to_now(&topic.id);
-            let ticket = Ticket::from_str(&topic.id).expect("Invalid ticket string");
-            let update_message = TopicMetadataMessage::new(
-                ticket.topic,
-                &topic.name,
-                topic.avatar_url,
-                time,
-                topic.members.into_iter().collect(),
-            );
+            // Only the fields that changed are written, so concurrent edits of the others win.
+            // The settings reserved to the admin are ignored by every peer unless the admin signed
+            // them.
+            let topic_state = desktop_client
+                .edit_topic_state(&ticket.topic, |state, tag| {
+                    if *state.name.get() != topic.name {
+                        state.name.set(topic.name.clone(), tag);
+                    }
+                    if *state.avatar.get() != avatar {
+                        state.avatar.set(avatar, tag);
+                    }
+                    if is_admin && *state.slow_mode.get() != topic.slow_mode {
+                        state.slow_mode.set(topic.slow_mode, tag);
+                    }
+                    if is_admin && *state.disappearing.get() != topic.disappearing {
+                        state.disappearing.set(topic.disappearing, tag);
+                    }
+                })
+                .map_err(|e| Error::TopicModification(e.to_string()))?;
+            app_state.with_mut(|state| {
+                message_handler::apply_topic_state(state, &topic.id, &topic_state);
+                state.set_last_changed_to_now(&topic.id);
+            });
+            let update_message = TopicMetadataMessage::new(ticket.topic, topic_state);
 
             if let Err(e) = desktop_client
-                .lock()
-                .await
                 .send(MessageTypes::TopicMetadata(update_message))
                 .await
             {
                 eprintln!("Failed to send update topic message: {e}");
             }
 
-            if save_topics_to_file(&app_state.read().get_all_topics()).is_err() {
+            if app_state.with_mut(store::save_chats).is_err() {
                 return Err(Error::TopicModification(
-                    "Failed to save topics to file".to_string(),
+                    "Failed to save topics".to_string(),
                 ));
             }
 
@@ -972,9 +1481,13 @@ impl AppController {
     async fn do_modify_profile(
         profile: Profile,
         mut app_state: Signal<AppState>,
-        desktop_client: Arc<Mutex<DesktopClient>>,
+        desktop_client: Arc<DesktopClient>,
     ) {
         let result: Result<(), Error> = async {
+            let mut profile = profile;
+            let avatar = Self::store_avatar(&desktop_client, profile.avatar.as_deref()).await?;
+            profile.avatar = avatar.map(|hash| hash.to_string());
+
             app_state.with_mut(|state| {
                 state.set_profile_name(&profile.name);
                 state.set_profile_avatar(profile.avatar.as_deref());
@@ -983,16 +1496,21 @@ impl AppController {
             let message = DmProfileMetadataMessage::new(
                 profile.id.parse().unwrap(),
                 profile.name.clone(),
-                profile.avatar.clone(),
+                avatar,
                 profile.last_connection.get_u64(),
             );
 
             let contacts = app_state.read().get_all_contacts();
 
             for contact in contacts {
-                let client = desktop_client.lock().await;
-
-                if let Err(e) = client
+                if let Ok(endpoint_id) = contact.id.parse() {
+                    message_handler::share_avatar(
+                        &desktop_client,
+                        &profile,
+                        BlobScope::Peer(endpoint_id),
+                    );
+                }
+                if let Err(e) = desktop_client
                     .send_dm(
                         &contact.id,
                         DmMessageTypes::ProfileMetadata(message.clone()),
@@ -1003,6 +1521,14 @@ impl AppController {
                 }
             }
 
+            let topics = app_state.read().get_all_topics();
+            for topic in topics {
+                if let Ok(ticket) = Ticket::from_str(&topic.id) {
+                    message_handler::announce_profile(&desktop_client, app_state, ticket.topic)
+                        .await;
+                }
+            }
+
             utils::contacts::save_profile(&profile)
                 .map_err(|e| Error::ProfileSave(e.to_string()))?;
 
@@ -1015,26 +1541,104 @@ impl AppController {
         }
     }
 
+    #[allow(clippy::future_not_send)]
+    async fn do_modify_media_settings(settings: MediaSettings, mut app_state: Signal<AppState>) {
+        let result: Result<(), Error> = async {
+            app_state.write().set_media_settings(settings);
+
+            utils::settings::save_media_settings(&settings)
+                .map_err(|e| Error::FileSave(e.to_string()))?;
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            eprintln!("Failed to save media settings: {e}");
+        }
+    }
+
+    #[allow(clippy::future_not_send)]
+    async fn do_modify_retention_settings(
+        settings: RetentionSettings,
+        mut app_state: Signal<AppState>,
+        desktop_client: &Arc<DesktopClient>,
+    ) {
+        if let Err(e) = utils::settings::save_retention_settings(&settings) {
+            eprintln!("Failed to save retention settings: {e}");
+        }
+        app_state.write().set_retention_settings(settings);
+
+        retention::sweep(desktop_client, app_state).await;
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    async fn do_send_typing_to_topic(ticket_id: String, desktop_client: Arc<DesktopClient>) {
+        let result: Result<(), Error> = async {
+            let ticket =
+                Ticket::from_str(&ticket_id).map_err(|e| Error::InvalidTicket(e.to_string()))?;
+            let peer_id = desktop_client
+                .peer_id()
+                .map_err(|e| Error::PeerId(e.to_string()))?;
+
+            let msg =
+                TypingMessage::new(ticket.topic, peer_id, Utc::now().timestamp_millis() as u64);
+
+            desktop_client
+                .send(MessageTypes::Typing(msg))
+                .await
+                .map_err(|e| Error::MessageSend(e.to_string()))?;
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            eprintln!("Failed to send typing signal to topic: {e}");
+        }
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    async fn do_send_typing_to_user(user_addr: String, desktop_client: Arc<DesktopClient>) {
+        let result: Result<(), Error> = async {
+            let peer_id = desktop_client
+                .peer_id()
+                .map_err(|e| Error::PeerId(e.to_string()))?;
+            let receiver = user_addr
+                .parse::<EndpointId>()
+                .map_err(|_| Error::InvalidPeerId)?;
+
+            let msg = DmTypingMessage::new(peer_id, receiver, Utc::now().timestamp_millis() as u64);
+
+            desktop_client
+                .send_dm(&user_addr, DmMessageTypes::Typing(msg))
+                .await
+                .map_err(|e| Error::MessageSend(e.to_string()))?;
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            eprintln!("Failed to send typing signal to {user_addr}: {e}");
+        }
+    }
+
     #[allow(clippy::future_not_send)]
     #[allow(clippy::cast_sign_loss)]
     async fn do_connect_to_user(
         user_id: String,
         mut app_state: Signal<AppState>,
-        desktop_client: Arc<Mutex<DesktopClient>>,
+        desktop_client: Arc<DesktopClient>,
     ) {
         let result: Result<(), Error> = async {
             desktop_client
-                .lock()
-                .await
                 .connect_to_user(&user_id)
                 .await
                 .map_err(|e| Error::PeerId(e.to_string()))?;
 
             let self_address = desktop_client
-                .lock()
-                .await
                 .peer_id()
-                .await
                 .map_err(|e| Error::PeerId(e.to_string()))?;
 
             let join_msg = p2p::DmJoinMessage::new(
@@ -1043,38 +1647,37 @@ impl AppController {
                 Utc::now().timestamp_millis() as u64,
             );
 
+            // Connecting to a peer that asked us first accepts its request.
             let user_id_clone = user_id.clone();
-            app_state.with_mut(|state| {
+            let accepted = app_state.with_mut(|state| {
                 if state.get_contact(&user_id_clone).is_none() {
                     let profile = Profile::new_with_id(&user_id_clone);
                     state.add_contact(profile);
                 }
+                state.remove_contact_request(&user_id_clone).is_some()
             });
 
             desktop_client
-                .lock()
-                .await
-                .send_dm(&user_id, DmMessageTypes::JoinPetition(join_msg))
-                .await
-                .map_err(|e| Error::MessageSend(e.to_string()))?;
-
-            let profile = app_state.read().get_profile();
-
-            let msg = DmProfileMetadataMessage::new(
-                profile.id.parse().expect("id should be an EndpointId"),
-                profile.name,
-                profile.avatar,
-                profile.last_connection.get_u64(),
-            );
+                .watch_presence(&user_id)
+                .map_err(|e| Error::PeerId(e.to_string()))?;
 
             desktop_client
-                .lock()
-                .await
-                .send_dm(&user_id, DmMessageTypes::ProfileMetadata(msg))
+                .send_dm(&user_id, DmMessageTypes::JoinPetition(join_msg))
                 .await
                 .map_err(|e| Error::MessageSend(e.to_string()))?;
 
-            utils::contacts::save_contacts(&app_state.read().get_all_contacts_chat())
+            // Otherwise the profile is only sent once the peer accepts, answering with its own
+            // petition.
+            if accepted {
+                message_handler::send_profile(&desktop_client, app_state, &user_id)
+                    .await
+                    .map_err(|e| Error::MessageSend(e.to_string()))?;
+                utils::contacts::save_contact_requests(&app_state.read().get_contact_requests())
+                    .map_err(|e| Error::FileSave(e.to_string()))?;
+            }
+
+            app_state
+                .with_mut(store::save_chats)
                 .map_err(|e| Error::ProfileSave(e.to_string()))?;
 
             Ok(())
@@ -1090,19 +1693,22 @@ impl AppController {
     async fn do_reconnect_to_user(
         chat: ProfileChat,
         mut app_state: Signal<AppState>,
-        desktop_client: Arc<Mutex<DesktopClient>>,
+        desktop_client: Arc<DesktopClient>,
     ) {
         let result: Result<(), Error> = async {
             app_state.write().add_contact_chat(chat.clone());
 
             desktop_client
-                .lock()
-                .await
+                .watch_presence(&chat.profile.id)
+                .map_err(|e| Error::PeerId(e.to_string()))?;
+
+            desktop_client
                 .connect_to_user(&chat.profile.id)
                 .await
                 .map_err(|e| Error::PeerId(e.to_string()))?;
 
-            utils::contacts::save_contacts(&app_state.read().get_all_contacts_chat())
+            app_state
+                .with_mut(store::save_chats)
                 .map_err(|e| Error::ProfileSave(e.to_string()))?;
 
             Ok(())
@@ -1114,12 +1720,86 @@ impl AppController {
         }
     }
 
+    /// Drops a contact request without answering it.
+    fn do_reject_contact_request(user_id: &str, mut app_state: Signal<AppState>) {
+        app_state.with_mut(|state| state.remove_contact_request(user_id));
+
+        if let Err(e) =
+            utils::contacts::save_contact_requests(&app_state.read().get_contact_requests())
+        {
+            eprintln!("Failed to save contact requests: {e}");
+        }
+    }
+
     #[allow(clippy::future_not_send)]
-    async fn do_remove_contact(profile_id: String, mut app_state: Signal<AppState>) {
+    async fn do_block_user(
+        user_id: String,
+        mut app_state: Signal<AppState>,
+        desktop_client: Arc<DesktopClient>,
+    ) {
+        let result: Result<(), Error> = async {
+            desktop_client
+                .block_peer(&user_id)
+                .map_err(|e| Error::PeerId(e.to_string()))?;
+
+            let was_contact = app_state.with_mut(|state| {
+                state.block(&user_id);
+                let was_contact = state.get_contact(&user_id).is_some();
+                state.remove_contact(&user_id);
+                was_contact
+            });
+
+            utils::contacts::save_blocked(&app_state.read().get_blocked())
+                .map_err(|e| Error::FileSave(e.to_string()))?;
+            utils::contacts::save_contact_requests(&app_state.read().get_contact_requests())
+                .map_err(|e| Error::FileSave(e.to_string()))?;
+            if was_contact {
+                app_state
+                    .with_mut(store::save_chats)
+                    .map_err(|e| Error::ProfileSave(e.to_string()))?;
+            }
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            eprintln!("Failed to block {user_id}: {e}");
+        }
+    }
+
+    fn do_unblock_user(
+        user_id: &str,
+        mut app_state: Signal<AppState>,
+        desktop_client: &Arc<DesktopClient>,
+    ) {
+        if let Err(e) = desktop_client.unblock_peer(user_id) {
+            eprintln!("Failed to unblock {user_id}: {e}");
+            return;
+        }
+
+        if app_state.with_mut(|state| state.unblock(user_id))
+            && let Err(e) = utils::contacts::save_blocked(&app_state.read().get_blocked())
+        {
+            eprintln!("Failed to save blocked peers: {e}");
+        }
+    }
+
+    #[allow(clippy::future_not_send)]
+    async fn do_remove_contact(
+        profile_id: String,
+        mut app_state: Signal<AppState>,
+        desktop_client: Arc<DesktopClient>,
+    ) {
         let result: Result<(), Error> = async {
             app_state.write().remove_contact(&profile_id);
 
-            utils::contacts::save_contacts(&app_state.read().get_all_contacts_chat())
+            desktop_client
+                .unwatch_presence(&profile_id)
+                .map_err(|e| Error::PeerId(e.to_string()))?;
+
+            app_state
+                .with_mut(store::save_chats)
                 .map_err(|e| Error::ProfileSave(e.to_string()))?;
 
             Ok(())
@@ -1131,6 +1811,70 @@ impl AppController {
         }
     }
 
+    /// Stores the current safety number of a contact, so a later change of either key shows up.
+    fn do_verify_contact(
+        profile_id: &str,
+        verified: bool,
+        mut app_state: Signal<AppState>,
+        desktop_client: &Arc<DesktopClient>,
+    ) {
+        let safety_number = if verified {
+            match desktop_client.safety_number(profile_id) {
+                Ok(safety_number) => Some(safety_number),
+                Err(e) => {
+                    eprintln!("Failed to compute the safety number of {profile_id}: {e}");
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+
+        app_state.with_mut(|state| state.set_contact_verified(profile_id, safety_number));
+
+        if let Err(e) = app_state.with_mut(store::save_chats) {
+            eprintln!("Failed to save contacts: {e}");
+        }
+    }
+
+    #[allow(clippy::future_not_send)]
+    #[allow(clippy::cast_sign_loss)]
+    async fn do_set_contact_disappearing(
+        profile_id: String,
+        seconds: Option<u64>,
+        mut app_state: Signal<AppState>,
+        desktop_client: Arc<DesktopClient>,
+    ) {
+        let result: Result<(), Error> = async {
+            let peer_id = desktop_client
+                .peer_id()
+                .map_err(|e| Error::PeerId(e.to_string()))?;
+            let receiver = profile_id
+                .parse::<EndpointId>()
+                .map_err(|_| Error::InvalidPeerId)?;
+            let timestamp = Utc::now().timestamp_millis() as u64;
+
+            app_state
+                .with_mut(|state| state.set_contact_disappearing(&profile_id, seconds, timestamp));
+            app_state
+                .with_mut(store::save_chats)
+                .map_err(|_| Error::FileSave("Failed to save contacts".to_string()))?;
+
+            let msg = DmDisappearingMessage::new(peer_id, receiver, seconds, timestamp);
+            desktop_client
+                .send_dm(&profile_id, DmMessageTypes::Disappearing(msg))
+                .await
+                .map_err(|e| Error::MessageSend(e.to_string()))?;
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            eprintln!("Failed to set the message timer of {profile_id}: {e}");
+        }
+    }
+
     #[allow(clippy::future_not_send)]
     pub async fn reconnect_to_user_async(&self, app_state: Signal<AppState>, chat: ProfileChat) {
         Self::do_reconnect_to_user(chat, app_state, Arc::clone(&self.desktop_client)).await;
@@ -1154,6 +1898,7 @@ pub enum Error {
     BlobSave(String),
     DownloadBlob(String),
     InvalidUserId(String),
+    MediaProcessing(String),
 }
 
 impl std::fmt::Display for Error {
@@ -1174,6 +1919,7 @@ impl std::fmt::Display for Error {
             Self::BlobSave(msg) => write!(f, "Blob save error: {msg}"),
             Self::DownloadBlob(msg) => write!(f, "Download blob error: {msg}"),
             Self::InvalidUserId(id) => write!(f, "Invalid user ID: {id}"),
+            Self::MediaProcessing(msg) => write!(f, "Media processing error: {msg}"),
         }
     }
 }
@@ -1197,6 +1943,14 @@ impl ui::desktop::models::Controller for AppController {
         self.send_command(Command::RemoveContact(profile_id));
     }
 
+    fn verify_contact(&self, profile_id: String, verified: bool) {
+        self.send_command(Command::VerifyContact(profile_id, verified));
+    }
+
+    fn set_contact_disappearing(&self, profile_id: String, seconds: Option<u64>) {
+        self.send_command(Command::SetContactDisappearing(profile_id, seconds));
+    }
+
     fn send_message_to_topic(&self, ticket_id: String, message: String) {
         self.send_command(Command::SendMessageToTopic { ticket_id, message });
     }
@@ -1209,14 +1963,46 @@ impl ui::desktop::models::Controller for AppController {
         self.send_command(Command::ModifyProfile(profile));
     }
 
+    fn modify_media_settings(&self, settings: MediaSettings) {
+        self.send_command(Command::ModifyMediaSettings(settings));
+    }
+
+    fn modify_retention_settings(&self, settings: RetentionSettings) {
+        self.send_command(Command::ModifyRetentionSettings(settings));
+    }
+
     fn send_message_to_user(&self, user_addr: String, message: String) {
         self.send_command(Command::SendMessageToUser { user_addr, message });
     }
 
+    fn send_typing_to_topic(&self, ticket_id: String) {
+        self.send_command(Command::SendTypingToTopic(ticket_id));
+    }
+
+    fn send_typing_to_user(&self, user_addr: String) {
+        self.send_command(Command::SendTypingToUser(user_addr));
+    }
+
     fn connect_to_user(&self, user_id: String) {
         self.send_command(Command::ConnectToUser(user_id));
     }
 
+    fn accept_contact_request(&self, user_id: String) {
+        self.send_command(Command::AcceptContactRequest(user_id));
+    }
+
+    fn reject_contact_request(&self, user_id: String) {
+        self.send_command(Command::RejectContactRequest(user_id));
+    }
+
+    fn block_user(&self, user_id: String) {
+        self.send_command(Command::BlockUser(user_id));
+    }
+
+    fn unblock_user(&self, user_id: String) {
+        self.send_command(Command::UnblockUser(user_id));
+    }
+
     fn send_blob_to_user(
         &self,
         user_addr: String,
@@ -1254,84 +2040,60 @@ impl ui::desktop::models::Controller for AppController {
         });
     }
 
-    fn get_from_storage(&self, hash: String, name: &str) -> Option<PathBuf> {
-        let extension = name.split('.').next_back().unwrap_or("");
-        self.get_blob_from_storage(
-            hash.parse().expect("Image hash should be parseable"),
-            extension,
-        )
-    }
-
-    fn has_blob(&self, image_hash: &str, image_name: &str) -> bool {
-        let extension = image_name.split('.').next_back().unwrap_or("");
-        self.has_blob_impl(image_hash, extension)
-    }
-
-    fn get_or_download(&self, hash: &str, user_id: &str, name: &str) -> anyhow::Result<PathBuf> {
-        let hash = hash
-            .parse::<Hash>()
-            .expect("Image hash should be parseable");
-
-        let extension = name.split('.').next_back().unwrap_or("");
-
-        if let Some(data) = self.get_blob_from_storage(hash, extension) {
-            return Ok(data);
-        }
+    fn get_from_storage(
+        &self,
+        hash: String,
+        name: &str,
+    ) -> impl Future<Output = Option<PathBuf>> + 'static {
         let desktop_client = Arc::clone(&self.desktop_client);
-        let progress_sender = self.progress_bar_sender.clone();
-
-        tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                let endpoint_id =
-                    EndpointId::from_str(user_id).expect("Endpoint ID should be parseable");
-                let addr = EndpointAddr::from(endpoint_id);
-                let ticket = BlobTicket::new(addr, hash, Raw);
+        let extension = name.split('.').next_back().unwrap_or("").to_string();
 
-                let progress = desktop_client
-                    .lock()
-                    .await
-                    .download_blob(&ticket)
-                    .await
-                    .map_err(|e| anyhow::anyhow!("Failed to start blob download: {e}"))?;
+        async move {
+            let hash = hash.parse::<Hash>().ok()?;
+            desktop_client.get_blob_path(hash, extension).await.ok()
+        }
+    }
 
-                let mut stream = progress.stream().await.map_err(|e| {
-                    anyhow::anyhow!("Failed to get download progress stream: {e}")
-                })?;
+    fn send_collection_to_topic(
+        &self,
+        ticket_id: String,
+        files: Vec<FileData>,
+        name: Option<String>,
+    ) {
+        self.send_command(Command::SendCollectionToTopic {
+            ticket_id,
+            files,
+            name,
+        });
+    }
 
-                while let Some(item) = stream.next().await {
-                    match item {
-                        DownloadProgressItem::Progress(progress) => {
-                            let _ = progress_sender.send(progress);
-                        }
-                        DownloadProgressItem::Error(e) => {
-                            let _ = progress_sender.send(u64::MAX);
-                            return Err(anyhow::anyhow!(e));
-                        }
-                        DownloadProgressItem::DownloadError => {
-                            let _ = progress_sender.send(u64::MAX);
-                            return Err(anyhow::anyhow!("Download error occurred"));
-                        }
-                        DownloadProgressItem::PartComplete { request } => {
-                            println!("Part complete for request {request:?}");
-                        }
-                        DownloadProgressItem::TryProvider { id, request } => {
-                            println!("Trying provider {id} for request {request:?}");
-                        }
-                        DownloadProgressItem::ProviderFailed { id, request } => {
-                            eprintln!("Provider {id} failed for request {request:?}");
-                        }
-                    }
-                }
+    fn send_collection_to_user(
+        &self,
+        user_addr: String,
+        files: Vec<FileData>,
+        name: Option<String>,
+    ) {
+        self.send_command(Command::SendCollectionToUser {
+            user_addr,
+            files,
+            name,
+        });
+    }
 
-                let _ = progress_sender.send(u64::MAX);
+    fn download_collection(&self, hash: String, user_id: String, target_dir: PathBuf) {
+        self.send_command(Command::DownloadCollection {
+            blob_hash: hash,
+            user_id,
+            target_dir,
+        });
+    }
 
-                desktop_client
-                    .lock()
-                    .await
-                    .get_blob_path(hash, extension)
-                    .await
-            })
-        })
+    fn download_collection_entry(&self, hash: String, user_id: String, target: PathBuf) {
+        self.send_command(Command::DownloadCollectionEntry {
+            blob_hash: hash,
+            user_id,
+            target,
+        });
     }
 
     fn get_media_url(&self, hash: &str, name: &str) -> String {
@@ -1343,4 +2105,12 @@ impl ui::desktop::models::Controller for AppController {
             extension
         )
     }
+
+    fn get_avatar_url(&self, hash: &str) -> String {
+        self.media_server.avatar_url(hash)
+    }
+
+    fn get_safety_number(&self, user_id: &str) -> Option<String> {
+        self.desktop_client.safety_number(user_id).ok()
+    }
 }
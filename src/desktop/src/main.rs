@@ -2,23 +2,26 @@ mod client;
 mod controller;
 mod media_server;
 mod message_handler;
+mod retention;
+mod store;
 mod utils;
 
 use crate::client::DesktopClient;
-use crate::utils::contacts::{load_contacts, load_profile};
-use crate::utils::topics::{load_topics_from_file, save_topics_to_file};
+use crate::utils::contacts::{load_blocked, load_contact_requests, load_profile};
+use crate::utils::settings::{load_media_settings, load_retention_settings};
 use chrono::Utc;
 use dioxus::desktop::tao::dpi::LogicalSize;
 use dioxus::desktop::tao::window::Icon;
 use dioxus::desktop::{Config, WindowBuilder, use_wry_event_handler};
 use dioxus::prelude::*;
-use p2p::{MessageTypes, Ticket};
+use p2p::{
+    BlobKey, BlobScope, DEFAULT_HEARTBEAT_INTERVAL, EndpointId, Hlc, MessageTypes, Tag, Ticket,
+};
 use std::error::Error;
 use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use ui::desktop::desktop_web_components::Desktop;
-use ui::desktop::models::{AppState, Topic};
+use ui::desktop::models::{AppState, DmMessage, Message, Topic};
 
 fn main() {
     LaunchBuilder::new()
@@ -42,9 +45,10 @@ fn App() -> Element {
     use_effect(move || {
         let client_ref = controller.read().get_desktop_client();
         let progress_sender = controller.read().progress_bar_sender.clone();
+        let transfer_sender = progress_sender.clone();
 
         spawn(async move {
-            if let Err(e) = client_ref.lock().await.initialize().await {
+            if let Err(e) = client_ref.initialize().await {
                 eprintln!("Failed to initialize DesktopClient: {e}");
                 return;
             }
@@ -53,7 +57,7 @@ fn App() -> Element {
                 controller.read().start_media_server().await;
             });
 
-            let peer_id = match client_ref.lock().await.peer_id().await {
+            let peer_id = match client_ref.peer_id() {
                 Ok(id) => id.to_string(),
                 Err(e) => {
                     eprintln!("Failed to get peer_id: {e}");
@@ -68,6 +72,22 @@ fn App() -> Element {
                         eprintln!("Failed to update profile ID: {e}");
                     });
                 }
+                // Avatars used to be saved inline, they are only shared as blobs now.
+                if let Some(bytes) = profile
+                    .avatar
+                    .as_deref()
+                    .and_then(utils::avatars::decode_data_url)
+                {
+                    match client_ref.save_blob(bytes).await {
+                        Ok(hash) => {
+                            profile.avatar = Some(hash.to_string());
+                            utils::contacts::save_profile(&profile).unwrap_or_else(|e| {
+                                eprintln!("Failed to update profile avatar: {e}");
+                            });
+                        }
+                        Err(e) => eprintln!("Failed to store profile avatar: {e}"),
+                    }
+                }
                 let mut state = app_state;
                 state.write().set_profile_id(&peer_id);
                 state.write().set_profile_name(&profile.name);
@@ -79,8 +99,57 @@ fn App() -> Element {
                 state.write().set_profile_name(&peer_id);
             }
 
-            if let Ok(loaded_contacts) = load_contacts() {
+            if let Ok(settings) = load_media_settings() {
+                let mut state = app_state;
+                state.write().set_media_settings(settings);
+            }
+
+            if let Ok(settings) = load_retention_settings() {
+                let mut state = app_state;
+                state.write().set_retention_settings(settings);
+            }
+
+            if let Ok(blocked) = load_blocked() {
+                let mut state = app_state;
+                for id in &blocked {
+                    if let Err(e) = client_ref.block_peer(id) {
+                        eprintln!("Failed to restore block of {id}: {e}");
+                    }
+                    state.with_mut(|s| s.block(id));
+                }
+            }
+
+            if let Ok(requests) = load_contact_requests() {
+                let mut state = app_state;
+                state.with_mut(|s| {
+                    for request in requests {
+                        s.add_contact_request(request);
+                    }
+                });
+            }
+
+            if let Err(e) = client_ref.start_heartbeat(DEFAULT_HEARTBEAT_INTERVAL) {
+                eprintln!("Failed to start the heartbeat: {e}");
+            }
+
+            if let Err(e) = store::open() {
+                eprintln!("Failed to open the message store: {e}");
+            }
+
+            if let Ok(loaded_contacts) = store::load_contacts() {
                 for contact_chat in loaded_contacts {
+                    if let Ok(endpoint_id) = EndpointId::from_str(&contact_chat.profile.id) {
+                        restore_blobs(
+                            &client_ref,
+                            dm_blobs(&contact_chat.messages),
+                            BlobScope::Peer(endpoint_id),
+                        );
+                        message_handler::share_avatar(
+                            &client_ref,
+                            &app_state().get_profile(),
+                            BlobScope::Peer(endpoint_id),
+                        );
+                    }
                     controller
                         .read()
                         .reconnect_to_user_async(app_state, contact_chat)
@@ -88,9 +157,18 @@ fn App() -> Element {
                 }
             }
 
-            if let Ok(loaded_topics) = load_topics_from_file() {
+            if let Ok(topic_states) = store::load_topic_states() {
+                client_ref.set_topic_states(topic_states);
+            }
+
+            if let Ok(loaded_topics) = store::load_topics() {
                 for topic in loaded_topics {
                     let client_ref = controller.read().get_desktop_client();
+                    if let Ok(ticket) = Ticket::from_str(&topic.id) {
+                        let mut blobs = topic_blobs(&topic.messages);
+                        blobs.extend(topic.avatar_url.clone().map(|hash| (hash, None)));
+                        restore_blobs(&client_ref, blobs, BlobScope::Topic(ticket.topic));
+                    }
                     join_topic_internal(&client_ref, app_state, topic)
                         .await
                         .unwrap_or_else(|e| {
@@ -99,9 +177,21 @@ fn App() -> Element {
                 }
             }
 
-            loop {
-                message_handler::process_all_messages(&client_ref, app_state).await;
-                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            spawn(retention::run(Arc::clone(&client_ref), app_state));
+
+            let events = match client_ref.get_event_receiver() {
+                Ok(events) => events,
+                Err(e) => {
+                    eprintln!("Failed to get the event receiver: {e}");
+                    return;
+                }
+            };
+
+            while let Ok(event) = events.recv_async().await {
+                let mut batch = vec![event];
+                batch.extend(events.try_iter());
+                message_handler::process_events(&client_ref, app_state, &transfer_sender, batch)
+                    .await;
             }
         });
 
@@ -134,8 +224,7 @@ fn App() -> Element {
             let client_ref = controller.read().get_desktop_client();
             tokio::task::block_in_place(|| {
                 tokio::runtime::Handle::current().block_on(async {
-                    let client = client_ref.lock().await;
-                    let id = client.peer_id().await.expect("Failed to get peer_id");
+                    let id = client_ref.peer_id().expect("Failed to get peer_id");
 
                     let all_topics = app_state().get_all_topics();
 
@@ -147,7 +236,7 @@ fn App() -> Element {
                             id,
                             Utc::now().timestamp_millis() as u64,
                         ));
-                        if let Err(e) = client.send(message).await {
+                        if let Err(e) = client_ref.send(message).await {
                             eprintln!("Failed to send DisconnectTopic message: {e}");
                         }
                     }
@@ -170,14 +259,78 @@ fn load_icon() -> Option<Icon> {
     Icon::from_rgba(rgba.into_raw(), width, height).ok()
 }
 
+/// Grants access again to the blobs shared in a chat and registers their keys, neither is
+/// persisted by the p2p client.
+fn restore_blobs(
+    desktop_client: &Arc<DesktopClient>,
+    blobs: Vec<(String, Option<String>)>,
+    scope: BlobScope,
+) {
+    for (hash, key) in blobs {
+        let Ok(hash) = p2p::Hash::from_str(&hash) else {
+            continue;
+        };
+        if let Err(e) = desktop_client.grant_blob_access(hash, scope) {
+            eprintln!("Failed to restore blob access: {e}");
+        }
+        if let Some(key) = key.and_then(|key| BlobKey::from_str(&key).ok())
+            && let Err(e) = desktop_client.register_blob_key(hash, key)
+        {
+            eprintln!("Failed to restore blob key: {e}");
+        }
+    }
+}
+
+fn topic_blobs(messages: &[Message]) -> Vec<(String, Option<String>)> {
+    messages
+        .iter()
+        .flat_map(|message| match message {
+            Message::Blob(msg) if !msg.media_removed => {
+                vec![(msg.blob_hash.clone(), msg.key.clone())]
+            }
+            Message::Collection(msg) if !msg.media_removed => {
+                std::iter::once((msg.collection_hash.clone(), None))
+                    .chain(
+                        msg.entries
+                            .iter()
+                            .map(|entry| (entry.hash.clone(), entry.key.clone())),
+                    )
+                    .collect()
+            }
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+fn dm_blobs(messages: &[DmMessage]) -> Vec<(String, Option<String>)> {
+    messages
+        .iter()
+        .flat_map(|message| match message {
+            DmMessage::Blob(msg) if !msg.media_removed => {
+                vec![(msg.blob_hash.clone(), msg.key.clone())]
+            }
+            DmMessage::Collection(msg) if !msg.media_removed => {
+                std::iter::once((msg.collection_hash.clone(), None))
+                    .chain(
+                        msg.entries
+                            .iter()
+                            .map(|entry| (entry.hash.clone(), entry.key.clone())),
+                    )
+                    .collect()
+            }
+            DmMessage::Chat(_) | DmMessage::Blob(_) | DmMessage::Collection(_) => Vec::new(),
+        })
+        .collect()
+}
+
 #[allow(clippy::future_not_send)]
 #[allow(clippy::cast_sign_loss)]
 async fn join_topic_internal(
-    desktop_client: &Arc<Mutex<DesktopClient>>,
+    desktop_client: &Arc<DesktopClient>,
     mut app_state: Signal<AppState>,
     mut topic: Topic,
 ) -> std::result::Result<(), Box<dyn Error>> {
-    let join_result = desktop_client.lock().await.join_topic(&topic.id).await;
+    let join_result = desktop_client.join_topic(&topic.id).await;
 
     match join_result {
         Ok(ticket_str) => {
@@ -186,23 +339,43 @@ async fn join_topic_internal(
             topic.add_join_message(ui::desktop::models::JoinMessage::new_me(
                 Utc::now().timestamp_millis() as u64,
             ));
+            let own_id = desktop_client.peer_id()?;
+            // Topics saved before their state was replicated start from what they showed, dated
+            // by their last change, so newer edits from other peers still win.
+            let is_new = !desktop_client.has_topic_state(&ticket.topic);
+            let is_admin = ticket.creator() == Some(own_id);
+            let seed = Tag::new(Hlc::from_wall(topic.last_changed), own_id);
+            desktop_client.edit_topic_state(&ticket.topic, |state, tag| {
+                if is_new && topic.name != topic.id {
+                    state.name.set(topic.name.clone(), seed);
+                    let avatar = utils::avatars::avatar_hash(topic.avatar_url.as_deref());
+                    state.avatar.set(avatar, seed);
+                    if is_admin {
+                        state.slow_mode.set(topic.slow_mode, seed);
+                        state.disappearing.set(topic.disappearing, seed);
+                    }
+                }
+                if !state.members.contains(&own_id) {
+                    state.members.insert(own_id, tag);
+                }
+            })?;
+
             let profile = app_state().get_profile();
 
             topic.add_member(&profile.id);
+            topic.admin = ticket.creator().map(|creator| creator.to_string());
 
             app_state.with_mut(|state| state.add_topic(&topic));
 
-            if save_topics_to_file(&app_state().get_all_topics()).is_err() {
-                eprintln!("Failed to save topics to file");
+            if let Err(e) = app_state.with_mut(store::save_chats) {
+                eprintln!("Failed to save topics: {e}");
             }
 
             tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
 
-            let id = desktop_client.lock().await.peer_id().await?;
+            let id = desktop_client.peer_id()?;
 
             desktop_client
-                .lock()
-                .await
                 .send(MessageTypes::JoinTopic(p2p::JoinMessage::new(
                     ticket.topic,
                     id,
@@ -211,6 +384,8 @@ async fn join_topic_internal(
                 .await
                 .expect("Failed to send JoinTopic message");
 
+            message_handler::announce_profile(desktop_client, app_state, ticket.topic).await;
+
             Ok(())
         }
         Err(e) => {
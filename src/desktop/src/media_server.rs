@@ -1,5 +1,11 @@
+use crate::client::DesktopClient;
+use crate::utils;
+use axum::extract::{Path, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
 use axum::{Router, routing::get};
-use std::path::PathBuf;
+use std::sync::Arc;
 use tower_http::services::ServeDir;
 
 #[derive(Clone)]
@@ -12,10 +18,23 @@ impl MediaServer {
         Self { port }
     }
 
-    pub async fn start(&self, media_path: PathBuf) -> anyhow::Result<()> {
+    pub async fn start(&self, desktop_client: Arc<DesktopClient>) -> anyhow::Result<()> {
+        let media_path = desktop_client.get_store_path();
+
+        let media = Router::new()
+            .fallback_service(ServeDir::new(media_path))
+            .layer(middleware::from_fn_with_state(
+                Arc::clone(&desktop_client),
+                export_blob,
+            ));
+
         let app = Router::new()
             .route("/", get("Hello World!!"))
-            .nest_service("/media", ServeDir::new(media_path));
+            .route(
+                "/avatars/{hash}",
+                get(serve_avatar).with_state(desktop_client),
+            )
+            .nest("/media", media);
 
         let addr = format!("127.0.0.1:{}", self.port);
         let listener = tokio::net::TcpListener::bind(&addr).await?;
@@ -34,4 +53,57 @@ impl MediaServer {
     pub const fn port(&self) -> u16 {
         self.port
     }
+
+    pub fn avatar_url(&self, hash: &str) -> String {
+        format!("http://127.0.0.1:{}/avatars/{hash}", self.port)
+    }
+}
+
+/// Exports the requested blob from the store before serving it, decrypting it if needed.
+async fn export_blob(
+    State(desktop_client): State<Arc<DesktopClient>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let file_name = request.uri().path().trim_start_matches('/').to_string();
+
+    if let Some((hash, extension)) = file_name.split_once('.')
+        && let Ok(hash) = hash.parse::<p2p::Hash>()
+    {
+        if !desktop_client.get_store_path().join(&file_name).exists()
+            && let Err(e) = desktop_client.get_blob_path(hash, extension).await
+        {
+            eprintln!("Failed to export blob {hash}: {e}");
+        }
+    }
+
+    next.run(request).await
+}
+
+/// Serves an avatar from the blob store. Avatars have no file name, so their type is sniffed.
+async fn serve_avatar(
+    State(desktop_client): State<Arc<DesktopClient>>,
+    Path(hash): Path<String>,
+) -> Response {
+    let Ok(hash) = hash.parse::<p2p::Hash>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let path = match desktop_client.get_blob_path(hash, "avatar").await {
+        Ok(path) => path,
+        // Not fetched (yet) from the peer that shared it.
+        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    match tokio::fs::read(&path).await {
+        Ok(bytes) => (
+            [(header::CONTENT_TYPE, utils::avatars::mime_type(&bytes))],
+            bytes,
+        )
+            .into_response(),
+        Err(e) => {
+            eprintln!("Failed to read avatar {hash}: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
 }
@@ -1,18 +1,27 @@
 use crate::client::DesktopClient;
-use crate::utils::topics::save_topics_to_file;
+use crate::store;
+use crate::utils;
+use crate::utils::contacts::save_contact_requests;
 use chrono::Utc;
 use dioxus::core::spawn;
 use dioxus::prelude::{Signal, WritableExt};
 use dioxus::signals::ReadableExt;
+use flume::Sender;
 use p2p::DmChatMessage as P2pDmChatMessage;
 use p2p::{
-    DmBlobMessage as P2pDmBlobMessage, DmJoinMessage, DmMessageTypes, DmProfileMetadataMessage,
-    MessageTypes, Ticket, TopicMetadataMessage,
+    BlobScope, BlobTicket, ChatEvent, DmBlobMessage as P2pDmBlobMessage, DmDisappearingMessage,
+    DmJoinMessage, DmMessageTypes, DmProfileMetadataMessage, EndpointAddr, EndpointId,
+    GossipLimits, Hash, Hlc, MessageTypes, ProfileMetadataMessage, Raw, Ticket, TopicId,
+    TopicMetadataMessage, TopicState, TransferProgress,
 };
+use std::collections::HashSet;
 use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use ui::desktop::models::{AppState, ChatMessage, DmBlobMessage, DmChatMessage, Message};
+use std::time::Duration;
+use ui::desktop::models::{
+    AppState, ChatMessage, CollectionEntry, ContactRequest, DmBlobMessage, DmChatMessage,
+    DmCollectionMessage, DownloadState, MemberProfile, Message, Profile, TYPING_TIMEOUT,
+};
 
 pub fn handle_chat_message(mut state: Signal<AppState>, topic: &str, msg: &p2p::ChatMessage) {
     state.with_mut(|s| {
@@ -23,97 +32,149 @@ pub fn handle_chat_message(mut state: Signal<AppState>, topic: &str, msg: &p2p::
                 msg.content.clone(),
                 msg.timestamp,
                 false,
-            );
+            )
+            .with_hlc(msg.hlc.as_u64());
             topic_obj.add_message(message);
         }
+        s.clear_typing(topic, &msg.sender.to_string());
     });
 }
 
+/// Shows the replicated state of a topic: its name, avatar, timers and members.
+pub fn apply_topic_state(state: &mut AppState, topic: &str, topic_state: &TopicState) {
+    if topic_state.name.is_set() {
+        state.modify_topic_name(topic, topic_state.name.get());
+    }
+    if topic_state.avatar.is_set() {
+        let avatar = topic_state.avatar.get().map(|hash| hash.to_string());
+        state.modify_topic_avatar(topic, avatar);
+    }
+    if topic_state.slow_mode.is_set() {
+        state.set_topic_slow_mode(topic, *topic_state.slow_mode.get());
+    }
+    if topic_state.disappearing.is_set() {
+        state.set_topic_disappearing(topic, *topic_state.disappearing.get());
+    }
+    let members = topic_state
+        .members
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    state.set_topic_members(topic, members);
+}
+
+/// Merges the received topic state into ours, answering with the merged state when the sender
+/// is missing updates we know of.
 pub fn handle_topic_metadata(
+    client_ref: &Arc<DesktopClient>,
     mut state: Signal<AppState>,
     topic: &str,
     metadata: TopicMetadataMessage,
 ) -> Option<TopicMetadataMessage> {
-    if let Some(existing_topic) = state().get_topic(topic) {
-        if metadata.timestamp >= existing_topic.last_changed {
-            state.with_mut(|s| {
-                s.modify_topic_name(topic, &metadata.name);
-                s.modify_topic_avatar(topic, metadata.avatar_url.clone());
-                s.set_last_changed(topic, metadata.timestamp);
-                let own_id = s.get_profile().id;
-                let mut members = metadata.members;
-                if !members.contains(&own_id) {
-                    members.push(own_id);
-                }
-                s.set_topic_members(topic, members);
-            });
-            None
-        } else if let Ok(ticket) = Ticket::from_str(topic) {
-            Some(TopicMetadataMessage::new(
-                ticket.topic,
-                &existing_topic.name,
-                existing_topic.avatar_url.clone(),
-                existing_topic.last_changed,
-                existing_topic.members.clone().into_iter().collect(),
-            ))
-        } else {
-            None
+    let ticket = Ticket::from_str(topic).ok()?;
+    let own_id = client_ref.peer_id().ok()?;
+    state().get_topic(topic)?;
+
+    let (mut merged, _) = client_ref.merge_topic_state(&ticket, &metadata.state);
+    if let Some(hash) = *merged.avatar.get()
+        && let Some(writer) = merged.avatar.writer()
+        && writer != own_id
+    {
+        fetch_avatar(client_ref, hash, writer);
+    }
+    if !merged.members.contains(&own_id) {
+        merged = client_ref
+            .edit_topic_state(&ticket.topic, |s, tag| s.members.insert(own_id, tag))
+            .ok()?;
+    }
+
+    state.with_mut(|s| apply_topic_state(s, topic, &merged));
+
+    (merged != metadata.state).then(|| TopicMetadataMessage::new(ticket.topic, merged))
+}
+
+/// The maximum number of history pages sent to a peer joining a topic, so the neighbors relaying
+/// them stay under their gossip rate limit.
+const MAX_HISTORY_PAGES: usize = 5;
+
+/// Room left in a history page for the encoding around its messages.
+const PAGE_OVERHEAD: usize = 1024;
+
+/// Splits `messages` into `TopicMessages` within the gossip list limit and message size, so they
+/// are broadcast and peers do not drop them, and mute whoever relays them, as oversized.
+fn paginate(topic: TopicId, messages: &[p2p::ChatMessage]) -> Vec<p2p::TopicMessagesMessage> {
+    let max_messages = GossipLimits::default().max_list_length;
+    let max_bytes = p2p::MAX_MESSAGE_SIZE - PAGE_OVERHEAD;
+
+    let mut pages = Vec::new();
+    let mut page = Vec::new();
+    let mut page_bytes = 0;
+    for message in messages {
+        let size = postcard::to_stdvec(message).map_or(0, |encoded| encoded.len());
+        if !page.is_empty() && (page.len() == max_messages || page_bytes + size > max_bytes) {
+            pages.push(p2p::TopicMessagesMessage::new(
+                topic,
+                std::mem::take(&mut page),
+            ));
+            page_bytes = 0;
         }
-    } else {
-        None
+        page_bytes += size;
+        page.push(message.clone());
     }
+    if !page.is_empty() {
+        pages.push(p2p::TopicMessagesMessage::new(topic, page));
+    }
+    pages
 }
 
+/// Adds the peer joining the topic to its members, answering with the topic state and the pages
+/// of its most recent history.
 #[allow(clippy::cast_sign_loss)]
 pub fn handle_join_topic(
+    client_ref: &Arc<DesktopClient>,
     mut state: Signal<AppState>,
     topic: &str,
     join_message: &p2p::JoinMessage,
-) -> (
-    Option<TopicMetadataMessage>,
-    Option<p2p::TopicMessagesMessage>,
-) {
-    let metadata_to_send = state().get_all_topics().iter().find_map(|t| {
-        let ticket = Ticket::from_str(&t.id).ok()?;
-        if ticket.topic == join_message.topic {
-            Some(TopicMetadataMessage::new(
-                ticket.topic,
-                &t.name,
-                t.avatar_url.clone(),
-                t.last_changed,
-                t.members.clone().into_iter().collect(),
-            ))
-        } else {
-            None
-        }
-    });
-
-    let messages_to_send = state().get_topic(topic).and_then(|topic_obj| {
-        let chat_messages: Vec<p2p::ChatMessage> = topic_obj
-            .messages
-            .iter()
-            .filter_map(|msg| match msg {
-                Message::Chat(chat_msg) => Some(chat_msg.to_p2p_message()),
-                _ => None,
-            })
-            .collect();
+) -> (Option<TopicMetadataMessage>, Vec<p2p::TopicMessagesMessage>) {
+    if state().get_topic(topic).is_none() {
+        return (None, Vec::new());
+    }
 
-        if chat_messages.is_empty() {
-            None
-        } else {
-            Some(p2p::TopicMessagesMessage::new(
-                join_message.topic,
-                chat_messages,
-            ))
-        }
-    });
+    let metadata_to_send = client_ref
+        .edit_topic_state(&join_message.topic, |s, tag| {
+            s.members.insert(join_message.endpoint, tag);
+        })
+        .ok()
+        .map(|topic_state| TopicMetadataMessage::new(join_message.topic, topic_state));
+
+    let messages_to_send = state()
+        .get_topic(topic)
+        .map(|topic_obj| {
+            let chat_messages: Vec<p2p::ChatMessage> = topic_obj
+                .messages
+                .iter()
+                .filter_map(|msg| match msg {
+                    Message::Chat(chat_msg) => Some(chat_msg.to_p2p_message()),
+                    _ => None,
+                })
+                .collect();
+            let mut pages = paginate(join_message.topic, &chat_messages);
+            pages.drain(..pages.len().saturating_sub(MAX_HISTORY_PAGES));
+            // The newcomer answers the latest page with the recent messages we lack, if any.
+            if let Some(last) = pages.pop() {
+                pages.push(last.with_responder(join_message.endpoint));
+            }
+            pages
+        })
+        .unwrap_or_default();
 
     state.with_mut(|s| {
+        if let Some(topic_state) = &metadata_to_send {
+            apply_topic_state(s, topic, &topic_state.state);
+        }
         if let Some(topic_obj) = s.get_topic_mutable(topic) {
-            let sender_id = join_message.endpoint.to_string();
-            topic_obj.add_member(&sender_id);
             let message = ui::desktop::models::JoinMessage::new(
-                sender_id,
+                join_message.endpoint.to_string(),
                 Utc::now().timestamp_millis() as u64,
             );
             topic_obj.add_join_message(message);
@@ -123,14 +184,27 @@ pub fn handle_join_topic(
     (metadata_to_send, messages_to_send)
 }
 
+/// Removes the peer leaving the topic from its members.
 #[allow(clippy::cast_sign_loss)]
-pub fn handle_leave_topic(mut state: Signal<AppState>, topic: &str, leave_msg: &p2p::LeaveMessage) {
+pub fn handle_leave_topic(
+    client_ref: &Arc<DesktopClient>,
+    mut state: Signal<AppState>,
+    topic: &str,
+    leave_msg: &p2p::LeaveMessage,
+) {
+    let topic_state = client_ref
+        .edit_topic_state(&leave_msg.topic, |s, _| {
+            s.members.remove(&leave_msg.endpoint)
+        })
+        .ok();
+
     state.with_mut(|s| {
+        if let Some(topic_state) = &topic_state {
+            apply_topic_state(s, topic, topic_state);
+        }
         if let Some(topic_obj) = s.get_topic_mutable(topic) {
-            let sender_id = leave_msg.endpoint.to_string();
-            topic_obj.remove_member(&sender_id);
             let message = ui::desktop::models::LeaveMessage {
-                sender_id,
+                sender_id: leave_msg.endpoint.to_string(),
                 timestamp: Utc::now().timestamp_millis() as u64,
             };
             topic_obj.add_leave_message(message);
@@ -175,12 +249,60 @@ pub fn handle_blob_message(mut state: Signal<AppState>, topic: &str, msg: p2p::B
                 msg.timestamp,
                 false,
                 ui_blob_type,
-            );
+                msg.preview.map(utils::previews::to_ui_preview),
+                msg.key.map(|key| key.to_string()),
+            )
+            .with_hlc(msg.hlc.as_u64());
             topic_obj.add_blob_message(message);
         }
     });
 }
 
+/// Remembers the key of an encrypted blob, so it is decrypted when it is opened.
+fn register_blob_key(client_ref: &Arc<DesktopClient>, hash: p2p::Hash, key: Option<p2p::BlobKey>) {
+    if let Some(key) = key
+        && let Err(e) = client_ref.register_blob_key(hash, key)
+    {
+        eprintln!("Failed to register blob key: {e}");
+    }
+}
+
+pub fn to_ui_collection_entries(entries: Vec<p2p::CollectionEntry>) -> Vec<CollectionEntry> {
+    entries
+        .into_iter()
+        .map(|entry| {
+            CollectionEntry::new(
+                entry.name,
+                entry.hash.to_string(),
+                entry.size,
+                entry.key.map(|key| key.to_string()),
+            )
+        })
+        .collect()
+}
+
+pub fn handle_collection_message(
+    mut state: Signal<AppState>,
+    topic: &str,
+    msg: p2p::CollectionMessage,
+) {
+    state.with_mut(|s| {
+        if let Some(topic_obj) = s.get_topic_mutable(topic) {
+            let message = ui::desktop::models::CollectionMessage::new(
+                msg.sender.to_string(),
+                topic_obj.id.clone(),
+                msg.hash.to_string(),
+                msg.name,
+                to_ui_collection_entries(msg.entries),
+                msg.timestamp,
+                false,
+            )
+            .with_hlc(msg.hlc.as_u64());
+            topic_obj.add_collection_message(message);
+        }
+    });
+}
+
 pub fn handle_topic_messages(
     mut state: Signal<AppState>,
     topic: &str,
@@ -229,8 +351,13 @@ pub fn handle_topic_messages(
             })
             .collect();
 
+        // A partial history (e.g. a resync after a lag) only asks for the gaps since its oldest
+        // message, an empty one asks for everything.
+        let since = received_messages.iter().map(|msg| msg.timestamp).min();
+
         let missing: Vec<p2p::ChatMessage> = existing_messages
             .iter()
+            .filter(|msg| since.is_none_or(|since| msg.timestamp >= since))
             .filter(|msg| !received_messages.contains(msg))
             .map(ChatMessage::to_p2p_message)
             .collect();
@@ -243,9 +370,53 @@ pub fn handle_topic_messages(
     })
 }
 
+const RESYNC_MESSAGE_LIMIT: usize = 200;
+
+#[allow(clippy::future_not_send)]
+async fn resync_topic(client_ref: &Arc<DesktopClient>, state: Signal<AppState>, topic: &TopicId) {
+    let Some(ticket) = client_ref.get_topic_ticket(topic) else {
+        return;
+    };
+
+    let recent_messages = state()
+        .get_topic(&ticket)
+        .map(|topic_obj| {
+            let chat_messages: Vec<p2p::ChatMessage> = topic_obj
+                .messages
+                .iter()
+                .filter_map(|msg| match msg {
+                    Message::Chat(chat_msg) => Some(chat_msg.to_p2p_message()),
+                    _ => None,
+                })
+                .collect();
+            let skip = chat_messages.len().saturating_sub(RESYNC_MESSAGE_LIMIT);
+            chat_messages.into_iter().skip(skip).collect()
+        })
+        .unwrap_or_default();
+
+    // A single neighbor answers with the messages we are missing since the oldest one we send,
+    // the others only merge what they lack.
+    let Some(responder) = state().get_topic(&ticket).and_then(|topic_obj| {
+        topic_obj
+            .online
+            .iter()
+            .find_map(|member| member.parse::<EndpointId>().ok())
+    }) else {
+        return;
+    };
+    let sync_message =
+        p2p::TopicMessagesMessage::new(*topic, recent_messages).with_responder(responder);
+    if let Err(e) = client_ref
+        .send_to_neighbors(MessageTypes::TopicMessages(sync_message))
+        .await
+    {
+        eprintln!("Failed to resync topic {topic}: {e}");
+    }
+}
+
 #[allow(clippy::future_not_send)]
 pub async fn process_message(
-    client_ref: &Arc<Mutex<DesktopClient>>,
+    client_ref: &Arc<DesktopClient>,
     state: Signal<AppState>,
     topic: String,
     message: MessageTypes,
@@ -255,10 +426,9 @@ pub async fn process_message(
             handle_chat_message(state, &topic, &msg);
         }
         MessageTypes::TopicMetadata(metadata) => {
-            if let Some(metadata_to_send) = handle_topic_metadata(state, &topic, metadata)
+            if let Some(metadata_to_send) =
+                handle_topic_metadata(client_ref, state, &topic, metadata)
                 && let Err(e) = client_ref
-                    .lock()
-                    .await
                     .send(MessageTypes::TopicMetadata(metadata_to_send))
                     .await
             {
@@ -267,85 +437,181 @@ pub async fn process_message(
         }
         MessageTypes::JoinTopic(join_message) => {
             let (metadata_to_send, messages_to_send) =
-                handle_join_topic(state, &topic, &join_message);
+                handle_join_topic(client_ref, state, &topic, &join_message);
 
             if let Some(metadata) = metadata_to_send
-                && let Err(e) = client_ref
-                    .lock()
-                    .await
-                    .send(MessageTypes::TopicMetadata(metadata))
-                    .await
+                && let Err(e) = client_ref.send(MessageTypes::TopicMetadata(metadata)).await
             {
                 eprintln!("Failed to send TopicMetadataMessage: {e}");
             }
 
-            if let Some(messages) = messages_to_send
-                && let Err(e) = client_ref
-                    .lock()
-                    .await
-                    .send(MessageTypes::TopicMessages(messages))
-                    .await
-            {
-                eprintln!("Failed to send TopicMessagesMessage: {e}");
+            for messages in messages_to_send {
+                if let Err(e) = client_ref.send(MessageTypes::TopicMessages(messages)).await {
+                    eprintln!("Failed to send TopicMessagesMessage: {e}");
+                }
             }
+
+            // The newcomer only knows the members who announce themselves.
+            announce_profile(client_ref, state, join_message.topic).await;
         }
         MessageTypes::LeaveTopic(leave_msg) => {
-            handle_leave_topic(state, &topic, &leave_msg);
+            handle_leave_topic(client_ref, state, &topic, &leave_msg);
         }
         MessageTypes::DisconnectTopic(disconnect_msg) => {
             handle_disconnect_topic(state, &topic, &disconnect_msg);
         }
         MessageTypes::TopicMessages(topic_messages_msg) => {
-            if let Some(missing_messages) =
-                handle_topic_messages(state, &topic, &topic_messages_msg)
+            let missing_messages = handle_topic_messages(state, &topic, &topic_messages_msg);
+            // Only the peer asked to answer does, and only to its neighbors, the requester
+            // being one of them.
+            let is_responder = topic_messages_msg
+                .responder
+                .is_some_and(|responder| client_ref.peer_id().is_ok_and(|id| id == responder));
+            if is_responder
+                && let Some(missing_messages) = missing_messages
                 && let Ok(ticket) = Ticket::from_str(&topic)
             {
-                let sync_message = p2p::TopicMessagesMessage::new(ticket.topic, missing_messages);
-
-                if let Err(e) = client_ref
-                    .lock()
-                    .await
-                    .send(MessageTypes::TopicMessages(sync_message))
-                    .await
-                {
-                    eprintln!("Failed to send missing messages: {e}");
+                for sync_message in paginate(ticket.topic, &missing_messages) {
+                    if let Err(e) = client_ref
+                        .send_to_neighbors(MessageTypes::TopicMessages(sync_message))
+                        .await
+                    {
+                        eprintln!("Failed to send missing messages: {e}");
+                    }
                 }
             }
         }
         MessageTypes::Blob(image_message) => {
+            register_blob_key(client_ref, image_message.hash, image_message.key);
             handle_blob_message(state, &topic, image_message);
         }
+        MessageTypes::Collection(collection_message) => {
+            for entry in &collection_message.entries {
+                register_blob_key(client_ref, entry.hash, entry.key);
+            }
+            handle_collection_message(state, &topic, collection_message);
+        }
+        MessageTypes::Typing(typing_message) => {
+            handle_typing(state, &topic, &typing_message.sender.to_string());
+        }
+        MessageTypes::ProfileMetadata(profile_message) => {
+            handle_profile_metadata(client_ref, state, &topic, profile_message);
+        }
     }
 }
 
-pub async fn collect_messages(
-    client_ref: &Arc<Mutex<DesktopClient>>,
-) -> Vec<(String, MessageTypes)> {
-    let mut msgs = Vec::new();
-    for (topic, receiver) in client_ref.lock().await.get_message_receiver() {
-        while let Ok(message) = receiver.try_recv() {
-            msgs.push((topic.clone(), message));
-        }
+/// Marks a peer as reachable, or not, in the topic it is a gossip neighbor of.
+fn set_member_online(
+    client_ref: &Arc<DesktopClient>,
+    mut state: Signal<AppState>,
+    topic: &TopicId,
+    peer: &EndpointId,
+    online: bool,
+) {
+    if let Some(ticket) = client_ref.get_topic_ticket(topic) {
+        state.with_mut(|s| s.set_topic_member_online(&ticket, &peer.to_string(), online));
     }
-    msgs
 }
 
+/// Handles a batch of client events, saving what changed in the chats once at the end.
 #[allow(clippy::future_not_send)]
-pub async fn process_all_messages(client_ref: &Arc<Mutex<DesktopClient>>, state: Signal<AppState>) {
-    let messages = collect_messages(client_ref).await;
-
-    for (topic, message) in messages {
-        process_message(client_ref, state, topic, message).await;
+#[allow(clippy::cast_sign_loss)]
+pub async fn process_events(
+    client_ref: &Arc<DesktopClient>,
+    mut state: Signal<AppState>,
+    progress_sender: &Sender<u64>,
+    events: Vec<ChatEvent>,
+) {
+    let mut lagged_topics = HashSet::new();
+
+    for event in events {
+        match event {
+            ChatEvent::Message { topic, message } => {
+                let ticket = client_ref.get_topic_ticket(&topic);
+                if let Some(ticket) = ticket {
+                    process_message(client_ref, state, ticket, message).await;
+                }
+            }
+            ChatEvent::DirectMessage { sender, message } => {
+                // The DM protocol already dropped the connection of any peer sending a message on
+                // behalf of someone else, so the ids inside `message` are `sender` and us.
+                // Until their request is accepted, peers can only send the request itself.
+                if !matches!(message, DmMessageTypes::JoinPetition(_))
+                    && state.read().get_contact(&sender.to_string()).is_none()
+                {
+                    continue;
+                }
+                match &message {
+                    DmMessageTypes::Blob(msg) => register_blob_key(client_ref, msg.hash, msg.key),
+                    DmMessageTypes::Collection(msg) => {
+                        for entry in &msg.entries {
+                            register_blob_key(client_ref, entry.hash, entry.key);
+                        }
+                    }
+                    _ => {}
+                }
+                process_dm_message(client_ref.clone(), state, message);
+            }
+            ChatEvent::Transfer { hash, progress } => {
+                let hash_str = hash.to_string();
+                if state.peek().download_state(&hash_str).is_some() {
+                    let download = match &progress {
+                        TransferProgress::Progress(bytes) => DownloadState::Progress(*bytes),
+                        TransferProgress::Done => DownloadState::Done,
+                        TransferProgress::Failed(_) => DownloadState::Failed,
+                    };
+                    state.with_mut(|s| s.update_download(&hash_str, download));
+                }
+                let progress = match progress {
+                    TransferProgress::Progress(bytes) => bytes,
+                    TransferProgress::Done => u64::MAX,
+                    TransferProgress::Failed(e) => {
+                        eprintln!("Failed to download {hash}: {e}");
+                        u64::MAX
+                    }
+                };
+                let _ = progress_sender.send(progress);
+            }
+            ChatEvent::Lagged { topic } => {
+                eprintln!("Missed messages in topic {topic}, resyncing");
+                lagged_topics.insert(topic);
+            }
+            ChatEvent::DmAbuse(event) => {
+                eprintln!("Closed DM connection with {}: {event:?}", event.peer());
+            }
+            ChatEvent::GossipAbuse(event) => {
+                eprintln!("Muted {}: {event:?}", event.peer());
+            }
+            ChatEvent::NeighborUp { topic, peer } => {
+                set_member_online(client_ref, state, &topic, &peer, true);
+            }
+            ChatEvent::NeighborDown { topic, peer } => {
+                set_member_online(client_ref, state, &topic, &peer, false);
+            }
+            ChatEvent::Presence { peer, online } => {
+                state.with_mut(|s| {
+                    s.set_contact_online(
+                        &peer.to_string(),
+                        online,
+                        Utc::now().timestamp_millis() as u64,
+                    );
+                });
+            }
+            ChatEvent::PeerConnected { .. } | ChatEvent::PeerDisconnected { .. } => {}
+        }
     }
 
-    let dm_messages = collect_dm_messages(client_ref).await;
-
-    for (_sender, message) in dm_messages {
-        process_dm_message(client_ref.clone(), state, message);
+    for topic in &lagged_topics {
+        resync_topic(client_ref, state, topic).await;
     }
 
-    if let Err(e) = save_topics_to_file(&state().get_all_topics()) {
-        eprintln!("Failed to save topics to file: {e}");
+    // Most batches only carry typing signals or state we already know: the app state is only
+    // written back to mark the new messages saved.
+    let written = store::write_chats(&state.peek());
+    match written {
+        Ok(true) => state.with_mut(AppState::mark_chats_saved),
+        Ok(false) => {}
+        Err(e) => eprintln!("Failed to save chats: {e}"),
     }
 }
 
@@ -363,6 +629,7 @@ impl P2PMessageConvert for ChatMessage {
             msg.timestamp,
             false,
         )
+        .with_hlc(msg.hlc.as_u64())
     }
 
     fn to_p2p_message(&self) -> p2p::ChatMessage {
@@ -373,6 +640,7 @@ impl P2PMessageConvert for ChatMessage {
             self.timestamp,
             ticket.topic,
         )
+        .with_hlc(Hlc::from_u64(self.hlc))
     }
 }
 
@@ -388,19 +656,133 @@ pub fn handle_dm_chat_message(mut state: Signal<AppState>, msg: &P2pDmChatMessag
             msg.timestamp,
             false,
         );
+        s.clear_typing(&sender_id, &sender_id);
         s.add_dm_message(&sender_id, message);
     });
 }
 
-pub fn handle_dm_profile_metadata(mut state: Signal<AppState>, msg: DmProfileMetadataMessage) {
+/// Applies the message timer set by a contact, unless a newer change is already known.
+pub fn handle_dm_disappearing(mut state: Signal<AppState>, msg: &DmDisappearingMessage) {
+    let sender_id = msg.sender.to_string();
+    let changed =
+        state.with_mut(|s| s.set_contact_disappearing(&sender_id, msg.seconds, msg.timestamp));
+
+    if changed && let Err(e) = state.with_mut(store::save_chats) {
+        eprintln!("Failed to save contacts: {e}");
+    }
+}
+
+/// Shows `member` as typing in the chat and hides it again once the signal times out, unless it
+/// was repeated in the meantime. Typing signals are never stored as messages.
+#[allow(clippy::cast_sign_loss)]
+pub fn handle_typing(mut state: Signal<AppState>, chat_id: &str, member: &str) {
+    let now = Utc::now().timestamp_millis() as u64;
+    state.with_mut(|s| s.set_typing(chat_id, member, now + TYPING_TIMEOUT));
+
+    spawn(async move {
+        tokio::time::sleep(Duration::from_millis(TYPING_TIMEOUT)).await;
+        let now = Utc::now().timestamp_millis() as u64;
+        state.with_mut(|s| s.expire_typing(now));
+    });
+}
+
+/// Downloads an avatar in the background from the peer that shared it, unless it is already in
+/// the store. Avatars only travel as hashes, so they are fetched once and not with every update.
+pub fn fetch_avatar(client_ref: &Arc<DesktopClient>, hash: Hash, provider: EndpointId) {
+    let client_ref = Arc::clone(client_ref);
+    tokio::spawn(async move {
+        if client_ref.has_blob(hash).await.unwrap_or(false) {
+            return;
+        }
+        let ticket = BlobTicket::new(EndpointAddr::from(provider), hash, Raw);
+        let download = async { Ok::<_, anyhow::Error>(client_ref.download_blob(&ticket)?.await?) };
+        if let Err(e) = download.await {
+            eprintln!("Failed to fetch avatar {hash} from {provider}: {e}");
+        }
+    });
+}
+
+/// Returns the hash of our avatar, letting the peers in `scope` fetch it.
+pub fn share_avatar(
+    client_ref: &Arc<DesktopClient>,
+    profile: &Profile,
+    scope: BlobScope,
+) -> Option<Hash> {
+    let hash = utils::avatars::avatar_hash(profile.avatar.as_deref())?;
+    if let Err(e) = client_ref.grant_blob_access(hash, scope) {
+        eprintln!("Failed to share avatar: {e}");
+    }
+    Some(hash)
+}
+
+/// Caches the profile a topic member announced, so it is shown even if they are not a contact.
+pub fn handle_profile_metadata(
+    client_ref: &Arc<DesktopClient>,
+    mut state: Signal<AppState>,
+    topic: &str,
+    msg: ProfileMetadataMessage,
+) {
+    let member_id = msg.id.to_string();
+    if member_id == state.read().get_profile().id {
+        return;
+    }
+    if let Some(hash) = msg.avatar {
+        fetch_avatar(client_ref, hash, msg.id);
+    }
+    state.with_mut(|s| {
+        let avatar = msg.avatar.map(|hash| hash.to_string());
+        let profile = MemberProfile::new(msg.username, avatar, msg.timestamp);
+        s.set_topic_member_profile(topic, &member_id, profile);
+    });
+}
+
+/// Announces our profile to the members of `topic`.
+#[allow(clippy::future_not_send)]
+#[allow(clippy::cast_sign_loss)]
+pub async fn announce_profile(
+    client_ref: &Arc<DesktopClient>,
+    state: Signal<AppState>,
+    topic: TopicId,
+) {
+    let profile = state().get_profile();
+    let Ok(id) = profile.id.parse() else {
+        return;
+    };
+    let avatar = share_avatar(client_ref, &profile, BlobScope::Topic(topic));
+    let message = ProfileMetadataMessage::new(
+        topic,
+        id,
+        profile.name,
+        avatar,
+        Utc::now().timestamp_millis() as u64,
+    );
+
+    if let Err(e) = client_ref
+        .send(MessageTypes::ProfileMetadata(message))
+        .await
+    {
+        eprintln!("Failed to announce profile in topic {topic}: {e}");
+    }
+}
+
+pub fn handle_dm_profile_metadata(
+    client_ref: &Arc<DesktopClient>,
+    mut state: Signal<AppState>,
+    msg: DmProfileMetadataMessage,
+) {
+    if let Some(hash) = msg.avatar {
+        fetch_avatar(client_ref, hash, msg.id);
+    }
+
     state.with_mut(|s| {
         let profile_id = msg.id.to_string();
 
         let profile = ui::desktop::models::Profile {
             id: profile_id,
             name: msg.username,
-            avatar: msg.avatar_url,
+            avatar: msg.avatar.map(|hash| hash.to_string()),
             last_connection: ui::desktop::models::ConnectionStatus::Offline(msg.last_connection),
+            verified: None,
         };
 
         s.modify_contact(profile);
@@ -430,79 +812,88 @@ pub fn handle_dm_blob_message(mut state: Signal<AppState>, msg: P2pDmBlobMessage
             msg.timestamp,
             false,
             ui_blob_type,
+            msg.preview.map(utils::previews::to_ui_preview),
+            msg.key.map(|key| key.to_string()),
         );
         s.add_dm_blob_message(&sender_id, message);
     });
 }
 
-pub fn handle_dm_join_petition(
-    client_ref: Arc<Mutex<DesktopClient>>,
-    mut state: Signal<AppState>,
-    msg: &DmJoinMessage,
-) {
-    let petitioner_id = msg.petitioner.to_string();
-
+pub fn handle_dm_collection_message(mut state: Signal<AppState>, msg: p2p::DmCollectionMessage) {
     state.with_mut(|s| {
-        if s.get_contact(&petitioner_id).is_none() {
-            let profile = ui::desktop::models::Profile::new_with_id(&petitioner_id);
-            s.add_contact(profile);
-        }
+        let sender_id = msg.sender.to_string();
+        let receiver_id = msg.receiver.to_string();
+
+        let message = DmCollectionMessage::new(
+            sender_id.clone(),
+            receiver_id,
+            msg.hash.to_string(),
+            msg.name,
+            to_ui_collection_entries(msg.entries),
+            msg.timestamp,
+            false,
+        );
+        s.add_dm_collection_message(&sender_id, message);
     });
+}
 
+/// Sends our profile to a contact, letting them fetch our avatar.
+#[allow(clippy::future_not_send)]
+pub async fn send_profile(
+    client_ref: &Arc<DesktopClient>,
+    state: Signal<AppState>,
+    user_id: &str,
+) -> anyhow::Result<()> {
     let profile = state.read().get_profile();
-
-    let endpoint_id = profile.id.parse().expect("Invalid endpoint ID");
+    let avatar = share_avatar(client_ref, &profile, BlobScope::Peer(user_id.parse()?));
 
     let profile_metadata = DmProfileMetadataMessage::new(
-        endpoint_id,
+        profile.id.parse()?,
         profile.name,
-        profile.avatar,
+        avatar,
         profile.last_connection.get_u64(),
     );
 
-    spawn(async move {
-        let client = client_ref.lock().await;
+    client_ref
+        .send_dm(user_id, DmMessageTypes::ProfileMetadata(profile_metadata))
+        .await?;
+    Ok(())
+}
 
-        if let Err(e) = client.connect_to_user(&petitioner_id).await {
-            eprintln!("Failed to connect to petitioner: {e}");
-            return;
-        }
+/// Queues the petition of an unknown peer as a contact request. A petition from a contact means
+/// they accepted our own request, so they get our profile in return.
+#[allow(clippy::cast_sign_loss)]
+pub fn handle_dm_join_petition(
+    client_ref: Arc<DesktopClient>,
+    mut state: Signal<AppState>,
+    msg: &DmJoinMessage,
+) {
+    let petitioner_id = msg.petitioner.to_string();
 
-        if let Err(e) = client
-            .send_dm(
-                &petitioner_id,
-                DmMessageTypes::ProfileMetadata(profile_metadata),
-            )
-            .await
+    if state.read().get_contact(&petitioner_id).is_none() {
+        let request = ContactRequest::new(petitioner_id, Utc::now().timestamp_millis() as u64);
+        if state.with_mut(|s| s.add_contact_request(request))
+            && let Err(e) = save_contact_requests(&state.read().get_contact_requests())
         {
-            eprintln!("Failed to send profile metadata: {e}");
+            eprintln!("Failed to save contact requests: {e}");
         }
-    });
-
-    if let Err(e) = crate::utils::contacts::save_contacts(&state().get_all_contacts_chat()) {
-        eprintln!("Failed to save contacts: {e}");
+        return;
     }
-}
-
-pub async fn collect_dm_messages(
-    client_ref: &Arc<Mutex<DesktopClient>>,
-) -> Vec<(p2p::EndpointId, DmMessageTypes)> {
-    let receiver_result = client_ref.lock().await.get_global_dm_receiver().await;
 
-    let mut msgs = Vec::new();
+    spawn(async move {
+        if let Err(e) = client_ref.connect_to_user(&petitioner_id).await {
+            eprintln!("Failed to connect to petitioner: {e}");
+            return;
+        }
 
-    if let Ok(receiver) = receiver_result {
-        while let Ok((sender, message)) = receiver.try_recv() {
-            msgs.push((sender, message));
+        if let Err(e) = send_profile(&client_ref, state, &petitioner_id).await {
+            eprintln!("Failed to send profile metadata: {e}");
         }
-    } else {
-        eprintln!("Failed to get DM receiver");
-    }
-    msgs
+    });
 }
 
 pub fn process_dm_message(
-    client_ref: Arc<Mutex<DesktopClient>>,
+    client_ref: Arc<DesktopClient>,
     state: Signal<AppState>,
     message: DmMessageTypes,
 ) {
@@ -511,7 +902,7 @@ pub fn process_dm_message(
             handle_dm_chat_message(state, &msg);
         }
         DmMessageTypes::ProfileMetadata(msg) => {
-            handle_dm_profile_metadata(state, msg);
+            handle_dm_profile_metadata(&client_ref, state, msg);
         }
         DmMessageTypes::JoinPetition(msg) => {
             handle_dm_join_petition(client_ref, state, &msg);
@@ -519,5 +910,49 @@ pub fn process_dm_message(
         DmMessageTypes::Blob(msg) => {
             handle_dm_blob_message(state, msg);
         }
+        DmMessageTypes::Collection(msg) => {
+            handle_dm_collection_message(state, msg);
+        }
+        DmMessageTypes::Typing(msg) => {
+            let sender_id = msg.sender.to_string();
+            handle_typing(state, &sender_id, &sender_id);
+        }
+        DmMessageTypes::Disappearing(msg) => {
+            handle_dm_disappearing(state, &msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paginate_fits_maximum_length_messages() {
+        let topic = TopicId::from_bytes([1; 32]);
+        let mut identity = [0; 32];
+        identity[0] = 1;
+        let sender = EndpointId::from_bytes(&identity).unwrap();
+        let limits = GossipLimits::default();
+        let content = "a".repeat(limits.max_text_length);
+        let messages: Vec<_> = (0..limits.max_list_length as u64)
+            .map(|timestamp| p2p::ChatMessage::new(sender, content.clone(), timestamp, topic))
+            .collect();
+
+        let pages = paginate(topic, &messages);
+
+        assert!(pages.len() > 1);
+        for page in &pages {
+            let encoded = postcard::to_stdvec(&MessageTypes::TopicMessages(page.clone())).unwrap();
+            assert!(encoded.len() <= p2p::MAX_MESSAGE_SIZE);
+        }
+        let paged: Vec<_> = pages.into_iter().flat_map(|page| page.messages).collect();
+        assert_eq!(paged.len(), messages.len());
+        assert!(
+            paged
+                .iter()
+                .zip(&messages)
+                .all(|(a, b)| a.timestamp == b.timestamp)
+        );
     }
 }
@@ -0,0 +1,62 @@
+//! Removes the messages of chats with a message timer once it runs out, and those the local
+//! retention policies do not keep, along with the blobs nothing else refers to anymore.
+
+use crate::client::DesktopClient;
+use crate::store;
+use chrono::Utc;
+use dioxus::prelude::{Signal, WritableExt};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use ui::desktop::models::{AppState, Purge};
+
+/// How often expired messages are looked for.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Sweeps the chats at a fixed interval, for as long as the app runs.
+#[allow(clippy::future_not_send)]
+pub async fn run(desktop_client: Arc<DesktopClient>, app_state: Signal<AppState>) {
+    loop {
+        sweep(&desktop_client, app_state).await;
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+    }
+}
+
+/// Removes the expired messages and the ones the retention policies drop, saves the chats they
+/// were removed from and deletes their blobs.
+#[allow(clippy::future_not_send)]
+#[allow(clippy::cast_sign_loss)]
+pub async fn sweep(desktop_client: &Arc<DesktopClient>, mut app_state: Signal<AppState>) {
+    let now = Utc::now().timestamp_millis() as u64;
+    let expired = app_state.with_mut(|state| state.remove_expired_messages(now));
+    persist(desktop_client, app_state, expired).await;
+    let pruned = app_state.with_mut(|state| state.apply_retention(now));
+    persist(desktop_client, app_state, pruned).await;
+}
+
+/// Saves the chats messages were removed from and deletes the blobs left behind.
+#[allow(clippy::future_not_send)]
+async fn persist(
+    desktop_client: &Arc<DesktopClient>,
+    mut app_state: Signal<AppState>,
+    purge: Purge,
+) {
+    if purge.is_empty() {
+        return;
+    }
+
+    if let Err(e) = app_state.with_mut(store::save_chats) {
+        eprintln!("Failed to save chats: {e}");
+    }
+
+    let hashes: Vec<p2p::Hash> = purge
+        .blobs
+        .iter()
+        .filter_map(|hash| p2p::Hash::from_str(hash).ok())
+        .collect();
+    if !hashes.is_empty()
+        && let Err(e) = desktop_client.delete_blobs(&hashes).await
+    {
+        eprintln!("Failed to delete removed blobs: {e}");
+    }
+}
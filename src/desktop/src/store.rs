@@ -0,0 +1,770 @@
+//! # Message Store
+//!
+//! Topics and contacts are kept in an embedded [`redb`] database instead of files rewritten
+//! whole on every change. Messages are inserted one by one as they arrive, keyed by chat, time
+//! and a fingerprint of their content, so a chat is read back in order without touching the
+//! others and removing a message only deletes its own entry. The details of a chat, everything
+//! but its messages, are written again only when they differ from the ones on disk.
+//!
+//! The replicated state of each topic is kept next to them, written whenever an edit or a merge
+//! changes it.
+//!
+//! Chats saved to files by older versions are imported the first time the database is opened.
+
+use crate::utils;
+use crate::utils::DIR_NAME;
+use anyhow::anyhow;
+use p2p::{TopicId, TopicState};
+use redb::{
+    Database, ReadableDatabase, ReadableTable, ReadableTableMetadata, Table, TableDefinition,
+};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use ui::desktop::models::{AppState, DmMessage, Message, MessageChange, ProfileChat, Topic};
+
+const DATABASE_FILE: &str = "chats.redb";
+
+/// Topics without their messages, by ticket.
+const TOPICS: TableDefinition<&str, &[u8]> = TableDefinition::new("topics");
+/// Contacts without their messages, by id.
+const CONTACTS: TableDefinition<&str, &[u8]> = TableDefinition::new("contacts");
+/// Topic messages, by ticket, timestamp and fingerprint.
+const TOPIC_MESSAGES: TableDefinition<MessageKey, &[u8]> = TableDefinition::new("topic_messages");
+/// Direct messages, by contact id, timestamp and fingerprint.
+const DM_MESSAGES: TableDefinition<MessageKey, &[u8]> = TableDefinition::new("dm_messages");
+/// Replicated topic states, by topic id.
+const TOPIC_STATES: TableDefinition<&[u8], &[u8]> = TableDefinition::new("topic_states");
+
+/// A chat id, the timestamp of the message in milliseconds and a fingerprint of its content.
+type MessageKey = (&'static str, u64, u64);
+
+static STORE: OnceLock<MessageStore> = OnceLock::new();
+
+/// Opens the database in the data directory, importing the chats saved to files by older
+/// versions. Later calls do nothing.
+pub fn open() -> anyhow::Result<()> {
+    if STORE.get().is_some() {
+        return Ok(());
+    }
+    let path = dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(DIR_NAME)
+        .join(DATABASE_FILE);
+    let store = MessageStore::open_path(&path)?;
+    store.import_legacy_files()?;
+    store.import_topic_states_file()?;
+    let _ = STORE.set(store);
+    Ok(())
+}
+
+fn store() -> anyhow::Result<&'static MessageStore> {
+    STORE
+        .get()
+        .ok_or_else(|| anyhow!("Message store is not open"))
+}
+
+/// Writes what changed in the chats since they were last saved, see [`MessageStore::sync`].
+pub fn save_chats(state: &mut AppState) -> anyhow::Result<()> {
+    if store()?.sync(state)? {
+        state.mark_chats_saved();
+    }
+    Ok(())
+}
+
+/// Writes what changed in the chats like [`save_chats`], leaving it to the caller to mark them
+/// saved when it returns true, so a state that did not change is not written at all.
+pub fn write_chats(state: &AppState) -> anyhow::Result<bool> {
+    store()?.sync(state)
+}
+
+pub fn load_topics() -> anyhow::Result<Vec<Topic>> {
+    store()?.load_topics()
+}
+
+pub fn load_contacts() -> anyhow::Result<Vec<ProfileChat>> {
+    store()?.load_contacts()
+}
+
+pub fn save_topic_state(topic: &TopicId, state: &TopicState) -> anyhow::Result<()> {
+    store()?.save_topic_state(topic, state)
+}
+
+pub fn remove_topic_state(topic: &TopicId) -> anyhow::Result<()> {
+    store()?.remove_topic_state(topic)
+}
+
+pub fn load_topic_states() -> anyhow::Result<HashMap<TopicId, TopicState>> {
+    store()?.load_topic_states()
+}
+
+pub struct MessageStore {
+    db: Database,
+    /// The encoded details last written for each chat of the app state, so unchanged ones are
+    /// skipped and those no longer in it are deleted. Loaded chats are only added once synced,
+    /// so chats not restored yet are never taken for deleted ones.
+    written: Mutex<Written>,
+}
+
+#[derive(Default)]
+struct Written {
+    topics: HashMap<String, Vec<u8>>,
+    contacts: HashMap<String, Vec<u8>>,
+}
+
+impl MessageStore {
+    /// Opens the database at `path`, creating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened or its tables created.
+    pub fn open_path(path: &Path) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+        let db = Database::create(path)?;
+
+        let txn = db.begin_write()?;
+        txn.open_table(TOPICS)?;
+        txn.open_table(CONTACTS)?;
+        txn.open_table(TOPIC_MESSAGES)?;
+        txn.open_table(DM_MESSAGES)?;
+        txn.open_table(TOPIC_STATES)?;
+        txn.commit()?;
+
+        Ok(Self {
+            db,
+            written: Mutex::new(Written::default()),
+        })
+    }
+
+    /// Writes what changed in the chats since they were last saved, in one transaction: the
+    /// messages added or removed, the details that differ from the ones on disk, and the chats
+    /// that are gone along with their messages. Nothing is written when nothing changed.
+    ///
+    /// Returns whether message changes were written, the chats must be marked saved then.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encoding or writing fails, nothing is saved then.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn sync(&self, state: &AppState) -> anyhow::Result<bool> {
+        let mut written = self.written.lock().expect("written chats lock poisoned");
+        let mut topics_written = HashMap::new();
+        let mut contacts_written = HashMap::new();
+        let mut messages_changed = false;
+        let mut details_changed = false;
+
+        let txn = self.db.begin_write()?;
+        {
+            let mut details = txn.open_table(TOPICS)?;
+            let mut messages = txn.open_table(TOPIC_MESSAGES)?;
+            for topic in state.topics() {
+                write_changes(&mut messages, &topic.id, topic.unsaved())?;
+                messages_changed |= !topic.unsaved().is_empty();
+                let encoded = postcard::to_stdvec(&topic.details())?;
+                if written.topics.get(&topic.id) != Some(&encoded) {
+                    details.insert(topic.id.as_str(), encoded.as_slice())?;
+                    details_changed = true;
+                }
+                topics_written.insert(topic.id.clone(), encoded);
+            }
+            for id in written.topics.keys() {
+                if !topics_written.contains_key(id) {
+                    details.remove(id.as_str())?;
+                    messages.retain_in(chat_range(id), |_, _| false)?;
+                    details_changed = true;
+                }
+            }
+
+            let mut details = txn.open_table(CONTACTS)?;
+            let mut messages = txn.open_table(DM_MESSAGES)?;
+            for contact in state.contacts_chat() {
+                let id = &contact.profile.id;
+                write_changes(&mut messages, id, contact.unsaved())?;
+                messages_changed |= !contact.unsaved().is_empty();
+                let encoded = postcard::to_stdvec(&contact.details())?;
+                if written.contacts.get(id) != Some(&encoded) {
+                    details.insert(id.as_str(), encoded.as_slice())?;
+                    details_changed = true;
+                }
+                contacts_written.insert(id.clone(), encoded);
+            }
+            for id in written.contacts.keys() {
+                if !contacts_written.contains_key(id) {
+                    details.remove(id.as_str())?;
+                    messages.retain_in(chat_range(id), |_, _| false)?;
+                    details_changed = true;
+                }
+            }
+        }
+        if !messages_changed && !details_changed {
+            txn.abort()?;
+            return Ok(false);
+        }
+        txn.commit()?;
+
+        written.topics = topics_written;
+        written.contacts = contacts_written;
+        Ok(messages_changed)
+    }
+
+    /// Reads every topic with its messages, ordered as they are shown.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading or decoding fails.
+    pub fn load_topics(&self) -> anyhow::Result<Vec<Topic>> {
+        let txn = self.db.begin_read()?;
+        let details = txn.open_table(TOPICS)?;
+        let messages = txn.open_table(TOPIC_MESSAGES)?;
+
+        let mut topics = Vec::new();
+        for entry in details.iter()? {
+            let (id, encoded) = entry?;
+            let mut topic: Topic = postcard::from_bytes(encoded.value())?;
+            topic.messages = read_messages(&messages, id.value())?;
+            topic.messages.sort();
+            topics.push(topic);
+        }
+        Ok(topics)
+    }
+
+    /// Reads every contact with its messages, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading or decoding fails.
+    pub fn load_contacts(&self) -> anyhow::Result<Vec<ProfileChat>> {
+        let txn = self.db.begin_read()?;
+        let details = txn.open_table(CONTACTS)?;
+        let messages = txn.open_table(DM_MESSAGES)?;
+
+        let mut contacts = Vec::new();
+        for entry in details.iter()? {
+            let (id, encoded) = entry?;
+            let mut contact: ProfileChat = postcard::from_bytes(encoded.value())?;
+            contact.messages = read_messages(&messages, id.value())?;
+            contacts.push(contact);
+        }
+        Ok(contacts)
+    }
+
+    /// Writes the replicated state of a topic, replacing the one on disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encoding or writing fails.
+    pub fn save_topic_state(&self, topic: &TopicId, state: &TopicState) -> anyhow::Result<()> {
+        let encoded = postcard::to_stdvec(state)?;
+        let txn = self.db.begin_write()?;
+        txn.open_table(TOPIC_STATES)?
+            .insert(topic.as_bytes().as_slice(), encoded.as_slice())?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// Deletes the replicated state of a topic that was left.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn remove_topic_state(&self, topic: &TopicId) -> anyhow::Result<()> {
+        let txn = self.db.begin_write()?;
+        txn.open_table(TOPIC_STATES)?
+            .remove(topic.as_bytes().as_slice())?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// Reads the replicated state of every topic.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading or decoding fails.
+    pub fn load_topic_states(&self) -> anyhow::Result<HashMap<TopicId, TopicState>> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(TOPIC_STATES)?;
+
+        let mut states = HashMap::new();
+        for entry in table.iter()? {
+            let (topic, encoded) = entry?;
+            let topic = TopicId::from_bytes(topic.value().try_into()?);
+            states.insert(topic, postcard::from_bytes(encoded.value())?);
+        }
+        Ok(states)
+    }
+
+    /// Moves the topic states saved to a file by older versions into an empty table, then
+    /// deletes the file.
+    fn import_topic_states_file(&self) -> anyhow::Result<()> {
+        let Some(states) = read_legacy_file("topic states", utils::topics::load_topic_states())
+        else {
+            return Ok(());
+        };
+
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(TOPIC_STATES)?;
+            if table.is_empty()? {
+                for (topic, state) in &states {
+                    let encoded = postcard::to_stdvec(state)?;
+                    table.insert(topic.as_bytes().as_slice(), encoded.as_slice())?;
+                }
+            }
+        }
+        txn.commit()?;
+
+        if let Err(e) = utils::topics::remove_topic_states_file() {
+            eprintln!("Failed to remove the imported topic states file: {e}");
+        }
+        Ok(())
+    }
+
+    /// Moves the chats saved to files by older versions into an empty database, then deletes
+    /// the files. A file that does not decode is reported and left in place, so its history is
+    /// not lost.
+    fn import_legacy_files(&self) -> anyhow::Result<()> {
+        let topics = read_legacy_file("topics", utils::topics::load_topics_from_file());
+        let contacts = read_legacy_file("contacts", utils::contacts::load_contacts());
+        let imported = self.import_legacy(
+            topics.as_deref().unwrap_or_default(),
+            contacts.as_deref().unwrap_or_default(),
+        )?;
+        if !imported {
+            return Ok(());
+        }
+
+        if topics.is_some()
+            && let Err(e) = utils::topics::remove_topics_file()
+        {
+            eprintln!("Failed to remove the imported topics file: {e}");
+        }
+        if contacts.is_some()
+            && let Err(e) = utils::contacts::remove_contacts_file()
+        {
+            eprintln!("Failed to remove the imported contacts file: {e}");
+        }
+        Ok(())
+    }
+
+    /// Writes chats read from the files of older versions, if the database is still empty.
+    /// Returns whether they were written.
+    fn import_legacy(&self, topics: &[Topic], contacts: &[ProfileChat]) -> anyhow::Result<bool> {
+        if topics.is_empty() && contacts.is_empty() {
+            return Ok(false);
+        }
+
+        let txn = self.db.begin_read()?;
+        if !txn.open_table(TOPICS)?.is_empty()? || !txn.open_table(CONTACTS)?.is_empty()? {
+            return Ok(false);
+        }
+        drop(txn);
+
+        let txn = self.db.begin_write()?;
+        {
+            let mut details = txn.open_table(TOPICS)?;
+            let mut messages = txn.open_table(TOPIC_MESSAGES)?;
+            for topic in topics {
+                for message in &topic.messages {
+                    insert_message(&mut messages, &topic.id, message)?;
+                }
+                let encoded = postcard::to_stdvec(&topic.details())?;
+                details.insert(topic.id.as_str(), encoded.as_slice())?;
+            }
+
+            let mut details = txn.open_table(CONTACTS)?;
+            let mut messages = txn.open_table(DM_MESSAGES)?;
+            for contact in contacts {
+                let id = &contact.profile.id;
+                for message in &contact.messages {
+                    insert_message(&mut messages, id, message)?;
+                }
+                let encoded = postcard::to_stdvec(&contact.details())?;
+                details.insert(id.as_str(), encoded.as_slice())?;
+            }
+        }
+        txn.commit()?;
+        Ok(true)
+    }
+}
+
+/// The content of a file saved by older versions, or `None` if there is no such file or it does
+/// not decode, which is reported.
+fn read_legacy_file<T>(name: &str, result: std::io::Result<T>) -> Option<T> {
+    match result {
+        Ok(content) => Some(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            eprintln!(
+                "Failed to import the {name} saved by an older version, keeping the file: {e}"
+            );
+            None
+        }
+    }
+}
+
+/// A message stored in the database, with the timestamp it is indexed by.
+trait StoredMessage: Serialize + DeserializeOwned {
+    fn timestamp(&self) -> u64;
+}
+
+impl StoredMessage for Message {
+    fn timestamp(&self) -> u64 {
+        self.get_timestamp()
+    }
+}
+
+impl StoredMessage for DmMessage {
+    fn timestamp(&self) -> u64 {
+        self.get_timestamp()
+    }
+}
+
+/// Inserts the added messages and deletes the removed ones. Both are keyed by their content, so
+/// applying a change twice does nothing.
+fn write_changes<M: StoredMessage>(
+    table: &mut Table<'_, MessageKey, &'static [u8]>,
+    chat_id: &str,
+    changes: &[MessageChange<M>],
+) -> anyhow::Result<()> {
+    for change in changes {
+        match change {
+            MessageChange::Added(message) => insert_message(table, chat_id, message)?,
+            MessageChange::Removed(message) => {
+                let encoded = postcard::to_stdvec(message)?;
+                table.remove((chat_id, message.timestamp(), fingerprint(&encoded)))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn insert_message<M: StoredMessage>(
+    table: &mut Table<'_, MessageKey, &'static [u8]>,
+    chat_id: &str,
+    message: &M,
+) -> anyhow::Result<()> {
+    let encoded = postcard::to_stdvec(message)?;
+    let key = (chat_id, message.timestamp(), fingerprint(&encoded));
+    table.insert(key, encoded.as_slice())?;
+    Ok(())
+}
+
+/// Reads the messages of a chat, by time.
+fn read_messages<M: StoredMessage>(
+    table: &impl ReadableTable<MessageKey, &'static [u8]>,
+    chat_id: &str,
+) -> anyhow::Result<Vec<M>> {
+    let mut messages = Vec::new();
+    for entry in table.range(chat_range(chat_id))? {
+        let (_, encoded) = entry?;
+        messages.push(postcard::from_bytes(encoded.value())?);
+    }
+    Ok(messages)
+}
+
+/// Every key of a chat.
+fn chat_range(chat_id: &str) -> std::ops::RangeInclusive<(&str, u64, u64)> {
+    (chat_id, 0, 0)..=(chat_id, u64::MAX, u64::MAX)
+}
+
+/// The first bytes of the hash of an encoded message, telling apart those sent at the same time.
+fn fingerprint(encoded: &[u8]) -> u64 {
+    let hash = p2p::Hash::new(encoded);
+    let mut prefix = [0; 8];
+    prefix.copy_from_slice(&hash.as_bytes()[..8]);
+    u64::from_le_bytes(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use ui::desktop::models::{
+        BlobMessage, BlobType, ChatMessage, DmChatMessage, DmMessage, Profile, RetentionPolicy,
+        RetentionSettings,
+    };
+
+    fn open_store(temp_dir: &TempDir) -> MessageStore {
+        MessageStore::open_path(&temp_dir.path().join("test_chats.redb")).unwrap()
+    }
+
+    /// Syncs the state and marks it saved, as [`save_chats`] does with the opened store.
+    fn save(store: &MessageStore, state: &mut AppState) {
+        if store.sync(state).unwrap() {
+            state.mark_chats_saved();
+        }
+    }
+
+    fn create_test_topic(id: &str) -> Topic {
+        let mut topic = Topic::new(id.to_string(), "Test Topic".to_string(), None);
+        for (timestamp, content) in [(1000, "first"), (2000, "second")] {
+            topic.add_message(ChatMessage::new(
+                "sender".to_string(),
+                id.to_string(),
+                content.to_string(),
+                timestamp,
+                false,
+            ));
+        }
+        topic
+    }
+
+    #[test]
+    fn test_sync_and_load_topics() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = open_store(&temp_dir);
+
+        let mut state = AppState::new("me");
+        state.add_topic(&create_test_topic("topic1"));
+        save(&store, &mut state);
+        drop(store);
+
+        let reopened = open_store(&temp_dir);
+        let topics = reopened.load_topics().unwrap();
+        assert_eq!(topics.len(), 1);
+        assert_eq!(topics[0].name, "Test Topic");
+        assert_eq!(topics[0].messages.len(), 2);
+        assert!(topics[0].unsaved().is_empty());
+    }
+
+    #[test]
+    fn test_sync_only_writes_changes() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = open_store(&temp_dir);
+
+        let mut state = AppState::new("me");
+        state.add_topic(&create_test_topic("topic1"));
+        save(&store, &mut state);
+        assert!(state.get_topic("topic1").unwrap().unsaved().is_empty());
+
+        let topic = state.get_topic_mutable("topic1").unwrap();
+        topic.add_message(ChatMessage::new(
+            "sender".to_string(),
+            "topic1".to_string(),
+            "third".to_string(),
+            3000,
+            false,
+        ));
+        assert_eq!(topic.unsaved().len(), 1);
+        topic.remove_messages_before(2000);
+        assert_eq!(topic.unsaved().len(), 2);
+        save(&store, &mut state);
+
+        let topics = store.load_topics().unwrap();
+        let contents: Vec<String> = topics[0]
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                Message::Chat(msg) => Some(msg.content.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(contents, ["second", "third"]);
+    }
+
+    #[test]
+    fn test_sync_skips_unchanged_chats() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = open_store(&temp_dir);
+
+        let mut state = AppState::new("me");
+        state.add_topic(&create_test_topic("topic1"));
+        assert!(store.sync(&state).unwrap());
+        state.mark_chats_saved();
+        assert!(!store.sync(&state).unwrap());
+
+        state.modify_topic_name("topic1", "Renamed");
+        assert!(!store.sync(&state).unwrap());
+        assert_eq!(store.load_topics().unwrap()[0].name, "Renamed");
+    }
+
+    #[test]
+    fn test_sync_removes_deleted_chats() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = open_store(&temp_dir);
+
+        let mut state = AppState::new("me");
+        state.add_topic(&create_test_topic("topic1"));
+        let mut contact = ProfileChat::new(Profile::new_with_id("friend"));
+        contact.add_dm_message(DmChatMessage::new(
+            "friend".to_string(),
+            "me".to_string(),
+            "hello".to_string(),
+            1000,
+            false,
+        ));
+        state.add_contact_chat(contact);
+        save(&store, &mut state);
+        assert_eq!(store.load_contacts().unwrap()[0].messages.len(), 1);
+
+        state.remove_topic("topic1");
+        state.remove_contact("friend");
+        save(&store, &mut state);
+
+        assert!(store.load_topics().unwrap().is_empty());
+        assert!(store.load_contacts().unwrap().is_empty());
+        let txn = store.db.begin_read().unwrap();
+        assert!(txn.open_table(TOPIC_MESSAGES).unwrap().is_empty().unwrap());
+        assert!(txn.open_table(DM_MESSAGES).unwrap().is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_sync_keeps_chats_not_restored_yet() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut state = AppState::new("me");
+        state.add_topic(&create_test_topic("topic1"));
+        state.add_topic(&create_test_topic("topic2"));
+        save(&open_store(&temp_dir), &mut state);
+
+        let store = open_store(&temp_dir);
+        let mut topics = store.load_topics().unwrap();
+        let mut state = AppState::new("me");
+        state.add_topic(&topics.remove(0));
+        save(&store, &mut state);
+
+        assert_eq!(store.load_topics().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_retention_keeps_messages_whose_media_aged_out() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = open_store(&temp_dir);
+
+        let mut topic = create_test_topic("topic1");
+        topic.add_blob_message(BlobMessage::new(
+            "sender".to_string(),
+            "topic1".to_string(),
+            p2p::Hash::new(b"photo").to_string(),
+            "photo.png".to_string(),
+            1024,
+            1500,
+            false,
+            BlobType::Image,
+            None,
+            None,
+        ));
+        let mut state = AppState::new("me");
+        state.add_topic(&topic);
+        state.set_retention_settings(RetentionSettings {
+            global: RetentionPolicy {
+                media_age_days: Some(1),
+                ..RetentionPolicy::default()
+            },
+            ..RetentionSettings::default()
+        });
+        save(&store, &mut state);
+
+        let purge = state.apply_retention(2 * 86_400_000);
+        assert!(purge.topics);
+        assert_eq!(purge.blobs, [p2p::Hash::new(b"photo").to_string()]);
+        save(&store, &mut state);
+
+        let topics = store.load_topics().unwrap();
+        assert_eq!(topics[0].messages.len(), 3);
+        let Message::Blob(blob) = &topics[0].messages[1] else {
+            panic!("expected the blob message to be kept");
+        };
+        assert!(blob.media_removed);
+        assert_eq!(blob.blob_name, "photo.png");
+        // Nothing is left for a later sweep to remove.
+        assert!(state.apply_retention(3 * 86_400_000).is_empty());
+    }
+
+    #[test]
+    fn test_save_and_load_topic_states() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = open_store(&temp_dir);
+
+        let mut identity = [0; 32];
+        identity[0] = 1;
+        let member = p2p::EndpointId::from_bytes(&identity).unwrap();
+        let tag = p2p::Tag::new(p2p::Hlc::from_wall(1_234_567_890), member);
+        let mut state = TopicState::default();
+        state.name.set("Topic One".to_string(), tag);
+        let topic = TopicId::from_bytes([1; 32]);
+        let left = TopicId::from_bytes([2; 32]);
+        store.save_topic_state(&topic, &state).unwrap();
+        store.save_topic_state(&left, &state).unwrap();
+        store.remove_topic_state(&left).unwrap();
+
+        let states = store.load_topic_states().unwrap();
+        assert_eq!(states, HashMap::from([(topic, state)]));
+    }
+
+    #[test]
+    fn test_import_files_saved_by_the_first_release() {
+        let temp_dir = TempDir::new().unwrap();
+        let topics_path = temp_dir.path().join("topics_data.bin");
+        let contacts_path = temp_dir.path().join("contacts.bin");
+        // A topic with a join, a chat and an image message, and a contact with a direct message,
+        // encoded by the structs of the first release.
+        std::fs::write(
+            &topics_path,
+            b"\x01\x06topic1\x09Topic One\x00\x01\x05\x01\x02hi\x03\
+            \x02\x03You\x01\xf4\x03\
+            \x00\x05alice\x06topic1\x02hi\xe8\x07\x01\
+            \x04\x03bob\x06topic1\x03abc\x07cat.png\x80\x10\xd0\x0f\x00\x00\
+            \xd0\x0f\x01\x05alice",
+        )
+        .unwrap();
+        std::fs::write(
+            &contacts_path,
+            b"\x01\x05alice\x05Alice\x00\x01\x07\
+            \x01\x00\x05alice\x02me\x03hey\xb8\x17\x00\
+            \xb8\x17",
+        )
+        .unwrap();
+
+        let topics = utils::topics::load_topics_from_file_with_path(&topics_path).unwrap();
+        let contacts = utils::contacts::load_contacts_from_path(&contacts_path).unwrap();
+        let store = open_store(&temp_dir);
+        assert!(store.import_legacy(&topics, &contacts).unwrap());
+        assert!(!store.import_legacy(&topics, &contacts).unwrap());
+
+        let topics = store.load_topics().unwrap();
+        assert_eq!(topics.len(), 1);
+        assert_eq!(topics[0].name, "Topic One");
+        assert_eq!(topics[0].last_connection, Some(5));
+        assert_eq!(topics[0].last_changed, 2000);
+        assert!(topics[0].members.contains("alice"));
+        assert_eq!(topics[0].messages.len(), 3);
+        let Message::Blob(blob) = &topics[0].messages[2] else {
+            panic!("Expected the image to be the last message");
+        };
+        assert_eq!(blob.blob_name, "cat.png");
+        assert_eq!(blob.blob_size, 2048);
+        assert_eq!(blob.blob_type, BlobType::Image);
+
+        let contacts = store.load_contacts().unwrap();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].profile.name, "Alice");
+        let [DmMessage::Chat(dm)] = contacts[0].messages.as_slice() else {
+            panic!("Expected the direct message");
+        };
+        assert_eq!(dm.content, "hey");
+        assert_eq!(dm.timestamp, 3000);
+    }
+
+    #[test]
+    fn test_sync_keeps_verified_safety_number() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = open_store(&temp_dir);
+
+        let mut state = AppState::new("me");
+        let mut profile = Profile::new_with_id("friend");
+        profile.verified = Some("01234 56789".to_string());
+        state.add_contact_chat(ProfileChat::new(profile));
+        save(&store, &mut state);
+
+        let contacts = store.load_contacts().unwrap();
+        assert_eq!(
+            contacts[0].profile.verified,
+            Some("01234 56789".to_string())
+        );
+    }
+}
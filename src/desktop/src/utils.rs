@@ -1,64 +1,365 @@
-const DIR_NAME: &str = "nexu";
+pub const DIR_NAME: &str = "nexu";
+
+/// Chats and profiles as the first release saved them to files.
+///
+/// Postcard does not describe the fields it writes, so those files only decode with the exact
+/// structs that wrote them. These are frozen copies of them: never change them, convert them to
+/// the current models once decoded instead.
+pub mod legacy {
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashSet;
+    use ui::desktop::models;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Topic {
+        pub id: String,
+        pub name: String,
+        pub avatar_url: Option<String>,
+        pub last_connection: Option<u64>,
+        pub last_message: Option<String>,
+        pub messages: Vec<Message>,
+        pub last_changed: u64,
+        pub members: HashSet<String>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub enum Message {
+        Chat(ChatMessage),
+        Leave(LeaveMessage),
+        Join(JoinMessage),
+        Disconnect(DisconnectMessage),
+        Blob(BlobMessage),
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ChatMessage {
+        pub sender_id: String,
+        pub topic_id: String,
+        pub content: String,
+        pub timestamp: u64,
+        pub is_sent: bool,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct BlobMessage {
+        pub sender_id: String,
+        pub topic_id: String,
+        pub blob_hash: String,
+        pub blob_name: String,
+        pub blob_size: u64,
+        pub timestamp: u64,
+        pub is_sent: bool,
+        pub blob_type: BlobType,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct LeaveMessage {
+        pub sender_id: String,
+        pub timestamp: u64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct JoinMessage {
+        pub sender_id: String,
+        pub me: bool,
+        pub timestamp: u64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct DisconnectMessage {
+        pub sender_id: String,
+        pub timestamp: u64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub enum BlobType {
+        Image,
+        BigImage,
+        File,
+        Audio,
+        Video,
+        Other,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Profile {
+        pub id: String,
+        pub name: String,
+        pub avatar: Option<String>,
+        pub last_connection: ConnectionStatus,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub enum ConnectionStatus {
+        Online,
+        Offline(u64),
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ProfileChat {
+        pub profile: Profile,
+        pub messages: Vec<DmMessage>,
+        pub last_changed: u64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub enum DmMessage {
+        Chat(DmChatMessage),
+        Blob(DmBlobMessage),
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct DmChatMessage {
+        pub sender_id: String,
+        pub receiver_id: String,
+        pub content: String,
+        pub timestamp: u64,
+        pub is_sent: bool,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct DmBlobMessage {
+        pub sender_id: String,
+        pub receiver_id: String,
+        pub blob_hash: String,
+        pub blob_name: String,
+        pub blob_size: u64,
+        pub timestamp: u64,
+        pub is_sent: bool,
+        pub blob_type: BlobType,
+    }
+
+    impl From<Topic> for models::Topic {
+        fn from(legacy: Topic) -> Self {
+            let mut topic = Self::new(legacy.id, legacy.name, legacy.avatar_url);
+            topic.last_connection = legacy.last_connection;
+            topic.last_message = legacy.last_message;
+            topic.messages = legacy.messages.into_iter().map(Into::into).collect();
+            topic.messages.sort();
+            topic.last_changed = legacy.last_changed;
+            topic.members = legacy.members;
+            topic
+        }
+    }
+
+    impl From<Message> for models::Message {
+        fn from(legacy: Message) -> Self {
+            match legacy {
+                Message::Chat(msg) => Self::Chat(models::ChatMessage::new(
+                    msg.sender_id,
+                    msg.topic_id,
+                    msg.content,
+                    msg.timestamp,
+                    msg.is_sent,
+                )),
+                Message::Leave(msg) => Self::Leave(models::LeaveMessage {
+                    sender_id: msg.sender_id,
+                    timestamp: msg.timestamp,
+                }),
+                Message::Join(msg) => Self::Join(models::JoinMessage {
+                    sender_id: msg.sender_id,
+                    me: msg.me,
+                    timestamp: msg.timestamp,
+                }),
+                Message::Disconnect(msg) => Self::Disconnect(models::DisconnectMessage {
+                    sender_id: msg.sender_id,
+                    timestamp: msg.timestamp,
+                }),
+                Message::Blob(msg) => Self::Blob(models::BlobMessage::new(
+                    msg.sender_id,
+                    msg.topic_id,
+                    msg.blob_hash,
+                    msg.blob_name,
+                    msg.blob_size,
+                    msg.timestamp,
+                    msg.is_sent,
+                    msg.blob_type.into(),
+                    None,
+                    None,
+                )),
+            }
+        }
+    }
+
+    impl From<BlobType> for models::BlobType {
+        fn from(legacy: BlobType) -> Self {
+            match legacy {
+                BlobType::Image => Self::Image,
+                BlobType::BigImage => Self::BigImage,
+                BlobType::File => Self::File,
+                BlobType::Audio => Self::Audio,
+                BlobType::Video => Self::Video,
+                BlobType::Other => Self::Other,
+            }
+        }
+    }
+
+    impl From<Profile> for models::Profile {
+        fn from(legacy: Profile) -> Self {
+            Self {
+                id: legacy.id,
+                name: legacy.name,
+                avatar: legacy.avatar,
+                last_connection: match legacy.last_connection {
+                    ConnectionStatus::Online => models::ConnectionStatus::Online,
+                    ConnectionStatus::Offline(at) => models::ConnectionStatus::Offline(at),
+                },
+                verified: None,
+            }
+        }
+    }
+
+    impl From<ProfileChat> for models::ProfileChat {
+        fn from(legacy: ProfileChat) -> Self {
+            let mut contact = Self::new(legacy.profile.into());
+            contact.messages = legacy.messages.into_iter().map(Into::into).collect();
+            contact.last_changed = legacy.last_changed;
+            contact
+        }
+    }
+
+    impl From<DmMessage> for models::DmMessage {
+        fn from(legacy: DmMessage) -> Self {
+            match legacy {
+                DmMessage::Chat(msg) => Self::Chat(models::DmChatMessage::new(
+                    msg.sender_id,
+                    msg.receiver_id,
+                    msg.content,
+                    msg.timestamp,
+                    msg.is_sent,
+                )),
+                DmMessage::Blob(msg) => Self::Blob(models::DmBlobMessage::new(
+                    msg.sender_id,
+                    msg.receiver_id,
+                    msg.blob_hash,
+                    msg.blob_name,
+                    msg.blob_size,
+                    msg.timestamp,
+                    msg.is_sent,
+                    msg.blob_type.into(),
+                    None,
+                    None,
+                )),
+            }
+        }
+    }
+}
 
 pub mod topics {
+    use p2p::{TopicId, TopicState};
+    use std::collections::HashMap;
     use std::path::PathBuf;
     use std::{fs, io};
     use ui::desktop::models::Topic;
 
-    use crate::utils::DIR_NAME;
+    use crate::utils::{DIR_NAME, legacy};
 
     const TOPICS_FILE_PATH: &str = "topics_data.bin";
+    const TOPIC_STATES_FILE_PATH: &str = "topic_states.bin";
 
-    pub fn save_topics_to_file(topics: &Vec<Topic>) -> io::Result<()> {
+    // Topics are kept in the message store now, the file is only read to import them.
+    pub fn load_topics_from_file() -> io::Result<Vec<Topic>> {
         let path = dirs::data_dir()
             .unwrap_or_else(|| PathBuf::from("."))
             .join(DIR_NAME)
             .join(TOPICS_FILE_PATH);
-        fs::create_dir_all(path.parent().unwrap())?;
-        save_topics_to_file_with_path(topics, &path)
+        load_topics_from_file_with_path(&path)
     }
 
-    pub fn save_topics_to_file_with_path(topics: &Vec<Topic>, path: &PathBuf) -> io::Result<()> {
-        fs::create_dir_all(path.parent().unwrap())?;
-        let encoded_topics = postcard::to_stdvec(topics)
+    /// Reads topics in the format older versions saved them, failing with `InvalidData` when
+    /// the file does not decode.
+    pub fn load_topics_from_file_with_path(path: &PathBuf) -> io::Result<Vec<Topic>> {
+        let data = fs::read(path)?;
+        let topics: Vec<legacy::Topic> = postcard::from_bytes(&data)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        fs::write(path, encoded_topics)
+        Ok(topics.into_iter().map(Into::into).collect())
     }
 
-    pub fn load_topics_from_file() -> io::Result<Vec<Topic>> {
+    pub fn remove_topics_file() -> io::Result<()> {
         let path = dirs::data_dir()
             .unwrap_or_else(|| PathBuf::from("."))
             .join(DIR_NAME)
             .join(TOPICS_FILE_PATH);
-        load_topics_from_file_with_path(&path)
+        fs::remove_file(path)
     }
 
-    pub fn load_topics_from_file_with_path(path: &PathBuf) -> io::Result<Vec<Topic>> {
+    // Topic states are kept in the message store now, the file is only read to import them.
+    pub fn load_topic_states() -> io::Result<HashMap<TopicId, TopicState>> {
+        let path = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(DIR_NAME)
+            .join(TOPIC_STATES_FILE_PATH);
+        load_topic_states_with_path(&path)
+    }
+
+    pub fn load_topic_states_with_path(path: &PathBuf) -> io::Result<HashMap<TopicId, TopicState>> {
         let data = fs::read(path)?;
-        let topics: Vec<Topic> = postcard::from_bytes(&data).unwrap_or_default();
-        Ok(topics)
+        postcard::from_bytes(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn remove_topic_states_file() -> io::Result<()> {
+        let path = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(DIR_NAME)
+            .join(TOPIC_STATES_FILE_PATH);
+        fs::remove_file(path)
     }
 
     #[cfg(test)]
     mod tests {
         use super::*;
+        use std::collections::HashSet;
         use tempfile::TempDir;
-        use ui::desktop::models::{ChatMessage, Topic};
 
-        fn create_test_topic(id: &str, name: &str) -> Topic {
-            Topic::new(id.to_string(), name.to_string(), None)
+        /// Writes topics the way older versions saved them.
+        fn save_topics_to_file_with_path(
+            topics: &Vec<legacy::Topic>,
+            path: &PathBuf,
+        ) -> io::Result<()> {
+            let encoded_topics = postcard::to_stdvec(topics)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            fs::write(path, encoded_topics)
         }
 
-        fn create_test_topic_with_message(id: &str, name: &str) -> Topic {
+        /// Writes topic states the way older versions saved them.
+        fn save_topic_states_with_path(
+            states: &HashMap<TopicId, TopicState>,
+            path: &PathBuf,
+        ) -> io::Result<()> {
+            let encoded_states = postcard::to_stdvec(states)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            fs::write(path, encoded_states)
+        }
+
+        fn create_test_topic(id: &str, name: &str) -> legacy::Topic {
+            legacy::Topic {
+                id: id.to_string(),
+                name: name.to_string(),
+                avatar_url: None,
+                last_connection: None,
+                last_message: None,
+                messages: Vec::new(),
+                last_changed: 1_234_567_890,
+                members: HashSet::new(),
+            }
+        }
+
+        fn add_test_message(topic: &mut legacy::Topic, sender_id: &str, content: &str) {
+            topic.last_message = Some(content.to_string());
+            topic
+                .messages
+                .push(legacy::Message::Chat(legacy::ChatMessage {
+                    sender_id: sender_id.to_string(),
+                    topic_id: topic.id.clone(),
+                    content: content.to_string(),
+                    timestamp: 1_234_567_890,
+                    is_sent: true,
+                }));
+        }
+
+        fn create_test_topic_with_message(id: &str, name: &str) -> legacy::Topic {
             let mut topic = create_test_topic(id, name);
-            let message = ChatMessage::new(
-                "sender123".to_string(),
-                id.to_string(),
-                "Hello, World!".to_string(),
-                1_234_567_890,
-                true,
-            );
-            topic.add_message(message);
+            add_test_message(&mut topic, "sender123", "Hello, World!");
             topic
         }
 
@@ -121,15 +422,7 @@ pub mod topics {
             let mut topic = create_test_topic("topic1", "Topic One");
             topic.avatar_url = Some("https://example.com/avatar.png".to_string());
             topic.last_connection = Some(9_876_543_210);
-
-            let message = ChatMessage::new(
-                "sender1".to_string(),
-                "topic1".to_string(),
-                "Test message".to_string(),
-                1_234_567_890,
-                true,
-            );
-            topic.add_message(message);
+            add_test_message(&mut topic, "sender1", "Test message");
 
             let topics = vec![topic];
 
@@ -153,7 +446,7 @@ pub mod topics {
             let temp_dir = TempDir::new().unwrap();
             let test_file_path = temp_dir.path().join("empty_topics.json");
 
-            let topics: Vec<Topic> = Vec::new();
+            let topics: Vec<legacy::Topic> = Vec::new();
 
             let save_result = save_topics_to_file_with_path(&topics, &test_file_path);
             assert!(save_result.is_ok(), "Failed to save empty topics");
@@ -174,18 +467,6 @@ pub mod topics {
             );
         }
 
-        #[test]
-        fn test_save_to_invalid_path() {
-            let invalid_path = PathBuf::from("/nonexistent/directory/test.json");
-            let topics = vec![create_test_topic("topic1", "Topic One")];
-
-            let result = save_topics_to_file_with_path(&topics, &invalid_path);
-            assert!(
-                result.is_err(),
-                "Expected error when saving to invalid path"
-            );
-        }
-
         #[test]
         fn test_load_corrupted_file() {
             let temp_dir = TempDir::new().unwrap();
@@ -193,42 +474,33 @@ pub mod topics {
 
             fs::write(&test_file_path, b"corrupted data").unwrap();
 
-            let loaded_topics = load_topics_from_file_with_path(&test_file_path).unwrap();
+            let result = load_topics_from_file_with_path(&test_file_path);
             assert_eq!(
-                loaded_topics.len(),
-                0,
-                "Expected empty Vec for corrupted data"
+                result.unwrap_err().kind(),
+                io::ErrorKind::InvalidData,
+                "Expected an error for corrupted data"
             );
         }
 
-        #[test]
-        fn test_save_topics_overwrites_existing() {
+        #[tokio::test]
+        async fn test_save_and_load_topic_states_with_path() {
             let temp_dir = TempDir::new().unwrap();
-            let test_file_path = temp_dir.path().join("overwrite_test.json");
-
-            let topics1 = vec![create_test_topic("topic1", "Topic One")];
-            save_topics_to_file_with_path(&topics1, &test_file_path).unwrap();
-
-            let topics2 = vec![
-                create_test_topic("topic2", "Topic Two"),
-                create_test_topic("topic3", "Topic Three"),
-            ];
-            save_topics_to_file_with_path(&topics2, &test_file_path).unwrap();
-
-            let loaded_topics = load_topics_from_file_with_path(&test_file_path).unwrap();
-            assert_eq!(loaded_topics.len(), 2, "Expected 2 topics after overwrite");
-            assert!(
-                !loaded_topics.iter().any(|t| t.id == "topic1"),
-                "topic1 should be gone"
-            );
-            assert!(
-                loaded_topics.iter().any(|t| t.id == "topic2"),
-                "topic2 should exist"
-            );
-            assert!(
-                loaded_topics.iter().any(|t| t.id == "topic3"),
-                "topic3 should exist"
-            );
+            let test_file_path = temp_dir.path().join("topic_states.bin");
+
+            let member = p2p::load_secret_key(temp_dir.path().join("secret.key"))
+                .await
+                .unwrap()
+                .public();
+            let tag = p2p::Tag::new(p2p::Hlc::from_wall(1_234_567_890), member);
+            let mut state = TopicState::default();
+            state.name.set("Topic One".to_string(), tag);
+            state.members.insert(member, tag);
+            let states = HashMap::from([(TopicId::from_bytes([1; 32]), state)]);
+
+            save_topic_states_with_path(&states, &test_file_path).unwrap();
+            let loaded_states = load_topic_states_with_path(&test_file_path).unwrap();
+
+            assert_eq!(loaded_states, states);
         }
     }
 }
@@ -239,12 +511,14 @@ pub mod contacts {
     };
 
     use tokio::io;
-    use ui::desktop::models::{Profile, ProfileChat};
+    use ui::desktop::models::{ContactRequest, Profile, ProfileChat};
 
-    use crate::utils::DIR_NAME;
+    use crate::utils::{DIR_NAME, legacy};
 
     const CONTACTS_NAME_FILE: &str = "contacts.bin";
     const MY_PROFILE_NAME_FILE: &str = "profile.bin";
+    const CONTACT_REQUESTS_NAME_FILE: &str = "contact_requests.bin";
+    const BLOCKED_NAME_FILE: &str = "blocked.bin";
 
     pub fn save_profile(profile: &Profile) -> io::Result<()> {
         let path = dirs::data_dir()
@@ -270,42 +544,100 @@ pub mod contacts {
         load_profile_from_path(&path)
     }
 
+    /// Reads the profile, falling back to the format older versions saved it in.
     pub fn load_profile_from_path(path: &Path) -> io::Result<Profile> {
         let data = fs::read(path)?;
-        let profile: Profile = postcard::from_bytes(&data)
+        if let Ok(profile) = postcard::from_bytes::<Profile>(&data) {
+            return Ok(profile);
+        }
+        let profile: legacy::Profile = postcard::from_bytes(&data)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        Ok(profile)
+        Ok(profile.into())
     }
 
-    pub fn save_contacts(contacts: &[ProfileChat]) -> io::Result<()> {
+    // Contacts are kept in the message store now, the file is only read to import them.
+    pub fn load_contacts() -> io::Result<Vec<ProfileChat>> {
         let path = dirs::data_dir()
             .unwrap_or_else(|| PathBuf::from("."))
             .join(DIR_NAME)
             .join(CONTACTS_NAME_FILE);
-        fs::create_dir_all(path.parent().unwrap())?;
-        save_contacts_to_path(contacts, &path)
+        load_contacts_from_path(&path)
     }
 
-    pub fn save_contacts_to_path(contacts: &[ProfileChat], path: &Path) -> io::Result<()> {
-        fs::create_dir_all(path.parent().unwrap())?;
-        let encoded_contacts = postcard::to_stdvec(contacts)
+    /// Reads contacts in the format older versions saved them, failing with `InvalidData` when
+    /// the file does not decode.
+    pub fn load_contacts_from_path(path: &Path) -> io::Result<Vec<ProfileChat>> {
+        let data = fs::read(path)?;
+        let contacts: Vec<legacy::ProfileChat> = postcard::from_bytes(&data)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        fs::write(path, encoded_contacts)
+        Ok(contacts.into_iter().map(Into::into).collect())
     }
 
-    pub fn load_contacts() -> io::Result<Vec<ProfileChat>> {
+    pub fn remove_contacts_file() -> io::Result<()> {
         let path = dirs::data_dir()
             .unwrap_or_else(|| PathBuf::from("."))
             .join(DIR_NAME)
             .join(CONTACTS_NAME_FILE);
-        load_contacts_from_path(&path)
+        fs::remove_file(path)
     }
 
-    pub fn load_contacts_from_path(path: &Path) -> io::Result<Vec<ProfileChat>> {
+    pub fn save_contact_requests(requests: &[ContactRequest]) -> io::Result<()> {
+        let path = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(DIR_NAME)
+            .join(CONTACT_REQUESTS_NAME_FILE);
+        save_contact_requests_to_path(requests, &path)
+    }
+
+    pub fn save_contact_requests_to_path(
+        requests: &[ContactRequest],
+        path: &Path,
+    ) -> io::Result<()> {
+        fs::create_dir_all(path.parent().unwrap())?;
+        let encoded_requests = postcard::to_stdvec(requests)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, encoded_requests)
+    }
+
+    pub fn load_contact_requests() -> io::Result<Vec<ContactRequest>> {
+        let path = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(DIR_NAME)
+            .join(CONTACT_REQUESTS_NAME_FILE);
+        load_contact_requests_from_path(&path)
+    }
+
+    pub fn load_contact_requests_from_path(path: &Path) -> io::Result<Vec<ContactRequest>> {
         let data = fs::read(path)?;
-        let contacts: Vec<ProfileChat> = postcard::from_bytes(&data)
+        postcard::from_bytes(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save_blocked(blocked: &[String]) -> io::Result<()> {
+        let path = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(DIR_NAME)
+            .join(BLOCKED_NAME_FILE);
+        save_blocked_to_path(blocked, &path)
+    }
+
+    pub fn save_blocked_to_path(blocked: &[String], path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path.parent().unwrap())?;
+        let encoded_blocked = postcard::to_stdvec(blocked)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        Ok(contacts)
+        fs::write(path, encoded_blocked)
+    }
+
+    pub fn load_blocked() -> io::Result<Vec<String>> {
+        let path = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(DIR_NAME)
+            .join(BLOCKED_NAME_FILE);
+        load_blocked_from_path(&path)
+    }
+
+    pub fn load_blocked_from_path(path: &Path) -> io::Result<Vec<String>> {
+        let data = fs::read(path)?;
+        postcard::from_bytes(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
     #[cfg(test)]
@@ -313,7 +645,6 @@ pub mod contacts {
         use super::*;
         use tempfile::TempDir;
         use ui::desktop::models::ConnectionStatus::Offline;
-        use ui::desktop::models::{DmMessage, Profile, ProfileChat};
 
         fn create_test_profile(id: &str, name: &str, avatar: Option<&str>) -> Profile {
             Profile {
@@ -321,6 +652,7 @@ pub mod contacts {
                 name: name.to_string(),
                 avatar: avatar.map(str::to_string),
                 last_connection: Offline(1_234_567_890),
+                verified: None,
             }
         }
 
@@ -328,20 +660,27 @@ pub mod contacts {
             id: &str,
             name: &str,
             avatar: Option<&str>,
-            messages: Vec<DmMessage>,
-        ) -> ProfileChat {
-            ProfileChat {
-                profile: Profile {
+            messages: Vec<legacy::DmMessage>,
+        ) -> legacy::ProfileChat {
+            legacy::ProfileChat {
+                profile: legacy::Profile {
                     id: id.to_string(),
                     name: name.to_string(),
                     avatar: avatar.map(str::to_string),
-                    last_connection: Offline(1_234_567_890),
+                    last_connection: legacy::ConnectionStatus::Offline(1_234_567_890),
                 },
                 messages,
                 last_changed: 1_234_567_890,
             }
         }
 
+        /// Writes contacts the way older versions saved them.
+        fn save_contacts_to_path(contacts: &[legacy::ProfileChat], path: &Path) -> io::Result<()> {
+            let encoded_contacts = postcard::to_stdvec(contacts)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            fs::write(path, encoded_contacts)
+        }
+
         #[test]
         fn test_save_and_load_profile_with_path() {
             let temp_dir = TempDir::new().unwrap();
@@ -443,7 +782,7 @@ pub mod contacts {
             let temp_dir = TempDir::new().unwrap();
             let test_file_path = temp_dir.path().join("empty_contacts.bin");
 
-            let contacts: Vec<ProfileChat> = Vec::new();
+            let contacts: Vec<legacy::ProfileChat> = Vec::new();
 
             let save_result = save_contacts_to_path(&contacts, &test_file_path);
             assert!(save_result.is_ok(), "Failed to save empty contacts");
@@ -488,23 +827,6 @@ pub mod contacts {
             );
         }
 
-        #[test]
-        fn test_save_contacts_to_invalid_path() {
-            let invalid_path = PathBuf::from("/nonexistent/directory/test_contacts.bin");
-            let contacts = vec![create_test_profile_chat(
-                "contact1",
-                "Alice",
-                None,
-                Vec::new(),
-            )];
-
-            let result = save_contacts_to_path(&contacts, &invalid_path);
-            assert!(
-                result.is_err(),
-                "Expected error when saving to invalid path"
-            );
-        }
-
         #[test]
         fn test_load_corrupted_profile() {
             let temp_dir = TempDir::new().unwrap();
@@ -533,6 +855,26 @@ pub mod contacts {
             );
         }
 
+        #[test]
+        fn test_load_profile_saved_by_older_versions() {
+            let temp_dir = TempDir::new().unwrap();
+            let test_file_path = temp_dir.path().join("legacy_profile.bin");
+
+            let profile = legacy::Profile {
+                id: "user123".to_string(),
+                name: "John Doe".to_string(),
+                avatar: None,
+                last_connection: legacy::ConnectionStatus::Offline(1_234_567_890),
+            };
+            fs::write(&test_file_path, postcard::to_stdvec(&profile).unwrap()).unwrap();
+
+            let loaded_profile = load_profile_from_path(&test_file_path).unwrap();
+            assert_eq!(loaded_profile.id, "user123");
+            assert_eq!(loaded_profile.name, "John Doe");
+            assert_eq!(loaded_profile.last_connection, Offline(1_234_567_890));
+            assert_eq!(loaded_profile.verified, None);
+        }
+
         #[test]
         fn test_save_profile_overwrites_existing() {
             let temp_dir = TempDir::new().unwrap();
@@ -556,45 +898,6 @@ pub mod contacts {
             assert_eq!(loaded_profile.name, "Jane Smith");
         }
 
-        #[test]
-        fn test_save_contacts_overwrites_existing() {
-            let temp_dir = TempDir::new().unwrap();
-            let test_file_path = temp_dir.path().join("overwrite_contacts.bin");
-
-            let contacts1 = vec![create_test_profile_chat(
-                "contact1",
-                "Alice",
-                None,
-                Vec::new(),
-            )];
-            save_contacts_to_path(&contacts1, &test_file_path).unwrap();
-
-            let contacts2 = vec![
-                create_test_profile_chat("contact2", "Bob", None, Vec::new()),
-                create_test_profile_chat("contact3", "Charlie", None, Vec::new()),
-            ];
-            save_contacts_to_path(&contacts2, &test_file_path).unwrap();
-
-            let loaded_contacts = load_contacts_from_path(&test_file_path).unwrap();
-            assert_eq!(
-                loaded_contacts.len(),
-                2,
-                "Expected 2 contacts after overwrite"
-            );
-            assert!(
-                !loaded_contacts.iter().any(|c| c.profile.id == "contact1"),
-                "contact1 should be gone"
-            );
-            assert!(
-                loaded_contacts.iter().any(|c| c.profile.id == "contact2"),
-                "contact2 should exist"
-            );
-            assert!(
-                loaded_contacts.iter().any(|c| c.profile.id == "contact3"),
-                "contact3 should exist"
-            );
-        }
-
         #[test]
         fn test_round_trip_preserves_all_profile_fields() {
             let temp_dir = TempDir::new().unwrap();
@@ -605,6 +908,7 @@ pub mod contacts {
                 name: "Test User".to_string(),
                 avatar: Some("https://example.com/test.png".to_string()),
                 last_connection: Offline(1_234_567_890),
+                verified: None,
             };
 
             save_profile_to_path(&profile, &test_file_path).unwrap();
@@ -637,5 +941,568 @@ pub mod contacts {
             assert_eq!(loaded_contacts[1].profile.id, "contact2");
             assert_eq!(loaded_contacts[2].profile.id, "contact3");
         }
+
+        #[test]
+        fn test_save_and_load_contact_requests() {
+            let temp_dir = TempDir::new().unwrap();
+            let test_file_path = temp_dir.path().join("contact_requests.bin");
+
+            let requests = vec![
+                ContactRequest::new("peer1".to_string(), 100),
+                ContactRequest::new("peer2".to_string(), 200),
+            ];
+
+            save_contact_requests_to_path(&requests, &test_file_path).unwrap();
+            let loaded_requests = load_contact_requests_from_path(&test_file_path).unwrap();
+
+            assert_eq!(loaded_requests, requests);
+        }
+
+        #[test]
+        fn test_save_and_load_blocked() {
+            let temp_dir = TempDir::new().unwrap();
+            let test_file_path = temp_dir.path().join("blocked.bin");
+
+            let blocked = vec!["peer1".to_string(), "peer2".to_string()];
+
+            save_blocked_to_path(&blocked, &test_file_path).unwrap();
+            let loaded_blocked = load_blocked_from_path(&test_file_path).unwrap();
+
+            assert_eq!(loaded_blocked, blocked);
+        }
     }
-}
\ No newline at end of file
+}
+pub mod collections {
+    use std::path::{Path, PathBuf};
+
+    /// Returns the deepest directory that contains every path of the batch.
+    pub fn common_directory(paths: &[PathBuf]) -> PathBuf {
+        let mut common = paths
+            .first()
+            .and_then(|path| path.parent())
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        for path in paths.iter().skip(1) {
+            while !path.starts_with(&common) {
+                if !common.pop() {
+                    break;
+                }
+            }
+        }
+
+        common
+    }
+
+    /// Names each file of a batch relative to the directory shared by all of them.
+    ///
+    /// Picking a folder yields every file inside it, so stripping the common prefix keeps the
+    /// folder structure (`sub/file.txt`) without leaking the sender's absolute paths.
+    pub fn relative_entry_names(paths: &[PathBuf]) -> Vec<String> {
+        let common = common_directory(paths);
+
+        paths
+            .iter()
+            .map(|path| {
+                path.strip_prefix(&common)
+                    .unwrap_or_else(|_| Path::new(path.file_name().unwrap_or_default()))
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                    .collect::<Vec<String>>()
+                    .join("/")
+            })
+            .collect()
+    }
+
+    /// Replaces every directory of the selection with the files it contains, recursively.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if one of the directories cannot be read.
+    pub fn expand_directories(paths: &[PathBuf]) -> std::io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut pending: Vec<PathBuf> = paths.iter().rev().cloned().collect();
+
+        while let Some(path) = pending.pop() {
+            if path.is_dir() {
+                let mut children = std::fs::read_dir(&path)?
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<std::io::Result<Vec<PathBuf>>>()?;
+                children.sort();
+                pending.extend(children.into_iter().rev());
+            } else {
+                files.push(path);
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Returns the display name for a batch: the picked folder or the directory shared by all
+    /// the files.
+    pub fn collection_name(paths: &[PathBuf]) -> String {
+        let directory = match paths {
+            [path] if path.is_dir() => path.clone(),
+            _ => common_directory(paths),
+        };
+
+        directory.file_name().map_or_else(
+            || format!("{} files", paths.len()),
+            |name| name.to_string_lossy().into_owned(),
+        )
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use tempfile::TempDir;
+
+        #[test]
+        fn test_relative_entry_names_flat_selection() {
+            let paths = vec![
+                PathBuf::from("/home/user/docs/a.txt"),
+                PathBuf::from("/home/user/docs/b.txt"),
+            ];
+
+            assert_eq!(relative_entry_names(&paths), vec!["a.txt", "b.txt"]);
+            assert_eq!(collection_name(&paths), "docs");
+        }
+
+        #[test]
+        fn test_relative_entry_names_keeps_folder_structure() {
+            let paths = vec![
+                PathBuf::from("/home/user/photos/a.jpg"),
+                PathBuf::from("/home/user/photos/2025/b.jpg"),
+            ];
+
+            assert_eq!(relative_entry_names(&paths), vec!["a.jpg", "2025/b.jpg"]);
+            assert_eq!(collection_name(&paths), "photos");
+        }
+
+        #[test]
+        fn test_expand_directories() {
+            let temp_dir = TempDir::new().unwrap();
+            let root = temp_dir.path().join("photos");
+            std::fs::create_dir_all(root.join("2025")).unwrap();
+            std::fs::write(root.join("a.jpg"), b"a").unwrap();
+            std::fs::write(root.join("2025").join("b.jpg"), b"b").unwrap();
+
+            let selection = vec![root.clone()];
+            let files = expand_directories(&selection).unwrap();
+
+            assert_eq!(
+                files,
+                vec![root.join("2025").join("b.jpg"), root.join("a.jpg")]
+            );
+            assert_eq!(collection_name(&selection), "photos");
+        }
+    }
+}
+
+pub mod settings {
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use ui::desktop::models::{MediaSettings, RetentionSettings};
+
+    use crate::utils::DIR_NAME;
+
+    const MEDIA_SETTINGS_FILE: &str = "media_settings.bin";
+    const RETENTION_SETTINGS_FILE: &str = "retention_settings.bin";
+
+    pub fn save_media_settings(settings: &MediaSettings) -> io::Result<()> {
+        let path = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(DIR_NAME)
+            .join(MEDIA_SETTINGS_FILE);
+        save_media_settings_to_path(settings, &path)
+    }
+
+    pub fn save_media_settings_to_path(settings: &MediaSettings, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path.parent().unwrap())?;
+        let encoded = postcard::to_stdvec(settings)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, encoded)
+    }
+
+    pub fn load_media_settings() -> io::Result<MediaSettings> {
+        let path = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(DIR_NAME)
+            .join(MEDIA_SETTINGS_FILE);
+        load_media_settings_from_path(&path)
+    }
+
+    pub fn load_media_settings_from_path(path: &Path) -> io::Result<MediaSettings> {
+        let data = fs::read(path)?;
+        postcard::from_bytes(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save_retention_settings(settings: &RetentionSettings) -> io::Result<()> {
+        let path = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(DIR_NAME)
+            .join(RETENTION_SETTINGS_FILE);
+        save_retention_settings_to_path(settings, &path)
+    }
+
+    pub fn save_retention_settings_to_path(
+        settings: &RetentionSettings,
+        path: &Path,
+    ) -> io::Result<()> {
+        fs::create_dir_all(path.parent().unwrap())?;
+        let encoded = postcard::to_stdvec(settings)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, encoded)
+    }
+
+    pub fn load_retention_settings() -> io::Result<RetentionSettings> {
+        let path = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(DIR_NAME)
+            .join(RETENTION_SETTINGS_FILE);
+        load_retention_settings_from_path(&path)
+    }
+
+    pub fn load_retention_settings_from_path(path: &Path) -> io::Result<RetentionSettings> {
+        let data = fs::read(path)?;
+        postcard::from_bytes(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use tempfile::TempDir;
+        use ui::desktop::models::{ImageEncoding, RetentionPolicy};
+
+        #[test]
+        fn test_save_and_load_media_settings_with_path() {
+            let temp_dir = TempDir::new().unwrap();
+            let test_file_path = temp_dir.path().join("test_media_settings.bin");
+
+            let settings = MediaSettings {
+                max_dimension: 1024,
+                encoding: ImageEncoding::WebP,
+                quality: 70,
+                send_original: true,
+            };
+
+            save_media_settings_to_path(&settings, &test_file_path).unwrap();
+            let loaded = load_media_settings_from_path(&test_file_path).unwrap();
+
+            assert_eq!(loaded, settings);
+        }
+
+        #[test]
+        fn test_save_and_load_retention_settings_with_path() {
+            let temp_dir = TempDir::new().unwrap();
+            let test_file_path = temp_dir.path().join("test_retention_settings.bin");
+
+            let mut settings = RetentionSettings::default();
+            settings.global.media_age_days = Some(30);
+            settings.chats.insert(
+                "chat1".to_string(),
+                RetentionPolicy {
+                    max_messages: Some(100),
+                    max_age_days: Some(7),
+                    media_age_days: None,
+                },
+            );
+
+            save_retention_settings_to_path(&settings, &test_file_path).unwrap();
+            let loaded = load_retention_settings_from_path(&test_file_path).unwrap();
+
+            assert_eq!(loaded, settings);
+        }
+    }
+}
+
+pub mod media {
+    use image::codecs::jpeg::JpegEncoder;
+    use image::imageops::FilterType;
+    use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+    use std::io::Cursor;
+    use std::path::Path;
+    use ui::desktop::models::{ImageEncoding, MediaSettings};
+
+    /// An image ready to be added to the blob store.
+    pub struct ProcessedImage {
+        pub bytes: Vec<u8>,
+        pub name: String,
+    }
+
+    /// Decodes an image applying its EXIF orientation, so that it still looks right once the
+    /// metadata is gone.
+    pub fn decode_oriented(bytes: &[u8]) -> image::ImageResult<DynamicImage> {
+        let mut decoder = ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()?
+            .into_decoder()?;
+        let orientation = decoder.orientation()?;
+        let mut image = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+        Ok(image)
+    }
+
+    /// Downscales and re-encodes an image following the user's media settings.
+    ///
+    /// Re-encoding only writes the pixels, so EXIF (GPS included), XMP and ICC metadata are
+    /// dropped. Animated GIFs and images sent as originals are returned untouched.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image cannot be decoded or encoded.
+    pub fn process_image(
+        bytes: Vec<u8>,
+        name: &str,
+        settings: &MediaSettings,
+    ) -> image::ImageResult<ProcessedImage> {
+        if settings.send_original || image::guess_format(&bytes).ok() == Some(ImageFormat::Gif) {
+            return Ok(ProcessedImage {
+                bytes,
+                name: name.to_string(),
+            });
+        }
+
+        let mut image = decode_oriented(&bytes)?;
+        let max = settings.max_dimension.max(1);
+        if image.width() > max || image.height() > max {
+            image = image.resize(max, max, FilterType::Lanczos3);
+        }
+
+        let mut buf = Vec::new();
+        match settings.encoding {
+            ImageEncoding::Jpeg => {
+                JpegEncoder::new_with_quality(&mut buf, settings.quality.clamp(1, 100))
+                    .encode_image(&image.to_rgb8())?;
+            }
+            ImageEncoding::WebP => {
+                image
+                    .to_rgba8()
+                    .write_to(&mut Cursor::new(&mut buf), ImageFormat::WebP)?;
+            }
+        }
+
+        Ok(ProcessedImage {
+            bytes: buf,
+            name: Path::new(name)
+                .with_extension(settings.encoding.extension())
+                .to_string_lossy()
+                .into_owned(),
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use image::GenericImageView;
+
+        fn encode_png(width: u32, height: u32) -> Vec<u8> {
+            let mut buf = Vec::new();
+            DynamicImage::new_rgb8(width, height)
+                .write_to(&mut Cursor::new(&mut buf), ImageFormat::Png)
+                .unwrap();
+            buf
+        }
+
+        #[test]
+        fn test_process_image_downscales_and_renames() {
+            let settings = MediaSettings {
+                max_dimension: 100,
+                ..MediaSettings::default()
+            };
+
+            let processed = process_image(encode_png(400, 200), "photo.png", &settings).unwrap();
+
+            let image = image::load_from_memory(&processed.bytes).unwrap();
+            assert_eq!(image.dimensions(), (100, 50));
+            assert_eq!(
+                image::guess_format(&processed.bytes).unwrap(),
+                ImageFormat::Jpeg
+            );
+            assert_eq!(processed.name, "photo.jpg");
+        }
+
+        #[test]
+        fn test_process_image_keeps_small_images_size() {
+            let settings = MediaSettings {
+                encoding: ImageEncoding::WebP,
+                ..MediaSettings::default()
+            };
+
+            let processed = process_image(encode_png(64, 32), "icon.png", &settings).unwrap();
+
+            let image = image::load_from_memory(&processed.bytes).unwrap();
+            assert_eq!(image.dimensions(), (64, 32));
+            assert_eq!(processed.name, "icon.webp");
+        }
+
+        #[test]
+        fn test_process_image_send_original() {
+            let settings = MediaSettings {
+                send_original: true,
+                ..MediaSettings::default()
+            };
+            let original = encode_png(400, 200);
+
+            let processed = process_image(original.clone(), "photo.png", &settings).unwrap();
+
+            assert_eq!(processed.bytes, original);
+            assert_eq!(processed.name, "photo.png");
+        }
+    }
+}
+
+pub mod previews {
+    use base64::Engine;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{DynamicImage, GenericImageView};
+    use p2p::BlobPreview;
+    use std::path::Path;
+    use std::process::Command;
+
+    use crate::utils::media::decode_oriented;
+
+    /// Longest side of the embedded thumbnail. It has to stay tiny because the preview travels
+    /// inside the gossip message.
+    pub const PREVIEW_MAX_SIDE: u32 = 32;
+    const PREVIEW_JPEG_QUALITY: u8 = 60;
+
+    pub fn image_preview(bytes: &[u8]) -> Option<BlobPreview> {
+        let image = decode_oriented(bytes).ok()?;
+        preview_from_image(&image)
+    }
+
+    pub fn image_preview_from_path(path: &Path) -> Option<BlobPreview> {
+        let bytes = std::fs::read(path).ok()?;
+        image_preview(&bytes)
+    }
+
+    /// Uses the first frame of the video as poster. Requires `ffmpeg` to be installed, otherwise
+    /// the video is sent without a preview.
+    pub fn video_preview(path: &Path) -> Option<BlobPreview> {
+        let output = Command::new("ffmpeg")
+            .args(["-v", "error", "-i"])
+            .arg(path)
+            .args(["-frames:v", "1", "-f", "image2pipe", "-vcodec", "png", "-"])
+            .output()
+            .ok()?;
+
+        if !output.status.success() {
+            return None;
+        }
+
+        image_preview(&output.stdout)
+    }
+
+    pub fn preview_from_image(image: &DynamicImage) -> Option<BlobPreview> {
+        let (width, height) = image.dimensions();
+        let thumbnail = image
+            .thumbnail(PREVIEW_MAX_SIDE, PREVIEW_MAX_SIDE)
+            .to_rgb8();
+
+        let mut buf = Vec::new();
+        JpegEncoder::new_with_quality(&mut buf, PREVIEW_JPEG_QUALITY)
+            .encode_image(&thumbnail)
+            .ok()?;
+
+        Some(BlobPreview::new(width, height, buf))
+    }
+
+    pub fn to_ui_preview(preview: BlobPreview) -> ui::desktop::models::BlobPreview {
+        let b64 = base64::engine::general_purpose::STANDARD.encode(&preview.thumbnail);
+        ui::desktop::models::BlobPreview::new(
+            preview.width,
+            preview.height,
+            format!("data:image/jpeg;base64,{b64}"),
+        )
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_preview_keeps_original_dimensions() {
+            let image = DynamicImage::new_rgb8(640, 480);
+
+            let preview = preview_from_image(&image).unwrap();
+
+            assert_eq!(preview.width, 640);
+            assert_eq!(preview.height, 480);
+
+            let thumbnail = image::load_from_memory(&preview.thumbnail).unwrap();
+            assert_eq!(thumbnail.dimensions(), (PREVIEW_MAX_SIDE, 24));
+        }
+
+        #[test]
+        fn test_to_ui_preview_builds_data_url() {
+            let preview = BlobPreview::new(10, 20, vec![1, 2, 3]);
+
+            let ui_preview = to_ui_preview(preview);
+
+            assert_eq!(ui_preview.thumbnail_url, "data:image/jpeg;base64,AQID");
+            assert_eq!(ui_preview.aspect_ratio(), "10 / 20");
+        }
+    }
+}
+
+pub mod avatars {
+    use base64::Engine;
+    use p2p::Hash;
+
+    /// Largest avatar accepted, avatars are fetched by every contact and topic member.
+    pub const MAX_AVATAR_SIZE: usize = 512 * 1024 * 4 / 3; // 512 KB
+
+    /// Decodes the bytes of a base64 `data:` URL, the form a freshly picked avatar takes in the
+    /// UI before it is stored as a blob.
+    pub fn decode_data_url(url: &str) -> Option<Vec<u8>> {
+        let (_, data) = url.strip_prefix("data:")?.split_once(',')?;
+        base64::engine::general_purpose::STANDARD.decode(data).ok()
+    }
+
+    /// Parses the blob hash an avatar is stored as. Avatars saved before they were stored as
+    /// blobs are still `data:` URLs and have no hash.
+    pub fn avatar_hash(avatar: Option<&str>) -> Option<Hash> {
+        avatar.and_then(|avatar| avatar.parse().ok())
+    }
+
+    /// The MIME type of an avatar, guessed from its contents since avatars have no file name.
+    pub fn mime_type(bytes: &[u8]) -> &'static str {
+        image::guess_format(bytes)
+            .map_or("application/octet-stream", |format| format.to_mime_type())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_decode_data_url() {
+            assert_eq!(
+                decode_data_url("data:image/png;base64,AQID"),
+                Some(vec![1, 2, 3])
+            );
+            assert_eq!(decode_data_url("AQID"), None);
+            assert_eq!(decode_data_url("data:image/png;base64,%%%"), None);
+        }
+
+        #[test]
+        fn test_avatar_hash_ignores_data_urls() {
+            let hash = Hash::new(b"avatar");
+
+            assert_eq!(avatar_hash(Some(&hash.to_string())), Some(hash));
+            assert_eq!(avatar_hash(Some("data:image/png;base64,AQID")), None);
+            assert_eq!(avatar_hash(None), None);
+        }
+
+        #[test]
+        fn test_mime_type_is_sniffed() {
+            let png = [
+                0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', 0, 0, 0, 0,
+            ];
+
+            assert_eq!(mime_type(&png), "image/png");
+            assert_eq!(mime_type(b"not an image"), "application/octet-stream");
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! # Blob Access Control
+//!
+//! By default `iroh-blobs` serves every blob in the store to anyone who knows its hash. This module
+//! keeps track of who each blob was shared with and plugs into the provider events of the blobs
+//! protocol, so that only the DM partner or the current members of a topic can fetch it. Blocked
+//! peers cannot fetch anything.
+//!
+//! Gossip messages are not signed, so the members they declare can be forged. The members of a
+//! topic are therefore only the peers this endpoint is actually connected to in its swarm: the
+//! bootstrap peers it joined through, then its gossip neighbors as they come and go, including
+//! whoever delivers a message.
+
+use crate::block::BlockList;
+use iroh::EndpointId;
+use iroh_blobs::Hash;
+use iroh_blobs::provider::events::{
+    AbortReason, ConnectMode, EventMask, EventSender, ProviderMessage, RequestMode,
+};
+use iroh_gossip::proto::TopicId;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+/// Who a blob has been shared with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlobScope {
+    /// The blob was sent in a DM to this peer.
+    Peer(EndpointId),
+    /// The blob was posted to this topic, any current member may fetch it.
+    Topic(TopicId),
+}
+
+#[derive(Debug, Default)]
+struct AccessState {
+    grants: HashMap<Hash, HashSet<BlobScope>>,
+    topic_members: HashMap<TopicId, HashSet<EndpointId>>,
+}
+
+/// Shared access list consulted by the blobs protocol for every incoming request.
+///
+/// Cloning it is cheap, every clone points to the same list.
+#[derive(Clone, Debug, Default)]
+pub struct BlobAccess {
+    state: Arc<RwLock<AccessState>>,
+    blocked: BlockList,
+}
+
+impl BlobAccess {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an access list that denies every request from the peers in `blocked`.
+    #[must_use]
+    pub fn with_block_list(blocked: BlockList) -> Self {
+        Self {
+            state: Arc::default(),
+            blocked,
+        }
+    }
+
+    /// Allows the peers in `scope` to fetch the blob with the given hash.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn grant(&self, hash: Hash, scope: BlobScope) {
+        let mut state = self.state.write().expect("blob access lock poisoned");
+        state.grants.entry(hash).or_default().insert(scope);
+    }
+
+    /// Stops serving the blob with the given hash to anyone, used once it is deleted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn revoke(&self, hash: &Hash) {
+        let mut state = self.state.write().expect("blob access lock poisoned");
+        state.grants.remove(hash);
+    }
+
+    /// Marks `peer` as a current member of `topic`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn add_topic_member(&self, topic: TopicId, peer: EndpointId) {
+        let mut state = self.state.write().expect("blob access lock poisoned");
+        state.topic_members.entry(topic).or_default().insert(peer);
+    }
+
+    /// Removes `peer` from the members of `topic`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn remove_topic_member(&self, topic: TopicId, peer: &EndpointId) {
+        let mut state = self.state.write().expect("blob access lock poisoned");
+        if let Some(members) = state.topic_members.get_mut(&topic) {
+            members.remove(peer);
+        }
+    }
+
+    /// Forgets every member of `topic`, used when leaving it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn remove_topic(&self, topic: &TopicId) {
+        let mut state = self.state.write().expect("blob access lock poisoned");
+        state.topic_members.remove(topic);
+    }
+
+    /// Checks whether `peer` may fetch the blob with the given hash.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    #[must_use]
+    pub fn is_allowed(&self, peer: &EndpointId, hash: &Hash) -> bool {
+        if self.blocked.is_blocked(peer) {
+            return false;
+        }
+        let state = self.state.read().expect("blob access lock poisoned");
+        state.grants.get(hash).is_some_and(|scopes| {
+            scopes.iter().any(|scope| match scope {
+                BlobScope::Peer(allowed) => allowed == peer,
+                BlobScope::Topic(topic) => state
+                    .topic_members
+                    .get(topic)
+                    .is_some_and(|members| members.contains(peer)),
+            })
+        })
+    }
+
+    /// Creates the event sender for `BlobsProtocol` that enforces this access list.
+    ///
+    /// Every connection and get request is intercepted. Requests for blobs that were not shared
+    /// with the remote endpoint, and connections whose endpoint is unknown, are rejected and logged.
+    #[must_use]
+    pub fn event_sender(&self) -> EventSender {
+        let mask = EventMask {
+            connected: ConnectMode::Intercept,
+            get: RequestMode::Intercept,
+            get_many: RequestMode::Intercept,
+            ..EventMask::DEFAULT
+        };
+        let (tx, mut rx) = EventSender::channel(32, mask);
+        let access = self.clone();
+
+        tokio::spawn(async move {
+            let mut connections: HashMap<u64, EndpointId> = HashMap::new();
+
+            while let Some(msg) = rx.recv().await {
+                match msg {
+                    ProviderMessage::ClientConnected(msg) => {
+                        let res = if let Some(endpoint_id) = msg.endpoint_id {
+                            connections.insert(msg.connection_id, endpoint_id);
+                            Ok(())
+                        } else {
+                            eprintln!("Rejected blob connection from an unknown endpoint");
+                            Err(AbortReason::Permission)
+                        };
+                        msg.tx.send(res).await.ok();
+                    }
+                    ProviderMessage::ConnectionClosed(msg) => {
+                        connections.remove(&msg.connection_id);
+                    }
+                    ProviderMessage::GetRequestReceived(msg) => {
+                        let hash = msg.request.hash;
+                        let res = match connections.get(&msg.connection_id) {
+                            Some(peer) if access.is_allowed(peer, &hash) => Ok(()),
+                            Some(peer) => {
+                                eprintln!("Rejected request for blob {hash} from {peer}");
+                                Err(AbortReason::Permission)
+                            }
+                            None => {
+                                eprintln!(
+                                    "Rejected request for blob {hash} from an unknown endpoint"
+                                );
+                                Err(AbortReason::Permission)
+                            }
+                        };
+                        msg.tx.send(res).await.ok();
+                    }
+                    ProviderMessage::GetManyRequestReceived(msg) => {
+                        eprintln!(
+                            "Rejected get many request on connection {}",
+                            msg.connection_id
+                        );
+                        msg.tx.send(Err(AbortReason::Permission)).await.ok();
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        tx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::random_endpoint;
+
+    #[test]
+    fn test_dm_blob_only_allowed_for_partner() {
+        let access = BlobAccess::new();
+        let partner = random_endpoint();
+        let stranger = random_endpoint();
+        let hash = Hash::new(b"dm attachment");
+
+        access.grant(hash, BlobScope::Peer(partner));
+
+        assert!(access.is_allowed(&partner, &hash));
+        assert!(!access.is_allowed(&stranger, &hash));
+        assert!(!access.is_allowed(&partner, &Hash::new(b"other blob")));
+    }
+
+    #[test]
+    fn test_topic_blob_follows_current_members() {
+        let access = BlobAccess::new();
+        let member = random_endpoint();
+        let topic = TopicId::from_bytes([7; 32]);
+        let hash = Hash::new(b"topic media");
+
+        access.grant(hash, BlobScope::Topic(topic));
+        assert!(!access.is_allowed(&member, &hash));
+
+        access.add_topic_member(topic, member);
+        assert!(access.is_allowed(&member, &hash));
+
+        access.remove_topic_member(topic, &member);
+        assert!(!access.is_allowed(&member, &hash));
+    }
+
+    #[test]
+    fn test_blocked_peer_denied() {
+        let blocked = BlockList::new();
+        let access = BlobAccess::with_block_list(blocked.clone());
+        let partner = random_endpoint();
+        let hash = Hash::new(b"dm attachment");
+
+        access.grant(hash, BlobScope::Peer(partner));
+        blocked.block(partner);
+        assert!(!access.is_allowed(&partner, &hash));
+
+        blocked.unblock(&partner);
+        assert!(access.is_allowed(&partner, &hash));
+    }
+}
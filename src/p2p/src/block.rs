@@ -0,0 +1,96 @@
+//! # Block List
+//!
+//! Peers the user never wants to hear from again. A blocked peer cannot open a DM connection, its
+//! gossip messages are dropped before reaching the application and it cannot fetch any blob, even
+//! the ones shared with it before it was blocked.
+
+use iroh::EndpointId;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+/// Shared list of blocked peers, consulted by the DM protocol, the gossip listeners and the blobs
+/// protocol.
+///
+/// Cloning it is cheap, every clone points to the same list.
+#[derive(Clone, Debug, Default)]
+pub struct BlockList {
+    peers: Arc<RwLock<HashSet<EndpointId>>>,
+}
+
+impl BlockList {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Blocks `peer`, returning whether it was not blocked yet.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn block(&self, peer: EndpointId) -> bool {
+        self.peers
+            .write()
+            .expect("block list lock poisoned")
+            .insert(peer)
+    }
+
+    /// Unblocks `peer`, returning whether it was blocked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn unblock(&self, peer: &EndpointId) -> bool {
+        self.peers
+            .write()
+            .expect("block list lock poisoned")
+            .remove(peer)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    #[must_use]
+    pub fn is_blocked(&self, peer: &EndpointId) -> bool {
+        self.peers
+            .read()
+            .expect("block list lock poisoned")
+            .contains(peer)
+    }
+
+    /// Every blocked peer, in no particular order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    #[must_use]
+    pub fn peers(&self) -> Vec<EndpointId> {
+        self.peers
+            .read()
+            .expect("block list lock poisoned")
+            .iter()
+            .copied()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::random_endpoint;
+
+    #[test]
+    fn test_block_and_unblock() {
+        let block_list = BlockList::new();
+        let peer = random_endpoint();
+
+        assert!(!block_list.is_blocked(&peer));
+        assert!(block_list.block(peer));
+        assert!(!block_list.block(peer));
+        assert!(block_list.clone().is_blocked(&peer));
+        assert_eq!(block_list.peers(), vec![peer]);
+
+        assert!(block_list.unblock(&peer));
+        assert!(!block_list.is_blocked(&peer));
+    }
+}
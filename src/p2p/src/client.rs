@@ -4,37 +4,59 @@
 //! handles gossip subscriptions for group chats, establishes direct connections for DMs,
 //! and manages blob storage (files/images).
 
-use crate::messages::{DmMessageTypes, GossipMessage, MessageTypes};
-use crate::protocol::{DM_ALPN, DMProtocol, write_frame};
-use crate::types::Ticket;
-use crate::utils::load_secret_key;
-use flume::Receiver;
-use futures_lite::StreamExt;
+use crate::access::{BlobAccess, BlobScope};
+use crate::block::BlockList;
+use crate::crypto::{BlobKey, decrypt_stream, encrypt_blob, encrypt_stream};
+use crate::error::{Error, Result};
+use crate::events::{ChatEvent, EVENT_CHANNEL_CAPACITY, TransferProgress};
+use crate::hlc::{Hlc, HybridClock};
+use crate::messages::{CollectionEntry, DmMessageTypes, GossipMessage, MessageTypes};
+use crate::presence::{PRESENCE_ALPN, Presence, PresenceProtocol, ping};
+use crate::protocol::{DM_ALPN, DMProtocol, DmLimits, write_frame};
+use crate::spam::{GossipLimits, SpamFilter, Verdict, sender_of};
+use crate::types::{CollectionAddProgress, Ticket};
+use crate::utils::{collection_entry_path, load_secret_key};
+use flume::{Receiver, Sender};
+use futures_lite::{Stream, StreamExt};
 use iroh::discovery::dns::DnsDiscovery;
 use iroh::discovery::pkarr::PkarrPublisher;
 use iroh::endpoint::SendStream;
 use iroh::protocol::Router;
 use iroh::{Endpoint, EndpointAddr, EndpointId, RelayMode};
-use iroh_blobs::api::blobs::{AddProgress, BlobStatus};
-use iroh_blobs::api::downloader::{DownloadProgress, Downloader};
+use iroh_blobs::api::blobs::{AddProgress, AddProgressItem, BlobStatus};
+use iroh_blobs::api::downloader::{DownloadProgress, DownloadProgressItem, Downloader};
+use iroh_blobs::format::collection::Collection;
 use iroh_blobs::store::fs::FsStore;
 use iroh_blobs::ticket::BlobTicket;
-use iroh_blobs::{BlobsProtocol, Hash};
+use iroh_blobs::{BlobsProtocol, Hash, HashAndFormat};
 use iroh_gossip::api::{Event, GossipReceiver, GossipSender};
 use iroh_gossip::{ALPN, net::Gossip, proto::TopicId};
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use tokio::time::sleep;
 
+/// The maximum size in bytes of a serialized gossip message, larger ones are not broadcast.
+pub const MAX_MESSAGE_SIZE: usize = 1_048_576;
+
+/// An open DM stream, locked on its own so DMs to different peers are written in parallel.
+type DmStream = Arc<tokio::sync::Mutex<SendStream>>;
+
 /// The main client struct for the P2P chat application.
 ///
 /// It wraps an `iroh::Endpoint` and manages:
 /// - Gossip sub-protocols for group chats (`iroh_gossip`).
 /// - Direct Messaging (DM) protocol (`DMProtocol`).
 /// - Blob storage and transfer (`iroh_blobs`).
+///
+/// The client is a handle: cloning it is cheap and every clone shares the same endpoint, topics
+/// and connections. Every method takes `&self` and no internal lock is held across an `.await`,
+/// so sends, downloads and subscriptions from different tasks run in parallel.
+#[derive(Clone)]
 pub struct ChatClient {
     /// The local iroh endpoint.
     endpoint: Endpoint,
@@ -42,23 +64,39 @@ pub struct ChatClient {
     gossip: Gossip,
     /// The router that dispatches incoming connections to protocols.
     /// Kept alive to ensure protocols remain active.
-    _router: Router,
+    _router: Arc<Router>,
     /// Map of active gossip senders (one per topic).
-    gossip_sender: HashMap<TopicId, GossipSender>,
+    gossip_sender: Arc<RwLock<HashMap<TopicId, Arc<GossipSender>>>>,
     /// Map of active gossip receivers (one per topic), stored here until `listen` is called.
-    gossip_receiver: HashMap<TopicId, GossipReceiver>,
+    gossip_receiver: Arc<Mutex<HashMap<TopicId, GossipReceiver>>>,
     /// Map of active DM sending streams (one per peer).
-    dm_sender: HashMap<EndpointId, SendStream>,
+    dm_sender: Arc<RwLock<HashMap<EndpointId, DmStream>>>,
     /// Background tasks handling incoming gossip messages.
-    listen_tasks: HashMap<TopicId, tokio::task::JoinHandle<()>>,
-    /// Receiver for incoming direct messages.
-    dm_incoming: Receiver<(EndpointId, DmMessageTypes)>,
+    listen_tasks: Arc<Mutex<HashMap<TopicId, tokio::task::JoinHandle<()>>>>,
+    /// Peers pinged by the heartbeat, with their last reported state.
+    presence: Presence,
+    /// Background task pinging the watched peers.
+    heartbeat_task: Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    /// Sender and receiver of the events reported to the application.
+    events: (Sender<ChatEvent>, Receiver<ChatEvent>),
+    /// Filters floods and oversized messages out of the gossip topics.
+    spam_filter: SpamFilter,
     /// The file system store for blobs.
     store: FsStore,
     /// Path to the temporary directory for exported blobs.
     temp_store_path: PathBuf,
     /// Helper for downloading blobs.
     downloader: Downloader,
+    /// Decides which peers may fetch each blob from this node.
+    blob_access: BlobAccess,
+    /// Peers silenced across DMs, topics and blob serving.
+    block_list: BlockList,
+    /// Limits of the DM protocol, the frame size also caps the DMs sent.
+    dm_limits: DmLimits,
+    /// Keys of the encrypted blobs, used to decrypt them when they are exported.
+    blob_keys: Arc<RwLock<HashMap<Hash, BlobKey>>>,
+    /// Orders the messages sent and received, advanced by every message received.
+    clock: HybridClock,
 }
 
 impl ChatClient {
@@ -80,7 +118,16 @@ impl ChatClient {
     /// * The secret key cannot be loaded or generated.
     /// * The endpoint cannot be bound.
     /// * The blob store cannot be initialized.
-    pub async fn new(path_buf: PathBuf) -> anyhow::Result<Self> {
+    pub async fn new(path_buf: PathBuf) -> Result<Self> {
+        Self::new_with_dm_limits(path_buf, DmLimits::default()).await
+    }
+
+    /// Creates a new `ChatClient` instance enforcing custom limits on incoming DM connections.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the same cases as [`ChatClient::new`].
+    pub async fn new_with_dm_limits(path_buf: PathBuf, dm_limits: DmLimits) -> Result<Self> {
         let secret = load_secret_key(path_buf.join("key")).await?;
 
         // Initialize Endpoint with DNS and Pkarr discovery for finding peers.
@@ -89,56 +136,79 @@ impl ChatClient {
             .discovery(PkarrPublisher::n0_dns())
             .discovery(DnsDiscovery::n0_dns())
             .bind()
-            .await?;
+            .await
+            .map_err(|e| Error::Endpoint(e.to_string()))?;
 
         // Initialize Gossip protocol.
         let gossip = Gossip::builder()
-            .max_message_size(1_048_576) // 1MB max message size
+            .max_message_size(MAX_MESSAGE_SIZE)
             .spawn(endpoint.clone());
 
-        // Initialize DM protocol channels.
-        let (dm_tx, dm_rx) = flume::unbounded();
-        let dm_protocol = DMProtocol { tx: dm_tx };
+        // Initialize the event channel shared by every topic and the DM protocol.
+        let (events_tx, events_rx) = flume::bounded(EVENT_CHANNEL_CAPACITY);
+        let block_list = BlockList::new();
+        let dm_protocol = DMProtocol::new(
+            events_tx.clone(),
+            dm_limits,
+            block_list.clone(),
+            endpoint.id(),
+        );
 
         // Initialize Blob store (File System based).
-        let store = FsStore::load(path_buf.join("store")).await?;
+        let store = FsStore::load(path_buf.join("store"))
+            .await
+            .map_err(|e| Error::BlobStore(e.to_string()))?;
         let temp_store_path = path_buf.join("temp");
 
-        let blobs = BlobsProtocol::new(&store, None);
+        // Only serve blobs to the peers they were shared with.
+        let blob_access = BlobAccess::with_block_list(block_list.clone());
+        let blobs = BlobsProtocol::new(&store, Some(blob_access.event_sender()));
+
+        // Only answer presence pings from the watched peers.
+        let presence = Presence::default();
 
         // Bind protocols to the router.
         let router = Router::builder(endpoint.clone())
             .accept(ALPN, gossip.clone())
             .accept(DM_ALPN, dm_protocol.clone())
             .accept(iroh_blobs::ALPN, blobs)
+            .accept(
+                PRESENCE_ALPN,
+                PresenceProtocol::new(presence.clone(), block_list.clone()),
+            )
             .spawn();
 
         Ok(Self {
             endpoint: endpoint.clone(),
             gossip,
-            _router: router,
-            gossip_sender: HashMap::new(),
-            gossip_receiver: HashMap::new(),
-            dm_sender: HashMap::new(),
-            listen_tasks: HashMap::new(),
-            dm_incoming: dm_rx,
+            _router: Arc::new(router),
+            gossip_sender: Arc::default(),
+            gossip_receiver: Arc::default(),
+            dm_sender: Arc::default(),
+            listen_tasks: Arc::default(),
+            presence,
+            heartbeat_task: Arc::default(),
+            events: (events_tx, events_rx),
             store: store.clone(),
             temp_store_path,
             downloader: store.downloader(&endpoint),
+            blob_access,
+            block_list,
+            dm_limits,
+            blob_keys: Arc::default(),
+            clock: HybridClock::new(),
+            spam_filter: SpamFilter::new(GossipLimits::default()),
         })
     }
 
     /// Starts a background task to listen for messages on a specific gossip topic.
     ///
+    /// Messages and swarm changes of the topic are reported through [`ChatClient::events`].
+    ///
     /// # Arguments
     ///
     /// * `topic_id` - The ID of the topic to listen to.
     ///
-    /// # Returns
-    ///
-    /// * `Result<Receiver<MessageTypes>>` - A channel receiver for incoming messages.
-    ///   Returns an error if the client is not subscribed to the topic.
-    ///
     /// # Errors
     ///
     /// This function will return an error if the client has not previously subscribed
@@ -146,51 +216,130 @@ impl ChatClient {
     ///
     /// # Panics
     ///
-    /// This function will panic if it fails to send the message to the receiver(it should not fail)
-    pub fn listen(&mut self, topic_id: &TopicId) -> anyhow::Result<Receiver<MessageTypes>> {
+    /// Panics if the lock is poisoned.
+    pub fn listen(&self, topic_id: &TopicId) -> Result<()> {
         let mut receiver = self
             .gossip_receiver
+            .lock()
+            .expect("gossip receiver lock poisoned")
             .remove(topic_id)
-            .ok_or_else(|| anyhow::anyhow!("No gossip receiver for topic"))?;
+            .ok_or(Error::NotSubscribed(*topic_id))?;
 
-        let (tx, rx) = flume::unbounded::<MessageTypes>();
+        let events = self.events.0.clone();
+        let blob_access = self.blob_access.clone();
+        let spam_filter = self.spam_filter.clone();
+        let block_list = self.block_list.clone();
+        let clock = self.clock.clone();
+        let topic = *topic_id;
 
         let handle = tokio::spawn(async move {
             loop {
                 let event_option = receiver.next().await;
                 match event_option {
                     Some(Ok(Event::Received(msg))) => {
-                        if let Ok(message) = postcard::from_bytes::<MessageTypes>(&msg.content) {
-                            tx.send(message).expect("Failed to send message");
+                        let Ok(mut message) = postcard::from_bytes::<MessageTypes>(&msg.content)
+                        else {
+                            continue;
+                        };
+                        if block_list.is_blocked(&msg.delivered_from)
+                            || sender_of(&message)
+                                .is_some_and(|sender| block_list.is_blocked(&sender))
+                        {
+                            continue;
+                        }
+                        match spam_filter.check(topic, &message, msg.delivered_from, Instant::now())
+                        {
+                            Verdict::Accept => {
+                                blob_access.add_topic_member(topic, msg.delivered_from);
+                                observe_message(&clock, &mut message);
+                                let event = ChatEvent::Message { topic, message };
+                                if events.send_async(event).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Verdict::Drop => {}
+                            Verdict::Mute(event) => {
+                                let _ = events.try_send(ChatEvent::GossipAbuse(event));
+                            }
+                        }
+                    }
+                    // Swarm changes wait for space like messages, dropping one would leave a
+                    // stale neighbor or a missed resync.
+                    Some(Ok(Event::NeighborUp(peer))) => {
+                        blob_access.add_topic_member(topic, peer);
+                        let event = ChatEvent::NeighborUp { topic, peer };
+                        if events.send_async(event).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(Event::NeighborDown(peer))) => {
+                        blob_access.remove_topic_member(topic, &peer);
+                        let event = ChatEvent::NeighborDown { topic, peer };
+                        if events.send_async(event).await.is_err() {
+                            break;
                         }
                     }
-                    // Ignore connectivity events for now
-                    Some(
-                        Ok(Event::NeighborUp(_) | Event::NeighborDown(_) | Event::Lagged) | Err(_),
-                    ) => {}
+                    Some(Ok(Event::Lagged)) => {
+                        if events
+                            .send_async(ChatEvent::Lagged { topic })
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Some(Err(_)) => {}
                     None => break,
                 }
             }
         });
 
-        self.listen_tasks.insert(*topic_id, handle);
+        if let Some(previous) = self
+            .listen_tasks
+            .lock()
+            .expect("listen tasks lock poisoned")
+            .insert(*topic_id, handle)
+        {
+            previous.abort();
+        }
 
-        Ok(rx)
+        Ok(())
     }
 
     /// Internal helper to subscribe to a gossip topic with a set of bootstrap peers.
+    ///
+    /// The `admin` of the topic is the only peer whose slow mode settings are followed.
     async fn subscribe(
-        &mut self,
+        &self,
         topic_id: TopicId,
         bootstrap: Vec<EndpointAddr>,
-    ) -> anyhow::Result<()> {
+        admin: Option<EndpointId>,
+    ) -> Result<()> {
         sleep(Duration::from_millis(100)).await;
         let endpoint_ids: Vec<EndpointId> = bootstrap.iter().map(|addr| addr.id).collect();
+        if let Some(admin) = admin {
+            self.spam_filter.set_admin(topic_id, admin);
+        }
 
-        let (sender, receiver) = self.gossip.subscribe(topic_id, endpoint_ids).await?.split();
+        for endpoint_id in &endpoint_ids {
+            self.blob_access.add_topic_member(topic_id, *endpoint_id);
+        }
 
-        self.gossip_sender.insert(topic_id, sender);
-        self.gossip_receiver.insert(topic_id, receiver);
+        let (sender, receiver) = self
+            .gossip
+            .subscribe(topic_id, endpoint_ids)
+            .await
+            .map_err(|e| Error::Subscription(e.to_string()))?
+            .split();
+
+        self.gossip_sender
+            .write()
+            .expect("gossip sender lock poisoned")
+            .insert(topic_id, Arc::new(sender));
+        self.gossip_receiver
+            .lock()
+            .expect("gossip receiver lock poisoned")
+            .insert(topic_id, receiver);
 
         Ok(())
     }
@@ -207,51 +356,395 @@ impl ChatClient {
     ///
     /// This function will return an error if:
     /// * The client is not subscribed to the topic associated with the message.
+    /// * Slow mode is enabled in the topic and the last post was too recent.
     /// * The message serialization fails.
     /// * The broadcast operation fails.
-    pub async fn send(&mut self, message: MessageTypes) -> anyhow::Result<()> {
-        let topic_id = match &message {
-            MessageTypes::Chat(msg) => msg.topic_id(),
-            MessageTypes::TopicMetadata(msg) => msg.topic_id(),
-            MessageTypes::JoinTopic(msg) => msg.topic_id(),
-            MessageTypes::LeaveTopic(msg) => msg.topic_id(),
-            MessageTypes::DisconnectTopic(msg) => msg.topic_id(),
-            MessageTypes::TopicMessages(msg) => msg.topic_id(),
-            MessageTypes::Blob(msg) => msg.topic_id(),
-        };
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub async fn send(&self, message: MessageTypes) -> Result<()> {
+        let topic_id = message_topic_id(&message);
+
+        if matches!(
+            message,
+            MessageTypes::Chat(_) | MessageTypes::Blob(_) | MessageTypes::Collection(_)
+        ) && let Err(wait) =
+            self.spam_filter
+                .allow_own_post(topic_id, self.endpoint.id(), Instant::now())
+        {
+            return Err(Error::SlowMode {
+                topic: topic_id,
+                wait,
+            });
+        }
 
         let sender = self
             .gossip_sender
-            .get_mut(topic_id)
-            .ok_or_else(|| anyhow::anyhow!("Not subscribed to topic"))?;
+            .read()
+            .expect("gossip sender lock poisoned")
+            .get(&topic_id)
+            .cloned()
+            .ok_or(Error::NotSubscribed(topic_id))?;
 
         let serialized = postcard::to_stdvec(&message)?;
-        sender.broadcast(serialized.into()).await?;
+        sender
+            .broadcast(serialized.into())
+            .await
+            .map_err(|e| Error::Subscription(e.to_string()))?;
+        self.spam_filter.track_sent(topic_id, &message);
+        Ok(())
+    }
+
+    /// Returns a new timestamp of the client clock, for a message about to be sent.
+    ///
+    /// Set it on the message with `with_hlc` so it is ordered after every message this client
+    /// has sent or received.
+    #[must_use]
+    pub fn hlc_now(&self) -> Hlc {
+        self.clock.now()
+    }
+
+    /// Sends a message only to the direct neighbors of the topic swarm, without it being
+    /// forwarded further.
+    ///
+    /// Useful for requests that any nearby member can answer, such as a history sync after
+    /// [`ChatEvent::Lagged`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// * The client is not subscribed to the topic associated with the message.
+    /// * The message serialization fails.
+    /// * The broadcast operation fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub async fn send_to_neighbors(&self, message: MessageTypes) -> Result<()> {
+        let topic_id = message_topic_id(&message);
+        let sender = self
+            .gossip_sender
+            .read()
+            .expect("gossip sender lock poisoned")
+            .get(&topic_id)
+            .cloned()
+            .ok_or(Error::NotSubscribed(topic_id))?;
+
+        let serialized = postcard::to_stdvec(&message)?;
+        sender
+            .broadcast_neighbors(serialized.into())
+            .await
+            .map_err(|e| Error::Subscription(e.to_string()))?;
         Ok(())
     }
 
     /// Saves a raw byte slice as a blob in the local store.
-    pub fn save_blob(&mut self, data: &[u8]) -> AddProgress<'_> {
+    pub fn save_blob(&self, data: &[u8]) -> AddProgress<'_> {
         self.store.blobs().add_slice(data)
     }
 
+    /// Encrypts a byte slice with a new random key and saves the ciphertext as a blob.
+    ///
+    /// The key is remembered so the blob is decrypted when exported, and returned so it can be
+    /// sent along with the blob message.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the data cannot be encrypted.
+    pub fn save_encrypted_blob(&self, data: &[u8]) -> Result<(BlobKey, AddProgress<'_>)> {
+        let key = BlobKey::generate();
+        let encrypted = encrypt_blob(&key, data)?;
+        self.register_blob_key(Hash::new(&encrypted), key);
+        Ok((key, self.store.blobs().add_bytes(encrypted)))
+    }
+
+    /// Encrypts a file with a new random key and saves the ciphertext as a blob, one chunk at a
+    /// time so the file never has to fit in memory.
+    ///
+    /// The key is remembered once the import is done, so the blob is decrypted when exported,
+    /// and returned so it can be sent along with the blob message.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be opened.
+    pub async fn save_encrypted_file(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<(
+        BlobKey,
+        impl Stream<Item = AddProgressItem> + Send + 'static,
+    )> {
+        let key = BlobKey::generate();
+        let file = tokio::fs::File::open(path).await?;
+        let progress = self
+            .store
+            .blobs()
+            .add_stream(encrypt_stream(key, file))
+            .await;
+        let blob_keys = self.blob_keys.clone();
+        let stream = progress.stream().await.inspect(move |item| {
+            if let AddProgressItem::Done(temp_tag) = item {
+                blob_keys
+                    .write()
+                    .expect("blob keys lock poisoned")
+                    .insert(temp_tag.hash(), key);
+            }
+        });
+        Ok((key, stream))
+    }
+
+    /// Remembers the key of an encrypted blob, so it is decrypted when exported.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn register_blob_key(&self, hash: Hash, key: BlobKey) {
+        self.blob_keys
+            .write()
+            .expect("blob keys lock poisoned")
+            .insert(hash, key);
+    }
+
     /// Imports a file from a given path into the local blob store.
-    pub fn save_blob_from_path<P: AsRef<Path>>(&mut self, path: P) -> AddProgress<'_> {
+    pub fn save_blob_from_path<P: AsRef<Path>>(&self, path: P) -> AddProgress<'_> {
         self.store.blobs().add_path(path)
     }
 
+    /// Imports a batch of files into the local blob store as a single `HashSeq` collection.
+    ///
+    /// Each file is encrypted with its own key, carried by its [`CollectionEntry`], and added as
+    /// a raw blob, and the collection is stored on top of them. Import
+    /// runs in the background; the returned channel reports progress for the whole batch and
+    /// ends with either [`CollectionAddProgress::Done`] or [`CollectionAddProgress::Error`].
+    ///
+    /// # Arguments
+    ///
+    /// * `files` - The entries to import as `(name, path)` pairs. Names may contain `/` to
+    ///   preserve a folder structure.
+    #[must_use]
+    pub fn save_collection(
+        &self,
+        files: Vec<(String, PathBuf)>,
+    ) -> Receiver<CollectionAddProgress> {
+        let (tx, rx) = flume::unbounded();
+        let store = self.store.clone();
+        let blob_keys = self.blob_keys.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = import_collection(&store, &blob_keys, files, &tx).await {
+                let _ = tx.send(CollectionAddProgress::Error(e.to_string()));
+            }
+        });
+
+        rx
+    }
+
+    /// Initiates a download of a whole collection (the `HashSeq` and every entry) from a remote peer.
+    ///
+    /// The returned future drives the download and resolves once it finishes; progress is
+    /// reported through [`ChatClient::events`]. It does not borrow the client.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The root hash of the collection.
+    /// * `provider` - The peer that shared the collection.
+    pub fn download_collection(
+        &self,
+        hash: Hash,
+        provider: EndpointId,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let progress = self
+            .downloader
+            .download(HashAndFormat::hash_seq(hash), Some(provider));
+        track_download(hash, progress, self.events.0.clone())
+    }
+
+    /// Checks if a collection and every one of its entries are complete in the local store.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the collection cannot be read or checking the
+    /// status of a blob fails.
+    pub async fn has_collection(&self, hash: impl Into<Hash>) -> Result<bool> {
+        let hash: Hash = hash.into();
+        if !self.has_blob(hash).await? {
+            return Ok(false);
+        }
+
+        let collection = Collection::load(hash, &self.store)
+            .await
+            .map_err(|e| Error::BlobStore(e.to_string()))?;
+        for (_, child) in collection.iter() {
+            if !self.has_blob(*child).await? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Exports every entry of a locally complete collection into `target_dir`.
+    ///
+    /// Entry names are resolved relative to `target_dir`; names that would escape it are skipped.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<PathBuf>>` - The paths of the exported files.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the collection cannot be loaded, the directory
+    /// cannot be created or an entry fails to export.
+    pub async fn export_collection(
+        &self,
+        hash: impl Into<Hash>,
+        target_dir: impl AsRef<Path>,
+    ) -> Result<Vec<PathBuf>> {
+        let target_dir = target_dir.as_ref();
+        let collection = Collection::load(hash.into(), &self.store)
+            .await
+            .map_err(|e| Error::BlobStore(e.to_string()))?;
+        tokio::fs::create_dir_all(target_dir).await?;
+
+        let mut exported = Vec::new();
+        for (name, child) in collection.iter() {
+            let Some(relative) = collection_entry_path(name) else {
+                eprintln!("Skipping collection entry with unsafe name: {name}");
+                continue;
+            };
+            let path = target_dir.join(relative);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            self.export_decrypted(*child, &path).await?;
+            exported.push(path);
+        }
+
+        Ok(exported)
+    }
+
+    /// Exports a single blob from the internal store to the given path.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the blob cannot be exported from the store
+    /// (e.g. I/O errors, missing blob).
+    pub async fn export_blob(&self, hash: impl Into<Hash>, target: impl AsRef<Path>) -> Result<()> {
+        let target = target.as_ref();
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        self.export_decrypted(hash.into(), target).await
+    }
+
+    /// Exports a blob to `target`, decrypting it chunk by chunk if its key is known.
+    ///
+    /// A blob that fails to decrypt leaves no file behind.
+    async fn export_decrypted(&self, hash: Hash, target: &Path) -> Result<()> {
+        let key = self
+            .blob_keys
+            .read()
+            .expect("blob keys lock poisoned")
+            .get(&hash)
+            .copied();
+        if let Some(key) = key {
+            let reader = self.store.blobs().reader(hash);
+            let mut file = tokio::fs::File::create(target).await?;
+            if let Err(e) = decrypt_stream(&key, reader, &mut file).await {
+                drop(file);
+                let _ = tokio::fs::remove_file(target).await;
+                return Err(e);
+            }
+        } else {
+            self.store
+                .blobs()
+                .export(hash, target.to_path_buf())
+                .await
+                .map_err(|e| Error::BlobStore(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Allows the peers in `scope` to fetch a blob (or a collection root) from this node.
+    ///
+    /// Blobs that were never granted are not served to anyone.
+    pub fn grant_blob_access(&self, hash: Hash, scope: BlobScope) {
+        self.blob_access.grant(hash, scope);
+    }
+
+    /// Deletes blobs from the store, along with their exported copies, keys and access grants.
+    ///
+    /// Used to purge the attachments of messages that disappeared. Collections only delete their
+    /// root, their entries have to be passed as well.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the store fails to delete the blobs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub async fn delete_blobs(&self, hashes: &[Hash]) -> Result<()> {
+        for hash in hashes {
+            self.blob_access.revoke(hash);
+            self.blob_keys
+                .write()
+                .expect("blob keys lock poisoned")
+                .remove(hash);
+        }
+
+        // Blobs are protected from deletion while a tag points to them.
+        let mut tags = self
+            .store
+            .tags()
+            .list()
+            .await
+            .map_err(|e| Error::BlobStore(e.to_string()))?;
+        let mut protecting = Vec::new();
+        while let Some(tag) = tags.next().await {
+            let tag = tag.map_err(|e| Error::BlobStore(e.to_string()))?;
+            if hashes.contains(&tag.hash) {
+                protecting.push(tag.name);
+            }
+        }
+        for name in protecting {
+            self.store
+                .tags()
+                .delete(name)
+                .await
+                .map_err(|e| Error::BlobStore(e.to_string()))?;
+        }
+        self.store
+            .blobs()
+            .delete(hashes.iter().copied())
+            .await
+            .map_err(|e| Error::BlobStore(e.to_string()))?;
+
+        remove_exports(&self.temp_store_path, hashes).await;
+        Ok(())
+    }
+
     /// Initiates a download for a blob from a remote peer.
     ///
+    /// The returned future drives the download and resolves once it finishes; progress is
+    /// reported through [`ChatClient::events`]. It does not borrow the client.
+    ///
     /// # Arguments
     ///
     /// * `blob_ticket` - The ticket containing the hash and peer address.
-    pub fn download_blob(&mut self, blob_ticket: &BlobTicket) -> DownloadProgress {
-        let downloader = self.downloader.clone();
-        downloader.download(blob_ticket.hash(), Some(blob_ticket.addr().id))
+    pub fn download_blob(
+        &self,
+        blob_ticket: &BlobTicket,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let hash = blob_ticket.hash();
+        let progress = self.downloader.download(hash, Some(blob_ticket.addr().id));
+        track_download(hash, progress, self.events.0.clone())
     }
 
     /// Exports a blob from the internal store to a temporary file path.
     ///
+    /// Encrypted blobs whose key is known are decrypted on the way out.
+    ///
     /// # Arguments
     ///
     /// * `hash` - The hash of the blob to export.
@@ -269,12 +762,13 @@ impl ChatClient {
         &self,
         hash: impl Into<Hash>,
         extension: impl AsRef<OsStr>,
-    ) -> anyhow::Result<PathBuf> {
+    ) -> Result<PathBuf> {
         let hash: Hash = hash.into();
         let mut path = self.temp_store_path.join(hash.to_string());
         path.add_extension(extension);
-        self.store.blobs().export(hash, path.clone()).await?;
-        Ok(path.clone())
+        tokio::fs::create_dir_all(&self.temp_store_path).await?;
+        self.export_decrypted(hash, &path).await?;
+        Ok(path)
     }
 
     /// Checks if a blob exists and is complete in the local store.
@@ -282,8 +776,13 @@ impl ChatClient {
     /// # Errors
     ///
     /// This function will return an error if checking the status of the blob fails.
-    pub async fn has_blob(&self, hash: impl Into<Hash>) -> anyhow::Result<bool> {
-        if let BlobStatus::Complete { .. } = self.store().status(hash).await? {
+    pub async fn has_blob(&self, hash: impl Into<Hash>) -> Result<bool> {
+        let status = self
+            .store()
+            .status(hash)
+            .await
+            .map_err(|e| Error::BlobStore(e.to_string()))?;
+        if let BlobStatus::Complete { .. } = status {
             Ok(true)
         } else {
             Ok(false)
@@ -323,10 +822,11 @@ impl ChatClient {
     /// # Errors
     ///
     /// This function will return an error if subscription to the new topic fails.
-    pub async fn create_topic(&mut self) -> anyhow::Result<Ticket> {
+    pub async fn create_topic(&self) -> Result<Ticket> {
         let topic_id = TopicId::from_bytes(rand::random());
 
-        self.subscribe(topic_id, vec![]).await?;
+        self.subscribe(topic_id, vec![], Some(self.endpoint.id()))
+            .await?;
 
         let ticket = Ticket {
             topic: topic_id,
@@ -349,11 +849,11 @@ impl ChatClient {
     /// # Errors
     ///
     /// This function will return an error if subscription to the topic fails.
-    pub async fn join_topic(&mut self, ticket: Ticket) -> anyhow::Result<TopicId> {
+    pub async fn join_topic(&self, ticket: Ticket) -> Result<TopicId> {
         let topic_id = ticket.topic;
-        let endpoints = ticket.endpoints;
+        let admin = ticket.creator();
 
-        self.subscribe(topic_id, endpoints).await?;
+        self.subscribe(topic_id, ticket.endpoints, admin).await?;
 
         Ok(topic_id)
     }
@@ -365,8 +865,8 @@ impl ChatClient {
     /// This function will return an error if:
     /// * The ticket string cannot be parsed.
     /// * Subscription to the topic fails.
-    pub async fn join_topic_from_string(&mut self, ticket_str: &str) -> anyhow::Result<TopicId> {
-        let ticket = FromStr::from_str(ticket_str)?;
+    pub async fn join_topic_from_string(&self, ticket_str: &str) -> Result<TopicId> {
+        let ticket = Ticket::from_str(ticket_str)?;
         self.join_topic(ticket).await
     }
 
@@ -375,12 +875,29 @@ impl ChatClient {
     /// # Errors
     ///
     /// This function currently always returns `Ok`, but returns `Result` for future compatibility.
-    pub fn leave_topic(&mut self, topic_id: &TopicId) -> anyhow::Result<()> {
-        self.gossip_sender.remove(topic_id);
-        self.gossip_receiver.remove(topic_id);
-        if let Some(handle) = self.listen_tasks.remove(topic_id) {
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn leave_topic(&self, topic_id: &TopicId) -> Result<()> {
+        self.gossip_sender
+            .write()
+            .expect("gossip sender lock poisoned")
+            .remove(topic_id);
+        self.gossip_receiver
+            .lock()
+            .expect("gossip receiver lock poisoned")
+            .remove(topic_id);
+        let task = self
+            .listen_tasks
+            .lock()
+            .expect("listen tasks lock poisoned")
+            .remove(topic_id);
+        if let Some(handle) = task {
             handle.abort();
         }
+        self.blob_access.remove_topic(topic_id);
+        self.spam_filter.remove_topic(topic_id);
         Ok(())
     }
 
@@ -393,18 +910,50 @@ impl ChatClient {
     /// # Errors
     ///
     /// This function will return an error if the connection attempt fails.
-    pub async fn connect_peer(&mut self, addr: impl Into<EndpointAddr>) -> anyhow::Result<()> {
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub async fn connect_peer(&self, addr: impl Into<EndpointAddr>) -> Result<()> {
         let addr: EndpointAddr = addr.into();
 
-        if self.dm_sender.contains_key(&addr.id) {
+        if self
+            .dm_sender
+            .read()
+            .expect("dm sender lock poisoned")
+            .contains_key(&addr.id)
+        {
             return Ok(());
         }
 
-        let conn = self.endpoint.connect(addr, DM_ALPN).await?;
+        let conn = self
+            .endpoint
+            .connect(addr, DM_ALPN)
+            .await
+            .map_err(|e| Error::Connection(e.to_string()))?;
 
-        let (send, _recv) = conn.open_bi().await?;
+        let (send, _recv) = conn
+            .open_bi()
+            .await
+            .map_err(|e| Error::Connection(e.to_string()))?;
 
-        self.dm_sender.insert(conn.remote_id(), send);
+        let peer = conn.remote_id();
+        // Another task may have connected in the meantime, keep the stream it opened.
+        let inserted = match self
+            .dm_sender
+            .write()
+            .expect("dm sender lock poisoned")
+            .entry(peer)
+        {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(Arc::new(tokio::sync::Mutex::new(send)));
+                true
+            }
+        };
+        if inserted {
+            let _ = self.events.0.try_send(ChatEvent::PeerConnected { peer });
+        }
 
         Ok(())
     }
@@ -423,29 +972,169 @@ impl ChatClient {
     /// This function will return an error if:
     /// * There is no established DM channel (sender) for the address.
     /// * Serialization of the message fails.
-    /// * Writing the frame to the stream fails.
+    /// * Writing the frame to the stream fails. The broken channel is dropped, so the next
+    ///   `connect_peer` opens a new one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
     pub async fn send_dm(
-        &mut self,
+        &self,
         addr: impl Into<EndpointAddr>,
         message: DmMessageTypes,
-    ) -> anyhow::Result<()> {
+    ) -> Result<()> {
         let addr: EndpointAddr = addr.into();
-        let send = self
+        let stream = self
             .dm_sender
-            .get_mut(&addr.id)
-            .ok_or_else(|| anyhow::anyhow!("No DM sender for address"))?;
+            .read()
+            .expect("dm sender lock poisoned")
+            .get(&addr.id)
+            .cloned()
+            .ok_or(Error::NotConnected(addr.id))?;
 
         let serialized = postcard::to_stdvec(&message)?;
 
-        write_frame(send, &serialized).await?;
+        let max_frame_size = self.dm_limits.max_frame_size;
+        let result = write_frame(&mut *stream.lock().await, &serialized, max_frame_size).await;
+        if let Err(e) = result {
+            if matches!(e, Error::Connection(_)) {
+                let mut dm_sender = self.dm_sender.write().expect("dm sender lock poisoned");
+                // Only drop the stream that broke, not one opened by a reconnection meanwhile.
+                if dm_sender
+                    .get(&addr.id)
+                    .is_some_and(|current| Arc::ptr_eq(current, &stream))
+                {
+                    dm_sender.remove(&addr.id);
+                    drop(dm_sender);
+                    let _ = self
+                        .events
+                        .0
+                        .try_send(ChatEvent::PeerDisconnected { peer: addr.id });
+                }
+            }
+            return Err(e);
+        }
 
         Ok(())
     }
 
-    /// Returns a receiver channel for incoming Direct Messages.
+    /// Blocks a peer: its DM connections are refused, its gossip messages dropped and it cannot
+    /// fetch any blob from this node. The DM stream to it, if any, is closed.
+    ///
+    /// The block list is not persisted, the application restores it on startup.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn block_peer(&self, peer: EndpointId) {
+        self.block_list.block(peer);
+        self.dm_sender
+            .write()
+            .expect("dm sender lock poisoned")
+            .remove(&peer);
+    }
+
+    /// Unblocks a peer blocked with [`ChatClient::block_peer`].
+    pub fn unblock_peer(&self, peer: &EndpointId) {
+        self.block_list.unblock(peer);
+    }
+
     #[must_use]
-    pub fn incoming_dms(&self) -> Receiver<(EndpointId, DmMessageTypes)> {
-        self.dm_incoming.clone()
+    pub fn is_blocked(&self, peer: &EndpointId) -> bool {
+        self.block_list.is_blocked(peer)
+    }
+
+    /// Every blocked peer, in no particular order.
+    #[must_use]
+    pub fn blocked_peers(&self) -> Vec<EndpointId> {
+        self.block_list.peers()
+    }
+
+    /// Adds a peer to the ones pinged by the heartbeat, see [`ChatClient::start_heartbeat`].
+    pub fn watch_presence(&self, peer: EndpointId) {
+        self.presence.watch(peer);
+    }
+
+    /// Stops pinging a peer, no more [`ChatEvent::Presence`] events are reported for it.
+    pub fn unwatch_presence(&self, peer: &EndpointId) {
+        self.presence.unwatch(peer);
+    }
+
+    /// Starts pinging the watched peers every `interval`, replacing any previous heartbeat.
+    ///
+    /// A peer is online when it answers the ping in time. The first result for each peer and
+    /// every change afterwards are reported as [`ChatEvent::Presence`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn start_heartbeat(&self, interval: Duration) {
+        let endpoint = self.endpoint.clone();
+        let presence = self.presence.clone();
+        let events = self.events.0.clone();
+
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+
+                let mut pings = tokio::task::JoinSet::new();
+                for peer in presence.peers() {
+                    let endpoint = endpoint.clone();
+                    pings.spawn(async move { (peer, ping(&endpoint, peer, interval).await) });
+                }
+                while let Some(result) = pings.join_next().await {
+                    let Ok((peer, online)) = result else {
+                        continue;
+                    };
+                    // Only changes are reported, so none may be dropped.
+                    if presence.update(peer, online)
+                        && events
+                            .send_async(ChatEvent::Presence { peer, online })
+                            .await
+                            .is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+        });
+
+        if let Some(previous) = self
+            .heartbeat_task
+            .lock()
+            .expect("heartbeat task lock poisoned")
+            .replace(handle)
+        {
+            previous.abort();
+        }
+    }
+
+    /// Stops the heartbeat started by [`ChatClient::start_heartbeat`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn stop_heartbeat(&self) {
+        if let Some(handle) = self
+            .heartbeat_task
+            .lock()
+            .expect("heartbeat task lock poisoned")
+            .take()
+        {
+            handle.abort();
+        }
+    }
+
+    /// Returns the receiver of every [`ChatEvent`] reported by the client: topic messages, DMs,
+    /// swarm and connection changes, presence, download progress and abuse.
+    ///
+    /// Use `recv_async` or `into_stream` to wait on it. Clones share the same queue, so each
+    /// event is delivered to a single one of them.
+    #[must_use]
+    pub fn events(&self) -> Receiver<ChatEvent> {
+        self.events.1.clone()
     }
 
     /// Returns a reference to the underlying `FsStore` for direct blob operations.
@@ -455,13 +1144,207 @@ impl ChatClient {
     }
 }
 
+/// Drives a download to completion, reporting its progress on `events`.
+async fn track_download(
+    hash: Hash,
+    progress: DownloadProgress,
+    events: Sender<ChatEvent>,
+) -> Result<()> {
+    let report = |progress| {
+        let _ = events.try_send(ChatEvent::Transfer { hash, progress });
+    };
+    let fail = |error: String| {
+        report(TransferProgress::Failed(error.clone()));
+        Error::Download(error)
+    };
+
+    let mut stream = progress.stream().await.map_err(|e| fail(e.to_string()))?;
+    while let Some(item) = stream.next().await {
+        match item {
+            DownloadProgressItem::Progress(bytes) => report(TransferProgress::Progress(bytes)),
+            DownloadProgressItem::Error(e) => return Err(fail(e.to_string())),
+            DownloadProgressItem::DownloadError => {
+                return Err(fail("Download error occurred".to_string()));
+            }
+            DownloadProgressItem::TryProvider { .. }
+            | DownloadProgressItem::ProviderFailed { .. }
+            | DownloadProgressItem::PartComplete { .. } => {}
+        }
+    }
+
+    report(TransferProgress::Done);
+    Ok(())
+}
+
+/// Encrypts and imports every file of a batch and stores the resulting collection, reporting
+/// progress on `tx`.
+async fn import_collection(
+    store: &FsStore,
+    blob_keys: &RwLock<HashMap<Hash, BlobKey>>,
+    files: Vec<(String, PathBuf)>,
+    tx: &Sender<CollectionAddProgress>,
+) -> Result<()> {
+    let mut total = 0;
+    for (_, path) in &files {
+        total += tokio::fs::metadata(path).await?.len();
+    }
+    let _ = tx.send(CollectionAddProgress::Size(total));
+
+    let mut imported = 0;
+    let mut entries = Vec::with_capacity(files.len());
+    for (name, path) in files {
+        let key = BlobKey::generate();
+        let file = tokio::fs::File::open(&path).await?;
+        // Progress is reported in plaintext bytes, the ciphertext only adds the chunk tags.
+        let size = file.metadata().await?.len();
+        let mut stream = store
+            .blobs()
+            .add_stream(encrypt_stream(key, file))
+            .await
+            .stream()
+            .await;
+        let mut hash = None;
+        while let Some(item) = stream.next().await {
+            match item {
+                AddProgressItem::CopyProgress(_)
+                | AddProgressItem::CopyDone
+                | AddProgressItem::Size(_) => {}
+                AddProgressItem::OutboardProgress(offset) => {
+                    let _ = tx.send(CollectionAddProgress::Progress(imported + offset.min(size)));
+                }
+                AddProgressItem::Done(temp_tag) => {
+                    hash = Some(temp_tag.hash());
+                    break;
+                }
+                AddProgressItem::Error(error) => {
+                    return Err(Error::BlobStore(format!(
+                        "Failed to import {name}: {error}"
+                    )));
+                }
+            }
+        }
+
+        let hash = hash
+            .ok_or_else(|| Error::BlobStore(format!("Import of {name} finished without a hash")))?;
+        blob_keys
+            .write()
+            .expect("blob keys lock poisoned")
+            .insert(hash, key);
+        imported += size;
+        let _ = tx.send(CollectionAddProgress::Progress(imported));
+        entries.push(CollectionEntry::new(name, size, hash).with_key(key));
+    }
+
+    let collection: Collection = entries
+        .iter()
+        .map(|entry| (entry.name.clone(), entry.hash))
+        .collect();
+    let temp_tag = collection
+        .store(store)
+        .await
+        .map_err(|e| Error::BlobStore(e.to_string()))?;
+
+    let _ = tx.send(CollectionAddProgress::Done {
+        hash: temp_tag.hash(),
+        entries,
+    });
+
+    Ok(())
+}
+
+/// Removes the files exported from the given blobs, named after their hash, see
+/// [`ChatClient::get_blob_path`].
+async fn remove_exports(temp_store_path: &Path, hashes: &[Hash]) {
+    let Ok(mut entries) = tokio::fs::read_dir(temp_store_path).await else {
+        return;
+    };
+    let prefixes: Vec<String> = hashes.iter().map(|hash| format!("{hash}.")).collect();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if prefixes.iter().any(|prefix| name.starts_with(prefix.as_str()))
+            && let Err(e) = tokio::fs::remove_file(entry.path()).await
+        {
+            eprintln!("Failed to remove the export {name}: {e}");
+        }
+    }
+}
+
+/// Clamps the timestamps carried by a received message, then advances the clock past them.
+fn observe_message(clock: &HybridClock, message: &mut MessageTypes) {
+    let hlc = match message {
+        MessageTypes::Chat(msg) => {
+            msg.hlc = msg.hlc.clamped();
+            Some(msg.hlc)
+        }
+        MessageTypes::Blob(msg) => {
+            msg.hlc = msg.hlc.clamped();
+            Some(msg.hlc)
+        }
+        MessageTypes::Collection(msg) => {
+            msg.hlc = msg.hlc.clamped();
+            Some(msg.hlc)
+        }
+        MessageTypes::TopicMessages(msg) => {
+            for chat in &mut msg.messages {
+                chat.hlc = chat.hlc.clamped();
+            }
+            msg.messages.iter().map(|chat| chat.hlc).max()
+        }
+        MessageTypes::TopicMetadata(msg) => {
+            msg.state.clamp();
+            None
+        }
+        MessageTypes::JoinTopic(_)
+        | MessageTypes::LeaveTopic(_)
+        | MessageTypes::DisconnectTopic(_)
+        | MessageTypes::Typing(_)
+        | MessageTypes::ProfileMetadata(_) => None,
+    };
+    if let Some(hlc) = hlc {
+        clock.observe(hlc);
+    }
+}
+
+fn message_topic_id(message: &MessageTypes) -> TopicId {
+    let topic_id = match message {
+        MessageTypes::Chat(msg) => msg.topic_id(),
+        MessageTypes::TopicMetadata(msg) => msg.topic_id(),
+        MessageTypes::JoinTopic(msg) => msg.topic_id(),
+        MessageTypes::LeaveTopic(msg) => msg.topic_id(),
+        MessageTypes::DisconnectTopic(msg) => msg.topic_id(),
+        MessageTypes::TopicMessages(msg) => msg.topic_id(),
+        MessageTypes::Blob(msg) => msg.topic_id(),
+        MessageTypes::Collection(msg) => msg.topic_id(),
+        MessageTypes::Typing(msg) => msg.topic_id(),
+        MessageTypes::ProfileMetadata(msg) => msg.topic_id(),
+    };
+    *topic_id
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ChatMessage;
+    use crate::crypto::decrypt_blob;
+    use crate::protocol::DmAbuseEvent;
+    use crate::{ChatMessage, TopicMessagesMessage};
     use serial_test::serial;
     use tokio::time::{Duration, sleep};
 
+    /// Waits for the next direct message, skipping every other event.
+    async fn next_dm(events: &Receiver<ChatEvent>) -> (EndpointId, DmMessageTypes) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let event = events.recv_async().await.expect("Failed to receive event");
+                if let ChatEvent::DirectMessage { sender, message } = event {
+                    return (sender, message);
+                }
+            }
+        })
+        .await
+        .expect("Timeout waiting for DM")
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_chat_client_creation() {
@@ -469,19 +1352,74 @@ mod tests {
         let client = ChatClient::new(temp_dir.path().to_path_buf())
             .await
             .expect("Failed to create chat client");
-        assert!(client.gossip_sender.is_empty());
+        assert!(
+            client
+                .gossip_sender
+                .read()
+                .expect("gossip sender lock poisoned")
+                .is_empty()
+        );
     }
 
     #[tokio::test]
     #[serial]
     async fn test_subscribe_to_topic() {
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
-        let mut client = ChatClient::new(temp_dir.path().to_path_buf())
+        let client = ChatClient::new(temp_dir.path().to_path_buf())
             .await
             .expect("Failed to create chat client");
         let ticket = client.create_topic().await.expect("Failed to create topic");
 
-        assert!(client.gossip_sender.contains_key(&ticket.topic));
+        assert!(
+            client
+                .gossip_sender
+                .read()
+                .expect("gossip sender lock poisoned")
+                .contains_key(&ticket.topic)
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_cloned_handles_share_topics() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let client = ChatClient::new(temp_dir.path().to_path_buf())
+            .await
+            .expect("Failed to create chat client");
+
+        let tasks: Vec<_> = (0..2)
+            .map(|_| {
+                let handle = client.clone();
+                tokio::spawn(async move { handle.create_topic().await })
+            })
+            .collect();
+        let mut topics = Vec::new();
+        for task in tasks {
+            let ticket = task
+                .await
+                .expect("Task panicked")
+                .expect("Failed to create topic");
+            topics.push(ticket.topic);
+        }
+
+        let handle = client.clone();
+        client
+            .listen(&topics[0])
+            .expect("Failed to start listening");
+        assert!(matches!(
+            handle.listen(&topics[0]),
+            Err(Error::NotSubscribed(topic)) if topic == topics[0]
+        ));
+
+        handle
+            .leave_topic(&topics[0])
+            .expect("Failed to leave topic");
+        let senders = client
+            .gossip_sender
+            .read()
+            .expect("gossip sender lock poisoned");
+        assert!(!senders.contains_key(&topics[0]));
+        assert!(senders.contains_key(&topics[1]));
     }
 
     #[tokio::test]
@@ -489,10 +1427,10 @@ mod tests {
     async fn test_send_and_receive_message() {
         let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
         let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
-        let mut client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
             .await
             .expect("Failed to create client1");
-        let mut client2 = ChatClient::new(temp_dir2.path().to_path_buf())
+        let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
             .await
             .expect("Failed to create client2");
 
@@ -506,12 +1444,14 @@ mod tests {
             .await
             .expect("Failed to join topic");
 
-        let receiver1 = client1
+        client1
             .listen(&ticket.topic)
             .expect("Failed to start listening on client1");
-        let receiver2 = client2
+        let receiver1 = client1.events();
+        client2
             .listen(&ticket.topic)
             .expect("Failed to start listening on client2");
+        let receiver2 = client2.events();
 
         sleep(Duration::from_secs(2)).await;
 
@@ -555,12 +1495,20 @@ mod tests {
                 loop {
                     tokio::select! {
                         result = receiver1.recv_async() => {
-                            if let Ok(MessageTypes::Chat(chat_message)) = result {
+                            if let Ok(ChatEvent::Message {
+                                message: MessageTypes::Chat(chat_message),
+                                ..
+                            }) = result
+                            {
                                 messages_received_by_client1.push(chat_message);
                             }
                         }
                         result = receiver2.recv_async() => {
-                            if let Ok(MessageTypes::Chat(chat_message)) = result {
+                            if let Ok(ChatEvent::Message {
+                                message: MessageTypes::Chat(chat_message),
+                                ..
+                            }) = result
+                            {
                                 messages_received_by_client2.push(chat_message);
                             }
                         }
@@ -594,19 +1542,85 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_send_to_neighbors() {
+        let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
+        let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+            .await
+            .expect("Failed to create client1");
+        let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
+            .await
+            .expect("Failed to create client2");
+
+        let ticket = client1
+            .create_topic()
+            .await
+            .expect("Failed to create topic");
+        client2
+            .join_topic(ticket.clone())
+            .await
+            .expect("Failed to join topic");
+
+        client1
+            .listen(&ticket.topic)
+            .expect("Failed to start listening on client1");
+        let receiver1 = client1.events();
+        client2
+            .listen(&ticket.topic)
+            .expect("Failed to start listening on client2");
+
+        sleep(Duration::from_secs(2)).await;
+
+        let history = vec![ChatMessage::new(
+            client2.peer_id(),
+            "Last message seen".to_string(),
+            1_625_247_600_000,
+            ticket.topic,
+        )];
+        client2
+            .send_to_neighbors(MessageTypes::TopicMessages(TopicMessagesMessage::new(
+                ticket.topic,
+                history,
+            )))
+            .await
+            .expect("Failed to send to neighbors");
+
+        let received = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Ok(event) = receiver1.recv_async().await {
+                if let ChatEvent::Message {
+                    message: MessageTypes::TopicMessages(sync),
+                    ..
+                } = event
+                {
+                    return Some(sync);
+                }
+            }
+            None
+        })
+        .await
+        .expect("Test timed out")
+        .expect("Event channel closed");
+
+        assert_eq!(received.topic, ticket.topic);
+        assert_eq!(received.messages.len(), 1);
+        assert_eq!(received.messages[0].content, "Last message seen");
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_send_and_receive_message_three_clients() {
         let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
         let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
         let temp_dir3 = tempfile::tempdir().expect("Failed to create temp dir");
-        let mut client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
             .await
             .expect("Failed to create client1");
-        let mut client2 = ChatClient::new(temp_dir2.path().to_path_buf())
+        let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
             .await
             .expect("Failed to create client2");
-        let mut client3 = ChatClient::new(temp_dir3.path().to_path_buf())
+        let client3 = ChatClient::new(temp_dir3.path().to_path_buf())
             .await
             .expect("Failed to create client3");
 
@@ -625,15 +1639,18 @@ mod tests {
             .await
             .expect("Failed to join topic for client3");
 
-        let receiver1 = client1
+        client1
             .listen(&ticket.topic)
             .expect("Failed to start listening on client1");
-        let receiver2 = client2
+        let receiver1 = client1.events();
+        client2
             .listen(&ticket.topic)
             .expect("Failed to start listening on client2");
-        let receiver3 = client3
+        let receiver2 = client2.events();
+        client3
             .listen(&ticket.topic)
             .expect("Failed to start listening on client3");
+        let receiver3 = client3.events();
 
         sleep(Duration::from_secs(3)).await;
 
@@ -689,17 +1706,29 @@ mod tests {
                 loop {
                     tokio::select! {
                         result = receiver1.recv_async() => {
-                            if let Ok(MessageTypes::Chat(chat_message)) = result {
+                            if let Ok(ChatEvent::Message {
+                                message: MessageTypes::Chat(chat_message),
+                                ..
+                            }) = result
+                            {
                                 messages_received_by_client1.push(chat_message);
                             }
                         }
                         result = receiver2.recv_async() => {
-                            if let Ok(MessageTypes::Chat(chat_message)) = result {
+                            if let Ok(ChatEvent::Message {
+                                message: MessageTypes::Chat(chat_message),
+                                ..
+                            }) = result
+                            {
                                 messages_received_by_client2.push(chat_message);
                             }
                         }
                         result = receiver3.recv_async() => {
-                            if let Ok(MessageTypes::Chat(chat_message)) = result {
+                            if let Ok(ChatEvent::Message {
+                                message: MessageTypes::Chat(chat_message),
+                                ..
+                            }) = result
+                            {
                                 messages_received_by_client3.push(chat_message);
                             }
                         }
@@ -762,7 +1791,7 @@ mod tests {
         let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
         let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
 
-        let mut client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
             .await
             .expect("Failed to create client1");
         let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
@@ -783,7 +1812,7 @@ mod tests {
             DmMessageTypes::ProfileMetadata(crate::messages::DmProfileMetadataMessage {
                 id: client1_id,
                 username: "user1".to_string(),
-                avatar_url: None,
+                avatar: None,
                 last_connection: 12_345,
             });
 
@@ -792,13 +1821,7 @@ mod tests {
             .await
             .expect("Failed to send DM");
 
-        let incoming = client2.incoming_dms();
-
-        let (sender, received_msg) =
-            tokio::time::timeout(Duration::from_secs(5), incoming.recv_async())
-                .await
-                .expect("Timeout waiting for DM")
-                .expect("Failed to receive DM");
+        let (sender, received_msg) = next_dm(&client2.events()).await;
 
         assert_eq!(sender, client1_id);
 
@@ -811,13 +1834,85 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_heartbeat_reports_presence() {
+        let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
+        let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+            .await
+            .expect("Failed to create client1");
+        let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
+            .await
+            .expect("Failed to create client2");
+        let client2_id = client2.peer_id();
+
+        let events = client1.events();
+        client1.watch_presence(client2_id);
+        client2.watch_presence(client1.peer_id());
+        client1.start_heartbeat(Duration::from_secs(5));
+
+        let online = tokio::time::timeout(Duration::from_secs(20), async {
+            while let Ok(event) = events.recv_async().await {
+                if let ChatEvent::Presence { peer, online } = event
+                    && peer == client2_id
+                {
+                    return Some(online);
+                }
+            }
+            None
+        })
+        .await
+        .expect("Test timed out")
+        .expect("Event channel closed");
+
+        assert!(online, "Client2 should be reported online");
+        client1.stop_heartbeat();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_presence_is_hidden_from_strangers() {
+        let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
+        let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+            .await
+            .expect("Failed to create client1");
+        let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
+            .await
+            .expect("Failed to create client2");
+        let client2_id = client2.peer_id();
+
+        // Client2 does not watch client1, so it does not answer its pings.
+        let events = client1.events();
+        client1.watch_presence(client2_id);
+        client1.start_heartbeat(Duration::from_secs(5));
+
+        let online = tokio::time::timeout(Duration::from_secs(20), async {
+            while let Ok(event) = events.recv_async().await {
+                if let ChatEvent::Presence { peer, online } = event
+                    && peer == client2_id
+                {
+                    return Some(online);
+                }
+            }
+            None
+        })
+        .await
+        .expect("Test timed out")
+        .expect("Event channel closed");
+
+        assert!(!online, "Client2 should be reported offline");
+        client1.stop_heartbeat();
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_dm_send_without_connection_fails() {
         let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
         let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
 
-        let mut client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
             .await
             .expect("Failed to create client1");
         let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
@@ -832,13 +1927,12 @@ mod tests {
             DmMessageTypes::ProfileMetadata(crate::messages::DmProfileMetadataMessage {
                 id: client1.peer_id(),
                 username: "user1".to_string(),
-                avatar_url: None,
+                avatar: None,
                 last_connection: 12345,
             });
 
         let result = client1.send_dm(addr2.clone(), msg_content).await;
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err().to_string(), "No DM sender for address");
+        assert!(matches!(result, Err(Error::NotConnected(id)) if id == addr2.id));
     }
 
     #[tokio::test]
@@ -847,10 +1941,10 @@ mod tests {
         let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
         let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
 
-        let mut client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
             .await
             .expect("Failed to create client1");
-        let mut client2 = ChatClient::new(temp_dir2.path().to_path_buf())
+        let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
             .await
             .expect("Failed to create client2");
 
@@ -872,7 +1966,7 @@ mod tests {
             DmMessageTypes::ProfileMetadata(crate::messages::DmProfileMetadataMessage {
                 id: client1_id,
                 username: "user1".to_string(),
-                avatar_url: None,
+                avatar: None,
                 last_connection: 100,
             });
 
@@ -880,7 +1974,7 @@ mod tests {
             DmMessageTypes::ProfileMetadata(crate::messages::DmProfileMetadataMessage {
                 id: client2_id,
                 username: "user2".to_string(),
-                avatar_url: None,
+                avatar: None,
                 last_connection: 200,
             });
 
@@ -894,12 +1988,7 @@ mod tests {
             .await
             .expect("Failed to send DM from client2");
 
-        let incoming2 = client2.incoming_dms();
-        let (sender2, received_msg2) =
-            tokio::time::timeout(Duration::from_secs(5), incoming2.recv_async())
-                .await
-                .expect("Timeout waiting for DM on client2")
-                .expect("Failed to receive DM on client2");
+        let (sender2, received_msg2) = next_dm(&client2.events()).await;
 
         assert_eq!(sender2, client1_id);
         match received_msg2 {
@@ -909,12 +1998,7 @@ mod tests {
             _ => panic!("Expected ProfileMetadata"),
         }
 
-        let incoming1 = client1.incoming_dms();
-        let (sender1, received_msg1) =
-            tokio::time::timeout(Duration::from_secs(5), incoming1.recv_async())
-                .await
-                .expect("Timeout waiting for DM on client1")
-                .expect("Failed to receive DM on client1");
+        let (sender1, received_msg1) = next_dm(&client1.events()).await;
 
         assert_eq!(sender1, client2_id);
         match received_msg1 {
@@ -931,7 +2015,7 @@ mod tests {
         let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
         let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
 
-        let mut client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
             .await
             .expect("Failed to create client1");
         let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
@@ -952,7 +2036,7 @@ mod tests {
             let msg = DmMessageTypes::ProfileMetadata(crate::messages::DmProfileMetadataMessage {
                 id: client1_id,
                 username: format!("user1_message_{i}"),
-                avatar_url: None,
+                avatar: None,
                 last_connection: i,
             });
             client1
@@ -961,13 +2045,9 @@ mod tests {
                 .expect("Failed to send DM");
         }
 
-        let incoming = client2.incoming_dms();
+        let events = client2.events();
         for i in 0..5 {
-            let (sender, received_msg) =
-                tokio::time::timeout(Duration::from_secs(5), incoming.recv_async())
-                    .await
-                    .expect("Timeout waiting for DM")
-                    .expect("Failed to receive DM");
+            let (sender, received_msg) = next_dm(&events).await;
 
             assert_eq!(sender, client1_id);
             match received_msg {
@@ -980,13 +2060,113 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_dm_rate_limit_closes_connection() {
+        let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
+        let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
+
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+            .await
+            .expect("Failed to create client1");
+        let limits = DmLimits::new(crate::protocol::DEFAULT_MAX_FRAME_SIZE, 2, u64::MAX);
+        let client2 = ChatClient::new_with_dm_limits(temp_dir2.path().to_path_buf(), limits)
+            .await
+            .expect("Failed to create client2");
+
+        sleep(Duration::from_secs(1)).await;
+
+        let client1_id = client1.peer_id();
+        let client2_id = client2.peer_id();
+
+        client1
+            .connect_peer(client2_id)
+            .await
+            .expect("Failed to connect");
+
+        for i in 0..5u64 {
+            let msg = DmMessageTypes::ProfileMetadata(crate::messages::DmProfileMetadataMessage {
+                id: client1_id,
+                username: format!("user1_message_{i}"),
+                avatar: None,
+                last_connection: i,
+            });
+            // Sending may start failing once the connection is closed.
+            let _ = client1.send_dm(client2_id, msg).await;
+        }
+
+        let events = client2.events();
+        let mut received = 0;
+        let event = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match events.recv_async().await.expect("Failed to receive event") {
+                    ChatEvent::DirectMessage { .. } => received += 1,
+                    ChatEvent::DmAbuse(event) => return event,
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .expect("Timeout waiting for abuse event");
+        assert_eq!(
+            event,
+            DmAbuseEvent::MessageRateExceeded { peer: client1_id }
+        );
+        assert_eq!(received, 2);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_dm_from_blocked_peer_is_refused() {
+        let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
+        let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
+
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+            .await
+            .expect("Failed to create client1");
+        let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
+            .await
+            .expect("Failed to create client2");
+
+        sleep(Duration::from_secs(1)).await;
+
+        let client1_id = client1.peer_id();
+        let client2_id = client2.peer_id();
+        client2.block_peer(client1_id);
+        assert!(client2.is_blocked(&client1_id));
+
+        // Opening the stream may succeed before the connection is closed by client2.
+        if client1.connect_peer(client2_id).await.is_ok() {
+            let msg = DmMessageTypes::ProfileMetadata(crate::messages::DmProfileMetadataMessage {
+                id: client1_id,
+                username: "user1".to_string(),
+                avatar: None,
+                last_connection: 0,
+            });
+            let _ = client1.send_dm(client2_id, msg).await;
+        }
+
+        let events = client2.events();
+        let received = tokio::time::timeout(Duration::from_secs(3), async {
+            loop {
+                if let ChatEvent::DirectMessage { .. } =
+                    events.recv_async().await.expect("Failed to receive event")
+                {
+                    return;
+                }
+            }
+        })
+        .await;
+        assert!(received.is_err(), "DM from a blocked peer was delivered");
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_dm_chat_message() {
         let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
         let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
 
-        let mut client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
             .await
             .expect("Failed to create client1");
         let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
@@ -1015,13 +2195,7 @@ mod tests {
             .await
             .expect("Failed to send DM");
 
-        let incoming = client2.incoming_dms();
-
-        let (sender, received_msg) =
-            tokio::time::timeout(Duration::from_secs(5), incoming.recv_async())
-                .await
-                .expect("Timeout waiting for DM")
-                .expect("Failed to receive DM");
+        let (sender, received_msg) = next_dm(&client2.events()).await;
 
         assert_eq!(sender, client1_id);
 
@@ -1039,7 +2213,7 @@ mod tests {
     #[serial]
     async fn test_save_blob() {
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
-        let mut client = ChatClient::new(temp_dir.path().to_path_buf())
+        let client = ChatClient::new(temp_dir.path().to_path_buf())
             .await
             .expect("Failed to create chat client");
 
@@ -1056,11 +2230,38 @@ mod tests {
         assert_eq!(slice, test_data);
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_delete_blob() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let client = ChatClient::new(temp_dir.path().to_path_buf())
+            .await
+            .expect("Failed to create chat client");
+
+        let result = client
+            .save_blob(b"This message will disappear")
+            .await
+            .expect("Failed to save blob");
+        let path = client
+            .get_blob_path(result.hash, "txt")
+            .await
+            .expect("Failed to export blob");
+        assert!(client.has_blob(result.hash).await.unwrap());
+
+        client
+            .delete_blobs(&[result.hash])
+            .await
+            .expect("Failed to delete blob");
+
+        assert!(!client.has_blob(result.hash).await.unwrap());
+        assert!(!path.exists());
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_save_blob_large_data() {
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
-        let mut client = ChatClient::new(temp_dir.path().to_path_buf())
+        let client = ChatClient::new(temp_dir.path().to_path_buf())
             .await
             .expect("Failed to create chat client");
 
@@ -1081,7 +2282,7 @@ mod tests {
     #[serial]
     async fn test_save_blob_empty_data() {
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
-        let mut client = ChatClient::new(temp_dir.path().to_path_buf())
+        let client = ChatClient::new(temp_dir.path().to_path_buf())
             .await
             .expect("Failed to create chat client");
 
@@ -1099,10 +2300,10 @@ mod tests {
         let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
         let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
 
-        let mut client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
             .await
             .expect("Failed to create client1");
-        let mut client2 = ChatClient::new(temp_dir2.path().to_path_buf())
+        let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
             .await
             .expect("Failed to create client2");
 
@@ -1112,6 +2313,8 @@ mod tests {
         let progress = client1.save_blob(test_data).await;
         let result = progress.expect("Failed to save blob");
 
+        client1.grant_blob_access(result.hash, BlobScope::Peer(client2.peer_id()));
+
         let blob_ticket = BlobTicket::new(
             client1.endpoint_addr(),
             result.hash,
@@ -1129,16 +2332,50 @@ mod tests {
         assert_eq!(bytes.as_ref(), test_data);
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_download_blob_without_access_fails() {
+        let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
+        let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
+
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+            .await
+            .expect("Failed to create client1");
+        let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
+            .await
+            .expect("Failed to create client2");
+
+        sleep(Duration::from_secs(1)).await;
+
+        let progress = client1.save_blob(b"Private attachment").await;
+        let result = progress.expect("Failed to save blob");
+
+        let blob_ticket = BlobTicket::new(
+            client1.endpoint_addr(),
+            result.hash,
+            iroh_blobs::BlobFormat::Raw,
+        );
+
+        let download_result = client2.download_blob(&blob_ticket).await;
+        assert!(download_result.is_err());
+        assert!(
+            !client2
+                .has_blob(result.hash)
+                .await
+                .expect("Failed to check blob")
+        );
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_download_blob_large_data_between_clients() {
         let temp_dir1 = tempfile::tempdir().expect("Failed to create temp dir");
         let temp_dir2 = tempfile::tempdir().expect("Failed to create temp dir");
 
-        let mut client1 = ChatClient::new(temp_dir1.path().to_path_buf())
+        let client1 = ChatClient::new(temp_dir1.path().to_path_buf())
             .await
             .expect("Failed to create client1");
-        let mut client2 = ChatClient::new(temp_dir2.path().to_path_buf())
+        let client2 = ChatClient::new(temp_dir2.path().to_path_buf())
             .await
             .expect("Failed to create client2");
 
@@ -1148,6 +2385,8 @@ mod tests {
         let progress = client1.save_blob(&test_data).await;
         let result = progress.expect("Failed to save blob");
 
+        client1.grant_blob_access(result.hash, BlobScope::Peer(client2.peer_id()));
+
         let blob_ticket = BlobTicket::new(
             client1.endpoint_addr(),
             result.hash,
@@ -1169,7 +2408,7 @@ mod tests {
     #[serial]
     async fn test_save_multiple_blobs() {
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
-        let mut client = ChatClient::new(temp_dir.path().to_path_buf())
+        let client = ChatClient::new(temp_dir.path().to_path_buf())
             .await
             .expect("Failed to create chat client");
 
@@ -1217,7 +2456,7 @@ mod tests {
     #[serial]
     async fn test_save_same_data_twice() {
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
-        let mut client = ChatClient::new(temp_dir.path().to_path_buf())
+        let client = ChatClient::new(temp_dir.path().to_path_buf())
             .await
             .expect("Failed to create chat client");
 
@@ -1236,7 +2475,7 @@ mod tests {
     #[serial]
     async fn test_get_blob_from_storage() {
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
-        let mut client = ChatClient::new(temp_dir.path().to_path_buf())
+        let client = ChatClient::new(temp_dir.path().to_path_buf())
             .await
             .expect("Failed to create chat client");
 
@@ -1253,4 +2492,132 @@ mod tests {
 
         assert_eq!(retrieved_bytes, test_data);
     }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_get_encrypted_blob_from_storage() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let client = ChatClient::new(temp_dir.path().to_path_buf())
+            .await
+            .expect("Failed to create chat client");
+
+        let test_data = b"Private attachment";
+        let (key, progress) = client
+            .save_encrypted_blob(test_data)
+            .expect("Failed to encrypt blob");
+        let result = progress.await.expect("Failed to save blob");
+
+        assert_ne!(result.hash, Hash::new(test_data));
+        let stored = client
+            .store()
+            .blobs()
+            .get_bytes(result.hash)
+            .await
+            .expect("Failed to read stored blob");
+        assert_eq!(
+            decrypt_blob(&key, &stored).expect("Failed to decrypt"),
+            test_data
+        );
+
+        let path = client
+            .get_blob_path(result.hash, "bin")
+            .await
+            .expect("Failed to get blob from storage");
+        let retrieved_bytes = std::fs::read(path).expect("Failed to read blob from path");
+        assert_eq!(retrieved_bytes, test_data);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_save_encrypted_file() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let files_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let client = ChatClient::new(temp_dir.path().to_path_buf())
+            .await
+            .expect("Failed to create chat client");
+
+        let test_data: Vec<u8> = (0..300 * 1024)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect();
+        let file = files_dir.path().join("large.bin");
+        std::fs::write(&file, &test_data).expect("Failed to write file");
+
+        let (_, stream) = client
+            .save_encrypted_file(&file)
+            .await
+            .expect("Failed to encrypt file");
+        let mut stream = std::pin::pin!(stream);
+        let mut hash = None;
+        while let Some(item) = stream.next().await {
+            if let AddProgressItem::Done(temp_tag) = item {
+                hash = Some(temp_tag.hash());
+            }
+        }
+        let hash = hash.expect("Import did not finish");
+        assert_ne!(hash, Hash::new(&test_data));
+
+        let path = client
+            .get_blob_path(hash, "bin")
+            .await
+            .expect("Failed to get blob from storage");
+        assert_eq!(std::fs::read(path).expect("Failed to read blob"), test_data);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_save_and_export_collection() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let files_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let export_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let client = ChatClient::new(temp_dir.path().to_path_buf())
+            .await
+            .expect("Failed to create chat client");
+
+        let first = files_dir.path().join("first.txt");
+        let second = files_dir.path().join("second.txt");
+        std::fs::write(&first, b"first file").expect("Failed to write first file");
+        std::fs::write(&second, b"second file data").expect("Failed to write second file");
+
+        let progress = client.save_collection(vec![
+            ("first.txt".to_string(), first),
+            ("nested/second.txt".to_string(), second),
+        ]);
+
+        let mut result = None;
+        while let Ok(item) = progress.recv_async().await {
+            match item {
+                CollectionAddProgress::Done { hash, entries } => {
+                    result = Some((hash, entries));
+                    break;
+                }
+                CollectionAddProgress::Error(e) => panic!("Failed to save collection: {e}"),
+                CollectionAddProgress::Size(_) | CollectionAddProgress::Progress(_) => {}
+            }
+        }
+        let (hash, entries) = result.expect("Collection import did not finish");
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].size, 10);
+        assert_eq!(entries[1].size, 16);
+        assert!(entries.iter().all(|entry| entry.key.is_some()));
+        assert_ne!(entries[0].hash, Hash::new(b"first file"));
+        assert!(
+            client
+                .has_collection(hash)
+                .await
+                .expect("Failed to check collection")
+        );
+
+        let exported = client
+            .export_collection(hash, export_dir.path())
+            .await
+            .expect("Failed to export collection");
+
+        assert_eq!(exported.len(), 2);
+        assert_eq!(
+            std::fs::read(export_dir.path().join("nested").join("second.txt"))
+                .expect("Failed to read exported file"),
+            b"second file data"
+        );
+    }
 }
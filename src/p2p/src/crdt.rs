@@ -0,0 +1,546 @@
+//! # CRDTs
+//!
+//! Conflict-free replicated data types for the state a topic shares over gossip. Merging is
+//! commutative, associative and idempotent, so every peer converges to the same state whatever
+//! the order in which, and the number of times, the updates are delivered.
+
+use crate::hlc::Hlc;
+use iroh::{EndpointId, SecretKey, Signature};
+use iroh_blobs::Hash;
+use iroh_gossip::proto::TopicId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Identifies a single write by the clock and the identity of its writer.
+///
+/// Tags are totally ordered: the writer breaks ties between writes made at the same clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Tag {
+    pub hlc: Hlc,
+    pub replica: EndpointId,
+}
+
+impl Tag {
+    #[must_use]
+    pub const fn new(hlc: Hlc, replica: EndpointId) -> Self {
+        Self { hlc, replica }
+    }
+}
+
+/// A last-writer-wins register: the value with the greatest [`Tag`] wins.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LwwRegister<T> {
+    value: T,
+    /// The tag of the last write, `None` if the register still holds its initial value.
+    tag: Option<Tag>,
+}
+
+impl<T: Clone> LwwRegister<T> {
+    #[must_use]
+    pub const fn new(value: T) -> Self {
+        Self { value, tag: None }
+    }
+
+    #[must_use]
+    pub const fn get(&self) -> &T {
+        &self.value
+    }
+
+    /// Whether the register was ever written, rather than holding its initial value.
+    #[must_use]
+    pub const fn is_set(&self) -> bool {
+        self.tag.is_some()
+    }
+
+    /// The peer that made the last write, if any.
+    #[must_use]
+    pub fn writer(&self) -> Option<EndpointId> {
+        self.tag.map(|tag| tag.replica)
+    }
+
+    /// Writes `value`, unless a later write is already known.
+    pub fn set(&mut self, value: T, tag: Tag) {
+        if self.tag < Some(tag) {
+            self.value = value;
+            self.tag = Some(tag);
+        }
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        if self.tag < other.tag {
+            self.value = other.value.clone();
+            self.tag = other.tag;
+        }
+    }
+
+    /// Pulls the tag of a write received from a peer back to at most [`crate::hlc::MAX_CLOCK_DRIFT`]
+    /// ahead of the local wall clock, so a far-future write cannot win every later merge.
+    pub fn clamp(&mut self) {
+        if let Some(tag) = &mut self.tag {
+            tag.hlc = tag.hlc.clamped();
+        }
+    }
+}
+
+/// A last-writer-wins register that only takes writes made by a single peer, such as the
+/// settings reserved to the admin of a topic.
+///
+/// The tag alone does not prove who wrote a value, since any peer relaying the register can put
+/// the admin in it. Every write is therefore signed by its writer over the value, the tag and a
+/// context naming what the register holds, so a signature cannot be replayed into another
+/// register.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedRegister<T> {
+    register: LwwRegister<T>,
+    /// The signature of the last write, `None` until its writer signs it.
+    signature: Option<Signature>,
+}
+
+impl<T: Clone + Default + Serialize> SignedRegister<T> {
+    #[must_use]
+    pub const fn get(&self) -> &T {
+        self.register.get()
+    }
+
+    /// Whether the register was ever written, rather than holding its initial value.
+    #[must_use]
+    pub const fn is_set(&self) -> bool {
+        self.register.is_set()
+    }
+
+    /// The peer that made the last write, if any.
+    #[must_use]
+    pub fn writer(&self) -> Option<EndpointId> {
+        self.register.writer()
+    }
+
+    /// Writes `value`, unless a later write is already known. The write stays unsigned until
+    /// [`Self::sign`] is called.
+    pub fn set(&mut self, value: T, tag: Tag) {
+        if self.register.tag < Some(tag) {
+            self.register.set(value, tag);
+            self.signature = None;
+        }
+    }
+
+    /// Signs the last write with `secret_key`, if it was made by its owner and is not signed yet.
+    pub fn sign(&mut self, secret_key: &SecretKey, context: &impl Serialize) {
+        if self.signature.is_some() || self.writer() != Some(secret_key.public()) {
+            return;
+        }
+        if let Some(message) = self.signed_message(context) {
+            self.signature = Some(secret_key.sign(&message));
+        }
+    }
+
+    /// Whether the last write carries a valid signature of `writer`.
+    #[must_use]
+    pub fn is_signed_by(&self, writer: EndpointId, context: &impl Serialize) -> bool {
+        let (Some(signature), Some(message)) = (&self.signature, self.signed_message(context))
+        else {
+            return false;
+        };
+        self.writer() == Some(writer) && writer.verify(&message, signature).is_ok()
+    }
+
+    /// Merges `other` only if `writer` made and signed its last write, so the register only ever
+    /// holds a value written by `writer`. A value written by anyone else is replaced.
+    pub fn merge_from(
+        &mut self,
+        other: &Self,
+        writer: Option<EndpointId>,
+        context: &impl Serialize,
+    ) {
+        let Some(writer) = writer else {
+            return;
+        };
+        if !other.is_signed_by(writer, context) {
+            return;
+        }
+        if self.writer() != Some(writer) || self.register.tag < other.register.tag {
+            *self = other.clone();
+        }
+    }
+
+    /// Drops a write received from a peer if its tag is more than
+    /// [`crate::hlc::MAX_CLOCK_DRIFT`] ahead of the local wall clock.
+    ///
+    /// Unlike [`LwwRegister::clamp`], the tag cannot be pulled back without voiding the
+    /// signature, so the write is discarded instead.
+    pub fn clamp(&mut self) {
+        if let Some(tag) = self.register.tag
+            && tag.hlc.clamped() != tag.hlc
+        {
+            *self = Self::default();
+        }
+    }
+
+    fn signed_message(&self, context: &impl Serialize) -> Option<Vec<u8>> {
+        let tag = self.register.tag?;
+        postcard::to_stdvec(&(context, &self.register.value, tag)).ok()
+    }
+}
+
+/// An observed-remove set.
+///
+/// Every insertion is tagged and a removal only removes the tags it has seen, so an insertion
+/// concurrent with a removal survives the merge (add wins).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrSet<T: Ord> {
+    /// The live tags of every element in the set, never empty.
+    entries: BTreeMap<T, BTreeSet<Tag>>,
+    /// The tags of every removed insertion.
+    tombstones: BTreeSet<Tag>,
+}
+
+impl<T: Ord> Default for OrSet<T> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            tombstones: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> OrSet<T> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, value: T, tag: Tag) {
+        if !self.tombstones.contains(&tag) {
+            self.entries.entry(value).or_default().insert(tag);
+        }
+    }
+
+    /// Removes `value`, as far as this replica has observed it.
+    pub fn remove(&mut self, value: &T) {
+        if let Some(tags) = self.entries.remove(value) {
+            self.tombstones.extend(tags);
+        }
+    }
+
+    #[must_use]
+    pub fn contains(&self, value: &T) -> bool {
+        self.entries.contains_key(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.entries.keys()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.tombstones.extend(other.tombstones.iter().copied());
+        for (value, tags) in &other.entries {
+            self.entries
+                .entry(value.clone())
+                .or_default()
+                .extend(tags.iter().copied());
+        }
+        let tombstones = &self.tombstones;
+        self.entries.retain(|_, tags| {
+            tags.retain(|tag| !tombstones.contains(tag));
+            !tags.is_empty()
+        });
+    }
+}
+
+impl<'a, T: Ord> IntoIterator for &'a OrSet<T> {
+    type Item = &'a T;
+    type IntoIter = std::collections::btree_map::Keys<'a, T, BTreeSet<Tag>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.keys()
+    }
+}
+
+/// The replicated state of a topic.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopicState {
+    pub name: LwwRegister<String>,
+    /// The hash of the avatar blob, fetched from the store of whoever set it.
+    pub avatar: LwwRegister<Option<Hash>>,
+    /// The minimum number of seconds between two posts of the same member, if slow mode is on.
+    /// Only the admin may set it.
+    pub slow_mode: SignedRegister<Option<u64>>,
+    /// The number of seconds messages are kept for, if they disappear. Only the admin may set it.
+    pub disappearing: SignedRegister<Option<u64>>,
+    pub members: OrSet<EndpointId>,
+}
+
+impl TopicState {
+    /// The context the slow mode of `topic` is signed with.
+    #[must_use]
+    pub const fn slow_mode_context(topic: TopicId) -> (TopicId, &'static str) {
+        (topic, "slow_mode")
+    }
+
+    /// The context the disappearing timer of `topic` is signed with.
+    #[must_use]
+    pub const fn disappearing_context(topic: TopicId) -> (TopicId, &'static str) {
+        (topic, "disappearing")
+    }
+
+    /// Merges `other`, the state of `topic` as another peer has it, into this state, returning
+    /// whether anything changed.
+    ///
+    /// The settings reserved to the topic `admin` only take writes signed by it, and are never
+    /// changed when the admin is unknown.
+    pub fn merge(&mut self, other: &Self, topic: TopicId, admin: Option<EndpointId>) -> bool {
+        let before = self.clone();
+        self.name.merge(&other.name);
+        self.avatar.merge(&other.avatar);
+        self.slow_mode
+            .merge_from(&other.slow_mode, admin, &Self::slow_mode_context(topic));
+        self.disappearing.merge_from(
+            &other.disappearing,
+            admin,
+            &Self::disappearing_context(topic),
+        );
+        self.members.merge(&other.members);
+        *self != before
+    }
+
+    /// Signs the settings of `topic` last written with `secret_key`, so the peers relaying them
+    /// cannot forge them.
+    pub fn sign(&mut self, topic: TopicId, secret_key: &SecretKey) {
+        self.slow_mode
+            .sign(secret_key, &Self::slow_mode_context(topic));
+        self.disappearing
+            .sign(secret_key, &Self::disappearing_context(topic));
+    }
+
+    /// Bounds the tags of a state received from a peer by the local clock, see
+    /// [`LwwRegister::clamp`].
+    ///
+    /// The tags of the member set are left alone: they identify insertions, and changing them
+    /// would keep the tombstones of other peers from ever matching.
+    pub fn clamp(&mut self) {
+        self.name.clamp();
+        self.avatar.clamp();
+        self.slow_mode.clamp();
+        self.disappearing.clamp();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hlc::{HybridClock, MAX_CLOCK_DRIFT};
+    use crate::utils::random_endpoint;
+
+    fn tag(wall: u64, replica: EndpointId) -> Tag {
+        Tag::new(Hlc::from_wall(wall), replica)
+    }
+
+    #[test]
+    fn test_register_keeps_last_write() {
+        let alice = random_endpoint();
+        let mut register = LwwRegister::new("initial".to_string());
+
+        register.set("second".to_string(), tag(2, alice));
+        register.set("first".to_string(), tag(1, alice));
+        assert_eq!(register.get(), "second");
+
+        let mut other = LwwRegister::new("initial".to_string());
+        other.set("third".to_string(), tag(3, alice));
+        register.merge(&other);
+        assert_eq!(register.get(), "third");
+    }
+
+    #[test]
+    fn test_register_breaks_ties_by_replica() {
+        let (alice, bob) = (random_endpoint(), random_endpoint());
+        let mut left = LwwRegister::new(String::new());
+        let mut right = LwwRegister::new(String::new());
+        left.set("alice".to_string(), tag(1, alice));
+        right.set("bob".to_string(), tag(1, bob));
+
+        let mut merged_left = left.clone();
+        merged_left.merge(&right);
+        let mut merged_right = right;
+        merged_right.merge(&left);
+
+        assert_eq!(merged_left, merged_right);
+    }
+
+    #[test]
+    fn test_register_reports_last_writer() {
+        let (alice, bob) = (random_endpoint(), random_endpoint());
+        let mut register = LwwRegister::new(None);
+        assert_eq!(register.writer(), None);
+
+        register.set(Some(Hash::new(b"alice")), tag(1, alice));
+        register.set(Some(Hash::new(b"bob")), tag(2, bob));
+
+        assert_eq!(register.writer(), Some(bob));
+        assert_eq!(*register.get(), Some(Hash::new(b"bob")));
+    }
+
+    #[test]
+    fn test_set_concurrent_insert_survives_remove() {
+        let (alice, bob, carol) = (random_endpoint(), random_endpoint(), random_endpoint());
+        let mut base = OrSet::new();
+        base.insert(carol, tag(1, alice));
+
+        // Alice removes Carol while Bob, unaware, adds her again.
+        let mut left = base.clone();
+        left.remove(&carol);
+        let mut right = base;
+        right.insert(carol, tag(2, bob));
+
+        left.merge(&right);
+        right.merge(&left);
+
+        assert_eq!(left, right);
+        assert!(left.contains(&carol));
+    }
+
+    #[test]
+    fn test_set_observed_remove_wins() {
+        let (alice, carol) = (random_endpoint(), random_endpoint());
+        let mut left = OrSet::new();
+        left.insert(carol, tag(1, alice));
+        let mut right = left.clone();
+
+        right.remove(&carol);
+        left.merge(&right);
+
+        assert!(!left.contains(&carol));
+        assert!(left.is_empty());
+    }
+
+    #[test]
+    fn test_signed_register_only_takes_writer() {
+        let (admin, mallory) = (SecretKey::generate(&mut rand::rng()), random_endpoint());
+        let context = TopicState::slow_mode_context(TopicId::from_bytes([1; 32]));
+        let mut register = SignedRegister::default();
+
+        let mut forged = SignedRegister::default();
+        forged.set(Some(1), tag(5, mallory));
+        register.merge_from(&forged, Some(admin.public()), &context);
+        assert_eq!(*register.get(), None);
+
+        let mut update = SignedRegister::default();
+        update.set(Some(30), tag(2, admin.public()));
+        update.sign(&admin, &context);
+        register.merge_from(&update, Some(admin.public()), &context);
+        assert_eq!(*register.get(), Some(30));
+
+        // A value written locally by anyone else gives way to the admin's, even an older one.
+        let mut local = SignedRegister::default();
+        local.set(Some(1), tag(9, mallory));
+        local.merge_from(&update, Some(admin.public()), &context);
+        assert_eq!(local, update);
+    }
+
+    #[test]
+    fn test_signed_register_rejects_forged_admin_writes() {
+        let admin = SecretKey::generate(&mut rand::rng());
+        let mallory = SecretKey::generate(&mut rand::rng());
+        let topic = TopicId::from_bytes([1; 32]);
+        let context = TopicState::slow_mode_context(topic);
+        let mut register = SignedRegister::default();
+
+        // Tagged as the admin, but unsigned or signed by someone else.
+        let mut unsigned = SignedRegister::default();
+        unsigned.set(Some(1), tag(5, admin.public()));
+        register.merge_from(&unsigned, Some(admin.public()), &context);
+        let mut signed = unsigned.clone();
+        signed.signature = Some(mallory.sign(&unsigned.signed_message(&context).unwrap()));
+        register.merge_from(&signed, Some(admin.public()), &context);
+        assert!(!register.is_set());
+
+        // A genuine write cannot be replayed into another setting or another topic.
+        let mut genuine = unsigned;
+        genuine.sign(&admin, &context);
+        register.merge_from(
+            &genuine,
+            Some(admin.public()),
+            &TopicState::disappearing_context(topic),
+        );
+        register.merge_from(
+            &genuine,
+            Some(admin.public()),
+            &TopicState::slow_mode_context(TopicId::from_bytes([2; 32])),
+        );
+        assert!(!register.is_set());
+
+        register.merge_from(&genuine, Some(admin.public()), &context);
+        assert_eq!(*register.get(), Some(1));
+    }
+
+    #[test]
+    fn test_clamp_bounds_far_future_tags() {
+        let admin = SecretKey::generate(&mut rand::rng());
+        let context = TopicState::slow_mode_context(TopicId::from_bytes([1; 32]));
+        let now = HybridClock::default().now();
+        let future = tag(now.wall() + 10 * MAX_CLOCK_DRIFT, admin.public());
+
+        let mut state = TopicState::default();
+        state.name.set("from the future".to_string(), future);
+        state.slow_mode.set(Some(30), future);
+        state.slow_mode.sign(&admin, &context);
+        state.clamp();
+
+        assert_eq!(state.name.get(), "from the future");
+        assert!(state.name.tag.unwrap().hlc < future.hlc);
+        // A write made once the allowed drift has passed still wins.
+        let later = tag(now.wall() + MAX_CLOCK_DRIFT + 1_000, admin.public());
+        state.name.set("later".to_string(), later);
+        assert_eq!(state.name.get(), "later");
+        // The signed setting cannot be clamped without voiding its signature, so it is dropped.
+        assert!(!state.slow_mode.is_set());
+    }
+
+    #[test]
+    fn test_topic_state_converges_in_any_order() {
+        let (alice, bob) = (random_endpoint(), random_endpoint());
+        let carol = SecretKey::generate(&mut rand::rng());
+        let topic = TopicId::from_bytes([1; 32]);
+
+        let mut first = TopicState::default();
+        first.name.set("alice's name".to_string(), tag(5, alice));
+        first.members.insert(alice, tag(1, alice));
+
+        let mut second = TopicState::default();
+        second.name.set("bob's name".to_string(), tag(4, bob));
+        second.members.insert(bob, tag(2, bob));
+
+        let mut third = TopicState::default();
+        third.slow_mode.set(Some(30), tag(3, carol.public()));
+        third.sign(topic, &carol);
+        second.disappearing.set(Some(3600), tag(2, bob));
+        third.members.insert(carol.public(), tag(3, carol.public()));
+        third.members.remove(&carol.public());
+
+        let admin = Some(carol.public());
+        let mut forward = TopicState::default();
+        for update in [&first, &second, &third] {
+            forward.merge(update, topic, admin);
+        }
+        let mut backward = TopicState::default();
+        for update in [&third, &second, &first, &second] {
+            backward.merge(update, topic, admin);
+        }
+
+        assert_eq!(forward, backward);
+        assert_eq!(forward.name.get(), "alice's name");
+        assert_eq!(*forward.slow_mode.get(), Some(30));
+        // Only Carol, the admin, may change the topic settings.
+        assert_eq!(*forward.disappearing.get(), None);
+        assert_eq!(forward.members.len(), 2);
+        assert!(!forward.merge(&first, topic, admin));
+    }
+}
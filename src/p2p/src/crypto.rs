@@ -0,0 +1,346 @@
+//! # Blob Encryption
+//!
+//! Private attachments are encrypted before they are added to the blob store, so the store (and
+//! any peer that fetches the blob) only ever sees ciphertext. Every file gets its own random
+//! [`BlobKey`], which travels inside the already authenticated blob message.
+//!
+//! The plaintext is split in chunks of [`CHUNK_SIZE`] bytes, each sealed with `ChaCha20-Poly1305`.
+//! The nonce of a chunk is its index plus a flag marking the final chunk, so reordered, dropped or
+//! truncated chunks fail to decrypt. Nonces never repeat because keys are never reused.
+//!
+//! Files are encrypted and decrypted as streams, one chunk at a time, so attachments of any size
+//! never have to fit in memory.
+
+use crate::error::{Error, Result};
+use bytes::Bytes;
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use futures_lite::Stream;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The size in bytes of each plaintext chunk.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The size in bytes of the authentication tag appended to each chunk.
+const TAG_SIZE: usize = 16;
+
+/// A symmetric key used to encrypt a single blob.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobKey([u8; 32]);
+
+impl BlobKey {
+    /// Generates a new random key.
+    #[must_use]
+    pub fn generate() -> Self {
+        Self(rand::random())
+    }
+
+    #[must_use]
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    #[must_use]
+    pub const fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for BlobKey {
+    /// Keeps the key material out of logs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BlobKey(..)")
+    }
+}
+
+impl fmt::Display for BlobKey {
+    /// Formats the key as a base58 string.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", bs58::encode(self.0).into_string())
+    }
+}
+
+impl FromStr for BlobKey {
+    type Err = Error;
+
+    /// Parses a key from a base58 string.
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = bs58::decode(s)
+            .into_vec()
+            .map_err(|e| Error::InvalidKey(e.to_string()))?;
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| Error::InvalidKey("A blob key must be 32 bytes long".to_string()))?;
+        Ok(Self(bytes))
+    }
+}
+
+/// Converts the position of a chunk into the index used in its nonce.
+fn chunk_index(position: usize) -> Result<u64> {
+    u64::try_from(position).map_err(|e| Error::Encryption(e.to_string()))
+}
+
+/// Builds the nonce for the chunk at `index`.
+fn chunk_nonce(index: u64, last: bool) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(&index.to_le_bytes());
+    nonce[8] = u8::from(last);
+    Nonce::from(nonce)
+}
+
+fn seal_chunk(cipher: &ChaCha20Poly1305, index: u64, last: bool, chunk: &[u8]) -> Result<Vec<u8>> {
+    cipher
+        .encrypt(&chunk_nonce(index, last), chunk)
+        .map_err(|_| Error::Encryption(format!("Failed to encrypt blob chunk {index}")))
+}
+
+fn open_chunk(cipher: &ChaCha20Poly1305, index: u64, last: bool, chunk: &[u8]) -> Result<Vec<u8>> {
+    cipher
+        .decrypt(&chunk_nonce(index, last), chunk)
+        .map_err(|_| Error::Encryption(format!("Failed to decrypt blob chunk {index}")))
+}
+
+/// Reads a stream in chunks of a fixed size, looking one chunk ahead to flag the last one.
+struct Chunks<R> {
+    reader: R,
+    size: usize,
+    index: u64,
+    /// The chunk read ahead, `None` before the first read and after the last chunk.
+    ahead: Option<Vec<u8>>,
+    started: bool,
+}
+
+impl<R: AsyncRead + Unpin> Chunks<R> {
+    const fn new(reader: R, size: usize) -> Self {
+        Self {
+            reader,
+            size,
+            index: 0,
+            ahead: None,
+            started: false,
+        }
+    }
+
+    /// Fills a chunk, shorter than `size` only at the end of the stream.
+    async fn read_chunk(&mut self) -> io::Result<Vec<u8>> {
+        let mut chunk = vec![0; self.size];
+        let mut filled = 0;
+        while filled < self.size {
+            let read = self.reader.read(&mut chunk[filled..]).await?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+        chunk.truncate(filled);
+        Ok(chunk)
+    }
+
+    /// Returns the index of the next chunk, the chunk, and whether it is the last one. An empty
+    /// stream still has one, empty, chunk.
+    async fn next(&mut self) -> io::Result<Option<(u64, Vec<u8>, bool)>> {
+        let current = match self.ahead.take() {
+            Some(chunk) => chunk,
+            None if !self.started => self.read_chunk().await?,
+            None => return Ok(None),
+        };
+        self.started = true;
+
+        let following = self.read_chunk().await?;
+        let last = following.is_empty();
+        if !last {
+            self.ahead = Some(following);
+        }
+        let index = self.index;
+        self.index += 1;
+        Ok(Some((index, current, last)))
+    }
+}
+
+/// Encrypts the contents of a blob with the given key.
+///
+/// # Errors
+///
+/// This function will return an error if a chunk cannot be sealed.
+pub fn encrypt_blob(key: &BlobKey, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key.as_bytes()));
+    let chunks: Vec<&[u8]> = if plaintext.is_empty() {
+        vec![plaintext]
+    } else {
+        plaintext.chunks(CHUNK_SIZE).collect()
+    };
+    let last = chunks.len() - 1;
+
+    let mut ciphertext = Vec::with_capacity(plaintext.len() + chunks.len() * TAG_SIZE);
+    for (position, chunk) in chunks.into_iter().enumerate() {
+        let sealed = seal_chunk(&cipher, chunk_index(position)?, position == last, chunk)?;
+        ciphertext.extend_from_slice(&sealed);
+    }
+
+    Ok(ciphertext)
+}
+
+/// Encrypts everything read from `reader` with the given key, yielding the same ciphertext as
+/// [`encrypt_blob`] one sealed chunk at a time.
+pub fn encrypt_stream<R>(
+    key: BlobKey,
+    reader: R,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + Sync + 'static
+where
+    R: AsyncRead + Unpin + Send + Sync + 'static,
+{
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key.as_bytes()));
+    futures_lite::stream::try_unfold(
+        (Chunks::new(reader, CHUNK_SIZE), cipher),
+        |(mut chunks, cipher)| async move {
+            let Some((index, chunk, last)) = chunks.next().await? else {
+                return Ok(None);
+            };
+            let sealed = seal_chunk(&cipher, index, last, &chunk).map_err(io::Error::other)?;
+            Ok(Some((Bytes::from(sealed), (chunks, cipher))))
+        },
+    )
+}
+
+/// Decrypts a blob previously encrypted with [`encrypt_blob`].
+///
+/// # Errors
+///
+/// This function will return an error if the key is wrong or the ciphertext was tampered with
+/// or truncated.
+pub fn decrypt_blob(key: &BlobKey, ciphertext: &[u8]) -> Result<Vec<u8>> {
+    if ciphertext.len() < TAG_SIZE {
+        return Err(Error::Encryption("Encrypted blob is too short".to_string()));
+    }
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key.as_bytes()));
+    let chunks: Vec<&[u8]> = ciphertext.chunks(CHUNK_SIZE + TAG_SIZE).collect();
+    let last = chunks.len() - 1;
+
+    let mut plaintext = Vec::with_capacity(ciphertext.len());
+    for (position, chunk) in chunks.into_iter().enumerate() {
+        let opened = open_chunk(&cipher, chunk_index(position)?, position == last, chunk)?;
+        plaintext.extend_from_slice(&opened);
+    }
+
+    Ok(plaintext)
+}
+
+/// Decrypts everything read from `reader`, encrypted with [`encrypt_blob`] or
+/// [`encrypt_stream`], into `writer` one chunk at a time.
+///
+/// # Errors
+///
+/// This function will return an error if reading or writing fails, the key is wrong or the
+/// ciphertext was tampered with or truncated. The chunks written before the error are
+/// authentic, but the output is incomplete.
+pub async fn decrypt_stream<R, W>(key: &BlobKey, reader: R, writer: &mut W) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key.as_bytes()));
+    let mut chunks = Chunks::new(reader, CHUNK_SIZE + TAG_SIZE);
+    while let Some((index, chunk, last)) = chunks.next().await? {
+        if chunk.len() < TAG_SIZE {
+            return Err(Error::Encryption("Encrypted blob is too short".to_string()));
+        }
+        writer
+            .write_all(&open_chunk(&cipher, index, last, &chunk)?)
+            .await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        let key = BlobKey::generate();
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 10)
+            .map(|i| u8::try_from(i % 256).unwrap())
+            .collect();
+
+        let encrypted = encrypt_blob(&key, &data).expect("Failed to encrypt");
+        assert_ne!(encrypted[..data.len()], data[..]);
+        assert_eq!(encrypted.len(), data.len() + 3 * TAG_SIZE);
+
+        let decrypted = decrypt_blob(&key, &encrypted).expect("Failed to decrypt");
+        assert_eq!(decrypted, data);
+    }
+
+    #[test]
+    fn test_empty_blob_roundtrip() {
+        let key = BlobKey::generate();
+        let encrypted = encrypt_blob(&key, &[]).expect("Failed to encrypt");
+        assert_eq!(encrypted.len(), TAG_SIZE);
+        assert!(
+            decrypt_blob(&key, &encrypted)
+                .expect("Failed to decrypt")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_decrypt_rejects_wrong_key_and_truncation() {
+        let key = BlobKey::generate();
+        let data = vec![7u8; CHUNK_SIZE + 1];
+        let encrypted = encrypt_blob(&key, &data).expect("Failed to encrypt");
+
+        assert!(decrypt_blob(&BlobKey::generate(), &encrypted).is_err());
+        assert!(decrypt_blob(&key, &encrypted[..CHUNK_SIZE + TAG_SIZE]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream_matches_whole_buffer() {
+        let key = BlobKey::generate();
+        for size in [0, 10, CHUNK_SIZE, CHUNK_SIZE * 2 + 10] {
+            let data: Vec<u8> = (0..size).map(|i| u8::try_from(i % 256).unwrap()).collect();
+
+            let mut encrypted = Vec::new();
+            let mut stream = std::pin::pin!(encrypt_stream(key, io::Cursor::new(data.clone())));
+            while let Some(chunk) = futures_lite::StreamExt::next(&mut stream).await {
+                encrypted.extend_from_slice(&chunk.expect("Failed to encrypt"));
+            }
+            assert_eq!(
+                encrypted,
+                encrypt_blob(&key, &data).expect("Failed to encrypt")
+            );
+
+            let mut decrypted = Vec::new();
+            decrypt_stream(&key, encrypted.as_slice(), &mut decrypted)
+                .await
+                .expect("Failed to decrypt");
+            assert_eq!(decrypted, data);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_rejects_truncation() {
+        let key = BlobKey::generate();
+        let encrypted = encrypt_blob(&key, &vec![7u8; CHUNK_SIZE + 1]).expect("Failed to encrypt");
+
+        let mut decrypted = Vec::new();
+        let truncated = &encrypted[..CHUNK_SIZE + TAG_SIZE];
+        assert!(
+            decrypt_stream(&key, truncated, &mut decrypted)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_key_string_roundtrip() {
+        let key = BlobKey::generate();
+        let parsed: BlobKey = key.to_string().parse().expect("Failed to parse key");
+        assert_eq!(parsed, key);
+        assert!("abc".parse::<BlobKey>().is_err());
+    }
+}
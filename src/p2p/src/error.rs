@@ -0,0 +1,87 @@
+//! # Errors
+//!
+//! The error type returned by the public API of the crate. Each variant maps to a failure the
+//! caller can react to differently, e.g. reconnecting to a peer or rejoining a topic.
+
+use iroh::EndpointId;
+use iroh_gossip::proto::TopicId;
+use std::fmt;
+use std::time::Duration;
+
+/// A specialized `Result` type for p2p operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The errors that can happen in the p2p layer.
+#[derive(Debug)]
+pub enum Error {
+    /// The local endpoint could not be started.
+    Endpoint(String),
+    /// The client is not subscribed to the topic.
+    NotSubscribed(TopicId),
+    /// Subscribing or broadcasting to a gossip topic failed.
+    Subscription(String),
+    /// The message was not sent because the topic is in slow mode; posting is allowed again
+    /// after `wait`.
+    SlowMode { topic: TopicId, wait: Duration },
+    /// There is no open DM connection with the peer, `connect_peer` must be called first.
+    NotConnected(EndpointId),
+    /// Connecting to a peer, or writing to an open connection, failed.
+    Connection(String),
+    /// The serialized message is above the maximum frame size.
+    MessageTooLarge(usize),
+    /// A message could not be serialized or deserialized.
+    Serialization(String),
+    /// The blob store failed to add, read or export a blob.
+    BlobStore(String),
+    /// Downloading a blob or collection from a peer failed.
+    Download(String),
+    /// A blob could not be encrypted or decrypted.
+    Encryption(String),
+    /// A ticket string could not be parsed.
+    InvalidTicket(String),
+    /// A blob key string could not be parsed.
+    InvalidKey(String),
+    /// Reading or writing a local file failed.
+    Io(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Endpoint(msg) => write!(f, "Endpoint error: {msg}"),
+            Self::NotSubscribed(topic) => write!(f, "Not subscribed to topic {topic}"),
+            Self::Subscription(msg) => write!(f, "Subscription error: {msg}"),
+            Self::SlowMode { topic, wait } => write!(
+                f,
+                "Slow mode is enabled in topic {topic}, wait {}s before posting again",
+                wait.as_secs().max(1)
+            ),
+            Self::NotConnected(peer) => write!(f, "No DM connection with {peer}"),
+            Self::Connection(msg) => write!(f, "Connection error: {msg}"),
+            Self::MessageTooLarge(size) => {
+                write!(f, "Message of {size} bytes exceeds the maximum frame size")
+            }
+            Self::Serialization(msg) => write!(f, "Serialization error: {msg}"),
+            Self::BlobStore(msg) => write!(f, "Blob store error: {msg}"),
+            Self::Download(msg) => write!(f, "Download error: {msg}"),
+            Self::Encryption(msg) => write!(f, "Encryption error: {msg}"),
+            Self::InvalidTicket(msg) => write!(f, "Invalid ticket: {msg}"),
+            Self::InvalidKey(msg) => write!(f, "Invalid blob key: {msg}"),
+            Self::Io(msg) => write!(f, "I/O error: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<postcard::Error> for Error {
+    fn from(error: postcard::Error) -> Self {
+        Self::Serialization(error.to_string())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error.to_string())
+    }
+}
@@ -0,0 +1,66 @@
+//! # Client Events
+//!
+//! Everything the [`crate::ChatClient`] receives or notices is reported through a single
+//! channel of [`ChatEvent`]s, so the application can wait on one source instead of polling a
+//! receiver per topic.
+
+use crate::messages::{DmMessageTypes, MessageTypes};
+use crate::protocol::DmAbuseEvent;
+use crate::spam::GossipAbuseEvent;
+use iroh::EndpointId;
+use iroh_blobs::Hash;
+use iroh_gossip::proto::TopicId;
+
+/// The capacity of the event channel.
+///
+/// Messages, swarm changes and presence changes wait for space when the application falls behind,
+/// which applies backpressure to the remote peers. Other notifications (connections, progress,
+/// abuse) are dropped instead.
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// An event reported by the client.
+#[derive(Debug, Clone)]
+pub enum ChatEvent {
+    /// A message received on a gossip topic.
+    Message {
+        topic: TopicId,
+        message: MessageTypes,
+    },
+    /// A direct message received from a peer.
+    DirectMessage {
+        sender: EndpointId,
+        message: DmMessageTypes,
+    },
+    /// A peer became a direct neighbor in the gossip swarm of a topic.
+    NeighborUp { topic: TopicId, peer: EndpointId },
+    /// A peer is no longer a direct neighbor in the gossip swarm of a topic.
+    NeighborDown { topic: TopicId, peer: EndpointId },
+    /// The client fell behind and missed messages on a topic.
+    Lagged { topic: TopicId },
+    /// A DM connection with a peer was opened, by either side.
+    PeerConnected { peer: EndpointId },
+    /// A DM connection with a peer was closed or broke.
+    PeerDisconnected { peer: EndpointId },
+    /// A watched peer answered the heartbeat, or stopped answering it.
+    Presence { peer: EndpointId, online: bool },
+    /// Progress of a blob or collection download.
+    Transfer {
+        hash: Hash,
+        progress: TransferProgress,
+    },
+    /// A peer broke the DM limits and its connection was closed.
+    DmAbuse(DmAbuseEvent),
+    /// A topic member broke the gossip limits and was muted.
+    GossipAbuse(GossipAbuseEvent),
+}
+
+/// The state of a download, reported in [`ChatEvent::Transfer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferProgress {
+    /// The number of bytes downloaded so far.
+    Progress(u64),
+    /// The download finished and the data is complete in the local store.
+    Done,
+    /// The download failed.
+    Failed(String),
+}
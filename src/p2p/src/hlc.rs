@@ -0,0 +1,181 @@
+//! # Hybrid Logical Clock
+//!
+//! Orders messages consistently across peers whose wall clocks disagree. Every timestamp is at
+//! least the local wall clock, always grows, and moves past every timestamp received, so a reply
+//! is always ordered after the message it answers.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Remote timestamps further ahead of the local wall clock than this, in milliseconds, do not
+/// advance the local clock, so a peer with a skewed clock cannot drag every clock forward.
+pub const MAX_CLOCK_DRIFT: u64 = 60_000;
+
+/// A hybrid logical clock timestamp.
+///
+/// The upper 48 bits hold the wall clock in milliseconds and the lower 16 bits a counter that
+/// orders events within the same millisecond, so timestamps compare as plain integers.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Hlc(u64);
+
+impl Hlc {
+    const COUNTER_BITS: u32 = 16;
+
+    #[must_use]
+    pub const fn new(wall: u64, counter: u16) -> Self {
+        Self((wall << Self::COUNTER_BITS) | counter as u64)
+    }
+
+    /// A timestamp taken from the wall clock alone, for messages created without a clock.
+    #[must_use]
+    pub const fn from_wall(wall: u64) -> Self {
+        Self::new(wall, 0)
+    }
+
+    #[must_use]
+    pub const fn from_u64(value: u64) -> Self {
+        Self(value)
+    }
+
+    #[must_use]
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// The wall clock part, in milliseconds since the Unix epoch.
+    #[must_use]
+    pub const fn wall(self) -> u64 {
+        self.0 >> Self::COUNTER_BITS
+    }
+
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn counter(self) -> u16 {
+        self.0 as u16
+    }
+
+    /// Caps a received timestamp at [`MAX_CLOCK_DRIFT`] ahead of the local wall clock, so a
+    /// peer with a skewed clock cannot order its messages after every message sent later.
+    #[must_use]
+    pub fn clamped(self) -> Self {
+        self.clamped_at(wall_clock())
+    }
+
+    fn clamped_at(self, wall: u64) -> Self {
+        self.min(Self::from_wall(wall.saturating_add(MAX_CLOCK_DRIFT)))
+    }
+}
+
+/// A hybrid logical clock shared by every clone.
+#[derive(Debug, Clone, Default)]
+pub struct HybridClock {
+    last: Arc<Mutex<Hlc>>,
+}
+
+impl HybridClock {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the timestamp of a new local event, such as a message about to be sent.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    #[must_use]
+    pub fn now(&self) -> Hlc {
+        self.tick(wall_clock(), None)
+    }
+
+    /// Advances the clock past a received timestamp and returns the timestamp of the receipt.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn observe(&self, remote: Hlc) -> Hlc {
+        self.tick(wall_clock(), Some(remote))
+    }
+
+    fn tick(&self, wall: u64, remote: Option<Hlc>) -> Hlc {
+        let mut last = self.last.lock().expect("clock lock poisoned");
+        let remote = remote
+            .filter(|remote| remote.wall() <= wall.saturating_add(MAX_CLOCK_DRIFT))
+            .unwrap_or_default();
+        let latest = (*last).max(remote);
+
+        let next = if wall > latest.wall() {
+            Hlc::from_wall(wall)
+        } else {
+            Hlc(latest.0 + 1)
+        };
+        *last = next;
+        next
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn wall_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packing() {
+        let hlc = Hlc::new(1_700_000_000_000, 7);
+        assert_eq!(hlc.wall(), 1_700_000_000_000);
+        assert_eq!(hlc.counter(), 7);
+        assert!(Hlc::new(10, u16::MAX) < Hlc::new(11, 0));
+    }
+
+    #[test]
+    fn test_local_events_always_grow() {
+        let clock = HybridClock::new();
+        let first = clock.tick(1_000, None);
+        let second = clock.tick(1_000, None);
+        let earlier_wall = clock.tick(900, None);
+
+        assert_eq!(first, Hlc::new(1_000, 0));
+        assert_eq!(second, Hlc::new(1_000, 1));
+        assert_eq!(earlier_wall, Hlc::new(1_000, 2));
+        assert_eq!(clock.tick(1_001, None), Hlc::new(1_001, 0));
+    }
+
+    #[test]
+    fn test_receipt_moves_past_remote() {
+        let clock = HybridClock::new();
+        let remote = Hlc::new(5_000, 3);
+
+        let receipt = clock.tick(1_000, Some(remote));
+        assert!(receipt > remote);
+        assert!(clock.tick(1_000, None) > receipt);
+    }
+
+    #[test]
+    fn test_far_future_remote_is_ignored() {
+        let clock = HybridClock::new();
+        let skewed = Hlc::from_wall(1_000 + MAX_CLOCK_DRIFT + 1);
+
+        assert_eq!(clock.tick(1_000, Some(skewed)), Hlc::new(1_000, 0));
+    }
+
+    #[test]
+    fn test_far_future_remote_is_clamped() {
+        let skewed = Hlc::from_wall(1_000 + 10 * MAX_CLOCK_DRIFT);
+        let close = Hlc::new(1_000 + MAX_CLOCK_DRIFT - 1, 5);
+
+        assert_eq!(
+            skewed.clamped_at(1_000),
+            Hlc::from_wall(1_000 + MAX_CLOCK_DRIFT)
+        );
+        assert_eq!(close.clamped_at(1_000), close);
+    }
+}
@@ -7,53 +7,92 @@
 //! - Connecting to the iroh network.
 //! - Subscribing to gossip topics for group chats.
 //! - Direct messaging (DM) between peers.
-//! - File/Blob transfer (uploading and downloading) using iroh-blobs.
-//! - Peer discovery and management.
+//! - File/Blob transfer (uploading and downloading) using iroh-blobs, including
+//!   multi-file collections, served only to the peers they were shared with. Private
+//!   attachments are encrypted before being stored.
+//! - Flood protection for gossip topics: per-neighbor rate limits, size caps and slow mode.
+//! - A block list silencing peers across DMs, topics and blob serving.
+//! - Safety numbers, letting two peers check out-of-band that they know each other's key.
+//! - Peer discovery and management, including a heartbeat reporting which contacts are online.
 //!
 //! ## Key Components
 //!
 //! - **Client**: The [`ChatClient`] is the central struct managing connections,
-//!   message sending/receiving, and blob storage.
+//!   message sending/receiving, and blob storage. It is a cheap to clone handle, so it can be
+//!   shared between tasks without a lock.
+//! - **Events**: Everything the client receives is reported as a [`ChatEvent`] through a
+//!   single channel returned by [`ChatClient::events`].
 //! - **Messages**: Defines the protocol message structures (e.g., [`ChatMessage`], [`BlobMessage`])
-//!   serialized via `postcard`.
+//!   serialized via `postcard`. Topic posts carry an [`Hlc`] so every peer orders them the same.
+//! - **CRDTs**: The name, avatar, timers and members of a topic are shared as a [`TopicState`] that
+//!   every peer merges into the same result, whatever order the updates arrive in.
 //! - **Protocol**: Implements the direct messaging protocol handler.
 //! - **Types**: Shared types and utilities, such as invitation [`Ticket`]s.
+//! - **Errors**: Every fallible operation returns the crate [`Error`], so callers can tell
+//!   apart failures such as a missing subscription, an unreachable peer or a missing blob.
 //!
 //! ## Usage
 //!
 //! ```rust,no_run
-//! use p2p::{ChatClient, load_secret_key};
+//! use p2p::{ChatClient, ChatEvent};
 //! use std::path::PathBuf;
 //!
 //! # async fn example() -> anyhow::Result<()> {
-//! let key_path = PathBuf::from("secret.key");
-//! let secret_key = load_secret_key(key_path).await?;
+//! // Initialize the client, its key and blob store live in the given directory
+//! let client = ChatClient::new(PathBuf::from("nexu")).await?;
 //!
-//! // Initialize the client
-//! let client = ChatClient::new(secret_key, None).await?;
+//! // Create a topic and start listening on it
+//! let ticket = client.create_topic().await?;
+//! client.listen(&ticket.topic)?;
 //!
-//! // Start listening for incoming messages
-//! client.listen();
-//!
-//! // Join a topic or send a DM...
+//! // Handle everything the client receives from a single channel
+//! let events = client.events();
+//! while let Ok(event) = events.recv_async().await {
+//!     if let ChatEvent::Message { message, .. } = event {
+//!         println!("{message:?}");
+//!     }
+//! }
 //! # Ok(())
 //! # }
 //! ```
 
+pub mod access;
+pub mod block;
 pub mod client;
+pub mod crdt;
+pub mod crypto;
+pub mod error;
+pub mod events;
+pub mod hlc;
 pub mod messages;
+pub mod presence;
 pub mod protocol;
+pub mod safety;
+pub mod spam;
 pub mod types;
 pub mod utils;
 
-pub use client::ChatClient;
+pub use access::{BlobAccess, BlobScope};
+pub use block::BlockList;
+pub use client::{ChatClient, MAX_MESSAGE_SIZE};
+pub use crdt::{LwwRegister, OrSet, SignedRegister, Tag, TopicState};
+pub use crypto::BlobKey;
+pub use error::{Error, Result};
+pub use events::{ChatEvent, TransferProgress};
+pub use hlc::{Hlc, HybridClock};
 pub use iroh::{EndpointAddr, EndpointId};
 pub use messages::{
-    BlobMessage, ChatMessage, DisconnectMessage, DmBlobMessage, DmChatMessage, DmJoinMessage,
-    DmMessageTypes, DmProfileMetadataMessage, GossipMessage, JoinMessage, LeaveMessage,
-    MessageTypes, TopicMessagesMessage, TopicMetadataMessage,
+    BlobMessage, BlobPreview, ChatMessage, CollectionEntry, CollectionMessage, DisconnectMessage,
+    DmBlobMessage, DmChatMessage, DmCollectionMessage, DmDisappearingMessage, DmJoinMessage,
+    DmMessageTypes, DmProfileMetadataMessage, DmTypingMessage, GossipMessage, JoinMessage,
+    LeaveMessage, MessageTypes, ProfileMetadataMessage, TopicMessagesMessage, TopicMetadataMessage,
+    TypingMessage,
 };
-pub use types::Ticket;
+pub use presence::DEFAULT_HEARTBEAT_INTERVAL;
+pub use protocol::{DmAbuseEvent, DmLimits};
+pub use safety::safety_number;
+pub use spam::{GossipAbuseEvent, GossipLimits};
+pub use types::{CollectionAddProgress, Ticket};
 pub use utils::load_secret_key;
 
 pub use iroh_blobs::api::blobs::{AddProgress, AddProgressItem, ExportProgress};
@@ -62,3 +101,4 @@ pub use iroh_blobs::api::downloader::{DownloadProgress, DownloadProgressItem};
 pub use iroh_blobs::BlobFormat::Raw;
 pub use iroh_blobs::Hash;
 pub use iroh_blobs::ticket::BlobTicket;
+pub use iroh_gossip::proto::TopicId;
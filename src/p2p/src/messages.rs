@@ -1,3 +1,6 @@
+use crate::crdt::TopicState;
+use crate::crypto::BlobKey;
+use crate::hlc::Hlc;
 use iroh::EndpointId;
 use iroh_blobs::Hash;
 use iroh_gossip::proto::TopicId;
@@ -22,6 +25,12 @@ pub enum MessageTypes {
     TopicMessages(TopicMessagesMessage),
     /// A notification about a blob (file/image) shared in the topic.
     Blob(BlobMessage),
+    /// A notification about a set of files (or a folder) shared in the topic as one collection.
+    Collection(CollectionMessage),
+    /// An ephemeral signal that the sender is typing. It is never stored or synced as history.
+    Typing(TypingMessage),
+    /// The profile of a member, announced when joining and whenever it changes.
+    ProfileMetadata(ProfileMetadataMessage),
 }
 
 /// A trait for messages that are associated with a specific gossip topic.
@@ -47,9 +56,16 @@ pub struct BlobMessage {
     pub timestamp: u64,
     /// The type of blob (Image, File, etc.).
     pub blob_type: BlobType,
+    /// An inline preview for images and videos, available before the blob is downloaded.
+    pub preview: Option<BlobPreview>,
+    /// The key the blob was encrypted with, if it was encrypted before being stored.
+    pub key: Option<BlobKey>,
+    /// The hybrid logical clock of the sender when the blob was shared, used for ordering.
+    pub hlc: Hlc,
 }
 
 impl BlobMessage {
+    #[allow(clippy::too_many_arguments)]
     #[must_use]
     pub const fn new(
         topic: TopicId,
@@ -59,6 +75,8 @@ impl BlobMessage {
         hash: Hash,
         timestamp: u64,
         blob_type: BlobType,
+        preview: Option<BlobPreview>,
+        key: Option<BlobKey>,
     ) -> Self {
         Self {
             topic,
@@ -68,6 +86,40 @@ impl BlobMessage {
             hash,
             timestamp,
             blob_type,
+            preview,
+            key,
+            hlc: Hlc::from_wall(timestamp),
+        }
+    }
+
+    #[must_use]
+    pub const fn with_hlc(mut self, hlc: Hlc) -> Self {
+        self.hlc = hlc;
+        self
+    }
+}
+
+/// A tiny low-resolution preview of an image or video blob.
+///
+/// It travels inside the message itself, so it must stay small enough to fit in a single gossip
+/// message next to the rest of the fields.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobPreview {
+    /// The width in pixels of the original image or video.
+    pub width: u32,
+    /// The height in pixels of the original image or video.
+    pub height: u32,
+    /// A JPEG thumbnail of the image, or of the poster frame for videos.
+    pub thumbnail: Vec<u8>,
+}
+
+impl BlobPreview {
+    #[must_use]
+    pub const fn new(width: u32, height: u32, thumbnail: Vec<u8>) -> Self {
+        Self {
+            width,
+            height,
+            thumbnail,
         }
     }
 }
@@ -89,26 +141,129 @@ impl GossipMessage for BlobMessage {
     }
 }
 
+/// A single named file inside a shared collection.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionEntry {
+    /// The relative path of the file inside the collection.
+    pub name: String,
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// The hash of the raw blob holding the file contents.
+    pub hash: Hash,
+    /// The key the file is encrypted with, if it is.
+    pub key: Option<BlobKey>,
+}
+
+impl CollectionEntry {
+    #[must_use]
+    pub const fn new(name: String, size: u64, hash: Hash) -> Self {
+        Self {
+            name,
+            size,
+            hash,
+            key: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_key(mut self, key: BlobKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+}
+
+/// Represents a set of files or a whole folder shared in a topic.
+///
+/// The files are stored as an iroh-blobs `HashSeq` collection, so the whole batch can be
+/// fetched with a single download of `hash`, or each entry can be fetched on its own.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CollectionMessage {
+    /// The topic where this collection was shared.
+    pub topic: TopicId,
+    /// The ID of the sender.
+    pub sender: EndpointId,
+    /// The display name of the collection (usually the folder name).
+    pub name: String,
+    /// The root hash of the `HashSeq` collection.
+    pub hash: Hash,
+    /// The files contained in the collection.
+    pub entries: Vec<CollectionEntry>,
+    /// The timestamp when the collection was shared.
+    pub timestamp: u64,
+    /// The hybrid logical clock of the sender when the collection was shared, used for ordering.
+    pub hlc: Hlc,
+}
+
+impl CollectionMessage {
+    #[must_use]
+    pub const fn new(
+        topic: TopicId,
+        sender: EndpointId,
+        name: String,
+        hash: Hash,
+        entries: Vec<CollectionEntry>,
+        timestamp: u64,
+    ) -> Self {
+        Self {
+            topic,
+            sender,
+            name,
+            hash,
+            entries,
+            timestamp,
+            hlc: Hlc::from_wall(timestamp),
+        }
+    }
+
+    #[must_use]
+    pub const fn with_hlc(mut self, hlc: Hlc) -> Self {
+        self.hlc = hlc;
+        self
+    }
+
+    /// Returns the combined size in bytes of every entry.
+    #[must_use]
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+}
+
+impl GossipMessage for CollectionMessage {
+    fn topic_id(&self) -> &TopicId {
+        &self.topic
+    }
+}
+
 /// Represents a collection of chat messages for a specific topic.
 /// Often used for syncing history or sending batched updates.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TopicMessagesMessage {
     pub topic: TopicId,
     pub messages: Vec<ChatMessage>,
+    /// The only peer that should answer with the messages missing from `messages`, if any.
+    /// Everyone else only merges them, so a sync request is not answered by the whole topic.
+    pub responder: Option<EndpointId>,
 }
 
 impl TopicMessagesMessage {
     #[must_use]
     pub const fn new(topic: TopicId, messages: Vec<ChatMessage>) -> Self {
-        Self { topic, messages }
+        Self {
+            topic,
+            messages,
+            responder: None,
+        }
     }
 
     #[must_use]
     pub const fn new_empty(topic: TopicId) -> Self {
-        Self {
-            topic,
-            messages: Vec::new(),
-        }
+        Self::new(topic, Vec::new())
+    }
+
+    #[must_use]
+    pub const fn with_responder(mut self, responder: EndpointId) -> Self {
+        self.responder = Some(responder);
+        self
     }
 }
 
@@ -193,33 +348,19 @@ impl GossipMessage for JoinMessage {
     }
 }
 
-/// Contains metadata describing a topic, such as its display name and members.
+/// Contains the replicated state of a topic, such as its display name and members.
+///
+/// Peers merge the state they receive into their own, so concurrent edits converge.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TopicMetadataMessage {
     pub topic: TopicId,
-    pub name: String,
-    pub avatar_url: Option<String>,
-    pub timestamp: u64,
-    /// A list of member identifiers (as strings) currently in the topic.
-    pub members: Vec<String>,
+    pub state: TopicState,
 }
 
 impl TopicMetadataMessage {
     #[must_use]
-    pub fn new(
-        topic: TopicId,
-        name: &str,
-        avatar_url: Option<String>,
-        timestamp: u64,
-        members: Vec<String>,
-    ) -> Self {
-        Self {
-            topic,
-            name: name.to_string(),
-            avatar_url,
-            timestamp,
-            members,
-        }
+    pub const fn new(topic: TopicId, state: TopicState) -> Self {
+        Self { topic, state }
     }
 }
 
@@ -235,7 +376,10 @@ pub struct ChatMessage {
     pub sender: EndpointId,
     pub topic_id: TopicId,
     pub content: String,
+    /// The wall clock of the sender, for display.
     pub timestamp: u64,
+    /// The hybrid logical clock of the sender, used for ordering.
+    pub hlc: Hlc,
 }
 
 impl ChatMessage {
@@ -251,8 +395,15 @@ impl ChatMessage {
             topic_id,
             content,
             timestamp,
+            hlc: Hlc::from_wall(timestamp),
         }
     }
+
+    #[must_use]
+    pub const fn with_hlc(mut self, hlc: Hlc) -> Self {
+        self.hlc = hlc;
+        self
+    }
 }
 
 impl GossipMessage for ChatMessage {
@@ -271,6 +422,71 @@ impl Display for ChatMessage {
     }
 }
 
+/// Signals that a member is typing in a topic.
+///
+/// The receiver shows it for a short time only, the sender repeats it while the user keeps typing.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TypingMessage {
+    pub topic: TopicId,
+    pub sender: EndpointId,
+    pub timestamp: u64,
+}
+
+impl TypingMessage {
+    #[must_use]
+    pub const fn new(topic: TopicId, sender: EndpointId, timestamp: u64) -> Self {
+        Self {
+            topic,
+            sender,
+            timestamp,
+        }
+    }
+}
+
+impl GossipMessage for TypingMessage {
+    fn topic_id(&self) -> &TopicId {
+        &self.topic
+    }
+}
+
+/// Announces the profile of a member to the rest of a topic, so members who are not contacts
+/// are shown by name.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProfileMetadataMessage {
+    pub topic: TopicId,
+    pub id: EndpointId,
+    pub username: String,
+    /// The hash of the avatar blob, fetched from the member announcing it.
+    pub avatar: Option<Hash>,
+    /// When the profile was announced, the latest announcement of a member wins.
+    pub timestamp: u64,
+}
+
+impl ProfileMetadataMessage {
+    #[must_use]
+    pub const fn new(
+        topic: TopicId,
+        id: EndpointId,
+        username: String,
+        avatar: Option<Hash>,
+        timestamp: u64,
+    ) -> Self {
+        Self {
+            topic,
+            id,
+            username,
+            avatar,
+            timestamp,
+        }
+    }
+}
+
+impl GossipMessage for ProfileMetadataMessage {
+    fn topic_id(&self) -> &TopicId {
+        &self.topic
+    }
+}
+
 /// Enum representing types of messages sent via Direct Message (DM).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DmMessageTypes {
@@ -278,6 +494,39 @@ pub enum DmMessageTypes {
     ProfileMetadata(DmProfileMetadataMessage),
     JoinPetition(DmJoinMessage),
     Blob(DmBlobMessage),
+    Collection(DmCollectionMessage),
+    Typing(DmTypingMessage),
+    Disappearing(DmDisappearingMessage),
+}
+
+impl DmMessageTypes {
+    /// The sender declared in the message.
+    #[must_use]
+    pub const fn sender(&self) -> EndpointId {
+        match self {
+            Self::Chat(msg) => msg.sender,
+            Self::ProfileMetadata(msg) => msg.id,
+            Self::JoinPetition(msg) => msg.petitioner,
+            Self::Blob(msg) => msg.sender,
+            Self::Collection(msg) => msg.sender,
+            Self::Typing(msg) => msg.sender,
+            Self::Disappearing(msg) => msg.sender,
+        }
+    }
+
+    /// The receiver declared in the message, profiles do not name one.
+    #[must_use]
+    pub const fn receiver(&self) -> Option<EndpointId> {
+        match self {
+            Self::Chat(msg) => Some(msg.receiver),
+            Self::ProfileMetadata(_) => None,
+            Self::JoinPetition(msg) => Some(msg.target),
+            Self::Blob(msg) => Some(msg.receiver),
+            Self::Collection(msg) => Some(msg.receiver),
+            Self::Typing(msg) => Some(msg.receiver),
+            Self::Disappearing(msg) => Some(msg.receiver),
+        }
+    }
 }
 
 /// Carries profile information for a user in a direct message context.
@@ -285,7 +534,8 @@ pub enum DmMessageTypes {
 pub struct DmProfileMetadataMessage {
     pub id: EndpointId,
     pub username: String,
-    pub avatar_url: Option<String>,
+    /// The hash of the avatar blob, fetched from the sender.
+    pub avatar: Option<Hash>,
     pub last_connection: u64,
 }
 
@@ -294,13 +544,13 @@ impl DmProfileMetadataMessage {
     pub const fn new(
         id: EndpointId,
         username: String,
-        avatar_url: Option<String>,
+        avatar: Option<Hash>,
         last_connection: u64,
     ) -> Self {
         Self {
             id,
             username,
-            avatar_url,
+            avatar,
             last_connection,
         }
     }
@@ -346,6 +596,54 @@ impl DmJoinMessage {
     }
 }
 
+/// Signals that a peer is typing in a direct chat, see [`TypingMessage`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DmTypingMessage {
+    pub sender: EndpointId,
+    pub receiver: EndpointId,
+    pub timestamp: u64,
+}
+
+impl DmTypingMessage {
+    #[must_use]
+    pub const fn new(sender: EndpointId, receiver: EndpointId, timestamp: u64) -> Self {
+        Self {
+            sender,
+            receiver,
+            timestamp,
+        }
+    }
+}
+
+/// Sets how long the messages of a direct chat are kept before they disappear, on both sides.
+///
+/// The latest setting wins, whichever peer changed it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DmDisappearingMessage {
+    pub sender: EndpointId,
+    pub receiver: EndpointId,
+    /// The number of seconds messages are kept for, `None` to keep them.
+    pub seconds: Option<u64>,
+    pub timestamp: u64,
+}
+
+impl DmDisappearingMessage {
+    #[must_use]
+    pub const fn new(
+        sender: EndpointId,
+        receiver: EndpointId,
+        seconds: Option<u64>,
+        timestamp: u64,
+    ) -> Self {
+        Self {
+            sender,
+            receiver,
+            seconds,
+            timestamp,
+        }
+    }
+}
+
 /// A file or binary object shared directly between two peers.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DmBlobMessage {
@@ -356,9 +654,12 @@ pub struct DmBlobMessage {
     pub hash: Hash,
     pub timestamp: u64,
     pub blob_type: BlobType,
+    pub preview: Option<BlobPreview>,
+    pub key: Option<BlobKey>,
 }
 
 impl DmBlobMessage {
+    #[allow(clippy::too_many_arguments)]
     #[must_use]
     pub const fn new(
         sender: EndpointId,
@@ -368,6 +669,8 @@ impl DmBlobMessage {
         hash: Hash,
         timestamp: u64,
         blob_type: BlobType,
+        preview: Option<BlobPreview>,
+        key: Option<BlobKey>,
     ) -> Self {
         Self {
             sender,
@@ -377,8 +680,48 @@ impl DmBlobMessage {
             hash,
             timestamp,
             blob_type,
+            preview,
+            key,
+        }
+    }
+}
+
+/// A set of files or a whole folder shared directly between two peers as one collection.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DmCollectionMessage {
+    pub sender: EndpointId,
+    pub receiver: EndpointId,
+    pub name: String,
+    pub hash: Hash,
+    pub entries: Vec<CollectionEntry>,
+    pub timestamp: u64,
+}
+
+impl DmCollectionMessage {
+    #[must_use]
+    pub const fn new(
+        sender: EndpointId,
+        receiver: EndpointId,
+        name: String,
+        hash: Hash,
+        entries: Vec<CollectionEntry>,
+        timestamp: u64,
+    ) -> Self {
+        Self {
+            sender,
+            receiver,
+            name,
+            hash,
+            entries,
+            timestamp,
         }
     }
+
+    /// Returns the combined size in bytes of every entry.
+    #[must_use]
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
 }
 
 #[cfg(test)]
@@ -412,4 +755,28 @@ mod tests {
         assert_eq!(original_message.content, deserialized.content);
         assert_eq!(original_message.timestamp, deserialized.timestamp);
     }
+
+    #[test]
+    fn test_collection_message_total_size() {
+        let message = CollectionMessage::new(
+            TopicId::from_bytes(rand::random()),
+            SecretKey::generate(&mut rand::rng()).public(),
+            "holiday".to_string(),
+            Hash::new(b"root"),
+            vec![
+                CollectionEntry::new("a.jpg".to_string(), 1_024, Hash::new(b"a")),
+                CollectionEntry::new("nested/b.jpg".to_string(), 2_048, Hash::new(b"b")),
+            ],
+            1_625_247_600_000,
+        );
+
+        let serialized =
+            postcard::to_allocvec(&message).expect("Failed to serialize collection message");
+        let deserialized: CollectionMessage =
+            postcard::from_bytes(&serialized).expect("Failed to deserialize collection message");
+
+        assert_eq!(deserialized.total_size(), 3_072);
+        assert_eq!(deserialized.entries, message.entries);
+        assert_eq!(deserialized.hash, message.hash);
+    }
 }
@@ -0,0 +1,186 @@
+//! # Presence
+//!
+//! A tiny heartbeat protocol: a peer is online if it answers a ping on [`PRESENCE_ALPN`].
+//! The [`crate::ChatClient`] pings every watched peer once per heartbeat interval and reports
+//! each change as a [`crate::ChatEvent::Presence`]. Only watched peers that are not blocked get
+//! an answer, everyone else sees us offline.
+
+use crate::block::BlockList;
+use iroh::endpoint::{Connection, VarInt};
+use iroh::protocol::{AcceptError, ProtocolHandler};
+use iroh::{Endpoint, EndpointId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The Application-Layer Protocol Negotiation (ALPN) string used for presence pings.
+pub const PRESENCE_ALPN: &[u8] = b"nexu/presence/0";
+
+/// The default time between two heartbeats.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The longest a single ping may take before the peer is considered offline.
+const MAX_PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Application error code used when refusing a ping from a peer that is not watched, or blocked.
+const REFUSED_CLOSE_CODE: u32 = 1;
+
+const PING: &[u8; 4] = b"ping";
+const PONG: &[u8; 4] = b"pong";
+
+/// The protocol handler answering presence pings.
+///
+/// The watched peers are our contacts, so they double as the list of peers allowed to see us
+/// online.
+#[derive(Debug, Clone)]
+pub struct PresenceProtocol {
+    contacts: Presence,
+    blocked: BlockList,
+}
+
+impl PresenceProtocol {
+    pub(crate) const fn new(contacts: Presence, blocked: BlockList) -> Self {
+        Self { contacts, blocked }
+    }
+}
+
+impl ProtocolHandler for PresenceProtocol {
+    /// Answers a single ping from a contact and closes the connection once the pong is
+    /// delivered.
+    fn accept(
+        &self,
+        connection: Connection,
+    ) -> impl Future<Output = Result<(), AcceptError>> + Send {
+        let protocol = self.clone();
+        Box::pin(async move {
+            let remote_id = connection.remote_id();
+            if protocol.blocked.is_blocked(&remote_id) || !protocol.contacts.is_watched(&remote_id)
+            {
+                connection.close(VarInt::from_u32(REFUSED_CLOSE_CODE), b"refused");
+                return Ok(());
+            }
+
+            let answer = async {
+                let (mut send, mut recv) = connection.accept_bi().await.ok()?;
+                let mut ping = [0u8; PING.len()];
+                recv.read_exact(&mut ping).await.ok()?;
+                if &ping != PING {
+                    return None;
+                }
+                send.write_all(PONG).await.ok()?;
+                send.finish().ok()?;
+                // Closing before the pong is acknowledged would discard it.
+                send.stopped().await.ok()
+            };
+            let _ = tokio::time::timeout(MAX_PING_TIMEOUT, answer).await;
+            connection.close(VarInt::from_u32(0), b"pong sent");
+            Ok(())
+        })
+    }
+}
+
+/// Pings `peer`, returning whether it answered before `interval` (capped to ten seconds).
+pub(crate) async fn ping(endpoint: &Endpoint, peer: EndpointId, interval: Duration) -> bool {
+    let attempt = async {
+        let connection = endpoint.connect(peer, PRESENCE_ALPN).await.ok()?;
+        let (mut send, mut recv) = connection.open_bi().await.ok()?;
+        send.write_all(PING).await.ok()?;
+        send.finish().ok()?;
+        let mut pong = [0u8; PONG.len()];
+        recv.read_exact(&mut pong).await.ok()?;
+        connection.close(VarInt::from_u32(0), b"pong received");
+        Some(&pong == PONG)
+    };
+
+    matches!(
+        tokio::time::timeout(interval.min(MAX_PING_TIMEOUT), attempt).await,
+        Ok(Some(true))
+    )
+}
+
+/// The peers whose presence is watched, with the last state reported for each one.
+///
+/// The state is `None` until the first heartbeat, so the first result is always reported.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Presence {
+    peers: Arc<Mutex<HashMap<EndpointId, Option<bool>>>>,
+}
+
+impl Presence {
+    pub(crate) fn watch(&self, peer: EndpointId) {
+        self.peers
+            .lock()
+            .expect("presence lock poisoned")
+            .entry(peer)
+            .or_insert(None);
+    }
+
+    pub(crate) fn unwatch(&self, peer: &EndpointId) {
+        self.peers
+            .lock()
+            .expect("presence lock poisoned")
+            .remove(peer);
+    }
+
+    pub(crate) fn is_watched(&self, peer: &EndpointId) -> bool {
+        self.peers
+            .lock()
+            .expect("presence lock poisoned")
+            .contains_key(peer)
+    }
+
+    pub(crate) fn peers(&self) -> Vec<EndpointId> {
+        self.peers
+            .lock()
+            .expect("presence lock poisoned")
+            .keys()
+            .copied()
+            .collect()
+    }
+
+    /// Records the result of a ping, returning whether the peer is still watched and its state
+    /// changed.
+    pub(crate) fn update(&self, peer: EndpointId, online: bool) -> bool {
+        let mut peers = self.peers.lock().expect("presence lock poisoned");
+        match peers.get_mut(&peer) {
+            Some(state) if *state != Some(online) => {
+                *state = Some(online);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::random_endpoint;
+
+    #[test]
+    fn test_update_reports_changes_only() {
+        let presence = Presence::default();
+        let peer = random_endpoint();
+        presence.watch(peer);
+
+        assert!(presence.update(peer, false));
+        assert!(!presence.update(peer, false));
+        assert!(presence.update(peer, true));
+        assert!(!presence.update(peer, true));
+    }
+
+    #[test]
+    fn test_unwatched_peers_are_ignored() {
+        let presence = Presence::default();
+        let peer = random_endpoint();
+
+        assert!(!presence.update(peer, true));
+
+        presence.watch(peer);
+        assert!(presence.is_watched(&peer));
+        presence.unwatch(&peer);
+        assert!(!presence.is_watched(&peer));
+        assert!(presence.peers().is_empty());
+        assert!(!presence.update(peer, true));
+    }
+}
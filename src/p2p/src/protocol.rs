@@ -4,11 +4,17 @@
 //! system in Nexu. It handles the low-level details of accepting connections,
 //! reading/writing frames, and dispatching incoming messages to the application via a channel.
 
+use crate::block::BlockList;
+use crate::error::Error;
+use crate::events::ChatEvent;
 use crate::messages::DmMessageTypes;
 use flume::Sender;
 use iroh::EndpointId;
-use iroh::endpoint::{Connection, RecvStream, SendStream};
+use iroh::endpoint::{Connection, RecvStream, SendStream, VarInt};
 use iroh::protocol::{AcceptError, ProtocolHandler};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 /// The Application-Layer Protocol Negotiation (ALPN) string used for Nexu Direct Messages.
@@ -16,45 +22,286 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 /// This string identifies the protocol version `nexu/dm/0`.
 pub const DM_ALPN: &[u8] = b"nexu/dm/0";
 
+/// The default maximum size in bytes of a single DM frame, matching the gossip message limit.
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 1_048_576;
+
+/// Application error code used when closing a connection that broke the limits.
+const ABUSE_CLOSE_CODE: u32 = 1;
+
+/// Application error code used when refusing a connection from a blocked peer.
+const BLOCKED_CLOSE_CODE: u32 = 2;
+
+/// Limits enforced on every incoming DM connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmLimits {
+    /// The maximum size in bytes of a single frame.
+    pub max_frame_size: u32,
+    /// The maximum number of messages a peer may send per second.
+    pub max_messages_per_second: u32,
+    /// The maximum number of bytes a peer may send per second.
+    pub max_bytes_per_second: u64,
+}
+
+impl DmLimits {
+    #[must_use]
+    pub const fn new(
+        max_frame_size: u32,
+        max_messages_per_second: u32,
+        max_bytes_per_second: u64,
+    ) -> Self {
+        Self {
+            max_frame_size,
+            max_messages_per_second,
+            max_bytes_per_second,
+        }
+    }
+}
+
+impl Default for DmLimits {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_MAX_FRAME_SIZE,
+            50,
+            4 * u64::from(DEFAULT_MAX_FRAME_SIZE),
+        )
+    }
+}
+
+/// A peer broke one of the [`DmLimits`] or forged a message; its connection has been closed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DmAbuseEvent {
+    /// The peer announced a frame larger than the maximum frame size.
+    FrameTooLarge { peer: EndpointId, size: u32 },
+    /// The peer sent more messages per second than allowed.
+    MessageRateExceeded { peer: EndpointId },
+    /// The peer sent more bytes per second than allowed.
+    ByteRateExceeded { peer: EndpointId },
+    /// The peer sent a message claiming another sender, or meant for another receiver.
+    IdentityMismatch {
+        peer: EndpointId,
+        sender: EndpointId,
+        receiver: Option<EndpointId>,
+    },
+}
+
+impl DmAbuseEvent {
+    /// The peer that caused the event.
+    #[must_use]
+    pub const fn peer(&self) -> &EndpointId {
+        match self {
+            Self::FrameTooLarge { peer, .. }
+            | Self::MessageRateExceeded { peer }
+            | Self::ByteRateExceeded { peer }
+            | Self::IdentityMismatch { peer, .. } => peer,
+        }
+    }
+}
+
+/// Messages and bytes received from a peer during the current one second window.
+#[derive(Debug)]
+struct PeerRate {
+    window_start: Instant,
+    messages: u32,
+    bytes: u64,
+    /// Open connections of the peer, the rate is dropped once the last one closes.
+    connections: usize,
+}
+
+impl PeerRate {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            messages: 0,
+            bytes: 0,
+            connections: 0,
+        }
+    }
+
+    /// Records a frame of `size` bytes, returning the event if it breaks the rate limits.
+    fn record(
+        &mut self,
+        peer: EndpointId,
+        size: u32,
+        now: Instant,
+        limits: &DmLimits,
+    ) -> Option<DmAbuseEvent> {
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            self.window_start = now;
+            self.messages = 0;
+            self.bytes = 0;
+        }
+        self.messages += 1;
+        self.bytes += u64::from(size);
+
+        if self.messages > limits.max_messages_per_second {
+            Some(DmAbuseEvent::MessageRateExceeded { peer })
+        } else if self.bytes > limits.max_bytes_per_second {
+            Some(DmAbuseEvent::ByteRateExceeded { peer })
+        } else {
+            None
+        }
+    }
+}
+
 /// The protocol handler for Direct Messages.
 ///
 /// This struct implements [`ProtocolHandler`], allowing it to be registered with the iroh Router.
 /// When a peer connects using `DM_ALPN`, the `accept` method is called.
 #[derive(Debug, Clone)]
 pub struct DMProtocol {
-    /// Channel sender to forward received messages to the main application logic.
-    pub tx: Sender<(EndpointId, DmMessageTypes)>,
+    /// Channel sender to forward received messages, connection changes and abuse to the
+    /// application.
+    events: Sender<ChatEvent>,
+    /// Limits enforced on every incoming connection.
+    limits: DmLimits,
+    /// Rates per peer, shared by every connection of the same peer.
+    rates: Arc<Mutex<HashMap<EndpointId, PeerRate>>>,
+    /// Peers whose connections are refused.
+    blocked: BlockList,
+    /// The id of this endpoint, the only receiver incoming messages may name.
+    local_id: EndpointId,
+}
+
+impl DMProtocol {
+    #[must_use]
+    pub fn new(
+        events: Sender<ChatEvent>,
+        limits: DmLimits,
+        blocked: BlockList,
+        local_id: EndpointId,
+    ) -> Self {
+        Self {
+            events,
+            limits,
+            rates: Arc::new(Mutex::new(HashMap::new())),
+            blocked,
+            local_id,
+        }
+    }
+
+    /// Counts a new connection from `peer`, sharing its rate with the ones already open.
+    fn connect(&self, peer: EndpointId) {
+        let mut rates = self.rates.lock().expect("DM rates lock poisoned");
+        rates
+            .entry(peer)
+            .or_insert_with(|| PeerRate::new(Instant::now()))
+            .connections += 1;
+    }
+
+    /// Forgets a closed connection from `peer`, dropping its rate with the last one.
+    fn disconnect(&self, peer: &EndpointId) {
+        let mut rates = self.rates.lock().expect("DM rates lock poisoned");
+        if let Some(rate) = rates.get_mut(peer) {
+            rate.connections = rate.connections.saturating_sub(1);
+            if rate.connections == 0 {
+                rates.remove(peer);
+            }
+        }
+    }
+
+    /// Records a frame from `peer`, returning the event if it breaks the rate limits.
+    fn record(&self, peer: EndpointId, size: u32) -> Option<DmAbuseEvent> {
+        let now = Instant::now();
+        let mut rates = self.rates.lock().expect("DM rates lock poisoned");
+        rates
+            .entry(peer)
+            .or_insert_with(|| PeerRate::new(now))
+            .record(peer, size, now, &self.limits)
+    }
+
+    /// Checks the identities embedded in a message against the authenticated connection: the
+    /// sender must be the remote peer and the receiver, if any, this endpoint.
+    fn check_identity(&self, remote_id: EndpointId, msg: &DmMessageTypes) -> Option<DmAbuseEvent> {
+        let sender = msg.sender();
+        let receiver = msg.receiver();
+        if sender == remote_id && receiver.is_none_or(|receiver| receiver == self.local_id) {
+            return None;
+        }
+        Some(DmAbuseEvent::IdentityMismatch {
+            peer: remote_id,
+            sender,
+            receiver,
+        })
+    }
+
+    /// Closes an abusive connection and reports why.
+    fn report(&self, connection: &Connection, event: DmAbuseEvent) {
+        eprintln!("Closing DM connection: {event:?}");
+        connection.close(VarInt::from_u32(ABUSE_CLOSE_CODE), b"limit exceeded");
+        // The connection is closed anyway, so the event can be dropped if nobody reads them.
+        let _ = self.events.try_send(ChatEvent::DmAbuse(event));
+    }
 }
 
 impl ProtocolHandler for DMProtocol {
     /// Accepts an incoming connection for the DM protocol.
     ///
     /// This method spawns a task to continuously read messages from the incoming bidirectional stream.
-    /// Received messages are deserialized and sent through the `tx` channel along with the sender's `EndpointId`.
+    /// Received messages are deserialized and sent through the event channel along with the sender's `EndpointId`.
+    /// Frames above the maximum size, peers going over the rate limits, or messages naming another
+    /// sender or receiver than the two ends of the connection, close the connection.
+    /// Connections from blocked peers are closed right away.
     fn accept(
         &self,
         connection: Connection,
     ) -> impl Future<Output = Result<(), AcceptError>> + Send {
-        let tx = self.tx.clone();
+        let protocol = self.clone();
         Box::pin(async move {
-            let (_send, mut recv) = connection.accept_bi().await?;
             let remote_id = connection.remote_id();
+            if protocol.blocked.is_blocked(&remote_id) {
+                connection.close(VarInt::from_u32(BLOCKED_CLOSE_CODE), b"blocked");
+                return Ok(());
+            }
 
+            let (_send, mut recv) = connection.accept_bi().await?;
+            let _ = protocol
+                .events
+                .try_send(ChatEvent::PeerConnected { peer: remote_id });
+
+            protocol.connect(remote_id);
             tokio::spawn(async move {
                 loop {
-                    match read_frame(&mut recv).await {
-                        Ok(Some(msg)) => {
-                            if tx.send((remote_id, msg)).is_err() {
+                    match read_frame(&mut recv, protocol.limits.max_frame_size).await {
+                        Ok(Frame::Message(msg, size)) => {
+                            // The peer may have been blocked after connecting.
+                            if protocol.blocked.is_blocked(&remote_id) {
+                                connection.close(VarInt::from_u32(BLOCKED_CLOSE_CODE), b"blocked");
+                                break;
+                            }
+                            if let Some(event) = protocol
+                                .record(remote_id, size)
+                                .or_else(|| protocol.check_identity(remote_id, &msg))
+                            {
+                                protocol.report(&connection, event);
+                                break;
+                            }
+                            let event = ChatEvent::DirectMessage {
+                                sender: remote_id,
+                                message: msg,
+                            };
+                            if protocol.events.send_async(event).await.is_err() {
                                 break;
                             }
                         }
-                        Ok(None) => break,
+                        Ok(Frame::TooLarge(size)) => {
+                            let event = DmAbuseEvent::FrameTooLarge {
+                                peer: remote_id,
+                                size,
+                            };
+                            protocol.report(&connection, event);
+                            break;
+                        }
+                        Ok(Frame::Closed) => break,
                         Err(e) => {
                             eprintln!("Error reading frame: {e}");
                             break;
                         }
                     }
                 }
+                protocol.disconnect(&remote_id);
+                let _ = protocol
+                    .events
+                    .try_send(ChatEvent::PeerDisconnected { peer: remote_id });
             });
 
             Ok(())
@@ -71,33 +318,180 @@ impl ProtocolHandler for DMProtocol {
 ///
 /// * `stream` - The output stream to write to.
 /// * `message` - The byte slice containing the serialized message.
+/// * `max_frame_size` - The largest frame allowed, see [`DmLimits::max_frame_size`].
 /// 
 /// # Errors
 /// 
-/// If the message length cant be parsed to a u32 or if it cant write to the stream
-pub async fn write_frame(stream: &mut SendStream, message: &[u8]) -> anyhow::Result<()> {
-    let len = u32::try_from(message.len())?;
-    stream.write_u32(len).await?;
-    stream.write_all(message).await?;
+/// If the message length cant be parsed to a u32, if it is above `max_frame_size`
+/// or if it cant write to the stream
+pub async fn write_frame(
+    stream: &mut SendStream,
+    message: &[u8],
+    max_frame_size: u32,
+) -> Result<(), Error> {
+    let len = u32::try_from(message.len())
+        .ok()
+        .filter(|len| *len <= max_frame_size)
+        .ok_or(Error::MessageTooLarge(message.len()))?;
+    stream
+        .write_u32(len)
+        .await
+        .map_err(|e| Error::Connection(e.to_string()))?;
+    stream
+        .write_all(message)
+        .await
+        .map_err(|e| Error::Connection(e.to_string()))?;
 
     Ok(())
 }
 
+/// The outcome of reading a single frame from a DM stream.
+enum Frame {
+    /// A message, together with the size in bytes of its frame.
+    Message(DmMessageTypes, u32),
+    /// The peer announced a frame above the maximum size; nothing was read or allocated.
+    TooLarge(u32),
+    /// The stream has closed.
+    Closed,
+}
+
 /// Reads a length-prefixed message frame from the stream.
 ///
 /// 1. Reads a `u32` to determine the message length.
-/// 2. Reads that many bytes into a buffer.
-/// 3. Deserializes the buffer into a [`DmMessageTypes`] enum using `postcard`.
+/// 2. Rejects the frame if the length is above `max_frame_size`.
+/// 3. Reads that many bytes into a buffer.
+/// 4. Deserializes the buffer into a [`DmMessageTypes`] enum using `postcard`.
 ///
-/// Returns `Ok(None)` if the stream has closed (EOF) while trying to read the length.
-async fn read_frame(stream: &mut RecvStream) -> anyhow::Result<Option<DmMessageTypes>> {
+/// Returns [`Frame::Closed`] if the stream has closed (EOF) while trying to read the length.
+async fn read_frame(stream: &mut RecvStream, max_frame_size: u32) -> anyhow::Result<Frame> {
     let Ok(frame_len) = stream.read_u32().await else {
-        return Ok(None);
+        return Ok(Frame::Closed);
     };
 
+    if frame_len > max_frame_size {
+        return Ok(Frame::TooLarge(frame_len));
+    }
+
     let mut buf = vec![0u8; frame_len as usize];
     stream.read_exact(&mut buf).await?;
 
     let message = postcard::from_bytes(&buf)?;
-    Ok(Some(message))
+    Ok(Frame::Message(message, frame_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::random_endpoint;
+
+    #[test]
+    fn test_message_rate_limit() {
+        let peer = random_endpoint();
+        let limits = DmLimits::new(DEFAULT_MAX_FRAME_SIZE, 3, u64::MAX);
+        let now = Instant::now();
+        let mut rate = PeerRate::new(now);
+
+        for _ in 0..3 {
+            assert_eq!(rate.record(peer, 10, now, &limits), None);
+        }
+        assert_eq!(
+            rate.record(peer, 10, now, &limits),
+            Some(DmAbuseEvent::MessageRateExceeded { peer })
+        );
+    }
+
+    #[test]
+    fn test_byte_rate_limit() {
+        let peer = random_endpoint();
+        let limits = DmLimits::new(DEFAULT_MAX_FRAME_SIZE, u32::MAX, 100);
+        let now = Instant::now();
+        let mut rate = PeerRate::new(now);
+
+        assert_eq!(rate.record(peer, 60, now, &limits), None);
+        assert_eq!(
+            rate.record(peer, 60, now, &limits),
+            Some(DmAbuseEvent::ByteRateExceeded { peer })
+        );
+    }
+
+    #[test]
+    fn test_rate_window_resets() {
+        let peer = random_endpoint();
+        let limits = DmLimits::new(DEFAULT_MAX_FRAME_SIZE, 1, u64::MAX);
+        let now = Instant::now();
+        let mut rate = PeerRate::new(now);
+
+        assert_eq!(rate.record(peer, 10, now, &limits), None);
+        let later = now + Duration::from_secs(1);
+        assert_eq!(rate.record(peer, 10, later, &limits), None);
+    }
+
+    #[test]
+    fn test_rate_is_dropped_with_the_last_connection() {
+        let peer = random_endpoint();
+        let (events, _) = flume::bounded(1);
+        let protocol = DMProtocol::new(
+            events,
+            DmLimits::default(),
+            BlockList::new(),
+            random_endpoint(),
+        );
+        let is_tracked = || {
+            protocol
+                .rates
+                .lock()
+                .expect("DM rates lock poisoned")
+                .contains_key(&peer)
+        };
+
+        protocol.connect(peer);
+        protocol.connect(peer);
+        assert_eq!(protocol.record(peer, 10), None);
+
+        protocol.disconnect(&peer);
+        assert!(is_tracked());
+        protocol.disconnect(&peer);
+        assert!(!is_tracked());
+    }
+
+    #[test]
+    fn test_identity_mismatch() {
+        let local = random_endpoint();
+        let remote = random_endpoint();
+        let other = random_endpoint();
+        let (events, _) = flume::bounded(1);
+        let protocol = DMProtocol::new(events, DmLimits::default(), BlockList::new(), local);
+
+        let typing = |sender, receiver| {
+            DmMessageTypes::Typing(crate::messages::DmTypingMessage::new(sender, receiver, 0))
+        };
+        let profile = |id| {
+            DmMessageTypes::ProfileMetadata(crate::messages::DmProfileMetadataMessage::new(
+                id,
+                "user".to_string(),
+                None,
+                0,
+            ))
+        };
+
+        assert_eq!(
+            protocol.check_identity(remote, &typing(remote, local)),
+            None
+        );
+        assert_eq!(protocol.check_identity(remote, &profile(remote)), None);
+        assert_eq!(
+            protocol.check_identity(remote, &typing(other, local)),
+            Some(DmAbuseEvent::IdentityMismatch {
+                peer: remote,
+                sender: other,
+                receiver: Some(local),
+            })
+        );
+        assert!(
+            protocol
+                .check_identity(remote, &typing(remote, other))
+                .is_some()
+        );
+        assert!(protocol.check_identity(remote, &profile(other)).is_some());
+    }
 }
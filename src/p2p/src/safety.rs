@@ -0,0 +1,74 @@
+//! # Safety Numbers
+//!
+//! A DM partner is identified by its [`EndpointId`], which is its public key, so checking that an
+//! id belongs to the right person is enough to know nobody sits in the middle. Ids are long and
+//! hard to read aloud, so both sides compare a safety number instead: a digest of the two keys
+//! that is the same on both ends, and that changes if either key does.
+//!
+//! Each key is hashed into a 30 digit fingerprint, and the safety number is both fingerprints
+//! ordered the same way on both sides, split in groups of five digits.
+
+use iroh::EndpointId;
+use iroh_blobs::Hash;
+
+/// Domain separation, so the fingerprints cannot be confused with any other hash of the keys.
+const FINGERPRINT_CONTEXT: &[u8] = b"nexu safety number v0";
+
+/// The number of five digit groups in a fingerprint.
+const FINGERPRINT_GROUPS: usize = 6;
+
+/// Computes the safety number shared by `local` and `remote`, e.g. `"01234 56789 ..."`.
+///
+/// The result does not depend on which side computes it.
+#[must_use]
+pub fn safety_number(local: &EndpointId, remote: &EndpointId) -> String {
+    let mut fingerprints = [fingerprint(local), fingerprint(remote)];
+    fingerprints.sort();
+    fingerprints
+        .iter()
+        .flatten()
+        .map(|group| format!("{group:05}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Hashes a key into six groups of five digits, each taken from five bytes of the digest.
+fn fingerprint(key: &EndpointId) -> [u64; FINGERPRINT_GROUPS] {
+    let hash = Hash::new([FINGERPRINT_CONTEXT, key.as_bytes()].concat());
+    let mut groups = [0; FINGERPRINT_GROUPS];
+    for (group, chunk) in groups.iter_mut().zip(hash.as_bytes().chunks_exact(5)) {
+        let value = chunk
+            .iter()
+            .fold(0u64, |value, byte| (value << 8) | u64::from(*byte));
+        *group = value % 100_000;
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::random_endpoint;
+
+    #[test]
+    fn test_safety_number_is_symmetric() {
+        let alice = random_endpoint();
+        let bob = random_endpoint();
+
+        let number = safety_number(&alice, &bob);
+        assert_eq!(number, safety_number(&bob, &alice));
+        assert_eq!(number.split(' ').count(), 2 * FINGERPRINT_GROUPS);
+        assert!(number.split(' ').all(|group| group.len() == 5));
+    }
+
+    #[test]
+    fn test_safety_number_changes_with_either_key() {
+        let alice = random_endpoint();
+        let bob = random_endpoint();
+        let mallory = random_endpoint();
+
+        let number = safety_number(&alice, &bob);
+        assert_ne!(number, safety_number(&alice, &mallory));
+        assert_ne!(number, safety_number(&mallory, &bob));
+    }
+}
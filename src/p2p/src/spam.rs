@@ -0,0 +1,699 @@
+//! # Gossip Flood Protection
+//!
+//! Any subscriber of a topic can broadcast to every other member. This module filters the
+//! incoming gossip messages in the `listen` task, before they reach the application: senders
+//! delivering oversized messages or going over the rate limit are muted locally in that topic for
+//! a while, and the optional slow mode of a topic (carried in its metadata and only taken from
+//! its admin) drops posts sent too close together.
+//!
+//! Gossip messages are not signed, so the sender they declare can be forged: rates and mutes are
+//! keyed on the neighbor that delivered the message together with that sender. A neighbor relays
+//! the messages of every member behind it, so it is only muted for the sender that flooded.
+//! Topic state and history pages declare no sender and come in bursts every time someone joins,
+//! so they have their own budget per neighbor, going over it only drops them.
+
+use crate::crdt::{SignedRegister, TopicState};
+use crate::messages::MessageTypes;
+use iroh::EndpointId;
+use iroh_gossip::proto::TopicId;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The number of typing signals a sender may deliver through a neighbor in a topic per rate
+/// limit window. They do not count against the message rate, so members typing are not muted.
+const MAX_TYPING_PER_WINDOW: u32 = 20;
+
+/// The number of topic states and history pages a neighbor may deliver in a topic per rate limit
+/// window. Every member answers a join with its state and up to five history pages, so this
+/// leaves room for a couple of joins in a busy topic.
+const MAX_SYNC_PER_WINDOW: u32 = 200;
+
+/// A topic, the neighbor that delivered a message in it and the sender the message declares, if
+/// any.
+type RateKey = (TopicId, EndpointId, Option<EndpointId>);
+
+/// Limits enforced on the messages received in every topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GossipLimits {
+    /// The maximum length in bytes of a chat message, a file name or a topic name.
+    pub max_text_length: usize,
+    /// The maximum number of items in a list (synced messages, collection entries, members).
+    pub max_list_length: usize,
+    /// The maximum number of messages a neighbor may deliver from one sender in a topic per
+    /// `window`.
+    pub max_messages_per_window: u32,
+    /// The length of the rate limit window.
+    pub window: Duration,
+    /// How long an offender stays muted.
+    pub mute_duration: Duration,
+}
+
+impl GossipLimits {
+    #[must_use]
+    pub const fn new(
+        max_text_length: usize,
+        max_list_length: usize,
+        max_messages_per_window: u32,
+        window: Duration,
+        mute_duration: Duration,
+    ) -> Self {
+        Self {
+            max_text_length,
+            max_list_length,
+            max_messages_per_window,
+            window,
+            mute_duration,
+        }
+    }
+}
+
+impl Default for GossipLimits {
+    fn default() -> Self {
+        Self::new(
+            4096,
+            1000,
+            20,
+            Duration::from_secs(10),
+            Duration::from_secs(600),
+        )
+    }
+}
+
+/// A sender broke one of the [`GossipLimits`] and its messages delivered by the neighbor `peer`
+/// have been muted locally in a topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GossipAbuseEvent {
+    /// The neighbor delivered a message above the size limits.
+    Oversized {
+        topic: TopicId,
+        peer: EndpointId,
+        sender: Option<EndpointId>,
+    },
+    /// The neighbor delivered more messages of the sender than allowed in the rate limit window.
+    RateExceeded {
+        topic: TopicId,
+        peer: EndpointId,
+        sender: Option<EndpointId>,
+    },
+}
+
+impl GossipAbuseEvent {
+    /// The neighbor that delivered the muted messages.
+    #[must_use]
+    pub const fn peer(&self) -> &EndpointId {
+        match self {
+            Self::Oversized { peer, .. } | Self::RateExceeded { peer, .. } => peer,
+        }
+    }
+}
+
+/// What to do with an incoming gossip message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Forward the message to the application.
+    Accept,
+    /// Silently drop the message.
+    Drop,
+    /// Drop the message; its sender has just been muted on the neighbor that delivered it.
+    Mute(GossipAbuseEvent),
+}
+
+#[derive(Debug)]
+struct SenderRate {
+    window_start: Instant,
+    messages: u32,
+}
+
+#[derive(Debug, Default)]
+struct SpamState {
+    /// The rate of every sender through every neighbor in every topic.
+    rates: HashMap<RateKey, SenderRate>,
+    /// The rate of typing signals of every sender through every neighbor in every topic.
+    typing_rates: HashMap<RateKey, SenderRate>,
+    /// The rate of topic states and history pages of every neighbor in every topic.
+    sync_rates: HashMap<RateKey, SenderRate>,
+    /// The senders muted on every neighbor in every topic, until when.
+    muted: HashMap<RateKey, Instant>,
+    /// The admin of each topic, the only peer allowed to set its slow mode.
+    admins: HashMap<TopicId, EndpointId>,
+    /// The slow mode of each topic, merged from every topic state seen.
+    slow_mode: HashMap<TopicId, SignedRegister<Option<u64>>>,
+    /// The last post of every member in every topic, for slow mode.
+    last_post: HashMap<(TopicId, EndpointId), Instant>,
+    last_pruned: Option<Instant>,
+}
+
+impl SpamState {
+    /// The slow mode interval of `topic`, if it is on.
+    fn slow_mode_interval(&self, topic: &TopicId) -> Option<Duration> {
+        self.slow_mode
+            .get(topic)
+            .and_then(|slow_mode| *slow_mode.get())
+            .filter(|seconds| *seconds > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// Forgets the rates, mutes and posts that no longer matter at `now`.
+    fn prune(&mut self, window: Duration, now: Instant) {
+        self.rates
+            .retain(|_, rate| now.duration_since(rate.window_start) < window);
+        self.typing_rates
+            .retain(|_, rate| now.duration_since(rate.window_start) < window);
+        self.sync_rates
+            .retain(|_, rate| now.duration_since(rate.window_start) < window);
+        self.muted.retain(|_, until| *until > now);
+        let slow_mode = &self.slow_mode;
+        self.last_post.retain(|(topic, _), last| {
+            slow_mode
+                .get(topic)
+                .and_then(|slow_mode| *slow_mode.get())
+                .is_some_and(|seconds| now.duration_since(*last) < Duration::from_secs(seconds))
+        });
+        self.last_pruned = Some(now);
+    }
+}
+
+/// Shared filter applied to every gossip message received by the client.
+///
+/// Cloning it is cheap, every clone points to the same state.
+#[derive(Clone, Debug)]
+pub struct SpamFilter {
+    limits: GossipLimits,
+    state: Arc<Mutex<SpamState>>,
+}
+
+impl SpamFilter {
+    #[must_use]
+    pub fn new(limits: GossipLimits) -> Self {
+        Self {
+            limits,
+            state: Arc::new(Mutex::new(SpamState::default())),
+        }
+    }
+
+    /// Decides what to do with a message received on `topic` from the neighbor
+    /// `delivered_from`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn check(
+        &self,
+        topic: TopicId,
+        message: &MessageTypes,
+        delivered_from: EndpointId,
+        now: Instant,
+    ) -> Verdict {
+        let mut state = self.state.lock().expect("spam filter lock poisoned");
+        if state
+            .last_pruned
+            .is_none_or(|pruned| now.duration_since(pruned) >= self.limits.window)
+        {
+            state.prune(self.limits.window, now);
+        }
+
+        let sender = sender_of(message);
+        let key = (topic, delivered_from, sender);
+        match state.muted.get(&key) {
+            Some(until) if *until > now => return Verdict::Drop,
+            Some(_) => {
+                state.muted.remove(&key);
+            }
+            None => {}
+        }
+
+        // Going over the typing budget only drops the signal, it is harmless.
+        if matches!(message, MessageTypes::Typing(_)) {
+            let typing = count_in_window(&mut state.typing_rates, key, self.limits.window, now);
+            return if typing > MAX_TYPING_PER_WINDOW {
+                Verdict::Drop
+            } else {
+                Verdict::Accept
+            };
+        }
+
+        let event = if self.is_oversized(message) {
+            Some(GossipAbuseEvent::Oversized {
+                topic,
+                peer: delivered_from,
+                sender,
+            })
+        } else if sender.is_none() {
+            // Honest joins cause the bursts, so going over only drops the message.
+            if count_in_window(&mut state.sync_rates, key, self.limits.window, now)
+                > MAX_SYNC_PER_WINDOW
+            {
+                return Verdict::Drop;
+            }
+            None
+        } else if count_in_window(&mut state.rates, key, self.limits.window, now)
+            > self.limits.max_messages_per_window
+        {
+            Some(GossipAbuseEvent::RateExceeded {
+                topic,
+                peer: delivered_from,
+                sender,
+            })
+        } else {
+            None
+        };
+        if let Some(event) = event {
+            state.muted.insert(key, now + self.limits.mute_duration);
+            state.rates.remove(&key);
+            return Verdict::Mute(event);
+        }
+
+        if is_post(message)
+            && let Some(sender) = sender
+            && Self::respects_slow_mode(&mut state, topic, sender, now).is_err()
+        {
+            return Verdict::Drop;
+        }
+
+        Self::track(&mut state, topic, message);
+        Verdict::Accept
+    }
+
+    /// Checks whether the local peer may post to `topic` now, recording the post if it may.
+    ///
+    /// # Errors
+    ///
+    /// Returns how long to wait before posting if slow mode forbids it now.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn allow_own_post(
+        &self,
+        topic: TopicId,
+        own_id: EndpointId,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let mut state = self.state.lock().expect("spam filter lock poisoned");
+        Self::respects_slow_mode(&mut state, topic, own_id, now)
+    }
+
+    /// Sets the admin of `topic`, whose slow mode settings are the only ones followed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn set_admin(&self, topic: TopicId, admin: EndpointId) {
+        let mut state = self.state.lock().expect("spam filter lock poisoned");
+        state.admins.insert(topic, admin);
+    }
+
+    /// Updates the topic settings from a message sent by the local peer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn track_sent(&self, topic: TopicId, message: &MessageTypes) {
+        let mut state = self.state.lock().expect("spam filter lock poisoned");
+        Self::track(&mut state, topic, message);
+    }
+
+    /// Forgets the settings of `topic`, used when leaving it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    pub fn remove_topic(&self, topic: &TopicId) {
+        let mut state = self.state.lock().expect("spam filter lock poisoned");
+        state.slow_mode.remove(topic);
+        state.admins.remove(topic);
+        state
+            .rates
+            .retain(|(rate_topic, _, _), _| rate_topic != topic);
+        state
+            .typing_rates
+            .retain(|(rate_topic, _, _), _| rate_topic != topic);
+        state
+            .sync_rates
+            .retain(|(rate_topic, _, _), _| rate_topic != topic);
+        state
+            .muted
+            .retain(|(muted_topic, _, _), _| muted_topic != topic);
+        state
+            .last_post
+            .retain(|(post_topic, _), _| post_topic != topic);
+    }
+
+    fn is_oversized(&self, message: &MessageTypes) -> bool {
+        let text = self.limits.max_text_length;
+        let list = self.limits.max_list_length;
+        match message {
+            MessageTypes::Chat(msg) => msg.content.len() > text,
+            MessageTypes::Blob(msg) => msg.name.len() > text,
+            MessageTypes::Collection(msg) => {
+                msg.name.len() > text
+                    || msg.entries.len() > list
+                    || msg.entries.iter().any(|entry| entry.name.len() > text)
+            }
+            MessageTypes::TopicMessages(msg) => {
+                msg.messages.len() > list
+                    || msg.messages.iter().any(|chat| chat.content.len() > text)
+            }
+            MessageTypes::TopicMetadata(msg) => {
+                msg.state.name.get().len() > text || msg.state.members.len() > list
+            }
+            MessageTypes::ProfileMetadata(msg) => msg.username.len() > text,
+            MessageTypes::JoinTopic(_)
+            | MessageTypes::LeaveTopic(_)
+            | MessageTypes::DisconnectTopic(_)
+            | MessageTypes::Typing(_) => false,
+        }
+    }
+
+    fn respects_slow_mode(
+        state: &mut SpamState,
+        topic: TopicId,
+        sender: EndpointId,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let Some(interval) = state.slow_mode_interval(&topic) else {
+            return Ok(());
+        };
+        if let Some(last) = state.last_post.get(&(topic, sender))
+            && now.duration_since(*last) < interval
+        {
+            return Err(interval - now.duration_since(*last));
+        }
+        state.last_post.insert((topic, sender), now);
+        Ok(())
+    }
+
+    fn track(state: &mut SpamState, topic: TopicId, message: &MessageTypes) {
+        if let MessageTypes::TopicMetadata(msg) = message {
+            let admin = state.admins.get(&topic).copied();
+            let mut slow_mode = msg.state.slow_mode.clone();
+            slow_mode.clamp();
+            state.slow_mode.entry(topic).or_default().merge_from(
+                &slow_mode,
+                admin,
+                &TopicState::slow_mode_context(topic),
+            );
+        }
+    }
+}
+
+/// Counts one more message of `key` in the current window, returning the count so far.
+fn count_in_window(
+    rates: &mut HashMap<RateKey, SenderRate>,
+    key: RateKey,
+    window: Duration,
+    now: Instant,
+) -> u32 {
+    let rate = rates.entry(key).or_insert(SenderRate {
+        window_start: now,
+        messages: 0,
+    });
+    if now.duration_since(rate.window_start) >= window {
+        rate.window_start = now;
+        rate.messages = 0;
+    }
+    rate.messages += 1;
+    rate.messages
+}
+
+/// The sender declared in the message, if it has one.
+pub(crate) const fn sender_of(message: &MessageTypes) -> Option<EndpointId> {
+    match message {
+        MessageTypes::Chat(msg) => Some(msg.sender),
+        MessageTypes::Blob(msg) => Some(msg.sender),
+        MessageTypes::Collection(msg) => Some(msg.sender),
+        MessageTypes::JoinTopic(msg) => Some(msg.endpoint),
+        MessageTypes::LeaveTopic(msg) => Some(msg.endpoint),
+        MessageTypes::DisconnectTopic(msg) => Some(msg.endpoint),
+        MessageTypes::Typing(msg) => Some(msg.sender),
+        MessageTypes::ProfileMetadata(msg) => Some(msg.id),
+        MessageTypes::TopicMetadata(_) | MessageTypes::TopicMessages(_) => None,
+    }
+}
+
+/// Whether the message is a post subject to slow mode.
+const fn is_post(message: &MessageTypes) -> bool {
+    matches!(
+        message,
+        MessageTypes::Chat(_) | MessageTypes::Blob(_) | MessageTypes::Collection(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::Tag;
+    use crate::hlc::Hlc;
+    use crate::messages::{
+        ChatMessage, ProfileMetadataMessage, TopicMessagesMessage, TopicMetadataMessage,
+        TypingMessage,
+    };
+    use crate::utils::random_endpoint;
+    use iroh::SecretKey;
+
+    fn chat(topic: TopicId, sender: EndpointId, content: &str) -> MessageTypes {
+        MessageTypes::Chat(ChatMessage::new(sender, content.to_string(), 0, topic))
+    }
+
+    #[test]
+    fn test_rate_limit_mutes_sender() {
+        let limits = GossipLimits::new(
+            4096,
+            1000,
+            2,
+            Duration::from_secs(10),
+            Duration::from_secs(60),
+        );
+        let filter = SpamFilter::new(limits);
+        let topic = TopicId::from_bytes([1; 32]);
+        let other_topic = TopicId::from_bytes([4; 32]);
+        let spammer = random_endpoint();
+        let other = random_endpoint();
+        let now = Instant::now();
+
+        for _ in 0..2 {
+            let verdict = filter.check(topic, &chat(topic, spammer, "hi"), spammer, now);
+            assert_eq!(verdict, Verdict::Accept);
+        }
+        assert_eq!(
+            filter.check(topic, &chat(topic, spammer, "hi"), spammer, now),
+            Verdict::Mute(GossipAbuseEvent::RateExceeded {
+                topic,
+                peer: spammer,
+                sender: Some(spammer),
+            })
+        );
+        assert_eq!(
+            filter.check(topic, &chat(topic, spammer, "hi"), spammer, now),
+            Verdict::Drop
+        );
+        // The neighbor still relays the other members, and the other neighbors the spammer
+        // until they are over the limit too.
+        assert_eq!(
+            filter.check(topic, &chat(topic, other, "hi"), spammer, now),
+            Verdict::Accept
+        );
+        assert_eq!(
+            filter.check(topic, &chat(topic, spammer, "hi"), other, now),
+            Verdict::Accept
+        );
+        // It only applies to the topic it was earned in.
+        assert_eq!(
+            filter.check(other_topic, &chat(other_topic, spammer, "hi"), spammer, now),
+            Verdict::Accept
+        );
+
+        let later = now + Duration::from_secs(61);
+        assert_eq!(
+            filter.check(topic, &chat(topic, spammer, "hi"), spammer, later),
+            Verdict::Accept
+        );
+    }
+
+    #[test]
+    fn test_typing_has_its_own_budget() {
+        let limits = GossipLimits::new(
+            4096,
+            1000,
+            2,
+            Duration::from_secs(10),
+            Duration::from_secs(60),
+        );
+        let filter = SpamFilter::new(limits);
+        let topic = TopicId::from_bytes([7; 32]);
+        let neighbor = random_endpoint();
+        let now = Instant::now();
+
+        let typing = MessageTypes::Typing(TypingMessage::new(topic, neighbor, 0));
+        for _ in 0..MAX_TYPING_PER_WINDOW {
+            assert_eq!(filter.check(topic, &typing, neighbor, now), Verdict::Accept);
+        }
+        assert_eq!(filter.check(topic, &typing, neighbor, now), Verdict::Drop);
+
+        // Neither counted against the message rate nor muted.
+        for _ in 0..2 {
+            assert_eq!(
+                filter.check(topic, &chat(topic, neighbor, "hi"), neighbor, now),
+                Verdict::Accept
+            );
+        }
+    }
+
+    #[test]
+    fn test_oversized_message_mutes_sender() {
+        let filter = SpamFilter::new(GossipLimits::default());
+        let topic = TopicId::from_bytes([2; 32]);
+        let sender = random_endpoint();
+        let content = "a".repeat(5000);
+
+        assert_eq!(
+            filter.check(
+                topic,
+                &chat(topic, sender, &content),
+                sender,
+                Instant::now()
+            ),
+            Verdict::Mute(GossipAbuseEvent::Oversized {
+                topic,
+                peer: sender,
+                sender: Some(sender),
+            })
+        );
+    }
+
+    #[test]
+    fn test_relayed_join_answers_are_accepted() {
+        let filter = SpamFilter::new(GossipLimits::default());
+        let topic = TopicId::from_bytes([8; 32]);
+        let neighbor = random_endpoint();
+        let now = Instant::now();
+
+        // Every member behind the neighbor answers a join with its state, its history pages
+        // and its profile.
+        for _ in 0..10 {
+            let member = random_endpoint();
+            let metadata = MessageTypes::TopicMetadata(TopicMetadataMessage::new(
+                topic,
+                TopicState::default(),
+            ));
+            assert_eq!(
+                filter.check(topic, &metadata, neighbor, now),
+                Verdict::Accept
+            );
+            for _ in 0..5 {
+                let page = MessageTypes::TopicMessages(TopicMessagesMessage::new(
+                    topic,
+                    vec![ChatMessage::new(member, "hi".to_string(), 0, topic)],
+                ));
+                assert_eq!(filter.check(topic, &page, neighbor, now), Verdict::Accept);
+            }
+            let profile = MessageTypes::ProfileMetadata(ProfileMetadataMessage::new(
+                topic,
+                member,
+                "member".to_string(),
+                None,
+                0,
+            ));
+            assert_eq!(
+                filter.check(topic, &profile, neighbor, now),
+                Verdict::Accept
+            );
+        }
+    }
+
+    #[test]
+    fn test_slow_mode_drops_fast_posts() {
+        let filter = SpamFilter::new(GossipLimits::default());
+        let topic = TopicId::from_bytes([3; 32]);
+        let sender = random_endpoint();
+        let admin = SecretKey::generate(&mut rand::rng());
+        let now = Instant::now();
+        filter.set_admin(topic, admin.public());
+
+        let mut state = TopicState::default();
+        state
+            .slow_mode
+            .set(Some(30), Tag::new(Hlc::from_wall(1), admin.public()));
+        state.sign(topic, &admin);
+        let metadata = MessageTypes::TopicMetadata(TopicMetadataMessage::new(topic, state));
+        assert_eq!(filter.check(topic, &metadata, sender, now), Verdict::Accept);
+
+        assert_eq!(
+            filter.check(topic, &chat(topic, sender, "first"), sender, now),
+            Verdict::Accept
+        );
+        let soon = now + Duration::from_secs(5);
+        assert_eq!(
+            filter.check(topic, &chat(topic, sender, "second"), sender, soon),
+            Verdict::Drop
+        );
+        assert_eq!(
+            filter.allow_own_post(topic, sender, soon),
+            Err(Duration::from_secs(25))
+        );
+
+        // Typing signals are not posts, so slow mode does not apply to them.
+        let typing = MessageTypes::Typing(TypingMessage::new(topic, sender, 0));
+        assert_eq!(filter.check(topic, &typing, sender, soon), Verdict::Accept);
+
+        let later = now + Duration::from_secs(31);
+        assert_eq!(
+            filter.check(topic, &chat(topic, sender, "third"), sender, later),
+            Verdict::Accept
+        );
+    }
+
+    #[test]
+    fn test_slow_mode_only_follows_admin() {
+        let filter = SpamFilter::new(GossipLimits::default());
+        let topic = TopicId::from_bytes([5; 32]);
+        let sender = SecretKey::generate(&mut rand::rng());
+        let admin = random_endpoint();
+        let now = Instant::now();
+        filter.set_admin(topic, admin);
+
+        // Set by a member, then claiming to be set by the admin without its signature.
+        let mut state = TopicState::default();
+        state
+            .slow_mode
+            .set(Some(30), Tag::new(Hlc::from_wall(1), sender.public()));
+        state.sign(topic, &sender);
+        let mut forged = TopicState::default();
+        forged
+            .slow_mode
+            .set(Some(30), Tag::new(Hlc::from_wall(1), admin));
+        let sender = sender.public();
+        for state in [state, forged] {
+            let metadata = MessageTypes::TopicMetadata(TopicMetadataMessage::new(topic, state));
+            assert_eq!(filter.check(topic, &metadata, sender, now), Verdict::Accept);
+        }
+
+        for content in ["first", "second"] {
+            assert_eq!(
+                filter.check(topic, &chat(topic, sender, content), sender, now),
+                Verdict::Accept
+            );
+        }
+    }
+
+    #[test]
+    fn test_stale_entries_are_pruned() {
+        let filter = SpamFilter::new(GossipLimits::default());
+        let topic = TopicId::from_bytes([6; 32]);
+        let now = Instant::now();
+        for _ in 0..10 {
+            let sender = random_endpoint();
+            filter.check(topic, &chat(topic, sender, "hi"), sender, now);
+        }
+        assert_eq!(filter.state.lock().unwrap().rates.len(), 10);
+
+        let sender = random_endpoint();
+        let later = now + Duration::from_secs(11);
+        filter.check(topic, &chat(topic, sender, "hi"), sender, later);
+
+        let state = filter.state.lock().unwrap();
+        assert_eq!(state.rates.len(), 1);
+        assert!(state.last_post.is_empty());
+    }
+}
@@ -1,8 +1,12 @@
 //! # Shared Types
 //!
-//! This module defines common types used across the p2p crate, such as invitation tickets.
+//! This module defines common types used across the p2p crate, such as invitation tickets
+//! and transfer progress events.
 
-use iroh::EndpointAddr;
+use crate::error::Error;
+use crate::messages::CollectionEntry;
+use iroh::{EndpointAddr, EndpointId};
+use iroh_blobs::Hash;
 use iroh_gossip::proto::TopicId;
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -11,7 +15,8 @@ use std::str::FromStr;
 /// A ticket used to invite peers to a specific gossip topic.
 ///
 /// It contains the `TopicId` to join and a list of `EndpointAddr`s (bootstrap nodes)
-/// that are already part of the topic to help with initial connection.
+/// that are already part of the topic to help with initial connection. The first one is the
+/// peer that created the topic.
 ///
 /// Tickets can be serialized to a base58 string for easy sharing.
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -22,6 +27,14 @@ pub struct Ticket {
     pub endpoints: Vec<EndpointAddr>,
 }
 
+impl Ticket {
+    /// The peer that created the topic, its admin.
+    #[must_use]
+    pub fn creator(&self) -> Option<EndpointId> {
+        self.endpoints.first().map(|addr| addr.id)
+    }
+}
+
 impl fmt::Display for Ticket {
     /// Formats the ticket as a base58 string.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -32,12 +45,34 @@ impl fmt::Display for Ticket {
 }
 
 impl FromStr for Ticket {
-    type Err = anyhow::Error;
+    type Err = Error;
 
     /// Parses a ticket from a base58 string.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let bytes = bs58::decode(s).into_vec()?;
-        let ticket = postcard::from_bytes(&bytes)?;
-        Ok(ticket)
+        let bytes = bs58::decode(s)
+            .into_vec()
+            .map_err(|e| Error::InvalidTicket(e.to_string()))?;
+        postcard::from_bytes(&bytes).map_err(|e| Error::InvalidTicket(e.to_string()))
     }
 }
+
+/// Progress of importing a batch of files as a single collection.
+///
+/// Emitted by [`crate::ChatClient::save_collection`]. Progress is reported for the batch as
+/// a whole rather than per file, so callers can drive a single progress bar.
+#[derive(Clone, Debug)]
+pub enum CollectionAddProgress {
+    /// The combined size in bytes of every file in the batch.
+    Size(u64),
+    /// The number of bytes imported so far across the whole batch.
+    Progress(u64),
+    /// The collection has been stored in the local blob store.
+    Done {
+        /// The root hash of the `HashSeq` collection.
+        hash: Hash,
+        /// The imported files, in the same order they were provided.
+        entries: Vec<CollectionEntry>,
+    },
+    /// Importing failed; no collection was created.
+    Error(String),
+}
@@ -2,8 +2,9 @@
 //!
 //! Helper functions for the P2P crate, primarily focused on key management.
 
+use crate::error::Result;
 use iroh::SecretKey;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
 /// Loads a secret key from a file, or generates a new one if it doesn't exist.
 ///
@@ -22,7 +23,7 @@ use std::path::PathBuf;
 /// # Errors
 ///
 /// This function will return an error if it failed to read/write/access the path or if it fails to parse the key in the existing file
-pub async fn load_secret_key(path_buf: PathBuf) -> anyhow::Result<SecretKey> {
+pub async fn load_secret_key(path_buf: PathBuf) -> Result<SecretKey> {
     if path_buf.exists() {
         let secret_key_bytes = tokio::fs::read(&path_buf).await?;
         match SecretKey::try_from(&secret_key_bytes[0..32]) {
@@ -40,7 +41,7 @@ pub async fn load_secret_key(path_buf: PathBuf) -> anyhow::Result<SecretKey> {
     }
 }
 
-async fn generate_key(path_buf: PathBuf) -> anyhow::Result<SecretKey> {
+async fn generate_key(path_buf: PathBuf) -> Result<SecretKey> {
     let secret_key = SecretKey::generate(&mut rand::rng());
     let secret_key_bytes = secret_key.to_bytes();
     if let Some(parent) = path_buf.parent() {
@@ -49,3 +50,54 @@ async fn generate_key(path_buf: PathBuf) -> anyhow::Result<SecretKey> {
     tokio::fs::write(path_buf, &secret_key_bytes).await?;
     Ok(secret_key)
 }
+
+/// Resolves the name of a collection entry to a path relative to the export directory.
+///
+/// Entry names come from remote peers, so anything that could escape the target directory
+/// (absolute paths, `..`, drive prefixes) is rejected.
+///
+/// Returns `None` if the name is empty or not a plain relative path.
+#[must_use]
+pub fn collection_entry_path(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        None
+    } else {
+        Some(relative)
+    }
+}
+
+/// A new random endpoint id, shared by the tests of every module.
+#[cfg(test)]
+pub(crate) fn random_endpoint() -> iroh::EndpointId {
+    SecretKey::generate(&mut rand::rng()).public()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collection_entry_path_accepts_nested_names() {
+        assert_eq!(
+            collection_entry_path("photos/./beach.jpg"),
+            Some(PathBuf::from("photos").join("beach.jpg"))
+        );
+    }
+
+    #[test]
+    fn test_collection_entry_path_rejects_escapes() {
+        assert_eq!(collection_entry_path("../secret.key"), None);
+        assert_eq!(collection_entry_path("/etc/passwd"), None);
+        assert_eq!(collection_entry_path("photos/../../x"), None);
+        assert_eq!(collection_entry_path(""), None);
+    }
+}
@@ -1,6 +1,10 @@
-use super::desktop_web_components::{CLIP_ICON, DEFAULT_AVATAR};
-use super::models::{AppState, BlobType, Controller, Message};
-use super::utils::{format_file_size, format_message_timestamp, get_sender_display_name};
+use super::desktop_web_components::CLIP_ICON;
+use super::models::{
+    AppState, BlobType, Controller, DownloadState, Message, TYPING_SEND_INTERVAL, Verification,
+};
+use super::utils::{
+    avatar_src, format_file_size, format_message_timestamp, get_sender_display_name,
+};
 use crate::components::toast::ToastProvider;
 use crate::desktop::utils::is_video_file;
 use base64::Engine;
@@ -22,6 +26,17 @@ pub fn Chat<C: Controller + 'static>(
 ) -> Element {
     let state = app_state();
     let mut show_attachment = use_signal(|| false);
+    let toast = use_toast();
+
+    // Notices are only taken when there are some, so taking them does not run this again.
+    use_effect(move || {
+        if app_state.read().has_notices() {
+            let mut app_state = app_state;
+            for notice in app_state.with_mut(AppState::take_notices) {
+                toast.error(notice, ToastOptions::default());
+            }
+        }
+    });
 
     let topic = topic_id.as_ref().and_then(|id| state.get_topic(id));
 
@@ -40,21 +55,49 @@ pub fn Chat<C: Controller + 'static>(
                 .map(Message::from)
                 .collect::<Vec<Message>>(),
             contact.profile.name.clone(),
-            contact
-                .profile
-                .avatar
-                .clone()
-                .unwrap_or_else(|| DEFAULT_AVATAR.to_string()),
+            avatar_src(&*controller.read(), contact.profile.avatar.as_deref()),
             contact.profile.id.clone(),
         )), |topic| (
             topic.messages.clone(),
             topic.name.clone(),
-            topic
-                .avatar_url
-                .clone()
-                .unwrap_or_else(|| DEFAULT_AVATAR.to_string()),
+            avatar_src(&*controller.read(), topic.avatar_url.as_deref()),
             topic.id.clone(),
         ));
+
+    // A verified contact whose safety number no longer matches may not be who it was.
+    let key_changed = contact.is_some_and(|contact| {
+        controller
+            .read()
+            .get_safety_number(&contact.profile.id)
+            .is_some_and(|number| contact.profile.verification(&number) == Verification::Changed)
+    });
+
+    // The members reachable right now, shown under the topic name with their names on hover.
+    let online_members = topic.map(|topic| {
+        let names: Vec<String> = topic
+            .online
+            .iter()
+            .map(|id| state.get_contact(id).map_or_else(|| id.clone(), |c| c.name.clone()))
+            .collect();
+        (names.len(), names.join(", "))
+    });
+
+    let typing_names: Vec<String> = state
+        .get_typing(&chat_id)
+        .iter()
+        .map(|id| {
+            state
+                .get_contact(id)
+                .map_or_else(|| id.clone(), |c| c.name.clone())
+        })
+        .collect();
+    let typing_text = match typing_names.as_slice() {
+        [] => None,
+        [name] => Some(format!("{name} is typing…")),
+        [first, second] => Some(format!("{first} and {second} are typing…")),
+        _ => Some("Several people are typing…".to_string()),
+    };
+
     if chat_id.is_empty() {
         rsx! {
             div { class: "flex-1 flex items-center justify-center bg-bg-input text-text-secondary",
@@ -94,6 +137,8 @@ pub fn Chat<C: Controller + 'static>(
             }
         });
 
+        let mut last_typing_sent = use_signal(|| 0u64);
+
         let send_message = use_callback({
             let id = chat_id.clone();
             let is_dm = contact.is_some();
@@ -107,6 +152,26 @@ pub fn Chat<C: Controller + 'static>(
                         controller.read().send_message_to_topic(id.clone(), content);
                     }
                     message_input.set(String::new());
+                    last_typing_sent.set(0);
+                }
+            }
+        });
+
+        // Typing signals are repeated at most once per interval while the user keeps typing.
+        let notify_typing = use_callback({
+            let id = chat_id.clone();
+            let is_dm = contact.is_some();
+            let controller = controller;
+            move |()| {
+                let now = u64::try_from(chrono::Utc::now().timestamp_millis()).unwrap_or_default();
+                if now.saturating_sub(last_typing_sent()) < TYPING_SEND_INTERVAL {
+                    return;
+                }
+                last_typing_sent.set(now);
+                if is_dm {
+                    controller.read().send_typing_to_user(id.clone());
+                } else {
+                    controller.read().send_typing_to_topic(id.clone());
                 }
             }
         });
@@ -150,14 +215,49 @@ pub fn Chat<C: Controller + 'static>(
             }
         };
 
+        let handle_collection_submit = {
+            let controller = controller;
+            let is_dm = contact.is_some();
+            let chat_id = chat_id.clone();
+            move |(files, name): (Vec<FileData>, Option<String>)| {
+                if files.is_empty() {
+                    return;
+                }
+                if is_dm {
+                    controller
+                        .read()
+                        .send_collection_to_user(chat_id.clone(), files, name);
+                } else {
+                    controller
+                        .read()
+                        .send_collection_to_topic(chat_id.clone(), files, name);
+                }
+                show_attachment.set(false);
+            }
+        };
+
         rsx! {
             div { class: "flex-1 flex flex-col bg-bg-input h-full",
                 div { class: "bg-bg-panel py-3.75 px-5 shadow-md flex items-center gap-3.75 border-b border-border",
                     img { class: "avatar w-11.25 h-11.25", src: "{avatar_url}" }
-                    h2 {
-                        class: "m-0 text-[clamp(1.1rem,2.5vw,1.4rem)] font-semibold text-text-primary max-w-100 overflow-hidden text-ellipsis whitespace-nowrap",
-                        title: "{title_text}",
-                        "{title_text}"
+                    div { class: "flex flex-col min-w-0",
+                        h2 {
+                            class: "m-0 text-[clamp(1.1rem,2.5vw,1.4rem)] font-semibold text-text-primary max-w-100 overflow-hidden text-ellipsis whitespace-nowrap",
+                            title: "{title_text}",
+                            "{title_text}"
+                        }
+                        if let Some((count, names)) = online_members {
+                            p {
+                                class: "m-0 text-xs text-text-secondary max-w-100 overflow-hidden text-ellipsis whitespace-nowrap",
+                                title: "{names}",
+                                "{count} online"
+                            }
+                        }
+                    }
+                }
+                if key_changed {
+                    p { class: "m-0 px-5 py-2 text-sm text-danger bg-danger/15 border-b border-border",
+                        "The safety number with {title_text} changed since you verified it. Check it again in their profile."
                     }
                 }
                 div {
@@ -175,9 +275,15 @@ pub fn Chat<C: Controller + 'static>(
                         }
                     }
                 }
+                if let Some(typing) = typing_text {
+                    p { class: "m-0 px-5 py-1 text-xs italic text-text-secondary bg-bg-dark",
+                        "{typing}"
+                    }
+                }
                 if show_attachment() {
                     AttachComponent {
                         on_select_media: handle_media_submit,
+                        on_select_collection: handle_collection_submit,
                         on_close: move |()| show_attachment.set(false),
                     }
                 }
@@ -189,7 +295,11 @@ pub fn Chat<C: Controller + 'static>(
                             placeholder: "Type a message...",
                             value: "{message_input()}",
                             oninput: move |e| {
-                                message_input.set(e.value());
+                                let value = e.value();
+                                if !value.trim().is_empty() {
+                                    notify_typing(());
+                                }
+                                message_input.set(value);
                             },
                             onkeypress: move |e| {
                                 if e.key() == Key::Enter {
@@ -219,6 +329,7 @@ pub fn Chat<C: Controller + 'static>(
 #[component]
 pub fn AttachComponent(
     on_select_media: EventHandler<(Vec<FileData>, BlobType)>,
+    on_select_collection: EventHandler<(Vec<FileData>, Option<String>)>,
     on_close: EventHandler<()>,
 ) -> Element {
     rsx! {
@@ -244,10 +355,28 @@ pub fn AttachComponent(
                             r#type: "file",
                             multiple: true,
                             accept: "*/*",
-                            onchange: move |e| on_select_media.call((e.files(), BlobType::File)),
+                            //Several files are sent together as a single collection
+                            onchange: move |e| {
+                                let files = e.files();
+                                if files.len() > 1 {
+                                    let name = format!("{} files", files.len());
+                                    on_select_collection.call((files, Some(name)));
+                                } else {
+                                    on_select_media.call((files, BlobType::File));
+                                }
+                            },
                         }
                         p { class: "text-text-primary font-medium", "Files" }
                     }
+                    label { class: "flex items-center gap-3 px-4 py-3 rounded-lg cursor-pointer transition-all duration-200 hover:bg-bg-hover active:bg-bg-active group",
+                        input {
+                            class: "hidden",
+                            r#type: "file",
+                            directory: true,
+                            onchange: move |e| on_select_collection.call((e.files(), None)),
+                        }
+                        p { class: "text-text-primary font-medium", "Folder" }
+                    }
                     label { class: "flex items-center gap-3 px-4 py-3 rounded-lg cursor-pointer transition-all duration-200 hover:bg-bg-hover active:bg-bg-active group",
                         input {
                             class: "hidden",
@@ -350,18 +479,127 @@ pub fn ChatMessageComponent<C: Controller + 'static>(
                 "self-start"
             };
 
-            let blob_path = controller.read().get_or_download(
-                &message.blob_hash,
-                &message.sender_id,
-                &message.blob_name,
-            );
+            // Follows the download started below, from the transfer events.
+            let download = use_memo({
+                let hash = message.blob_hash.clone();
+                move || app_state.read().download_state(&hash)
+            });
+            let downloaded = use_memo(move || download() == Some(DownloadState::Done));
+            // The blob is looked up off the UI thread, and again once its download is done.
+            let blob_path = use_resource({
+                let hash = message.blob_hash.clone();
+                let name = message.blob_name.clone();
+                move || {
+                    let _ = downloaded();
+                    controller.read().get_from_storage(hash.clone(), &name)
+                }
+            });
+            // Missing media is downloaded in the background, once unless the user retries, and
+            // never again once the retention policy removed it.
+            use_effect({
+                let hash = message.blob_hash.clone();
+                let sender_id = message.sender_id.clone();
+                let media_removed = message.media_removed;
+                move || {
+                    if !media_removed
+                        && matches!(*blob_path.read(), Some(None))
+                        && app_state.peek().download_state(&hash).is_none()
+                    {
+                        app_state.write().start_download(&hash);
+                        controller
+                            .read()
+                            .download_blob(hash.clone(), sender_id.clone());
+                    }
+                }
+            });
 
-            let Ok(blob_path) = blob_path else {
-                toast.error(
-                    "Failed to load media blob.".to_string(),
-                    ToastOptions::default(),
-                );
-                return rsx! {};
+            if message.media_removed {
+                return rsx! {
+                    div { class: "max-w-[50%] flex flex-col gap-1 {alignment}",
+                        if !message.is_sent {
+                            p {
+                                class: "m-0 text-[clamp(11px,1.6vw,12px)] font-medium opacity-80 text-text-secondary whitespace-nowrap overflow-hidden text-ellipsis",
+                                title: "{message.sender_id}",
+                                "{sender_display}"
+                            }
+                        }
+                        RemovedMedia { name: message.blob_name.clone() }
+                        p { class: "m-0 text-[clamp(10px,1.5vw,11px)] opacity-70 text-text-secondary self-end",
+                            "{format_message_timestamp(message.timestamp)}"
+                        }
+                    }
+                };
+            }
+
+            let Some(Some(blob_path)) = blob_path() else {
+                let failed = download() == Some(DownloadState::Failed)
+                    || (downloaded() && blob_path().is_some());
+                let received = match download() {
+                    Some(DownloadState::Progress(bytes)) => bytes,
+                    _ => 0,
+                };
+                let percent = (received * 100)
+                    .checked_div(message.blob_size)
+                    .unwrap_or(0)
+                    .min(100);
+                let is_video = message.blob_type == BlobType::Video;
+                let hash = message.blob_hash.clone();
+                let sender_id = message.sender_id.clone();
+                return rsx! {
+                    div { class: "max-w-[50%] flex flex-col gap-1 {alignment}",
+                        if !message.is_sent {
+                            p {
+                                class: "m-0 text-[clamp(11px,1.6vw,12px)] font-medium opacity-80 text-text-secondary whitespace-nowrap overflow-hidden text-ellipsis",
+                                title: "{message.sender_id}",
+                                "{sender_display}"
+                            }
+                        }
+                        if let Some(preview) = message.preview.as_ref() {
+                            div {
+                                class: "relative w-96 max-w-full rounded-xl shadow-md overflow-hidden bg-bg-panel",
+                                style: "aspect-ratio: {preview.aspect_ratio()};",
+                                img {
+                                    class: "w-full h-full object-cover blur-md scale-110",
+                                    src: "{preview.thumbnail_url}",
+                                }
+                                if !failed {
+                                    div { class: "absolute inset-0 flex items-center justify-center bg-black/20",
+                                        if is_video {
+                                            div { class: "w-16 h-16 rounded-full bg-white/90 flex items-center justify-center shadow-lg",
+                                                div { class: "w-0 h-0 border-t-8 border-t-transparent border-b-8 border-b-transparent border-l-12 border-l-gray-800 ml-1" }
+                                            }
+                                        } else {
+                                            div { class: "w-10 h-10 rounded-full border-4 border-white/60 border-t-transparent animate-spin" }
+                                        }
+                                    }
+                                }
+                            }
+                        } else {
+                            div { class: "bg-bg-panel rounded-xl p-4 border border-border flex flex-col gap-1",
+                                p { class: "m-0 text-text-primary text-sm font-medium truncate", "{message.blob_name}" }
+                                if !failed {
+                                    p { class: "m-0 text-text-secondary text-xs", "Downloading… {percent}%" }
+                                }
+                            }
+                        }
+                        if failed {
+                            div { class: "flex items-center gap-2",
+                                p { class: "m-0 text-text-secondary text-xs", "Download failed." }
+                                button {
+                                    class: "btn-secondary py-1 px-2 text-xs",
+                                    onclick: move |_| {
+                                        app_state.write().start_download(&hash);
+                                        controller.read().download_blob(hash.clone(), sender_id.clone());
+                                    },
+                                    "Retry"
+                                }
+                            }
+                        }
+                        p { class: "m-0 text-[clamp(10px,1.5vw,11px)] opacity-70 text-text-secondary self-end",
+                            "{format_message_timestamp(message.timestamp)}"
+                        }
+                    }
+                };
             };
 
             let blob_url = controller
@@ -512,5 +750,150 @@ pub fn ChatMessageComponent<C: Controller + 'static>(
                 }
             }
         }
+        Message::Collection(message) => {
+            let sender_display = get_sender_display_name(&state, &message.sender_id);
+            let alignment = if message.is_sent {
+                "self-end"
+            } else {
+                "self-start"
+            };
+            if message.media_removed {
+                return rsx! {
+                    div { class: "max-w-[50%] flex flex-col gap-1 {alignment}",
+                        if !message.is_sent {
+                            p {
+                                class: "m-0 text-[clamp(11px,1.6vw,12px)] font-medium opacity-80 text-text-secondary whitespace-nowrap overflow-hidden text-ellipsis",
+                                title: "{message.sender_id}",
+                                "{sender_display}"
+                            }
+                        }
+                        RemovedMedia { name: message.name.clone() }
+                        p { class: "m-0 text-[clamp(10px,1.5vw,11px)] opacity-70 text-text-secondary self-end",
+                            "{format_message_timestamp(message.timestamp)}"
+                        }
+                    }
+                };
+            }
+            let total_size = format_file_size(message.total_size());
+            let entry_count = message.entries.len();
+
+            let collection_hash = message.collection_hash.clone();
+            let collection_name = message.name.clone();
+            let provider_id = message.sender_id.clone();
+
+            rsx! {
+                div { class: "max-w-[50%] flex flex-col gap-1 {alignment}",
+                    if !message.is_sent {
+                        p {
+                            class: "m-0 text-[clamp(11px,1.6vw,12px)] font-medium opacity-80 text-text-secondary whitespace-nowrap overflow-hidden text-ellipsis",
+                            title: "{message.sender_id}",
+                            "{sender_display}"
+                        }
+                    }
+                    div { class: "bg-bg-panel rounded-xl p-4 border border-border flex flex-col gap-3",
+                        div { class: "flex items-center gap-3",
+                            div { class: "w-10 h-10 rounded-lg bg-bg-subtle flex items-center justify-center text-text-muted",
+                                "📁"
+                            }
+                            div { class: "flex-1 overflow-hidden",
+                                p { class: "m-0 text-text-primary text-sm font-medium truncate", "{message.name}" }
+                                p { class: "m-0 text-text-secondary text-xs", "{entry_count} files · {total_size}" }
+                            }
+                        }
+                        div { class: "flex flex-col gap-1 max-h-48 overflow-y-auto scrollbar-custom",
+                            for entry in message.entries.iter().cloned() {
+                                div { class: "flex items-center gap-2 px-2 py-1 rounded-lg hover:bg-bg-hover",
+                                    p {
+                                        class: "flex-1 m-0 text-text-primary text-xs truncate",
+                                        title: "{entry.name}",
+                                        "{entry.name}"
+                                    }
+                                    p { class: "m-0 text-text-secondary text-xs", "{format_file_size(entry.size)}" }
+                                    button {
+                                        class: "btn-secondary py-1 px-2 text-xs",
+                                        onclick: {
+                                            let provider_id = provider_id.clone();
+                                            move |_| {
+                                                let entry = entry.clone();
+                                                let provider_id = provider_id.clone();
+                                                spawn(async move {
+                                                    let file_name = entry
+                                                        .name
+                                                        .rsplit('/')
+                                                        .next()
+                                                        .unwrap_or_default()
+                                                        .to_string();
+                                                    let dir = dirs::download_dir()
+                                                        .unwrap_or_else(|| std::path::PathBuf::from("."));
+                                                    let file = rfd::AsyncFileDialog::new()
+                                                        .set_file_name(file_name)
+                                                        .set_directory(dir)
+                                                        .save_file()
+                                                        .await;
+                                                    if let Some(file) = file {
+                                                        controller
+                                                            .read()
+                                                            .download_collection_entry(
+                                                                entry.hash,
+                                                                provider_id,
+                                                                file.path().to_path_buf(),
+                                                            );
+                                                    }
+                                                });
+                                            }
+                                        },
+                                        "Save"
+                                    }
+                                }
+                            }
+                        }
+                        button {
+                            class: "btn-primary py-2 px-4 text-sm w-full",
+                            onclick: move |_| {
+                                let collection_hash = collection_hash.clone();
+                                let collection_name = collection_name.clone();
+                                let provider_id = provider_id.clone();
+                                spawn(async move {
+                                    let dir = dirs::download_dir()
+                                        .unwrap_or_else(|| std::path::PathBuf::from("."));
+                                    let folder = rfd::AsyncFileDialog::new()
+                                        .set_directory(dir)
+                                        .pick_folder()
+                                        .await;
+                                    //The name comes from the sender, keep only its last component
+                                    let folder_name = std::path::Path::new(&collection_name)
+                                        .file_name()
+                                        .map_or_else(|| "collection".into(), ToOwned::to_owned);
+                                    if let Some(folder) = folder {
+                                        controller
+                                            .read()
+                                            .download_collection(
+                                                collection_hash,
+                                                provider_id,
+                                                folder.path().join(folder_name),
+                                            );
+                                    }
+                                });
+                            },
+                            "Save all"
+                        }
+                    }
+                    p { class: "m-0 text-[clamp(10px,1.5vw,11px)] opacity-70 text-text-secondary self-end",
+                        "{format_message_timestamp(message.timestamp)}"
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Stands in for the files of a message once the retention policy removed them.
+#[component]
+fn RemovedMedia(name: String) -> Element {
+    rsx! {
+        div { class: "bg-bg-panel rounded-xl p-4 border border-border flex flex-col gap-1",
+            p { class: "m-0 text-text-primary text-sm font-medium truncate", "{name}" }
+            p { class: "m-0 text-text-secondary text-xs italic", "Media removed" }
+        }
     }
 }
@@ -1,18 +1,20 @@
-use super::desktop_web_components::DEFAULT_AVATAR;
-use super::models::{AppState, Profile, ProfileChat, RemovalType, Topic};
-use super::utils::format_relative_time;
+use super::models::{
+    AppState, ConnectionStatus, Controller, Profile, ProfileChat, RemovalType, Topic,
+};
+use super::utils::{avatar_src, format_last_seen, format_relative_time, truncate_id};
 use dioxus::prelude::*;
 use dioxus_primitives::context_menu::{
     ContextMenu, ContextMenuContent, ContextMenuItem, ContextMenuTrigger,
 };
 
 #[component]
-pub fn TopicColumn(
+pub fn TopicColumn<C: Controller + 'static>(
     search_query: Signal<String>,
     selected_topic_id: Signal<Option<String>>,
     show_topic_details: Signal<Option<Topic>>,
     show_leave_confirmation: Signal<Option<(String, String, RemovalType)>>,
     app_state: Signal<AppState>,
+    controller: Signal<C>,
 ) -> Element {
     let topic_list: Vec<Topic> = {
         let state = app_state();
@@ -33,6 +35,7 @@ pub fn TopicColumn(
                     .map(|topic| {
                         let topic_id = topic.id;
                         let topic_name = topic.name;
+                        let avatar_url = avatar_src(&*controller.read(), topic.avatar_url.as_deref());
                         let topic_id_open = topic_id.clone();
                         let topic_id_details = topic_id.clone();
                         let topic_id_leave = topic_id.clone();
@@ -43,7 +46,7 @@ pub fn TopicColumn(
                                     ColumnItem {
                                         id: topic_id,
                                         name: topic_name,
-                                        avatar_url: topic.avatar_url,
+                                        avatar_url,
                                         last_message: topic.last_message,
                                         last_connection: topic.last_connection,
                                         on_select: selected_topic_id,
@@ -97,12 +100,13 @@ pub fn TopicColumn(
 }
 
 #[component]
-pub fn ContactColumn(
+pub fn ContactColumn<C: Controller + 'static>(
     search_query: Signal<String>,
     selected_topic_id: Signal<Option<String>>,
     show_profile_details: Signal<Option<Profile>>,
     show_leave_confirmation: Signal<Option<(String, String, RemovalType)>>,
     app_state: Signal<AppState>,
+    controller: Signal<C>,
 ) -> Element {
     let contact_list: Vec<ProfileChat> = {
         let state = app_state();
@@ -147,12 +151,15 @@ pub fn ContactColumn(
                         let last_message = contact_chat.last_message();
                         let profile_id = contact_chat.profile.id;
                         let profile_name = contact_chat.profile.name;
-                        let avatar_url = contact_chat.profile.avatar;
-                        let last_connection = contact_chat.profile.last_connection.get_u64();
+                        let avatar_url =
+                            avatar_src(&*controller.read(), contact_chat.profile.avatar.as_deref());
+                        let presence = contact_chat.profile.last_connection;
                         let profile_id_open = profile_id.clone();
                         let profile_id_details = profile_id.clone();
                         let profile_id_leave = profile_id.clone();
                         let profile_name_leave = profile_name.clone();
+                        let profile_id_block = profile_id.clone();
+                        let profile_name_block = profile_name.clone();
                         rsx! {
                             ContextMenu {
                                 ContextMenuTrigger {
@@ -161,7 +168,8 @@ pub fn ContactColumn(
                                         name: profile_name,
                                         avatar_url,
                                         last_message,
-                                        last_connection,
+                                        last_connection: None,
+                                        presence,
                                         on_select: selected_topic_id,
                                         highlight: search_query(),
                                     }
@@ -203,6 +211,22 @@ pub fn ContactColumn(
                                         },
                                         "Remove Contact"
                                     }
+                                    ContextMenuItem {
+                                        class: "context-menu-item-danger",
+                                        value: "Block Contact".to_string(),
+                                        index: 3usize,
+                                        on_select: move |_| {
+                                            show_leave_confirmation
+                                                .set(
+                                                    Some((
+                                                        profile_id_block.clone(),
+                                                        profile_name_block.clone(),
+                                                        RemovalType::Block,
+                                                    )),
+                                                );
+                                        },
+                                        "Block Contact"
+                                    }
                                 }
                             }
                         }
@@ -212,29 +236,92 @@ pub fn ContactColumn(
     }
 }
 
+/// The peers asking to become a contact, each with buttons to accept, reject or block it.
+#[component]
+pub fn ContactRequests<C: Controller + 'static>(
+    app_state: Signal<AppState>,
+    controller: Signal<C>,
+) -> Element {
+    let requests = app_state().get_contact_requests();
+
+    if requests.is_empty() {
+        return rsx! {};
+    }
+
+    rsx! {
+        div { class: "border-b border-border pb-2",
+            p { class: "m-0 px-4 pt-3 pb-1 text-sm font-medium text-text-secondary uppercase tracking-wider",
+                "Contact requests"
+            }
+            ul {
+                {
+                    requests
+                        .into_iter()
+                        .map(|request| {
+                            let id_accept = request.id.clone();
+                            let id_reject = request.id.clone();
+                            let id_block = request.id.clone();
+                            let short_id = truncate_id(&request.id);
+                            let received = format_relative_time(
+                                i64::try_from(request.timestamp / 1000).unwrap_or_default(),
+                            );
+                            rsx! {
+                                li { class: "flex flex-col gap-2 px-4 py-2 list-none",
+                                    div { class: "flex items-center justify-between gap-2",
+                                        span {
+                                            class: "font-mono text-sm text-text-primary overflow-hidden text-ellipsis",
+                                            title: "{request.id}",
+                                            "{short_id}"
+                                        }
+                                        span { class: "text-xs text-text-muted shrink-0", "{received}" }
+                                    }
+                                    div { class: "flex gap-2",
+                                        button {
+                                            class: "btn-primary py-1 px-3 text-sm",
+                                            onclick: move |_| controller.read().accept_contact_request(id_accept.clone()),
+                                            "Accept"
+                                        }
+                                        button {
+                                            class: "btn-secondary py-1 px-3 text-sm",
+                                            onclick: move |_| controller.read().reject_contact_request(id_reject.clone()),
+                                            "Reject"
+                                        }
+                                        button {
+                                            class: "btn-danger py-1 px-3 text-sm",
+                                            onclick: move |_| controller.read().block_user(id_block.clone()),
+                                            "Block"
+                                        }
+                                    }
+                                }
+                            }
+                        })
+                }
+            }
+        }
+    }
+}
+
 #[component]
 pub fn ColumnItem(
     id: String,
     name: String,
-    avatar_url: Option<String>,
+    avatar_url: String,
     last_message: Option<String>,
     last_connection: Option<u64>,
     on_select: Signal<Option<String>>,
     #[props(default)] highlight: Option<String>,
+    #[props(default)] presence: Option<ConnectionStatus>,
 ) -> Element {
     let last_message_display = last_message.unwrap_or_default();
 
-    let avatar_display = if let Some(url) = &avatar_url
-        && !url.is_empty()
-    {
-        url.clone()
-    } else {
-        DEFAULT_AVATAR.to_string()
-    };
-
-    let time_display = last_connection.map_or_else(String::new, |timestamp| {
-        format_relative_time(i64::try_from(timestamp).unwrap())
-    });
+    let time_display = presence.as_ref().map_or_else(
+        || {
+            last_connection.map_or_else(String::new, |timestamp| {
+                format_relative_time(i64::try_from(timestamp).unwrap())
+            })
+        },
+        format_last_seen,
+    );
 
     let name_display = highlight.as_ref().filter(|q| !q.is_empty()).map_or_else(
         || rsx! { "{name}" },
@@ -275,7 +362,7 @@ pub fn ColumnItem(
             },
             img {
                 class: "avatar w-12.5 h-12.5 shrink-0 transition-colors duration-200 group-hover:border-text-muted",
-                src: "{avatar_display}",
+                src: "{avatar_url}",
                 alt: "{name}",
                 draggable: "false",
             }
@@ -1,6 +1,12 @@
-use super::desktop_web_components::{CLOSE_ICON, DEFAULT_AVATAR, DOWNLOAD_ICON};
-use super::models::{AppState, ConnectionStatus, Controller, Profile, Topic};
-use super::utils::{copy_to_clipboard, format_relative_time};
+use super::desktop_web_components::{CLOSE_ICON, DOWNLOAD_ICON};
+use super::models::{
+    AppState, Controller, ImageEncoding, MediaSettings, Profile, RetentionPolicy, Topic,
+    Verification,
+};
+use super::utils::{
+    DISAPPEARING_TIMERS, RETENTION_COUNTS, RETENTION_DAYS, avatar_src, copy_to_clipboard,
+    format_file_size, format_last_seen, format_timer, qr_code_src, truncate_id,
+};
 use arboard::Clipboard;
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
@@ -18,6 +24,16 @@ pub fn TopicDetails<C: Controller + 'static>(
 ) -> Element {
     let toast = use_toast();
     let mut edited_title = use_signal(|| topic.name.clone());
+    let mut edited_slow_mode = use_signal(|| topic.slow_mode.unwrap_or(0));
+    let mut edited_disappearing = use_signal(|| topic.disappearing.unwrap_or(0));
+    let edited_retention = use_signal(|| {
+        app_state()
+            .get_retention_settings()
+            .chats
+            .get(&topic.id)
+            .copied()
+    });
+    let is_admin = topic.admin.as_deref() == Some(app_state().get_profile().id.as_str());
 
     let handle_copy_topic_id = {
         let topic_id = topic.id.clone();
@@ -36,7 +52,15 @@ pub fn TopicDetails<C: Controller + 'static>(
     let handle_save = move |_event: Event<MouseData>| {
         let mut updated_topic = topic_clone.clone();
         updated_topic.name = edited_title().trim().to_string();
+        updated_topic.slow_mode = Some(edited_slow_mode()).filter(|seconds| *seconds > 0);
+        updated_topic.disappearing = Some(edited_disappearing()).filter(|seconds| *seconds > 0);
         let controller = controller;
+        save_chat_retention(
+            &*controller.read(),
+            app_state,
+            &topic_clone.id,
+            edited_retention(),
+        );
         controller.read().modify_topic(updated_topic);
         toast.success(
             "Topic updated successfully".to_owned(),
@@ -90,13 +114,7 @@ pub fn TopicDetails<C: Controller + 'static>(
         }
     };
 
-    let avatar_url = if let Some(url) = &topic.avatar_url
-        && !url.is_empty()
-    {
-        url.clone()
-    } else {
-        DEFAULT_AVATAR.to_string()
-    };
+    let avatar_url = avatar_src(&*controller.read(), topic.avatar_url.as_deref());
 
     rsx! {
         div {
@@ -139,25 +157,93 @@ pub fn TopicDetails<C: Controller + 'static>(
                     onclick: handle_copy_topic_id,
                     "{topic.id}"
                 }
+                if is_admin {
+                    p { class: "my-4 mb-2 text-sm font-medium text-text-secondary uppercase tracking-wider",
+                        "Slow mode"
+                    }
+                    select {
+                        class: "input-field w-full m-0 border border-border text-sm",
+                        value: "{edited_slow_mode}",
+                        onchange: move |e| {
+                            if let Ok(seconds) = e.value().parse() {
+                                edited_slow_mode.set(seconds);
+                            }
+                        },
+                        option { value: "0", "Off" }
+                        option { value: "10", "10 seconds" }
+                        option { value: "30", "30 seconds" }
+                        option { value: "60", "1 minute" }
+                        option { value: "300", "5 minutes" }
+                    }
+                } else if let Some(seconds) = topic.slow_mode {
+                    p { class: "my-4 mb-2 text-sm font-medium text-text-secondary uppercase tracking-wider",
+                        "Slow mode"
+                    }
+                    p { class: "input-field m-0 border border-border text-sm",
+                        "One message every {seconds} seconds"
+                    }
+                }
+                if is_admin {
+                    p { class: "my-4 mb-2 text-sm font-medium text-text-secondary uppercase tracking-wider",
+                        "Disappearing messages"
+                    }
+                    select {
+                        class: "input-field w-full m-0 border border-border text-sm",
+                        value: "{edited_disappearing}",
+                        onchange: move |e| {
+                            if let Ok(seconds) = e.value().parse() {
+                                edited_disappearing.set(seconds);
+                            }
+                        },
+                        for (seconds, label) in DISAPPEARING_TIMERS {
+                            option { value: "{seconds}", "{label}" }
+                        }
+                    }
+                } else if let Some(seconds) = topic.disappearing {
+                    p { class: "my-4 mb-2 text-sm font-medium text-text-secondary uppercase tracking-wider",
+                        "Disappearing messages"
+                    }
+                    p { class: "input-field m-0 border border-border text-sm",
+                        "Messages disappear after {format_timer(seconds)}"
+                    }
+                }
+                p { class: "my-4 mb-2 text-sm font-medium text-text-secondary uppercase tracking-wider",
+                    "Storage"
+                }
+                ChatRetention { chat_id: topic.id.clone(), app_state, policy: edited_retention }
                 div { class: "mb-4",
                     p { class: "my-4 mb-2 text-sm font-medium text-text-secondary uppercase tracking-wider",
                         "Members"
                     }
                     ul {
                         {
+                            // Presence changes while the panel is open, so read it from the state.
+                            let own_id = app_state().get_profile().id;
+                            let online = app_state()
+                                .get_topic(&topic.id)
+                                .map(|t| t.online.clone())
+                                .unwrap_or_default();
                             let members: Vec<Profile> = {
                                 let state = app_state();
                                 let own_profile = state.get_profile();
                                 topic
                                     .members
-                                    .iter()
+                                    .union(&online)
                                     .map(|member_id| {
                                         if let Some(contact) = state.get_contact(member_id) {
                                             contact.clone()
                                         } else if member_id == &own_profile.id {
                                             own_profile.clone()
                                         } else {
-                                            Profile::new_with_id(member_id)
+                                            let mut profile = Profile::new_with_id(member_id);
+                                            if let Some(member) = state
+                                                .get_topic(&topic.id)
+                                                .and_then(|t| t.get_member_profile(member_id))
+                                            {
+                                                profile.name.clone_from(&member.name);
+                                                profile.avatar.clone_from(&member.avatar);
+                                            }
+                                            profile
                                         }
                                     })
                                     .collect()
@@ -165,17 +251,13 @@ pub fn TopicDetails<C: Controller + 'static>(
                             members
                                 .into_iter()
                                 .map(|member| {
-                                    let avatar = if let Some(url) = &member.avatar && !url.is_empty() {
-                                        url.clone()
-                                    } else {
-                                        DEFAULT_AVATAR.to_string()
-                                    };
+                                    let avatar = avatar_src(&*controller.read(), member.avatar.as_deref());
 
-                                    let last_seen = match member.last_connection {
-                                        ConnectionStatus::Online => member.last_connection.to_string(),
-                                        ConnectionStatus::Offline(time) => {
-                                            format_relative_time(i64::try_from(time / 1000).unwrap())
-                                        }
+                                    let is_online = member.id == own_id || online.contains(&member.id);
+                                    let last_seen = if is_online {
+                                        "Online now".to_string()
+                                    } else {
+                                        format_last_seen(&member.last_connection)
                                     };
                                     let member_clone = member.clone();
                                     rsx! {
@@ -210,10 +292,25 @@ pub fn ProfileDetails<C: Controller + 'static>(
     mut toggle: Signal<Option<Profile>>,
     controller: Signal<C>,
     readonly: bool,
+    is_contact: bool,
+    disappearing: Option<u64>,
+    media_settings: MediaSettings,
+    blocked: Vec<String>,
+    app_state: Signal<AppState>,
 ) -> Element {
     let toast = use_toast();
     let mut edited_name = use_signal(|| profile.name.clone());
     let mut edited_avatar = use_signal(|| profile.avatar.clone());
+    let mut edited_media = use_signal(|| media_settings);
+    let mut compared_number = use_signal(String::new);
+    let mut edited_global_retention = use_signal(|| app_state().get_retention_settings().global);
+    let edited_chat_retention = use_signal(|| {
+        app_state()
+            .get_retention_settings()
+            .chats
+            .get(&profile.id)
+            .copied()
+    });
 
     let profile_id = profile.id.clone();
     let handle_copy_profile_id = {
@@ -235,6 +332,14 @@ pub fn ProfileDetails<C: Controller + 'static>(
         updated_profile.avatar = edited_avatar();
         let controller = controller;
         controller.read().modify_profile(updated_profile);
+        if edited_media() != media_settings {
+            controller.read().modify_media_settings(edited_media());
+        }
+        let mut retention = app_state().get_retention_settings();
+        if edited_global_retention() != retention.global {
+            retention.global = edited_global_retention();
+            controller.read().modify_retention_settings(retention);
+        }
         toast.success(
             "Profile updated successfully".to_owned(),
             ToastOptions::default(),
@@ -281,18 +386,34 @@ pub fn ProfileDetails<C: Controller + 'static>(
         }
     };
 
-    let avatar_url = if let Some(url) = edited_avatar()
-        && !url.is_empty()
-    {
-        url
-    } else {
-        DEFAULT_AVATAR.to_string()
-    };
+    let avatar_url = avatar_src(&*controller.read(), edited_avatar().as_deref());
 
-    let last_connection_text = match profile.last_connection {
-        ConnectionStatus::Online => profile.last_connection.to_string(),
-        ConnectionStatus::Offline(time) => format_relative_time(i64::try_from(time / 1000).unwrap()),
+    let last_connection_text = format_last_seen(&profile.last_connection);
+
+    // Only other peers share a safety number with us.
+    let safety_number = if readonly {
+        controller.read().get_safety_number(&profile.id)
+    } else {
+        None
     };
+    let verification = safety_number
+        .as_deref()
+        .map_or(Verification::Unverified, |number| {
+            profile.verification(number)
+        });
+    let safety_qr = safety_number.as_deref().and_then(qr_code_src);
+    let comparison = safety_number.as_deref().and_then(|number| {
+        let compared: String = compared_number().split_whitespace().collect();
+        (!compared.is_empty()).then(|| compared == number.replace(' ', ""))
+    });
+    let profile_id_verify = profile.id.clone();
+    let profile_id_timer = profile.id.clone();
+    let profile_id_retention = profile.id.clone();
+    let saved_chat_retention = app_state()
+        .get_retention_settings()
+        .chats
+        .get(&profile.id)
+        .copied();
 
     rsx! {
         div {
@@ -365,11 +486,348 @@ pub fn ProfileDetails<C: Controller + 'static>(
                         "{last_connection_text}"
                     }
                 }
+
+                if is_contact {
+                    div { class: "mt-4",
+                        p { class: "m-0 mb-2 text-sm font-medium text-text-secondary uppercase tracking-wider",
+                            "Disappearing messages"
+                        }
+                        // The timer applies to both sides, so it is sent right away.
+                        select {
+                            class: "input-field w-full m-0 border border-border text-sm",
+                            value: "{disappearing.unwrap_or(0)}",
+                            onchange: move |e| {
+                                if let Ok(seconds) = e.value().parse::<u64>() {
+                                    controller
+                                        .read()
+                                        .set_contact_disappearing(
+                                            profile_id_timer.clone(),
+                                            Some(seconds).filter(|seconds| *seconds > 0),
+                                        );
+                                }
+                            },
+                            for (seconds, label) in DISAPPEARING_TIMERS {
+                                option { value: "{seconds}", "{label}" }
+                            }
+                        }
+                    }
+                    div { class: "mt-4",
+                        p { class: "m-0 mb-2 text-sm font-medium text-text-secondary uppercase tracking-wider",
+                            "Storage"
+                        }
+                        ChatRetention {
+                            chat_id: profile.id.clone(),
+                            app_state,
+                            policy: edited_chat_retention,
+                        }
+                        // Applying may delete history, so nothing changes until it is confirmed.
+                        if edited_chat_retention() != saved_chat_retention {
+                            button {
+                                class: "btn-primary w-full mt-3 py-2 text-sm",
+                                onclick: move |_| {
+                                    save_chat_retention(
+                                        &*controller.read(),
+                                        app_state,
+                                        &profile_id_retention,
+                                        edited_chat_retention(),
+                                    );
+                                },
+                                "Apply"
+                            }
+                        }
+                    }
+                }
+
+                if let Some(number) = safety_number {
+                    div { class: "mt-4",
+                        div { class: "flex items-center justify-between mb-2",
+                            p { class: "m-0 text-sm font-medium text-text-secondary uppercase tracking-wider",
+                                "Safety Number"
+                            }
+                            if verification == Verification::Verified {
+                                span { class: "text-xs font-medium text-accent", "✓ Verified" }
+                            }
+                        }
+                        if verification == Verification::Changed {
+                            p { class: "m-0 mb-2 p-3 rounded-lg bg-danger/15 text-danger text-sm",
+                                "The safety number changed since you verified {profile.name}. One of your keys is different: compare it again before trusting this chat."
+                            }
+                        }
+                        div { class: "flex gap-3 items-center",
+                            p { class: "input-field flex-1 m-0 border border-border font-mono text-sm tracking-wider",
+                                "{number}"
+                            }
+                            if let Some(qr) = safety_qr {
+                                img {
+                                    class: "w-24 h-24 rounded bg-white p-1 shrink-0",
+                                    src: "{qr}",
+                                    alt: "Safety number QR code",
+                                }
+                            }
+                        }
+                        p { class: "m-0 mt-2 text-xs text-text-muted",
+                            "Compare it with {profile.name} in person or over a call, or scan their code. It only matches if you both have the right keys."
+                        }
+                        input {
+                            class: "input-field w-full mt-2 mb-0 border border-border font-mono text-sm",
+                            r#type: "text",
+                            value: "{compared_number}",
+                            placeholder: "Paste the number they sent you",
+                            oninput: move |e| compared_number.set(e.value()),
+                        }
+                        match comparison {
+                            Some(true) => rsx! {
+                                p { class: "m-0 mt-1 text-xs text-accent", "The numbers match." }
+                            },
+                            Some(false) => rsx! {
+                                p { class: "m-0 mt-1 text-xs text-danger", "The numbers do not match." }
+                            },
+                            None => rsx! {},
+                        }
+                        if is_contact {
+                            button {
+                                class: if verification == Verification::Verified { "btn-secondary w-full mt-3 py-2 text-sm" } else { "btn-primary w-full mt-3 py-2 text-sm" },
+                                onclick: move |_| {
+                                    controller
+                                        .read()
+                                        .verify_contact(
+                                            profile_id_verify.clone(),
+                                            verification != Verification::Verified,
+                                        );
+                                },
+                                if verification == Verification::Verified {
+                                    "Unverify"
+                                } else {
+                                    "Mark as Verified"
+                                }
+                            }
+                        }
+                    }
+                }
+
+                if !readonly {
+                    div { class: "mt-4",
+                        p { class: "m-0 mb-2 text-sm font-medium text-text-secondary uppercase tracking-wider",
+                            "Images"
+                        }
+                        label { class: "flex items-center gap-2 mb-3 text-sm text-text-primary cursor-pointer",
+                            input {
+                                r#type: "checkbox",
+                                checked: edited_media().send_original,
+                                onchange: move |e| edited_media.write().send_original = e.checked(),
+                            }
+                            "Send original files (keeps size and metadata)"
+                        }
+                        if !edited_media().send_original {
+                            div { class: "flex gap-3",
+                                select {
+                                    class: "input-field flex-1 m-0 border border-border text-sm",
+                                    value: "{edited_media().max_dimension}",
+                                    onchange: move |e| {
+                                        if let Ok(max_dimension) = e.value().parse() {
+                                            edited_media.write().max_dimension = max_dimension;
+                                        }
+                                    },
+                                    option { value: "1280", "Max 1280 px" }
+                                    option { value: "2048", "Max 2048 px" }
+                                    option { value: "4096", "Max 4096 px" }
+                                }
+                                select {
+                                    class: "input-field flex-1 m-0 border border-border text-sm",
+                                    value: if edited_media().encoding == ImageEncoding::WebP { "webp" } else { "jpeg" },
+                                    onchange: move |e| {
+                                        edited_media.write().encoding = if e.value() == "webp" {
+                                            ImageEncoding::WebP
+                                        } else {
+                                            ImageEncoding::Jpeg
+                                        };
+                                    },
+                                    option { value: "jpeg", "JPEG" }
+                                    option { value: "webp", "WebP (lossless)" }
+                                }
+                            }
+                            if edited_media().encoding == ImageEncoding::Jpeg {
+                                label { class: "flex items-center gap-3 mt-3 text-sm text-text-secondary",
+                                    "Quality"
+                                    input {
+                                        class: "flex-1",
+                                        r#type: "range",
+                                        min: "40",
+                                        max: "100",
+                                        value: "{edited_media().quality}",
+                                        oninput: move |e| {
+                                            if let Ok(quality) = e.value().parse() {
+                                                edited_media.write().quality = quality;
+                                            }
+                                        },
+                                    }
+                                    "{edited_media().quality}"
+                                }
+                            }
+                        }
+                    }
+                    div { class: "mt-4",
+                        p { class: "m-0 mb-2 text-sm font-medium text-text-secondary uppercase tracking-wider",
+                            "Storage"
+                        }
+                        p { class: "m-0 mb-3 text-xs text-text-muted",
+                            "Applies to the chats without a policy of their own. It only frees space on this device."
+                        }
+                        RetentionEditor {
+                            policy: edited_global_retention(),
+                            freed: app_state().retention_savings(&edited_global_retention(), None, now_millis()),
+                            onchange: move |policy| edited_global_retention.set(policy),
+                        }
+                    }
+                    if !blocked.is_empty() {
+                        div { class: "mt-4",
+                            p { class: "m-0 mb-2 text-sm font-medium text-text-secondary uppercase tracking-wider",
+                                "Blocked"
+                            }
+                            ul { class: "m-0 p-0 max-h-40 overflow-y-auto",
+                                {
+                                    blocked
+                                        .into_iter()
+                                        .map(|id| {
+                                            let short_id = truncate_id(&id);
+                                            let id_unblock = id.clone();
+                                            rsx! {
+                                                li { class: "flex items-center justify-between gap-2 py-1 list-none",
+                                                    span {
+                                                        class: "font-mono text-sm text-text-primary overflow-hidden text-ellipsis",
+                                                        title: "{id}",
+                                                        "{short_id}"
+                                                    }
+                                                    button {
+                                                        class: "btn-secondary py-1 px-3 text-sm",
+                                                        onclick: move |_| controller.read().unblock_user(id_unblock.clone()),
+                                                        "Unblock"
+                                                    }
+                                                }
+                                            }
+                                        })
+                                }
+                            }
+                        }
+                    }
+                }
             }
         }
     }
 }
 
+/// Edits the retention policy of a single chat, which replaces the global one when set.
+#[component]
+fn ChatRetention(
+    chat_id: String,
+    app_state: Signal<AppState>,
+    mut policy: Signal<Option<RetentionPolicy>>,
+) -> Element {
+    let global = app_state().get_retention_settings().global;
+
+    rsx! {
+        label { class: "flex items-center gap-2 mb-3 text-sm text-text-primary cursor-pointer",
+            input {
+                r#type: "checkbox",
+                checked: policy().is_none(),
+                onchange: move |e| policy.set(if e.checked() { None } else { Some(global) }),
+            }
+            "Follow the global policy"
+        }
+        if let Some(own) = policy() {
+            RetentionEditor {
+                policy: own,
+                freed: app_state().retention_savings(&own, Some(chat_id.as_str()), now_millis()),
+                onchange: move |own| policy.set(Some(own)),
+            }
+        }
+    }
+}
+
+/// Edits a retention policy and shows how much space applying it would free.
+#[component]
+fn RetentionEditor(
+    policy: RetentionPolicy,
+    freed: u64,
+    onchange: EventHandler<RetentionPolicy>,
+) -> Element {
+    rsx! {
+        div { class: "flex flex-col gap-2",
+            label { class: "flex items-center gap-3 text-sm text-text-secondary",
+                span { class: "w-36 shrink-0", "Keep" }
+                select {
+                    class: "input-field flex-1 m-0 border border-border text-sm",
+                    value: "{policy.max_messages.unwrap_or(0)}",
+                    onchange: move |e| {
+                        if let Ok(max) = e.value().parse::<usize>() {
+                            let max_messages = Some(max).filter(|max| *max > 0);
+                            onchange.call(RetentionPolicy { max_messages, ..policy });
+                        }
+                    },
+                    for (max, label) in RETENTION_COUNTS {
+                        option { value: "{max}", "{label}" }
+                    }
+                }
+            }
+            label { class: "flex items-center gap-3 text-sm text-text-secondary",
+                span { class: "w-36 shrink-0", "Delete messages" }
+                select {
+                    class: "input-field flex-1 m-0 border border-border text-sm",
+                    value: "{policy.max_age_days.unwrap_or(0)}",
+                    onchange: move |e| {
+                        if let Ok(days) = e.value().parse::<u64>() {
+                            let max_age_days = Some(days).filter(|days| *days > 0);
+                            onchange.call(RetentionPolicy { max_age_days, ..policy });
+                        }
+                    },
+                    for (days, label) in RETENTION_DAYS {
+                        option { value: "{days}", "{label}" }
+                    }
+                }
+            }
+            label { class: "flex items-center gap-3 text-sm text-text-secondary",
+                span { class: "w-36 shrink-0", "Drop media" }
+                select {
+                    class: "input-field flex-1 m-0 border border-border text-sm",
+                    value: "{policy.media_age_days.unwrap_or(0)}",
+                    onchange: move |e| {
+                        if let Ok(days) = e.value().parse::<u64>() {
+                            let media_age_days = Some(days).filter(|days| *days > 0);
+                            onchange.call(RetentionPolicy { media_age_days, ..policy });
+                        }
+                    },
+                    for (days, label) in RETENTION_DAYS {
+                        option { value: "{days}", "{label}" }
+                    }
+                }
+            }
+            p { class: "m-0 text-xs text-text-muted", "Frees about {format_file_size(freed)}" }
+        }
+    }
+}
+
+/// Stores the policy of a chat, `None` to follow the global one, if it changed.
+fn save_chat_retention<C: Controller>(
+    controller: &C,
+    app_state: Signal<AppState>,
+    chat_id: &str,
+    policy: Option<RetentionPolicy>,
+) {
+    let mut settings = app_state().get_retention_settings();
+    if settings.chats.get(chat_id).copied() == policy {
+        return;
+    }
+    match policy {
+        Some(policy) => settings.chats.insert(chat_id.to_string(), policy),
+        None => settings.chats.remove(chat_id),
+    };
+    controller.modify_retention_settings(settings);
+}
+
+fn now_millis() -> u64 {
+    u64::try_from(chrono::Utc::now().timestamp_millis()).unwrap_or_default()
+}
+
 #[component]
 pub fn ImageDetails(image: String, name: String, on_close: EventHandler<()>) -> Element {
     let image_rc = Rc::new(image);
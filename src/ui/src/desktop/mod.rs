@@ -11,7 +11,7 @@ pub mod desktop_web_components {
     use crate::desktop::dialogs::{ProgressBar, TopicDialog};
 
     pub use super::chat::Chat;
-    pub use super::columns::{ContactColumn, TopicColumn};
+    pub use super::columns::{ContactColumn, ContactRequests, TopicColumn};
     pub use super::details::{ProfileDetails, TopicDetails};
     pub use super::dialogs::{ConfirmationDialog, ContactDialog};
     pub use super::models::{
@@ -19,6 +19,7 @@ pub mod desktop_web_components {
     };
 
     use crate::components::toast::ToastProvider;
+    use crate::desktop::utils::avatar_src;
     use crate::desktop::details::ImageDetails;
     use crate::desktop::details::VideoDetails;
     use dioxus::prelude::*;
@@ -52,13 +53,7 @@ pub mod desktop_web_components {
             state.get_profile()
         };
 
-        let avatar_url = if let Some(url) = &profile_data.avatar
-            && !url.is_empty()
-        {
-            url.clone()
-        } else {
-            DEFAULT_AVATAR.to_string()
-        };
+        let avatar_url = avatar_src(&*controller.read(), profile_data.avatar.as_deref());
 
         let profile_for_click = profile_data.clone();
 
@@ -121,14 +116,17 @@ pub mod desktop_web_components {
                                 show_topic_details,
                                 show_leave_confirmation,
                                 app_state,
+                                controller,
                             }
                         } else {
+                            ContactRequests { app_state, controller }
                             ContactColumn {
                                 search_query,
                                 selected_topic_id,
                                 show_profile_details,
                                 show_leave_confirmation,
                                 app_state,
+                                controller,
                             }
                         }
                     }
@@ -152,10 +150,16 @@ pub mod desktop_web_components {
                     if let Some(profile) = show_profile_details() {
                         ToastProvider {
                             ProfileDetails {
-                                profile: profile.clone(),
+                                // Contacts are read again so verifying them shows up right away.
+                                profile: app_state().get_contact(&profile.id).cloned().unwrap_or_else(|| profile.clone()),
+                                is_contact: app_state().get_contact(&profile.id).is_some(),
+                                disappearing: app_state().get_contact_chat(&profile.id).and_then(|chat| chat.disappearing),
                                 toggle: show_profile_details,
                                 controller,
                                 readonly: profile.id != profile_data.id,
+                                media_settings: app_state().get_media_settings(),
+                                blocked: app_state().get_blocked(),
+                                app_state,
                             }
                         }
                     }
@@ -223,6 +227,15 @@ pub mod desktop_web_components {
                                         "Remove".to_string(),
                                     )
                                 }
+                                RemovalType::Block => {
+                                    (
+                                        "Block Contact".to_string(),
+                                        format!(
+                                            "Are you sure you want to block \"{name}\"? They will be removed from your contacts and nothing they send will reach you."
+                                        ),
+                                        "Block".to_string(),
+                                    )
+                                }
                             };
                             rsx! {
                                 ConfirmationDialog {
@@ -236,6 +249,7 @@ pub mod desktop_web_components {
                                         match removal_type {
                                             RemovalType::Topic => controller.read().leave_topic(id.clone()),
                                             RemovalType::Contact => controller.read().remove_contact(id.clone()),
+                                            RemovalType::Block => controller.read().block_user(id.clone()),
                                         }
                                         show_leave_confirmation.set(None);
                                         selected_topic_id.set(None);
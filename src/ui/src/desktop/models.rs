@@ -7,6 +7,11 @@ use std::fmt::{Debug, Display};
 use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
 
+/// Minimum time between two typing signals sent for the same chat, in milliseconds.
+pub const TYPING_SEND_INTERVAL: u64 = 3_000;
+/// How long a typing signal is shown if it is not repeated, in milliseconds.
+pub const TYPING_TIMEOUT: u64 = 6_000;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Topic {
     pub id: String,
@@ -17,6 +22,40 @@ pub struct Topic {
     pub messages: Vec<Message>,
     pub last_changed: u64,
     pub members: HashSet<String>,
+    /// The identifier of the topic admin, its creator.
+    pub admin: Option<String>,
+    /// Minimum number of seconds between two posts of the same member, if set.
+    pub slow_mode: Option<u64>,
+    /// Number of seconds messages are kept for before they disappear, if set.
+    pub disappearing: Option<u64>,
+    /// Profiles announced by the members of the topic, by member id.
+    pub member_profiles: HashMap<String, MemberProfile>,
+    /// Peers currently connected to us in the gossip swarm of the topic. Not persisted.
+    #[serde(skip)]
+    pub online: HashSet<String>,
+    /// Messages added or removed since the topic was last written to disk.
+    #[serde(skip)]
+    unsaved: Vec<MessageChange<Message>>,
+}
+
+/// The name and avatar a topic member announced, shown for members who are not contacts.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemberProfile {
+    pub name: String,
+    pub avatar: Option<String>,
+    /// When the profile was announced, in milliseconds.
+    pub timestamp: u64,
+}
+
+impl MemberProfile {
+    #[must_use]
+    pub const fn new(name: String, avatar: Option<String>, timestamp: u64) -> Self {
+        Self {
+            name,
+            avatar,
+            timestamp,
+        }
+    }
 }
 
 impl Topic {
@@ -32,6 +71,12 @@ impl Topic {
             messages: Vec::new(),
             last_changed: chrono::Utc::now().timestamp_millis() as u64,
             members: HashSet::new(),
+            admin: None,
+            slow_mode: None,
+            disappearing: None,
+            member_profiles: HashMap::new(),
+            online: HashSet::new(),
+            unsaved: Vec::new(),
         }
     }
 
@@ -46,33 +91,139 @@ impl Topic {
             messages: Vec::new(),
             last_changed: 0,
             members: HashSet::new(),
+            admin: None,
+            slow_mode: None,
+            disappearing: None,
+            member_profiles: HashMap::new(),
+            online: HashSet::new(),
+            unsaved: Vec::new(),
         }
     }
 
     pub fn add_message(&mut self, message: ChatMessage) {
         self.last_message = Some(message.content.clone());
-        self.messages.push(Message::Chat(message));
+        self.push_message(Message::Chat(message));
         self.messages.sort();
     }
 
     pub fn add_leave_message(&mut self, message: LeaveMessage) {
-        self.messages.push(Message::Leave(message));
+        self.push_message(Message::Leave(message));
     }
 
     pub fn add_join_message(&mut self, message: JoinMessage) {
-        self.messages.push(Message::Join(message));
+        self.push_message(Message::Join(message));
     }
 
     pub fn add_disconnect_message(&mut self, message: DisconnectMessage) {
-        self.messages.push(Message::Disconnect(message));
+        self.push_message(Message::Disconnect(message));
     }
 
     pub fn add_blob_message(&mut self, message: BlobMessage) {
         self.last_message = Some(format!("[{}]", message.blob_name));
-        self.messages.push(Message::Blob(message));
+        self.push_message(Message::Blob(message));
+        self.messages.sort();
+    }
+
+    pub fn add_collection_message(&mut self, message: CollectionMessage) {
+        self.last_message = Some(format!("[{}]", message.name));
+        self.push_message(Message::Collection(message));
         self.messages.sort();
     }
 
+    fn push_message(&mut self, message: Message) {
+        self.unsaved.push(MessageChange::Added(message.clone()));
+        self.messages.push(message);
+    }
+
+    /// The messages added or removed since the chat was last written to disk.
+    #[must_use]
+    pub fn unsaved(&self) -> &[MessageChange<Message>] {
+        &self.unsaved
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.unsaved.clear();
+    }
+
+    /// A copy of the topic without its messages, which are stored one by one.
+    #[must_use]
+    pub fn details(&self) -> Self {
+        Self {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            avatar_url: self.avatar_url.clone(),
+            last_connection: self.last_connection,
+            last_message: self.last_message.clone(),
+            messages: Vec::new(),
+            last_changed: self.last_changed,
+            members: self.members.clone(),
+            admin: self.admin.clone(),
+            slow_mode: self.slow_mode,
+            disappearing: self.disappearing,
+            member_profiles: self.member_profiles.clone(),
+            online: HashSet::new(),
+            unsaved: Vec::new(),
+        }
+    }
+
+    /// Removes the messages sent before `cutoff`, in milliseconds, and returns them.
+    pub fn remove_messages_before(&mut self, cutoff: u64) -> Vec<Message> {
+        self.remove_messages_where(|_, message| message.get_timestamp() < cutoff)
+    }
+
+    /// Removes the messages a retention policy does not keep at `now` and the files it does not
+    /// keep from the others, and returns the messages as they were.
+    pub fn prune(&mut self, policy: &RetentionPolicy, now: u64) -> Vec<Message> {
+        let len = self.messages.len();
+        let mut removed = self.remove_messages_where(|index, message| {
+            !policy.keeps(len - index, message.get_timestamp(), now)
+        });
+        removed.extend(self.remove_media_where(|message| {
+            message.has_media() && !policy.keeps_media(message.get_timestamp(), now)
+        }));
+        removed
+    }
+
+    /// Removes the messages matching `predicate`, given their index and themselves.
+    fn remove_messages_where(
+        &mut self,
+        mut predicate: impl FnMut(usize, &Message) -> bool,
+    ) -> Vec<Message> {
+        let mut removed = Vec::new();
+        let mut index = 0;
+        self.messages.retain(|message| {
+            let matches = predicate(index, message);
+            index += 1;
+            if matches {
+                removed.push(message.clone());
+            }
+            !matches
+        });
+        self.unsaved
+            .extend(removed.iter().cloned().map(MessageChange::Removed));
+        if !removed.is_empty() {
+            self.last_message = self.messages.iter().rev().find_map(Message::preview);
+        }
+        removed
+    }
+
+    /// Drops the files of the messages matching `predicate`, keeping the messages, and returns
+    /// them as they were before.
+    fn remove_media_where(&mut self, mut predicate: impl FnMut(&Message) -> bool) -> Vec<Message> {
+        let mut stripped = Vec::new();
+        for message in &mut self.messages {
+            if !predicate(message) {
+                continue;
+            }
+            let before = message.clone();
+            message.remove_media();
+            self.unsaved.push(MessageChange::Removed(before.clone()));
+            self.unsaved.push(MessageChange::Added(message.clone()));
+            stripped.push(before);
+        }
+        stripped
+    }
+
     pub fn add_member(&mut self, profile_id: &str) {
         self.members.insert(profile_id.to_string());
     }
@@ -94,6 +245,29 @@ impl Topic {
     pub fn update_member(&mut self, profile_id: &str) {
         self.members.replace(profile_id.to_string());
     }
+
+    /// Caches the profile announced by a member, unless a newer one is already known.
+    pub fn set_member_profile(&mut self, profile_id: &str, profile: MemberProfile) {
+        match self.member_profiles.get(profile_id) {
+            Some(known) if known.timestamp > profile.timestamp => {}
+            _ => {
+                self.member_profiles.insert(profile_id.to_string(), profile);
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn get_member_profile(&self, profile_id: &str) -> Option<&MemberProfile> {
+        self.member_profiles.get(profile_id)
+    }
+
+    pub fn set_online(&mut self, profile_id: &str, online: bool) {
+        if online {
+            self.online.insert(profile_id.to_string());
+        } else {
+            self.online.remove(profile_id);
+        }
+    }
 }
 
 impl PartialEq for Topic {
@@ -115,6 +289,20 @@ pub struct AppState {
     current_topic_id: Option<String>,
     contacts: HashMap<String, ProfileChat>,
     profile: Profile,
+    media_settings: MediaSettings,
+    /// Who is typing in each chat (topic or contact id), with the time in milliseconds their
+    /// indicator expires. Never persisted.
+    typing: HashMap<String, HashMap<String, u64>>,
+    /// Peers asking to become a contact, waiting for the user to accept or reject them.
+    contact_requests: HashMap<String, ContactRequest>,
+    /// Peers whose contact requests are ignored.
+    blocked: HashSet<String>,
+    retention: RetentionSettings,
+    /// Messages for the user, e.g. why a post was rejected, shown once. Never persisted.
+    notices: Vec<String>,
+    /// Media downloads started from the chats, by blob hash, followed through the transfer
+    /// events. Never persisted.
+    downloads: HashMap<String, DownloadState>,
 }
 
 #[cfg(feature = "desktop")]
@@ -126,6 +314,13 @@ impl AppState {
             current_topic_id: None,
             contacts: HashMap::new(),
             profile: Profile::new_with_id(profile_id),
+            media_settings: MediaSettings::default(),
+            typing: HashMap::new(),
+            contact_requests: HashMap::new(),
+            blocked: HashSet::new(),
+            retention: RetentionSettings::default(),
+            notices: Vec::new(),
+            downloads: HashMap::new(),
         }
     }
 
@@ -179,6 +374,251 @@ impl AppState {
         self.current_topic_id.as_ref().and_then(|id| self.topics.get(id))
     }
 
+    pub fn set_topic_slow_mode(&mut self, topic_id: &str, slow_mode: Option<u64>) {
+        if let Some(topic) = self.topics.get_mut(topic_id) {
+            topic.slow_mode = slow_mode;
+        }
+    }
+
+    pub fn set_topic_disappearing(&mut self, topic_id: &str, seconds: Option<u64>) {
+        if let Some(topic) = self.topics.get_mut(topic_id) {
+            topic.disappearing = seconds;
+        }
+    }
+
+    /// Applies the message timer of a direct chat, set at `timestamp` by either side, unless a
+    /// newer one is already known. Returns whether it was applied.
+    pub fn set_contact_disappearing(
+        &mut self,
+        profile_id: &str,
+        seconds: Option<u64>,
+        timestamp: u64,
+    ) -> bool {
+        match self.contacts.get_mut(profile_id) {
+            Some(contact) if timestamp >= contact.disappearing_changed => {
+                contact.disappearing = seconds;
+                contact.disappearing_changed = timestamp;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes the messages whose timer ran out at `now`, in milliseconds.
+    pub fn remove_expired_messages(&mut self, now: u64) -> Purge {
+        self.purge(
+            |topic| match topic.disappearing {
+                Some(seconds) => topic.remove_messages_before(expiry_cutoff(now, seconds)),
+                None => Vec::new(),
+            },
+            |contact| match contact.disappearing {
+                Some(seconds) => contact.remove_messages_before(expiry_cutoff(now, seconds)),
+                None => Vec::new(),
+            },
+        )
+    }
+
+    /// Removes the messages and the files the retention policies do not keep at `now`, in
+    /// milliseconds.
+    pub fn apply_retention(&mut self, now: u64) -> Purge {
+        let retention = self.retention.clone();
+        self.purge(
+            |topic| topic.prune(&retention.policy_for(&topic.id), now),
+            |contact| contact.prune(&retention.policy_for(&contact.profile.id), now),
+        )
+    }
+
+    /// Estimates the bytes a policy would free at `now`, from the text and files of the messages
+    /// it removes. Without a `chat_id` it is the global policy, applied to the chats without one
+    /// of their own. Files shared in several messages are counted each time.
+    #[must_use]
+    pub fn retention_savings(
+        &self,
+        policy: &RetentionPolicy,
+        chat_id: Option<&str>,
+        now: u64,
+    ) -> u64 {
+        let applies = |id: &str| match chat_id {
+            Some(chat_id) => id == chat_id,
+            None => !self.retention.chats.contains_key(id),
+        };
+        let freed = |len: usize, index: usize, timestamp: u64, has_media: bool, size: u64| {
+            if policy.keeps(len - index, timestamp, now)
+                && (!has_media || policy.keeps_media(timestamp, now))
+            {
+                0
+            } else {
+                size
+            }
+        };
+
+        let topics = self
+            .topics
+            .values()
+            .filter(|topic| applies(&topic.id))
+            .flat_map(|topic| {
+                let len = topic.messages.len();
+                topic
+                    .messages
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, message)| {
+                        let timestamp = message.get_timestamp();
+                        freed(len, index, timestamp, message.has_media(), message.size())
+                    })
+            });
+        let contacts = self
+            .contacts
+            .values()
+            .filter(|contact| applies(&contact.profile.id))
+            .flat_map(|contact| {
+                let len = contact.messages.len();
+                contact
+                    .messages
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, message)| {
+                        let timestamp = message.get_timestamp();
+                        freed(len, index, timestamp, message.has_media(), message.size())
+                    })
+            });
+        topics.chain(contacts).sum()
+    }
+
+    /// Removes the messages the closures pick from each topic and contact, and finds which of
+    /// their blobs nothing refers to anymore.
+    fn purge(
+        &mut self,
+        mut from_topic: impl FnMut(&mut Topic) -> Vec<Message>,
+        mut from_contact: impl FnMut(&mut ProfileChat) -> Vec<DmMessage>,
+    ) -> Purge {
+        let mut purge = Purge::default();
+        let mut hashes = Vec::new();
+
+        for topic in self.topics.values_mut() {
+            let removed = from_topic(topic);
+            purge.topics |= !removed.is_empty();
+            hashes.extend(removed.iter().flat_map(Message::blob_hashes));
+        }
+        for contact in self.contacts.values_mut() {
+            let removed = from_contact(contact);
+            purge.contacts |= !removed.is_empty();
+            hashes.extend(removed.iter().flat_map(DmMessage::blob_hashes));
+        }
+
+        // The same file may have been shared again somewhere else.
+        let referenced = self.referenced_blobs();
+        hashes.sort();
+        hashes.dedup();
+        hashes.retain(|hash| !referenced.contains(hash));
+        purge.blobs = hashes;
+        purge
+    }
+
+    /// Every blob hash still shown somewhere: in a message or as an avatar.
+    fn referenced_blobs(&self) -> HashSet<String> {
+        let topics = self.topics.values().flat_map(|topic| {
+            let messages = topic.messages.iter().flat_map(Message::blob_hashes);
+            let avatars = topic
+                .member_profiles
+                .values()
+                .filter_map(|member| member.avatar.clone());
+            messages.chain(avatars).chain(topic.avatar_url.clone())
+        });
+        let contacts = self.contacts.values().flat_map(|contact| {
+            let messages = contact.messages.iter().flat_map(DmMessage::blob_hashes);
+            messages.chain(contact.profile.avatar.clone())
+        });
+        topics
+            .chain(contacts)
+            .chain(self.profile.avatar.clone())
+            .collect()
+    }
+
+    pub fn set_topic_member_profile(
+        &mut self,
+        topic_id: &str,
+        member: &str,
+        profile: MemberProfile,
+    ) {
+        if let Some(topic) = self.topics.get_mut(topic_id) {
+            topic.set_member_profile(member, profile);
+        }
+    }
+
+    pub fn set_topic_member_online(&mut self, topic_id: &str, member: &str, online: bool) {
+        if let Some(topic) = self.topics.get_mut(topic_id) {
+            topic.set_online(member, online);
+        }
+    }
+
+    /// Shows `member` as typing in the chat until `expires_at`, in milliseconds.
+    pub fn set_typing(&mut self, chat_id: &str, member: &str, expires_at: u64) {
+        self.typing
+            .entry(chat_id.to_string())
+            .or_default()
+            .insert(member.to_string(), expires_at);
+    }
+
+    /// Hides the typing indicator of `member`, e.g. once its message arrived.
+    pub fn clear_typing(&mut self, chat_id: &str, member: &str) {
+        if let Some(members) = self.typing.get_mut(chat_id) {
+            members.remove(member);
+            if members.is_empty() {
+                self.typing.remove(chat_id);
+            }
+        }
+    }
+
+    /// Queues a message to show to the user.
+    pub fn push_notice(&mut self, notice: String) {
+        self.notices.push(notice);
+    }
+
+    #[must_use]
+    pub const fn has_notices(&self) -> bool {
+        !self.notices.is_empty()
+    }
+
+    /// Removes the queued notices, once they are shown.
+    pub fn take_notices(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notices)
+    }
+
+    /// Follows the download of a blob started from a chat, again if it is retried.
+    pub fn start_download(&mut self, hash: &str) {
+        self.downloads
+            .insert(hash.to_string(), DownloadState::Progress(0));
+    }
+
+    /// Updates a download started from a chat, other transfers are ignored.
+    pub fn update_download(&mut self, hash: &str, download: DownloadState) {
+        if let Some(state) = self.downloads.get_mut(hash) {
+            *state = download;
+        }
+    }
+
+    #[must_use]
+    pub fn download_state(&self, hash: &str) -> Option<DownloadState> {
+        self.downloads.get(hash).copied()
+    }
+
+    /// Removes every typing indicator that expired at `now`, in milliseconds.
+    pub fn expire_typing(&mut self, now: u64) {
+        self.typing.retain(|_, members| {
+            members.retain(|_, expires_at| *expires_at > now);
+            !members.is_empty()
+        });
+    }
+
+    #[must_use]
+    pub fn get_typing(&self, chat_id: &str) -> Vec<String> {
+        self.typing
+            .get(chat_id)
+            .map(|members| members.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
     pub fn set_topic_members(&mut self, topic_id: &str, members: Vec<String>) {
         if let Some(topic) = self.topics.get_mut(topic_id) {
             topic.members = members.into_iter().collect();
@@ -207,11 +647,33 @@ impl AppState {
         self.topics.values().cloned().collect()
     }
 
+    pub fn topics(&self) -> impl Iterator<Item = &Topic> {
+        self.topics.values()
+    }
+
     #[must_use]
     pub fn get_profile(&self) -> Profile {
         self.profile.clone()
     }
 
+    #[must_use]
+    pub const fn get_media_settings(&self) -> MediaSettings {
+        self.media_settings
+    }
+
+    pub const fn set_media_settings(&mut self, settings: MediaSettings) {
+        self.media_settings = settings;
+    }
+
+    #[must_use]
+    pub fn get_retention_settings(&self) -> RetentionSettings {
+        self.retention.clone()
+    }
+
+    pub fn set_retention_settings(&mut self, settings: RetentionSettings) {
+        self.retention = settings;
+    }
+
     pub fn set_profile_id(&mut self, id: &str) {
         self.profile.id = id.to_string();
     }
@@ -266,17 +728,37 @@ impl AppState {
         self.contacts.values().cloned().collect()
     }
 
+    pub fn contacts_chat(&self) -> impl Iterator<Item = &ProfileChat> {
+        self.contacts.values()
+    }
+
+    /// Forgets the message changes of every chat, once they are written to disk.
+    pub fn mark_chats_saved(&mut self) {
+        self.topics.values_mut().for_each(Topic::mark_saved);
+        self.contacts.values_mut().for_each(ProfileChat::mark_saved);
+    }
+
     pub fn remove_contact(&mut self, profile_id: &str) {
         self.contacts.remove(profile_id);
     }
 
+    /// Records the safety number the user confirmed with a contact, `None` to unverify it.
+    pub fn set_contact_verified(&mut self, profile_id: &str, safety_number: Option<String>) {
+        if let Some(profile_chat) = self.contacts.get_mut(profile_id) {
+            profile_chat.profile.verified = safety_number;
+        }
+    }
+
     pub fn modify_contact(&mut self, profile: Profile) {
         if let Some(profile_chat) = self.contacts.get_mut(&profile.id)
             && let Some(existing_profile) = Some(&mut profile_chat.profile)
         {
             existing_profile.name = profile.name;
             existing_profile.avatar = profile.avatar;
-            existing_profile.last_connection = profile.last_connection;
+            // Metadata may carry an older last seen than the heartbeat, keep the most recent.
+            if profile.last_connection > existing_profile.last_connection {
+                existing_profile.last_connection = profile.last_connection;
+            }
         }
     }
 
@@ -288,6 +770,18 @@ impl AppState {
         }
     }
 
+    /// Applies a heartbeat result, a contact going offline is last seen at `timestamp`.
+    pub fn set_contact_online(&mut self, contact: &str, online: bool, timestamp: u64) {
+        if let Some(profile_chat) = self.contacts.get_mut(contact) {
+            let profile = &mut profile_chat.profile;
+            if online {
+                profile.last_connection = Online;
+            } else if profile.last_connection == Online {
+                profile.last_connection = Offline(timestamp);
+            }
+        }
+    }
+
     pub fn add_dm_message(&mut self, profile_id: &str, message: DmChatMessage) {
         if let Some(contact) = self.contacts.get_mut(profile_id) {
             contact.add_dm_message(message);
@@ -298,11 +792,67 @@ impl AppState {
         }
     }
 
+    /// Queues a contact request, unless the peer is already a contact or blocked.
+    ///
+    /// Returns whether the request is new.
+    pub fn add_contact_request(&mut self, request: ContactRequest) -> bool {
+        if self.contacts.contains_key(&request.id)
+            || self.blocked.contains(&request.id)
+            || self.contact_requests.contains_key(&request.id)
+        {
+            return false;
+        }
+        self.contact_requests.insert(request.id.clone(), request);
+        true
+    }
+
+    pub fn remove_contact_request(&mut self, id: &str) -> Option<ContactRequest> {
+        self.contact_requests.remove(id)
+    }
+
+    /// The pending contact requests, newest first.
+    #[must_use]
+    pub fn get_contact_requests(&self) -> Vec<ContactRequest> {
+        let mut requests: Vec<ContactRequest> = self.contact_requests.values().cloned().collect();
+        requests.sort_by_key(|request| std::cmp::Reverse(request.timestamp));
+        requests
+    }
+
+    /// Blocks `id`, dropping its pending request.
+    pub fn block(&mut self, id: &str) {
+        self.contact_requests.remove(id);
+        self.blocked.insert(id.to_string());
+    }
+
+    /// Returns whether `id` was blocked.
+    pub fn unblock(&mut self, id: &str) -> bool {
+        self.blocked.remove(id)
+    }
+
+    #[must_use]
+    pub fn is_blocked(&self, id: &str) -> bool {
+        self.blocked.contains(id)
+    }
+
+    /// The blocked peers, sorted so they are listed in a stable order.
+    #[must_use]
+    pub fn get_blocked(&self) -> Vec<String> {
+        let mut blocked: Vec<String> = self.blocked.iter().cloned().collect();
+        blocked.sort();
+        blocked
+    }
+
     pub fn add_dm_blob_message(&mut self, id: &str, message: DmBlobMessage) {
         if let Some(contact) = self.contacts.get_mut(id) {
             contact.add_dm_blob_message(message);
         }
     }
+
+    pub fn add_dm_collection_message(&mut self, id: &str, message: DmCollectionMessage) {
+        if let Some(contact) = self.contacts.get_mut(id) {
+            contact.add_dm_collection_message(message);
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -312,16 +862,202 @@ pub enum Message {
     Join(JoinMessage),
     Disconnect(DisconnectMessage),
     Blob(BlobMessage),
+    Collection(CollectionMessage),
+}
+
+/// Packs a wall clock timestamp into a hybrid logical clock value, matching `p2p::Hlc`.
+#[must_use]
+pub const fn hlc_from_wall(timestamp: u64) -> u64 {
+    timestamp << 16
+}
+
+/// The timestamp before which messages kept for `seconds` have expired at `now`.
+const fn expiry_cutoff(now: u64, seconds: u64) -> u64 {
+    now.saturating_sub(seconds.saturating_mul(1000))
+}
+
+/// How many milliseconds are in a day.
+const DAY_MILLIS: u64 = 86_400_000;
+
+/// Local limits on the history kept for a chat. They only apply to this device and are never
+/// shared, unlike the message timers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Keeps only the last messages, if set.
+    pub max_messages: Option<usize>,
+    /// Number of days messages are kept for, if set.
+    pub max_age_days: Option<u64>,
+    /// Number of days shared files are kept for, if set. Their messages stay in the chat, showing
+    /// that the files were removed.
+    pub media_age_days: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Whether a message is kept at `now`, given its `position` from the end of the chat (`1`
+    /// for the last one) and its timestamp in milliseconds.
+    #[must_use]
+    pub fn keeps(&self, position: usize, timestamp: u64, now: u64) -> bool {
+        self.max_messages.is_none_or(|max| position <= max)
+            && self
+                .max_age_days
+                .is_none_or(|days| timestamp >= days_cutoff(days, now))
+    }
+
+    /// Whether the files shared by a message sent at `timestamp` are kept at `now`.
+    #[must_use]
+    pub fn keeps_media(&self, timestamp: u64, now: u64) -> bool {
+        self.media_age_days
+            .is_none_or(|days| timestamp >= days_cutoff(days, now))
+    }
+}
+
+/// The time, in milliseconds, before which messages are more than `days` old at `now`.
+const fn days_cutoff(days: u64, now: u64) -> u64 {
+    now.saturating_sub(days.saturating_mul(DAY_MILLIS))
+}
+
+/// The retention policies of this device: a global one, and the chats that replace it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionSettings {
+    pub global: RetentionPolicy,
+    /// Policies of single chats, by topic or contact id.
+    pub chats: HashMap<String, RetentionPolicy>,
+}
+
+impl RetentionSettings {
+    /// The policy a chat follows, its own or else the global one.
+    #[must_use]
+    pub fn policy_for(&self, chat_id: &str) -> RetentionPolicy {
+        self.chats.get(chat_id).copied().unwrap_or(self.global)
+    }
+}
+
+/// A change to the messages of a chat, kept until it is written to disk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MessageChange<M> {
+    Added(M),
+    Removed(M),
+}
+
+/// What [`AppState::remove_expired_messages`] or [`AppState::apply_retention`] removed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Purge {
+    /// Whether messages, or their files, were removed from a topic.
+    pub topics: bool,
+    /// Whether messages, or their files, were removed from a direct chat.
+    pub contacts: bool,
+    /// The blobs of the removed messages that nothing else refers to anymore.
+    pub blobs: Vec<String>,
+}
+
+impl Purge {
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        !self.topics && !self.contacts
+    }
 }
 
 impl Message {
-    const fn get_timestamp(&self) -> u64 {
+    #[must_use]
+    pub const fn get_timestamp(&self) -> u64 {
         match self {
             Self::Chat(msg) => msg.timestamp,
             Self::Leave(msg) => msg.timestamp,
             Self::Join(msg) => msg.timestamp,
             Self::Disconnect(msg) => msg.timestamp,
             Self::Blob(msg) => msg.timestamp,
+            Self::Collection(msg) => msg.timestamp,
+        }
+    }
+
+    /// The hybrid logical clock of the message, falling back to its wall clock for local events
+    /// such as joins and leaves, which carry no clock.
+    const fn get_hlc(&self) -> u64 {
+        let hlc = match self {
+            Self::Chat(msg) => msg.hlc,
+            Self::Blob(msg) => msg.hlc,
+            Self::Collection(msg) => msg.hlc,
+            Self::Leave(_) | Self::Join(_) | Self::Disconnect(_) => 0,
+        };
+        let wall = hlc_from_wall(self.get_timestamp());
+        if hlc > wall { hlc } else { wall }
+    }
+
+    /// The hashes of the blobs shared by the message, a collection and each of its files, unless
+    /// they were removed.
+    #[must_use]
+    pub fn blob_hashes(&self) -> Vec<String> {
+        match self {
+            Self::Blob(msg) if !msg.media_removed => vec![msg.blob_hash.clone()],
+            Self::Collection(msg) if !msg.media_removed => {
+                std::iter::once(msg.collection_hash.clone())
+                    .chain(msg.entries.iter().map(|entry| entry.hash.clone()))
+                    .collect()
+            }
+            Self::Chat(_)
+            | Self::Leave(_)
+            | Self::Join(_)
+            | Self::Disconnect(_)
+            | Self::Blob(_)
+            | Self::Collection(_) => Vec::new(),
+        }
+    }
+
+    /// Whether the message shares files that were not removed.
+    #[must_use]
+    pub const fn has_media(&self) -> bool {
+        match self {
+            Self::Blob(msg) => !msg.media_removed,
+            Self::Collection(msg) => !msg.media_removed,
+            Self::Chat(_) | Self::Leave(_) | Self::Join(_) | Self::Disconnect(_) => false,
+        }
+    }
+
+    /// Drops the files shared by the message, which then only shows their names.
+    fn remove_media(&mut self) {
+        match self {
+            Self::Blob(msg) => {
+                msg.media_removed = true;
+                msg.preview = None;
+            }
+            Self::Collection(msg) => msg.media_removed = true,
+            Self::Chat(_) | Self::Leave(_) | Self::Join(_) | Self::Disconnect(_) => {}
+        }
+    }
+
+    /// The bytes the message takes: its text or the size of its files, none once removed.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::Chat(msg) => msg.content.len() as u64,
+            Self::Blob(msg) if !msg.media_removed => msg.blob_size,
+            Self::Collection(msg) if !msg.media_removed => msg.total_size(),
+            Self::Leave(_)
+            | Self::Join(_)
+            | Self::Disconnect(_)
+            | Self::Blob(_)
+            | Self::Collection(_) => 0,
+        }
+    }
+
+    /// The text shown as the last message of a topic, for the messages that can be one.
+    fn preview(&self) -> Option<String> {
+        match self {
+            Self::Chat(msg) => Some(msg.content.clone()),
+            Self::Blob(msg) => Some(format!("[{}]", msg.blob_name)),
+            Self::Collection(msg) => Some(format!("[{}]", msg.name)),
+            Self::Leave(_) | Self::Join(_) | Self::Disconnect(_) => None,
+        }
+    }
+
+    fn get_sender_id(&self) -> &str {
+        match self {
+            Self::Chat(msg) => &msg.sender_id,
+            Self::Leave(msg) => &msg.sender_id,
+            Self::Join(msg) => &msg.sender_id,
+            Self::Disconnect(msg) => &msg.sender_id,
+            Self::Blob(msg) => &msg.sender_id,
+            Self::Collection(msg) => &msg.sender_id,
         }
     }
 }
@@ -334,23 +1070,19 @@ impl PartialOrd for Message {
 
 impl PartialEq<Self> for Message {
     fn eq(&self, other: &Self) -> bool {
-        let self_timestamp = self.get_timestamp();
-
-        let other_timestamp = other.get_timestamp();
-
-        self_timestamp == other_timestamp
+        self.cmp(other) == Ordering::Equal
     }
 }
 
 impl Eq for Message {}
 
+/// Messages are ordered by (HLC, sender), which every peer computes the same way regardless of
+/// when the messages arrived.
 impl Ord for Message {
     fn cmp(&self, other: &Self) -> Ordering {
-        let self_timestamp = self.get_timestamp();
-
-        let other_timestamp = other.get_timestamp();
-
-        self_timestamp.cmp(&other_timestamp)
+        self.get_hlc()
+            .cmp(&other.get_hlc())
+            .then_with(|| self.get_sender_id().cmp(other.get_sender_id()))
     }
 }
 
@@ -361,6 +1093,7 @@ pub struct ChatMessage {
     pub content: String,
     pub timestamp: u64,
     pub is_sent: bool,
+    pub hlc: u64,
 }
 
 impl ChatMessage {
@@ -378,8 +1111,15 @@ impl ChatMessage {
             content,
             timestamp,
             is_sent,
+            hlc: hlc_from_wall(timestamp),
         }
     }
+
+    #[must_use]
+    pub const fn with_hlc(mut self, hlc: u64) -> Self {
+        self.hlc = hlc;
+        self
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -392,6 +1132,11 @@ pub struct BlobMessage {
     pub timestamp: u64,
     pub is_sent: bool,
     pub blob_type: BlobType,
+    pub preview: Option<BlobPreview>,
+    pub key: Option<String>, //Key the blob is encrypted with
+    pub hlc: u64,
+    /// Whether the retention policy removed the file, the message then only shows its name.
+    pub media_removed: bool,
 }
 
 impl BlobMessage {
@@ -406,6 +1151,8 @@ impl BlobMessage {
         timestamp: u64,
         is_sent: bool,
         blob_type: BlobType,
+        preview: Option<BlobPreview>,
+        key: Option<String>,
     ) -> Self {
         Self {
             sender_id,
@@ -416,8 +1163,113 @@ impl BlobMessage {
             timestamp,
             is_sent,
             blob_type,
+            preview,
+            key,
+            hlc: hlc_from_wall(timestamp),
+            media_removed: false,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_hlc(mut self, hlc: u64) -> Self {
+        self.hlc = hlc;
+        self
+    }
+}
+
+/// Low-resolution preview shown while the full image or video is not available yet.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobPreview {
+    pub width: u32,
+    pub height: u32,
+    pub thumbnail_url: String, //Data URL of the thumbnail
+}
+
+impl BlobPreview {
+    #[must_use]
+    pub const fn new(width: u32, height: u32, thumbnail_url: String) -> Self {
+        Self {
+            width,
+            height,
+            thumbnail_url,
+        }
+    }
+
+    /// CSS `aspect-ratio` value keeping the space of the original media.
+    #[must_use]
+    pub fn aspect_ratio(&self) -> String {
+        format!("{} / {}", self.width.max(1), self.height.max(1))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionEntry {
+    pub name: String,
+    pub hash: String,
+    pub size: u64, //Size in bytes
+    pub key: Option<String>, //Key the file is encrypted with
+}
+
+impl CollectionEntry {
+    #[must_use]
+    pub const fn new(name: String, hash: String, size: u64, key: Option<String>) -> Self {
+        Self {
+            name,
+            hash,
+            size,
+            key,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionMessage {
+    pub sender_id: String,
+    pub topic_id: String,
+    pub collection_hash: String,
+    pub name: String,
+    pub entries: Vec<CollectionEntry>,
+    pub timestamp: u64,
+    pub is_sent: bool,
+    pub hlc: u64,
+    /// Whether the retention policy removed the files, the message then only lists their names.
+    pub media_removed: bool,
+}
+
+impl CollectionMessage {
+    #[must_use]
+    pub const fn new(
+        sender_id: String,
+        topic_id: String,
+        collection_hash: String,
+        name: String,
+        entries: Vec<CollectionEntry>,
+        timestamp: u64,
+        is_sent: bool,
+    ) -> Self {
+        Self {
+            sender_id,
+            topic_id,
+            collection_hash,
+            name,
+            entries,
+            timestamp,
+            is_sent,
+            hlc: hlc_from_wall(timestamp),
+            media_removed: false,
         }
     }
+
+    #[must_use]
+    pub const fn with_hlc(mut self, hlc: u64) -> Self {
+        self.hlc = hlc;
+        self
+    }
+
+    #[must_use]
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -468,6 +1320,10 @@ pub struct DmBlobMessage {
     pub timestamp: u64,
     pub is_sent: bool,
     pub blob_type: BlobType,
+    pub preview: Option<BlobPreview>,
+    pub key: Option<String>, //Key the blob is encrypted with
+    /// Whether the retention policy removed the file, the message then only shows its name.
+    pub media_removed: bool,
 }
 
 impl DmBlobMessage {
@@ -482,6 +1338,8 @@ impl DmBlobMessage {
         timestamp: u64,
         is_sent: bool,
         blob_type: BlobType,
+        preview: Option<BlobPreview>,
+        key: Option<String>,
     ) -> Self {
         Self {
             sender_id,
@@ -492,6 +1350,46 @@ impl DmBlobMessage {
             timestamp,
             is_sent,
             blob_type,
+            preview,
+            key,
+            media_removed: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DmCollectionMessage {
+    pub sender_id: String,
+    pub receiver_id: String,
+    pub collection_hash: String,
+    pub name: String,
+    pub entries: Vec<CollectionEntry>,
+    pub timestamp: u64,
+    pub is_sent: bool,
+    /// Whether the retention policy removed the files, the message then only lists their names.
+    pub media_removed: bool,
+}
+
+impl DmCollectionMessage {
+    #[must_use]
+    pub const fn new(
+        sender_id: String,
+        receiver_id: String,
+        collection_hash: String,
+        name: String,
+        entries: Vec<CollectionEntry>,
+        timestamp: u64,
+        is_sent: bool,
+    ) -> Self {
+        Self {
+            sender_id,
+            receiver_id,
+            collection_hash,
+            name,
+            entries,
+            timestamp,
+            is_sent,
+            media_removed: false,
         }
     }
 }
@@ -500,14 +1398,66 @@ impl DmBlobMessage {
 pub enum DmMessage {
     Chat(DmChatMessage),
     Blob(DmBlobMessage),
+    Collection(DmCollectionMessage),
 }
 
 impl DmMessage {
+    /// The hashes of the blobs shared by the message, a collection and each of its files, unless
+    /// they were removed.
+    #[must_use]
+    pub fn blob_hashes(&self) -> Vec<String> {
+        match self {
+            Self::Blob(msg) if !msg.media_removed => vec![msg.blob_hash.clone()],
+            Self::Collection(msg) if !msg.media_removed => {
+                std::iter::once(msg.collection_hash.clone())
+                    .chain(msg.entries.iter().map(|entry| entry.hash.clone()))
+                    .collect()
+            }
+            Self::Chat(_) | Self::Blob(_) | Self::Collection(_) => Vec::new(),
+        }
+    }
+
+    /// Whether the message shares files that were not removed.
+    #[must_use]
+    pub const fn has_media(&self) -> bool {
+        match self {
+            Self::Blob(msg) => !msg.media_removed,
+            Self::Collection(msg) => !msg.media_removed,
+            Self::Chat(_) => false,
+        }
+    }
+
+    /// Drops the files shared by the message, which then only shows their names.
+    fn remove_media(&mut self) {
+        match self {
+            Self::Blob(msg) => {
+                msg.media_removed = true;
+                msg.preview = None;
+            }
+            Self::Collection(msg) => msg.media_removed = true,
+            Self::Chat(_) => {}
+        }
+    }
+
+    /// The bytes the message takes: its text or the size of its files, none once removed.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::Chat(msg) => msg.content.len() as u64,
+            Self::Blob(msg) if !msg.media_removed => msg.blob_size,
+            Self::Collection(msg) if !msg.media_removed => {
+                msg.entries.iter().map(|entry| entry.size).sum()
+            }
+            Self::Blob(_) | Self::Collection(_) => 0,
+        }
+    }
+
     #[must_use]
     pub const fn get_timestamp(&self) -> u64 {
         match self {
             Self::Chat(msg) => msg.timestamp,
             Self::Blob(msg) => msg.timestamp,
+            Self::Collection(msg) => msg.timestamp,
         }
     }
 
@@ -516,10 +1466,14 @@ impl DmMessage {
         match self {
             Self::Chat(msg) => msg.content.clone(),
             Self::Blob(msg) => msg.blob_name.clone(),
+            Self::Collection(msg) => msg.name.clone(),
         }
     }
 }
 
+/// DMs carry no hybrid logical clock: they are kept in the order they arrive over the single
+/// connection with the contact, which already orders the messages of each side, and are never
+/// sorted. Their wall clock only fills the clock of the converted message.
 impl From<DmMessage> for Message {
     fn from(msg: DmMessage) -> Self {
         match msg {
@@ -529,6 +1483,7 @@ impl From<DmMessage> for Message {
                 content: chat.content,
                 timestamp: chat.timestamp,
                 is_sent: chat.is_sent,
+                hlc: hlc_from_wall(chat.timestamp),
             }),
             DmMessage::Blob(blob) => Self::Blob(BlobMessage {
                 sender_id: blob.sender_id,
@@ -539,6 +1494,21 @@ impl From<DmMessage> for Message {
                 timestamp: blob.timestamp,
                 is_sent: blob.is_sent,
                 blob_type: blob.blob_type,
+                preview: blob.preview,
+                key: blob.key,
+                hlc: hlc_from_wall(blob.timestamp),
+                media_removed: blob.media_removed,
+            }),
+            DmMessage::Collection(collection) => Self::Collection(CollectionMessage {
+                sender_id: collection.sender_id,
+                topic_id: collection.receiver_id,
+                collection_hash: collection.collection_hash,
+                name: collection.name,
+                entries: collection.entries,
+                timestamp: collection.timestamp,
+                is_sent: collection.is_sent,
+                hlc: hlc_from_wall(collection.timestamp),
+                media_removed: collection.media_removed,
             }),
         }
     }
@@ -583,12 +1553,81 @@ pub struct DisconnectMessage {
     pub timestamp: u64,
 }
 
+/// Encoding used when images are recompressed before being sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImageEncoding {
+    #[default]
+    Jpeg,
+    /// Lossless WebP, keeps transparency but produces bigger files than JPEG.
+    WebP,
+}
+
+impl ImageEncoding {
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::WebP => "webp",
+        }
+    }
+}
+
+/// Preferences for the processing applied to images before they are sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaSettings {
+    /// Longest side, in pixels, of the images that are sent. Bigger images are downscaled.
+    pub max_dimension: u32,
+    pub encoding: ImageEncoding,
+    /// JPEG quality from 1 to 100, ignored for WebP.
+    pub quality: u8,
+    /// Skips the processing and sends the file as picked, metadata included.
+    pub send_original: bool,
+}
+
+impl Default for MediaSettings {
+    fn default() -> Self {
+        Self {
+            max_dimension: 2048,
+            encoding: ImageEncoding::Jpeg,
+            quality: 85,
+            send_original: false,
+        }
+    }
+}
+
+/// A peer asking to become a contact. Nothing about our profile is shared with it until the
+/// request is accepted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContactRequest {
+    pub id: String,
+    /// When the request was received, in milliseconds.
+    pub timestamp: u64,
+}
+
+impl ContactRequest {
+    #[must_use]
+    pub const fn new(id: String, timestamp: u64) -> Self {
+        Self { id, timestamp }
+    }
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize, Eq)]
 pub struct Profile {
     pub id: String,
     pub name: String,
     pub avatar: Option<String>,
     pub last_connection: ConnectionStatus,
+    /// The safety number the user confirmed with this contact, if they verified it.
+    pub verified: Option<String>,
+}
+
+/// Whether a contact's key was checked by comparing safety numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verification {
+    Unverified,
+    Verified,
+    /// The contact was verified, but the safety number changed since: one of the keys changed.
+    Changed,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Eq)]
@@ -658,7 +1697,8 @@ impl Profile {
             id: id.to_string(),
             name: name.to_string(),
             avatar: Some(avatar.to_string()),
-            last_connection: Online,
+            last_connection: Offline(0),
+            verified: None,
         }
     }
 
@@ -668,7 +1708,18 @@ impl Profile {
             id: id.to_string(),
             name: id.to_string(),
             avatar: None,
-            last_connection: Online,
+            last_connection: Offline(0),
+            verified: None,
+        }
+    }
+
+    /// Checks the verified safety number against the current one.
+    #[must_use]
+    pub fn verification(&self, safety_number: &str) -> Verification {
+        match &self.verified {
+            None => Verification::Unverified,
+            Some(verified) if verified == safety_number => Verification::Verified,
+            Some(_) => Verification::Changed,
         }
     }
 }
@@ -690,6 +1741,13 @@ pub struct ProfileChat {
     pub profile: Profile,
     pub messages: Vec<DmMessage>,
     pub last_changed: u64,
+    /// Number of seconds messages are kept for before they disappear, if set.
+    pub disappearing: Option<u64>,
+    /// When the message timer was last changed by either side, the latest change wins.
+    pub disappearing_changed: u64,
+    /// Messages added or removed since the chat was last written to disk.
+    #[serde(skip)]
+    unsaved: Vec<MessageChange<DmMessage>>,
 }
 
 impl ProfileChat {
@@ -700,9 +1758,70 @@ impl ProfileChat {
             profile,
             messages: Vec::new(),
             last_changed: chrono::Utc::now().timestamp_millis() as u64,
+            disappearing: None,
+            disappearing_changed: 0,
+            unsaved: Vec::new(),
         }
     }
 
+    /// Removes the messages sent before `cutoff`, in milliseconds, and returns them.
+    pub fn remove_messages_before(&mut self, cutoff: u64) -> Vec<DmMessage> {
+        self.remove_messages_where(|_, message| message.get_timestamp() < cutoff)
+    }
+
+    /// Removes the messages a retention policy does not keep at `now` and the files it does not
+    /// keep from the others, and returns the messages as they were.
+    pub fn prune(&mut self, policy: &RetentionPolicy, now: u64) -> Vec<DmMessage> {
+        let len = self.messages.len();
+        let mut removed = self.remove_messages_where(|index, message| {
+            !policy.keeps(len - index, message.get_timestamp(), now)
+        });
+        removed.extend(self.remove_media_where(|message| {
+            message.has_media() && !policy.keeps_media(message.get_timestamp(), now)
+        }));
+        removed
+    }
+
+    /// Removes the messages matching `predicate`, given their index and themselves.
+    fn remove_messages_where(
+        &mut self,
+        mut predicate: impl FnMut(usize, &DmMessage) -> bool,
+    ) -> Vec<DmMessage> {
+        let mut removed = Vec::new();
+        let mut index = 0;
+        self.messages.retain(|message| {
+            let matches = predicate(index, message);
+            index += 1;
+            if matches {
+                removed.push(message.clone());
+            }
+            !matches
+        });
+        self.unsaved
+            .extend(removed.iter().cloned().map(MessageChange::Removed));
+        removed
+    }
+
+    /// Drops the files of the messages matching `predicate`, keeping the messages, and returns
+    /// them as they were before.
+    fn remove_media_where(
+        &mut self,
+        mut predicate: impl FnMut(&DmMessage) -> bool,
+    ) -> Vec<DmMessage> {
+        let mut stripped = Vec::new();
+        for message in &mut self.messages {
+            if !predicate(message) {
+                continue;
+            }
+            let before = message.clone();
+            message.remove_media();
+            self.unsaved.push(MessageChange::Removed(before.clone()));
+            self.unsaved.push(MessageChange::Added(message.clone()));
+            stripped.push(before);
+        }
+        stripped
+    }
+
     #[must_use]
     pub fn last_message(&self) -> Option<String> {
         self.messages
@@ -712,13 +1831,55 @@ impl ProfileChat {
 
     pub fn add_dm_message(&mut self, message: DmChatMessage) {
         self.last_changed = message.timestamp;
-        self.messages.push(DmMessage::Chat(message));
+        self.push_message(DmMessage::Chat(message));
     }
 
     pub fn add_dm_blob_message(&mut self, message: DmBlobMessage) {
         self.last_changed = message.timestamp;
-        self.messages.push(DmMessage::Blob(message));
+        self.push_message(DmMessage::Blob(message));
+    }
+
+    pub fn add_dm_collection_message(&mut self, message: DmCollectionMessage) {
+        self.last_changed = message.timestamp;
+        self.push_message(DmMessage::Collection(message));
     }
+
+    fn push_message(&mut self, message: DmMessage) {
+        self.unsaved.push(MessageChange::Added(message.clone()));
+        self.messages.push(message);
+    }
+
+    /// The messages added or removed since the chat was last written to disk.
+    #[must_use]
+    pub fn unsaved(&self) -> &[MessageChange<DmMessage>] {
+        &self.unsaved
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.unsaved.clear();
+    }
+
+    /// A copy of the chat without its messages, which are stored one by one.
+    #[must_use]
+    pub fn details(&self) -> Self {
+        Self {
+            profile: self.profile.clone(),
+            messages: Vec::new(),
+            last_changed: self.last_changed,
+            disappearing: self.disappearing,
+            disappearing_changed: self.disappearing_changed,
+            unsaved: Vec::new(),
+        }
+    }
+}
+
+/// The state of a media download started from a chat.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DownloadState {
+    /// The number of bytes downloaded so far.
+    Progress(u64),
+    Done,
+    Failed,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -731,6 +1892,7 @@ pub enum ColumnState {
 pub enum RemovalType {
     Topic,
     Contact,
+    Block,
 }
 
 pub trait Controller {
@@ -738,11 +1900,30 @@ pub trait Controller {
     fn join_topic(&self, topic_id: String);
     fn leave_topic(&self, topic_id: String);
     fn remove_contact(&self, profile_id: String);
+    /// Marks a contact as verified with the current safety number, or unverifies it.
+    fn verify_contact(&self, profile_id: String, verified: bool);
+    /// Sets how long the messages of a direct chat are kept for, on both sides.
+    fn set_contact_disappearing(&self, profile_id: String, seconds: Option<u64>);
     fn send_message_to_topic(&self, ticket_id: String, message: String);
     fn modify_topic(&self, topic: Topic);
     fn modify_profile(&self, profile: Profile);
+    fn modify_media_settings(&self, settings: MediaSettings);
+    /// Replaces the retention policies and applies them right away.
+    fn modify_retention_settings(&self, settings: RetentionSettings);
     fn send_message_to_user(&self, user_addr: String, message: String);
+    /// Tells the topic members that the user is typing. Callers rate-limit it.
+    fn send_typing_to_topic(&self, ticket_id: String);
+    /// Tells a contact that the user is typing. Callers rate-limit it.
+    fn send_typing_to_user(&self, user_addr: String);
     fn connect_to_user(&self, user_id: String);
+    /// Accepts a contact request, sharing our profile with the peer.
+    fn accept_contact_request(&self, user_id: String);
+    /// Drops a contact request without sharing anything with the peer.
+    fn reject_contact_request(&self, user_id: String);
+    /// Blocks a peer, dropping its request or removing it from the contacts. Nothing it sends is
+    /// received anymore and it cannot fetch our blobs.
+    fn block_user(&self, user_id: String);
+    fn unblock_user(&self, user_id: String);
     fn send_blob_to_topic(
         &self,
         ticket_id: String,
@@ -751,8 +1932,13 @@ pub trait Controller {
         blob_type: BlobType,
     );
     fn download_blob(&self, hash: String, user_id: String);
-    fn get_from_storage(&self, hash: String, name: &str) -> Option<PathBuf>;
-    fn has_blob(&self, image_hash: &str, image_name: &str) -> bool;
+    /// Looks for a blob in the local store. The lookup exports the blob, so await it off the UI
+    /// thread.
+    fn get_from_storage(
+        &self,
+        hash: String,
+        name: &str,
+    ) -> impl Future<Output = Option<PathBuf>> + 'static;
     fn send_blob_to_user(
         &self,
         user_addr: String,
@@ -760,11 +1946,26 @@ pub trait Controller {
         name: String,
         blob_type: BlobType,
     );
-    ///Returns an empty vector if the image could not be found or downloaded
-    /// 
-    /// # Errors
-    /// 
-    /// Return an error if it fails to get the blob or download
-    fn get_or_download(&self, hash: &str, user_id: &str, name: &str) -> anyhow::Result<PathBuf>;
     fn get_media_url(&self, hash: &str, name: &str) -> String;
+    /// The URL an avatar stored as a blob is served from.
+    fn get_avatar_url(&self, hash: &str) -> String;
+    /// The safety number shared with a peer, `None` if its id is invalid.
+    fn get_safety_number(&self, user_id: &str) -> Option<String>;
+    /// Sends several files as one collection. Without a `name` the shared folder name is used.
+    fn send_collection_to_topic(
+        &self,
+        ticket_id: String,
+        files: Vec<FileData>,
+        name: Option<String>,
+    );
+    fn send_collection_to_user(
+        &self,
+        user_addr: String,
+        files: Vec<FileData>,
+        name: Option<String>,
+    );
+    /// Downloads every entry of a collection (if missing) and saves them inside `target_dir`.
+    fn download_collection(&self, hash: String, user_id: String, target_dir: PathBuf);
+    /// Downloads a single entry of a collection (if missing) and saves it at `target`.
+    fn download_collection_entry(&self, hash: String, user_id: String, target: PathBuf);
 }
@@ -1,7 +1,10 @@
 use std::path::PathBuf;
 
-use crate::desktop::models::AppState;
+use crate::desktop::desktop_web_components::DEFAULT_AVATAR;
+use crate::desktop::models::{AppState, ConnectionStatus, Controller};
 use arboard::Clipboard;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
 use chrono::{DateTime, Local, TimeDelta};
 use dioxus_primitives::toast::ToastOptions;
 use dioxus_primitives::toast::Toasts;
@@ -17,17 +20,48 @@ pub fn truncate_id(id: &str) -> String {
     }
 }
 
+/// Where to load an avatar from: avatars are blob hashes served by the controller, except a
+/// freshly picked one that is still a `data:` URL until it is saved.
+#[must_use]
+pub fn avatar_src<C: Controller>(controller: &C, avatar: Option<&str>) -> String {
+    match avatar.filter(|avatar| !avatar.is_empty()) {
+        None => DEFAULT_AVATAR.to_string(),
+        Some(url) if url.starts_with("data:") => url.to_string(),
+        Some(hash) => controller.get_avatar_url(hash),
+    }
+}
+
+/// Renders `data` as a QR code, returned as an SVG `data:` URL ready for an `img`.
+#[must_use]
+pub fn qr_code_src(data: &str) -> Option<String> {
+    let code = qrcode::QrCode::new(data.as_bytes()).ok()?;
+    let svg = code
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(160, 160)
+        .build();
+    Some(format!(
+        "data:image/svg+xml;base64,{}",
+        BASE64_STANDARD.encode(svg)
+    ))
+}
+
 #[must_use]
 pub fn get_sender_display_name(app_state: &AppState, sender_id: &str) -> String {
     let profile = app_state.get_profile();
     if sender_id == profile.id {
         return profile.name;
     }
-    app_state.get_contact(sender_id).map_or_else(|| truncate_id(sender_id), |contact| if contact.name == contact.id {
-            truncate_id(sender_id)
-        } else {
-            contact.name.clone()
-        })
+    if let Some(contact) = app_state.get_contact(sender_id)
+        && contact.name != contact.id
+    {
+        return contact.name.clone();
+    }
+    // Members who are not contacts are shown with the profile they announced in the topic.
+    app_state
+        .get_current_topic()
+        .and_then(|topic| topic.get_member_profile(sender_id))
+        .filter(|member| member.name != sender_id)
+        .map_or_else(|| truncate_id(sender_id), |member| member.name.clone())
 }
 
 #[must_use]
@@ -57,6 +91,57 @@ pub fn format_message_timestamp(timestamp: u64) -> String {
     datetime.format("%m/%d/%y %I:%M %p").to_string()
 }
 
+/// The message timers a chat can be set to, in seconds, `0` keeps messages.
+pub const DISAPPEARING_TIMERS: [(u64, &str); 6] = [
+    (0, "Off"),
+    (300, "5 minutes"),
+    (3_600, "1 hour"),
+    (28_800, "8 hours"),
+    (86_400, "1 day"),
+    (604_800, "1 week"),
+];
+
+/// The message counts a chat can be limited to, `0` keeps them all.
+pub const RETENTION_COUNTS: [(usize, &str); 4] = [
+    (0, "All messages"),
+    (100, "Last 100"),
+    (1_000, "Last 1,000"),
+    (10_000, "Last 10,000"),
+];
+
+/// The numbers of days history can be kept for, `0` keeps it forever.
+pub const RETENTION_DAYS: [(u64, &str); 5] = [
+    (0, "Never"),
+    (7, "After 7 days"),
+    (30, "After 30 days"),
+    (90, "After 90 days"),
+    (365, "After 1 year"),
+];
+
+/// Describes a message timer, e.g. `"1 hour"`.
+#[must_use]
+pub fn format_timer(seconds: u64) -> String {
+    DISAPPEARING_TIMERS
+        .iter()
+        .find(|(timer, _)| *timer == seconds)
+        .map_or_else(
+            || format!("{seconds} seconds"),
+            |(_, label)| (*label).to_string(),
+        )
+}
+
+#[must_use]
+pub fn format_last_seen(status: &ConnectionStatus) -> String {
+    match status {
+        ConnectionStatus::Online => String::from("Online"),
+        ConnectionStatus::Offline(0) => String::from("Offline"),
+        ConnectionStatus::Offline(time) => format!(
+            "Last seen {}",
+            format_relative_time(i64::try_from(time / 1000).unwrap_or_default())
+        ),
+    }
+}
+
 #[must_use]
 pub fn format_relative_time(timestamp: i64) -> String {
     let last_connection = match DateTime::from_timestamp(timestamp, 0) {
@@ -120,14 +205,6 @@ pub fn is_video_file(path: &PathBuf) -> bool {
     false
 }
 
-// pub fn process_image(file_bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
-//     let image = image::load_from_memory(file_bytes)?;
-//     let resized = image.thumbnail(500, 500);
-//     let mut buffer = std::io::Cursor::new(Vec::new());
-//     resized.write_to(&mut buffer, image::ImageFormat::WebP)?;
-//     Ok(buffer.into_inner())
-// }
-
 #[must_use]
 #[allow(clippy::cast_precision_loss)]
 pub fn format_file_size(size: u64) -> String {